@@ -0,0 +1,55 @@
+//! Command line flags, given as `--flag value` pairs or bare `--flag` switches
+
+/// Returns every value following `flag` in `args`, in order
+fn values_in<I>(args: I, flag: &str) -> Vec<String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter();
+    let mut values = vec![];
+    while let Some(arg) = args.next() {
+        if arg.eq(flag) {
+            match args.next() {
+                Some(value) => values.push(value),
+                None => break,
+            }
+        }
+    }
+    values
+}
+
+/// Returns the value following the first occurrence of `flag`
+pub fn value(flag: &str) -> Option<String> {
+    values(flag).into_iter().next()
+}
+
+/// Returns the values following every occurrence of `flag`
+pub fn values(flag: &str) -> Vec<String> {
+    values_in(std::env::args().skip(1), flag)
+}
+
+/// Returns true if `flag` was passed as a switch
+pub fn flag(flag: &str) -> bool {
+    std::env::args().skip(1).any(|arg| arg.eq(flag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::values_in;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split(' ').map(String::from).collect()
+    }
+
+    #[test]
+    fn values_follow_their_flag() {
+        let line = args("--directory /tmp --tls-sni a=x,y --tls-sni b=z,w");
+        assert_eq!(values_in(line.clone(), "--directory"), vec!["/tmp"]);
+        assert_eq!(values_in(line, "--tls-sni"), vec!["a=x,y", "b=z,w"]);
+    }
+
+    #[test]
+    fn trailing_flag_has_no_value() {
+        assert!(values_in(args("--directory"), "--directory").is_empty());
+    }
+}
@@ -0,0 +1,372 @@
+//! Basic and Bearer authentication of the paths under configured prefixes
+//!
+//! Each rule covers the paths under its prefix, segment by segment, and
+//! accepts either Basic credentials checked against an htpasswd file or
+//! Bearer tokens listed in a token file. A request covered by rules must
+//! satisfy one of them, or is answered with 401 and a challenge for each.
+use {
+    crate::http::{
+        header::{
+            authorization::{Challenge, Credentials},
+            typed::{self, Authorization},
+        },
+        middleware::{Layer, Next},
+        request::target,
+        response::Status,
+        router, Request, Response,
+    },
+    ring::digest::{self, SHA1_FOR_LEGACY_USE_ONLY, SHA256},
+    std::{
+        collections::{HashMap, HashSet},
+        fs, io,
+        path::Path,
+        sync::Mutex,
+    },
+};
+
+/// Compares `a` and `b` in a time that does not depend on where they differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Lines of a credentials file that are neither blank nor `#` comments, numbered from 1
+fn entries(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .map(str::trim)
+        .enumerate()
+        .map(|(index, line)| (index + 1, line))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+}
+
+/// Password hash of an htpasswd entry
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Hash {
+    /// `$2y$`, `$2b$` or `$2a$` hashes, as `htpasswd -B` writes them
+    Bcrypt(String),
+    /// `{SHA}` hashes, as `htpasswd -s` writes them, unsalted and only kept for older files
+    Sha1(Vec<u8>),
+}
+impl Hash {
+    fn parse(hash: &str) -> Option<Self> {
+        if ["$2y$", "$2b$", "$2a$"]
+            .iter()
+            .any(|prefix| hash.starts_with(prefix))
+        {
+            Some(Hash::Bcrypt(hash.to_string()))
+        } else {
+            let digest = crate::http::base64::decode(hash.strip_prefix("{SHA}")?)?;
+            Some(Hash::Sha1(digest))
+        }
+    }
+    fn verify(&self, password: &str) -> bool {
+        match self {
+            Hash::Bcrypt(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            Hash::Sha1(expected) => {
+                let digest = digest::digest(&SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes());
+                constant_time_eq(digest.as_ref(), expected)
+            }
+        }
+    }
+}
+
+/// Users and password hashes of an htpasswd file
+///
+/// Checking a bcrypt hash takes tens of milliseconds on purpose, so passwords
+/// that checked out are remembered by their SHA-256 digest for the next
+/// requests of the same user.
+pub struct Htpasswd {
+    users: HashMap<String, Hash>,
+    verified: Mutex<HashMap<String, Vec<u8>>>,
+}
+impl Htpasswd {
+    /// Parses `user:hash` lines, failing on hashes of a kind that can not be checked
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut users = HashMap::new();
+        for (number, line) in entries(text) {
+            let (user, hash) = line
+                .split_once(':')
+                .ok_or_else(|| format!("line {} is not user:hash", number))?;
+            let hash = Hash::parse(hash).ok_or_else(|| {
+                format!(
+                    "unsupported hash for {} on line {}, use bcrypt (htpasswd -B)",
+                    user, number
+                )
+            })?;
+            users.insert(user.to_string(), hash);
+        }
+        Ok(Self {
+            users,
+            verified: Mutex::new(HashMap::new()),
+        })
+    }
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?).map_err(|message| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), message),
+            )
+        })
+    }
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        let hash = match self.users.get(user) {
+            Some(hash) => hash,
+            None => return false,
+        };
+        let digest = digest::digest(&SHA256, password.as_bytes());
+        let remembered = {
+            let verified = self.verified.lock().unwrap_or_else(|e| e.into_inner());
+            verified
+                .get(user)
+                .is_some_and(|remembered| constant_time_eq(remembered, digest.as_ref()))
+        };
+        if remembered {
+            return true;
+        }
+        let valid = hash.verify(password);
+        if valid {
+            let mut verified = self.verified.lock().unwrap_or_else(|e| e.into_inner());
+            verified.insert(user.to_string(), digest.as_ref().to_vec());
+        }
+        valid
+    }
+}
+
+/// Bearer tokens of a token file, one per line, kept as SHA-256 digests
+pub struct Tokens {
+    digests: HashSet<Vec<u8>>,
+}
+impl Tokens {
+    pub fn parse(text: &str) -> Self {
+        let digests = entries(text)
+            .map(|(_, token)| digest::digest(&SHA256, token.as_bytes()).as_ref().to_vec())
+            .collect();
+        Self { digests }
+    }
+    pub fn load(path: &Path) -> io::Result<Self> {
+        fs::read_to_string(path).map(|text| Self::parse(&text))
+    }
+    /// Returns true for listed tokens, compared by digest so the lookup leaks nothing about them
+    pub fn verify(&self, token: &str) -> bool {
+        let digest = digest::digest(&SHA256, token.as_bytes());
+        self.digests.contains(digest.as_ref())
+    }
+}
+
+/// How a rule checks credentials
+pub enum Scheme {
+    Basic(Htpasswd),
+    Bearer(Tokens),
+}
+
+/// Credentials required for the paths under `prefix`
+pub struct Rule {
+    pub prefix: String,
+    pub scheme: Scheme,
+}
+impl Rule {
+    /// Parses `prefix=file`, like `/files=/etc/htpasswd`, into the prefix and file
+    fn parse(rule: &str) -> io::Result<(String, &Path)> {
+        match rule.split_once('=') {
+            Some((prefix, file)) if prefix.starts_with('/') && !file.is_empty() => {
+                Ok((prefix.to_string(), Path::new(file)))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("authentication rule {} is not prefix=file", rule),
+            )),
+        }
+    }
+    /// Returns true if `path` is the prefix or lies under it
+    fn covers(&self, path: &str) -> bool {
+        router::is_under(path, &self.prefix)
+    }
+    /// Challenge naming the scheme of the rule, its prefix being the realm
+    fn challenge(&self, credentials: Option<&Credentials>) -> Challenge {
+        match &self.scheme {
+            Scheme::Basic(_) => Challenge::new("Basic", &self.prefix).parameter("charset", "UTF-8"),
+            Scheme::Bearer(_) => match credentials {
+                Some(Credentials::Bearer(_)) => {
+                    Challenge::new("Bearer", &self.prefix).parameter("error", "invalid_token")
+                }
+                _ => Challenge::new("Bearer", &self.prefix),
+            },
+        }
+    }
+    fn accepts(&self, credentials: &Credentials) -> bool {
+        match (&self.scheme, credentials) {
+            (Scheme::Basic(users), Credentials::Basic { user, password }) => {
+                users.verify(user, password)
+            }
+            (Scheme::Bearer(tokens), Credentials::Bearer(token)) => tokens.verify(token),
+            _ => false,
+        }
+    }
+}
+
+/// Layer answering 401 to requests under a protected prefix that lack valid credentials
+///
+/// CONNECT requests name a destination rather than a path and are let
+/// through, the forward proxy checking their credentials.
+pub struct Auth {
+    rules: Vec<Rule>,
+}
+impl Auth {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Self { rules }
+    }
+    /// Reads Basic rules from every `--auth-basic prefix=htpasswd` and Bearer
+    /// rules from every `--auth-bearer prefix=tokens`
+    pub fn from_args() -> io::Result<Self> {
+        let mut rules = vec![];
+        for rule in crate::args::values("--auth-basic") {
+            let (prefix, file) = Rule::parse(&rule)?;
+            let scheme = Scheme::Basic(Htpasswd::load(file)?);
+            rules.push(Rule { prefix, scheme });
+        }
+        for rule in crate::args::values("--auth-bearer") {
+            let (prefix, file) = Rule::parse(&rule)?;
+            let scheme = Scheme::Bearer(Tokens::load(file)?);
+            rules.push(Rule { prefix, scheme });
+        }
+        Ok(Self::new(rules))
+    }
+}
+impl Layer for Auth {
+    fn handle(&self, request: Request, next: Next<'_>) -> io::Result<Response> {
+        if request.start_line.target.form.eq(&target::Form::Authority) {
+            return next.run(request);
+        }
+        let path = request.start_line.target.path.as_str();
+        let rules = self
+            .rules
+            .iter()
+            .filter(|rule| rule.covers(path))
+            .collect::<Vec<&Rule>>();
+        if rules.is_empty() {
+            return next.run(request);
+        }
+        let credentials = request
+            .headers
+            .typed::<Authorization>()
+            .map(|authorization| authorization.0);
+        if let Some(credentials) = &credentials {
+            if rules.iter().any(|rule| rule.accepts(credentials)) {
+                return next.run(request);
+            }
+            elog_from_mod!("rejecting credentials for", path.escape_debug());
+        }
+        let challenges = rules
+            .iter()
+            .map(|rule| rule.challenge(credentials.as_ref()))
+            .collect::<Vec<Challenge>>();
+        let mut response = Response::text(Status::Unauthorized, "authentication required");
+        let challenges = typed::WwwAuthenticate::from(challenges);
+        response.headers.insert_typed(&challenges);
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Auth, Htpasswd, Rule, Scheme, Tokens};
+    use crate::http::{
+        connection::Handler,
+        middleware::{Layer, Next},
+        response::Status,
+        Request, Response,
+    };
+    use std::sync::Arc;
+
+    /// `htpasswd -nbB alice secret`, at the lowest cost to keep tests fast
+    const ALICE: &str = "alice:$2y$04$dm8oY7ab0sncyP6wvRtTee1J0x1DIObIPTIP8bVbCMwynMPF6Os2K";
+    /// `htpasswd -nbs bob hunter2`
+    const BOB: &str = "bob:{SHA}87u9ZqY9S/F0eUBXjsPQEDUw4h0=";
+
+    #[test]
+    fn htpasswd_checks_bcrypt_and_sha_hashes() {
+        let users = Htpasswd::parse(&format!("# users\n{}\n\n{}\n", ALICE, BOB)).unwrap();
+        assert!(users.verify("alice", "secret"));
+        assert!(users.verify("alice", "secret"));
+        assert!(!users.verify("alice", "Secret"));
+        assert!(users.verify("bob", "hunter2"));
+        assert!(!users.verify("bob", "hunter3"));
+        assert!(!users.verify("carol", "secret"));
+        assert!(Htpasswd::parse("dave:$apr1$salt$hash").is_err());
+        assert!(Htpasswd::parse("no colon").is_err());
+    }
+
+    #[test]
+    fn rules_cover_whole_segments() {
+        let rule = Rule {
+            prefix: "/files".to_string(),
+            scheme: Scheme::Bearer(Tokens::parse("")),
+        };
+        assert!(rule.covers("/files"));
+        assert!(rule.covers("/files/a?b"));
+        assert!(rule.covers("//files//a"));
+        assert!(!rule.covers("/filesystem"));
+        assert!(!rule.covers("/"));
+    }
+
+    #[test]
+    fn requests_without_valid_credentials_are_challenged() {
+        let rules = vec![
+            Rule {
+                prefix: "/files".to_string(),
+                scheme: Scheme::Basic(Htpasswd::parse(BOB).unwrap()),
+            },
+            Rule {
+                prefix: "/files".to_string(),
+                scheme: Scheme::Bearer(Tokens::parse("# ci\nt0ken\n")),
+            },
+        ];
+        let layers: Vec<Arc<dyn Layer>> = vec![Arc::new(Auth::new(rules))];
+        let handler: Handler = Arc::new(|_| Ok(Response::empty(Status::Ok)));
+        let send = |path: &str, authorization: Option<&str>| {
+            let authorization = authorization
+                .map(|value| format!("Authorization: {}\r\n", value))
+                .unwrap_or_default();
+            let raw = format!("GET {} HTTP/1.1\r\nHost: a\r\n{}\r\n", path, authorization);
+            let request = Request::try_construct(raw.as_bytes()).unwrap();
+            Next::new(&layers, &handler).run(request).unwrap()
+        };
+        assert_eq!(send("/echo/a", None).start_line.status as u16, 200);
+        let response = send("/files/a", None);
+        assert_eq!(response.start_line.status as u16, 401);
+        assert_eq!(
+            response.headers.get("WWW-Authenticate"),
+            Some(r#"Basic realm="/files", charset="UTF-8", Bearer realm="/files""#)
+        );
+        let response = send("/files/a", Some("Bearer wrong"));
+        assert_eq!(response.start_line.status as u16, 401);
+        assert!(response
+            .headers
+            .get("WWW-Authenticate")
+            .unwrap()
+            .ends_with(r#"Bearer realm="/files", error="invalid_token""#));
+        assert_eq!(
+            send("/files/a", Some("Bearer t0ken")).start_line.status as u16,
+            200
+        );
+        let basic = "Basic Ym9iOmh1bnRlcjI=";
+        assert_eq!(send("/files/a", Some(basic)).start_line.status as u16, 200);
+        assert_eq!(
+            send("/files/a", Some("Basic Ym9iOm5v")).start_line.status as u16,
+            401
+        );
+    }
+
+    #[test]
+    fn connect_requests_are_left_to_the_forward_proxy() {
+        let rules = vec![Rule {
+            prefix: "/".to_string(),
+            scheme: Scheme::Bearer(Tokens::parse("t0ken")),
+        }];
+        let layers: Vec<Arc<dyn Layer>> = vec![Arc::new(Auth::new(rules))];
+        let handler: Handler = Arc::new(|_| Ok(Response::empty(Status::Ok)));
+        let raw = "CONNECT a.test:443 HTTP/1.1\r\nHost: a.test:443\r\n\r\n";
+        let request = Request::try_construct(raw.as_bytes()).unwrap();
+        let response = Next::new(&layers, &handler).run(request).unwrap();
+        assert_eq!(response.start_line.status as u16, 200);
+    }
+}
@@ -0,0 +1,88 @@
+/// Decodes base64 in either the standard or the URL safe alphabet, see RFC 4648
+///
+/// Padding is optional, whitespace and any other character are rejected.
+pub fn decode(encoded: &str) -> Option<Vec<u8>> {
+    let sextet = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' | b'-' => Some(62),
+        b'/' | b'_' => Some(63),
+        _ => None,
+    };
+    let trimmed = encoded.trim_end_matches('=');
+    if encoded.len() - trimmed.len() > 2 || trimmed.len() % 4 == 1 {
+        return None;
+    }
+    let mut decoded = Vec::with_capacity(trimmed.len() * 3 / 4);
+    for group in trimmed.as_bytes().chunks(4) {
+        let sextets = group
+            .iter()
+            .map(|c| sextet(*c))
+            .collect::<Option<Vec<u8>>>()?;
+        let bits = sextets.iter().enumerate().fold(0u32, |bits, (i, sextet)| {
+            bits | (*sextet as u32) << (18 - 6 * i)
+        });
+        let bytes = bits.to_be_bytes();
+        decoded.extend_from_slice(&bytes[1..sextets.len()]);
+    }
+    Some(decoded)
+}
+
+/// Encodes `bytes` as padded base64 in the standard alphabet
+pub fn encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let bits = group.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+            bits | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= group.len() {
+                let sextet = (bits >> (18 - 6 * i)) & 0x3f;
+                encoded.push(ALPHABET[sextet as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode};
+
+    #[test]
+    fn decodes_rfc_4648_vectors() {
+        let vectors = [
+            ("", ""),
+            ("Zg==", "f"),
+            ("Zm8=", "fo"),
+            ("Zm9v", "foo"),
+            ("Zm9vYg==", "foob"),
+            ("Zm9vYmE", "fooba"),
+            ("Zm9vYmFy", "foobar"),
+        ];
+        for (encoded, decoded) in vectors {
+            assert_eq!(decode(encoded).unwrap(), decoded.as_bytes());
+            assert_eq!(
+                encode(decoded.as_bytes()).trim_end_matches('='),
+                encoded.trim_end_matches('=')
+            );
+        }
+        assert_eq!(encode(b"fooba"), "Zm9vYmE=");
+    }
+
+    #[test]
+    fn accepts_both_alphabets() {
+        assert_eq!(decode("-_-_").unwrap(), decode("+/+/").unwrap());
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(decode("Zm9v!").is_none());
+        assert!(decode("Z").is_none());
+        assert!(decode("Zg===").is_none());
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    io::{self, Read},
+    sync::mpsc::Receiver,
+};
+
+/// Receives the chunks of a streamed body as they are produced
+///
+/// Every chunk is written out and flushed before `send` returns, so errors
+/// surface as soon as the client is gone.
+pub trait Sink {
+    fn send(&mut self, chunk: &[u8]) -> io::Result<()>;
+}
+
+/// Produces a streamed body, returning once the body is complete
+pub type Producer = Box<dyn FnOnce(&mut dyn Sink) -> io::Result<()> + Send>;
+
+/// Returns true for errors caused by the client going away mid response
+pub fn is_disconnect(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// Chunks of a request body passed from the connection to its handler
+pub type Piece = io::Result<Vec<u8>>;
+
+/// Body of a request, read as the connection receives it when streamed
+///
+/// Bodies read in whole before the handler ran are read from memory. A
+/// streamed body ends once the connection received all of it, and fails
+/// if the client does not send it in time or sends more than the limit.
+pub struct Source {
+    receiver: Option<Receiver<Piece>>,
+    chunk: Vec<u8>,
+    position: usize,
+    length: Option<u64>,
+    /// Bytes read so far
+    read: u64,
+}
+impl Source {
+    /// Body already read in whole
+    pub fn buffered(body: Vec<u8>) -> Self {
+        Self {
+            receiver: None,
+            length: Some(body.len() as u64),
+            chunk: body,
+            position: 0,
+            read: 0,
+        }
+    }
+    /// Body arriving through `receiver`, `length` bytes long if known beforehand
+    pub fn streamed(receiver: Receiver<Piece>, length: Option<u64>) -> Self {
+        Self {
+            receiver: Some(receiver),
+            chunk: vec![],
+            position: 0,
+            length,
+            read: 0,
+        }
+    }
+    /// Length of the whole body, unknown for streamed chunked bodies
+    pub fn length(&self) -> Option<u64> {
+        self.length
+    }
+    /// Returns true if bytes of the body were read already
+    pub fn started(&self) -> bool {
+        self.read > 0
+    }
+}
+impl Read for Source {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            let Some(receiver) = &self.receiver else {
+                return Ok(0);
+            };
+            match receiver.recv() {
+                Ok(Ok(chunk)) => (self.chunk, self.position) = (chunk, 0),
+                Ok(Err(e)) => return Err(e),
+                // the connection drops its end once the whole body was passed on
+                Err(_) => self.receiver = None,
+            }
+        }
+        let read = buffer.len().min(self.chunk.len() - self.position);
+        buffer[..read].copy_from_slice(&self.chunk[self.position..self.position + read]);
+        self.position += read;
+        self.read += read as u64;
+        Ok(read)
+    }
+}
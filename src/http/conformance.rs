@@ -0,0 +1,378 @@
+//! Conformance tests for request parsing against RFC 9110 and RFC 9112
+use crate::http::{
+    header::typed::{ContentLength, UserAgent},
+    Error, Request,
+};
+
+fn parse(raw: &str) -> Result<Request, Error> {
+    Request::try_construct(raw.as_bytes())
+}
+
+fn body(raw: &str) -> Vec<u8> {
+    parse(raw).unwrap().body.unwrap_or_default()
+}
+
+mod field_names {
+    use super::*;
+
+    #[test]
+    fn lowercase_names_are_recognized() {
+        let request = parse("POST /files/a HTTP/1.1\r\ncontent-length: 5\r\n\r\nhello").unwrap();
+        assert_eq!(
+            request.headers().typed::<ContentLength>(),
+            Some(ContentLength(5))
+        );
+        assert_eq!(request.body.unwrap(), b"hello");
+    }
+
+    #[test]
+    fn mixed_case_names_are_recognized() {
+        let request = parse("GET / HTTP/1.1\r\nuSeR-aGeNt: curl/8.0\r\n\r\n").unwrap();
+        assert!(request.headers().typed::<UserAgent>().is_some());
+    }
+
+    #[test]
+    fn unknown_fields_are_kept() {
+        let request = parse("GET / HTTP/1.1\r\nX-Request-Id: abc\r\n\r\n").unwrap();
+        assert_eq!(request.headers().get("x-request-id"), Some("abc"));
+    }
+
+    #[test]
+    fn whitespace_before_colon_is_rejected() {
+        assert!(parse("GET / HTTP/1.1\r\nHost : a\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn non_token_characters_are_rejected() {
+        assert!(parse("GET / HTTP/1.1\r\nX(Bad): a\r\n\r\n").is_err());
+        assert!(parse("GET / HTTP/1.1\r\n: a\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn missing_colon_is_rejected() {
+        assert!(parse("GET / HTTP/1.1\r\nHost a\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn obsolete_line_folding_is_rejected() {
+        assert!(parse("GET / HTTP/1.1\r\nX-A: a\r\n b\r\n\r\n").is_err());
+    }
+}
+
+mod field_values {
+    use super::*;
+
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        let request = parse("GET / HTTP/1.1\r\nX-A: \t a b \t\r\n\r\n").unwrap();
+        assert_eq!(request.headers().get("X-A"), Some("a b"));
+    }
+
+    #[test]
+    fn bare_cr_is_rejected() {
+        assert!(parse("GET / HTTP/1.1\r\nX-A: a\rb\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn bare_lf_is_rejected() {
+        assert!(parse("GET / HTTP/1.1\r\nX-A: a\nb\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn nul_is_rejected() {
+        assert!(parse("GET / HTTP/1.1\r\nX-A: a\0b\r\n\r\n").is_err());
+    }
+}
+
+mod framing {
+    use super::*;
+
+    #[test]
+    fn body_is_limited_to_content_length() {
+        assert_eq!(
+            body("POST /f HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcdef"),
+            b"abc"
+        );
+    }
+
+    #[test]
+    fn identical_repeated_content_lengths_are_accepted() {
+        let raw = "POST /f HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 3\r\n\r\nabc";
+        assert_eq!(body(raw), b"abc");
+        assert_eq!(
+            body("POST /f HTTP/1.1\r\nContent-Length: 3, 3\r\n\r\nabc"),
+            b"abc"
+        );
+    }
+
+    #[test]
+    fn conflicting_content_lengths_are_rejected() {
+        let raw = "POST /f HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\nabcd";
+        assert!(matches!(parse(raw), Err(Error::Header(_))));
+        assert!(parse("POST /f HTTP/1.1\r\nContent-Length: 3, 4\r\n\r\nabcd").is_err());
+    }
+
+    #[test]
+    fn signed_or_non_numeric_content_length_is_rejected() {
+        assert!(parse("POST /f HTTP/1.1\r\nContent-Length: +3\r\n\r\nabc").is_err());
+        assert!(parse("POST /f HTTP/1.1\r\nContent-Length: -1\r\n\r\n").is_err());
+        assert!(parse("POST /f HTTP/1.1\r\nContent-Length: 0x3\r\n\r\nabc").is_err());
+        assert!(parse("POST /f HTTP/1.1\r\nContent-Length:\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn content_length_with_transfer_encoding_is_rejected() {
+        let raw =
+            "POST /f HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n";
+        assert!(matches!(parse(raw), Err(Error::Framing(_))));
+    }
+
+    #[test]
+    fn transfer_encoding_must_end_in_chunked() {
+        let raw = "POST /f HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n";
+        assert!(matches!(parse(raw), Err(Error::Framing(_))));
+    }
+
+    #[test]
+    fn chunked_body_is_decoded() {
+        let raw = "POST /f HTTP/1.1\r\ntransfer-encoding: Chunked\r\n\r\n4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\nX-Trailer: a\r\n\r\n";
+        assert_eq!(body(raw), b"Wikipedia");
+    }
+
+    #[test]
+    fn malformed_chunks_are_rejected() {
+        let prefix = "POST /f HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert!(parse(&format!("{}zz\r\nab\r\n0\r\n\r\n", prefix)).is_err());
+        assert!(parse(&format!("{}4\r\nab\r\n0\r\n\r\n", prefix)).is_err());
+        assert!(parse(&format!("{}2\r\nab\r\n0\r\n", prefix)).is_err());
+    }
+
+    #[test]
+    fn oversized_chunk_lines_and_trailers_are_rejected_before_they_end() {
+        use crate::http::request::{decode_chunks, Chunks, MAX_CHUNK_LINE, MAX_TRAILER_SIZE};
+        let decode = |body: &[u8]| decode_chunks(body, &mut vec![], usize::MAX);
+        let extension = format!("1;{}", "a".repeat(MAX_CHUNK_LINE));
+        assert!(matches!(
+            decode(extension.as_bytes()),
+            Err(Error::Framing(_))
+        ));
+        let extension = format!("1;{}", "a".repeat(MAX_CHUNK_LINE - 8));
+        assert_eq!(decode(extension.as_bytes()), Ok(Chunks::Partial(0)));
+        let trailers = format!("0\r\n{}", "X-A: a\r\n".repeat(MAX_TRAILER_SIZE / 8 + 1));
+        assert!(matches!(
+            decode(trailers.as_bytes()),
+            Err(Error::Framing(_))
+        ));
+        let trailers = format!("0\r\n{}", "a".repeat(MAX_TRAILER_SIZE + 1));
+        assert!(matches!(
+            decode(trailers.as_bytes()),
+            Err(Error::Framing(_))
+        ));
+        assert_eq!(decode(b"0\r\nX-A: a\r\n"), Ok(Chunks::Partial(0)));
+    }
+
+    #[test]
+    fn missing_end_of_head_is_rejected() {
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nHost: a\r\n"),
+            Err(Error::ParseHead(_))
+        ));
+    }
+}
+
+mod versions {
+    use super::*;
+    use crate::http::Version;
+    use std::str::FromStr;
+
+    #[test]
+    fn version_round_trips() {
+        for version in ["HTTP/1.0", "HTTP/1.1", "HTTP/2"] {
+            assert_eq!(Version::from_str(version).unwrap().to_string(), version);
+        }
+    }
+
+    #[test]
+    fn malformed_versions_are_rejected() {
+        for version in ["HTTP/1.10", "HTTP/x.1", "http/1.1", "HTTP/", "HTTP/1."] {
+            assert!(Version::from_str(version).is_err(), "{}", version);
+        }
+    }
+
+    #[test]
+    fn negotiates_highest_minor_within_major() {
+        assert_eq!(Version::HTTP_1_0.negotiate(), Some(Version::HTTP_1_1));
+        assert_eq!(
+            Version::from_str("HTTP/1.9").unwrap().negotiate(),
+            Some(Version::HTTP_1_1)
+        );
+        assert_eq!(Version::from_str("HTTP/3").unwrap().negotiate(), None);
+    }
+
+    #[test]
+    fn unsupported_major_is_rejected() {
+        assert!(matches!(
+            parse("GET / HTTP/3.0\r\nHost: a\r\n\r\n"),
+            Err(Error::VersionNotSupported(_))
+        ));
+    }
+
+    #[test]
+    fn http_1_0_closes_by_default() {
+        let request = parse("GET / HTTP/1.0\r\n\r\n").unwrap();
+        assert!(!request.keep_alive());
+        let request = parse("GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n").unwrap();
+        assert!(request.keep_alive());
+    }
+
+    #[test]
+    fn http_1_1_persists_by_default() {
+        let request = parse("GET / HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        assert!(request.keep_alive());
+        let request = parse("GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n").unwrap();
+        assert!(!request.keep_alive());
+    }
+
+    #[test]
+    fn host_is_optional_only_for_http_1_0() {
+        assert!(parse("GET / HTTP/1.0\r\n\r\n")
+            .unwrap()
+            .validate_host()
+            .is_ok());
+        assert!(parse("GET / HTTP/1.1\r\n\r\n")
+            .unwrap()
+            .validate_host()
+            .is_err());
+        assert!(parse("GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n")
+            .unwrap()
+            .validate_host()
+            .is_err());
+    }
+}
+
+mod targets {
+    use super::*;
+    use crate::http::request::target::Form;
+
+    #[test]
+    fn forms_are_told_apart() {
+        let form = |raw: &str| parse(raw).unwrap().start_line().target.form;
+        assert_eq!(form("GET /a?b HTTP/1.1\r\nHost: a\r\n\r\n"), Form::Origin);
+        assert_eq!(
+            form("OPTIONS * HTTP/1.1\r\nHost: a\r\n\r\n"),
+            Form::Asterisk
+        );
+        assert_eq!(
+            form("GET http://a/b HTTP/1.1\r\nHost: a\r\n\r\n"),
+            Form::Absolute
+        );
+        assert_eq!(
+            form("CONNECT a:443 HTTP/1.1\r\nHost: a:443\r\n\r\n"),
+            Form::Authority
+        );
+    }
+
+    #[test]
+    fn absolute_form_names_authority_and_origin() {
+        for (target, authority, origin) in [
+            ("http://a.example:8080/b/c?d", "a.example:8080", "/b/c?d"),
+            ("http://a.example", "a.example", "/"),
+            ("HTTP://a.example?q", "a.example", "/?q"),
+        ] {
+            let request = parse(&format!("GET {} HTTP/1.1\r\nHost: a\r\n\r\n", target)).unwrap();
+            let target = &request.start_line().target;
+            assert_eq!(target.scheme().as_deref(), Some("http"));
+            assert_eq!(target.authority(), Some(authority));
+            assert_eq!(target.origin(), origin);
+        }
+    }
+
+    #[test]
+    fn authority_form_is_only_for_connect() {
+        assert!(parse("GET a:443 HTTP/1.1\r\nHost: a\r\n\r\n").is_err());
+        assert!(parse("CONNECT /a HTTP/1.1\r\nHost: a\r\n\r\n").is_err());
+        assert!(parse("CONNECT a HTTP/1.1\r\nHost: a\r\n\r\n").is_err());
+        assert!(parse("CONNECT a:0 HTTP/1.1\r\nHost: a\r\n\r\n").is_err());
+        assert!(parse("CONNECT [::1]:443 HTTP/1.1\r\nHost: a\r\n\r\n").is_ok());
+    }
+}
+
+mod methods {
+    use super::*;
+    use crate::http::{response::Status, Response};
+
+    fn builtin(raw: &str) -> Response {
+        Response::builtin(parse(raw).unwrap(), None).unwrap()
+    }
+
+    #[test]
+    fn unknown_methods_are_not_implemented() {
+        let error = parse("DELETE /a HTTP/1.1\r\nHost: a\r\n\r\n")
+            .err()
+            .unwrap();
+        assert_eq!(error, Error::MethodNotImplemented(String::from("DELETE")));
+        let response = Response::from_error(&error);
+        assert!(matches!(response.start_line.status, Status::NotImplemented));
+        assert!(matches!(
+            parse("GE(T / HTTP/1.1\r\nHost: a\r\n\r\n"),
+            Err(Error::ParseHead(_))
+        ));
+    }
+
+    #[test]
+    fn head_is_answered_like_get() {
+        let get = builtin("GET /echo/hi HTTP/1.1\r\nHost: a\r\n\r\n");
+        let head = builtin("HEAD /echo/hi HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert!(matches!(head.start_line.status, Status::Ok));
+        assert_eq!(head.body, get.body);
+    }
+
+    #[test]
+    fn unsupported_methods_are_not_allowed() {
+        let put = builtin("PUT /files/a HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert!(matches!(put.start_line.status, Status::MethodNotAllowed));
+        assert_eq!(put.headers.get("Allow"), Some("GET, HEAD, POST, OPTIONS"));
+        let post = builtin("POST /user-agent HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert!(matches!(post.start_line.status, Status::MethodNotAllowed));
+        assert_eq!(post.headers.get("Allow"), Some("GET, HEAD, OPTIONS"));
+    }
+
+    #[test]
+    fn files_without_a_directory_are_not_found() {
+        for raw in [
+            "GET /files/a HTTP/1.1\r\nHost: a\r\n\r\n",
+            "POST /files/a HTTP/1.1\r\nHost: a\r\nContent-Length: 1\r\n\r\na",
+            "GET * HTTP/1.1\r\nHost: a\r\n\r\n",
+        ] {
+            assert!(matches!(builtin(raw).start_line.status, Status::NotFound));
+        }
+    }
+
+    #[test]
+    fn unknown_paths_do_not_echo_request_headers() {
+        let raw =
+            "GET /nope HTTP/1.1\r\nHost: a\r\nCookie: a=b\r\nAuthorization: Basic YTpi\r\n\r\n";
+        let response = builtin(raw);
+        assert!(matches!(response.start_line.status, Status::NotFound));
+        for name in ["Cookie", "Authorization", "Host"] {
+            assert_eq!(response.headers.get(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn files_outside_the_directory_are_forbidden() {
+        for raw in [
+            "GET /files/../../etc/hostname HTTP/1.1\r\nHost: a\r\n\r\n",
+            "GET /files/a/../../b HTTP/1.1\r\nHost: a\r\n\r\n",
+            "POST /files/../a HTTP/1.1\r\nHost: a\r\nContent-Length: 1\r\n\r\na",
+        ] {
+            let request = parse(raw).unwrap();
+            let response = Response::builtin(request, Some(String::from("/tmp"))).unwrap();
+            assert!(
+                matches!(response.start_line.status, Status::Forbidden),
+                "{}",
+                raw
+            );
+        }
+    }
+}
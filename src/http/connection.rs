@@ -0,0 +1,1019 @@
+use {
+    crate::http::{
+        body::{self, Sink},
+        h2,
+        header::{
+            connection,
+            typed::{Connection, TransferEncoding},
+        },
+        proxy,
+        request::{self, id, Framing, Method},
+        response,
+        router::Router,
+        websocket::{self, WebSocket},
+        Error, Request, Response, Version,
+    },
+    std::{
+        io::{self, Read, Write},
+        net::{SocketAddr, TcpStream},
+        sync::{
+            mpsc::{self, SyncSender, TrySendError},
+            Arc,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+/// Size of each read from the connection
+const READ_CHUNK_SIZE: usize = 1024;
+/// Largest request head accepted before the request is rejected
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+/// Time a body gets before its transfer rate is checked
+const BODY_RATE_GRACE: Duration = Duration::from_secs(5);
+/// Largest request body accepted by default, see [`Router::body_limit`]
+pub const MAX_BODY_SIZE: usize = 8 * 1024 * 1024;
+/// Size of each read of a body streamed to its handler
+const STREAMED_CHUNK_SIZE: usize = 16 * 1024;
+/// Chunks of a streamed body waiting for its handler to take them
+const STREAMED_CHUNKS: usize = 16;
+/// Longest a streamed body waits on the client before checking on its handler
+const HANDLER_TICK: Duration = Duration::from_millis(20);
+/// Time a streamed body waits for its handler to make room for the next chunk
+const HANDLER_WAIT: Duration = Duration::from_millis(1);
+
+/// Streams whose blocking reads and writes can be bounded in time
+pub trait Socket {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+    /// Returns true if the stream is encrypted, requests on it having the `https` scheme
+    fn is_secure(&self) -> bool {
+        false
+    }
+}
+impl Socket for TcpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+}
+
+/// Returns true for errors of reads or writes that ran out of time
+pub fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// How long clients get to send requests and take responses
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Timeouts {
+    /// Time to send a whole request head, counted from its first byte or from
+    /// the start of the connection
+    pub header: Duration,
+    /// Time to send a whole request body, counted from the end of the head
+    pub body: Duration,
+    /// Time any single write may block
+    pub write: Duration,
+    /// Time a kept alive connection may wait for its next request
+    pub idle: Duration,
+    /// Bytes per second a body must average once past the first few seconds, 0 for any rate
+    pub min_body_rate: u64,
+}
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            header: Duration::from_secs(10),
+            body: Duration::from_secs(60),
+            write: Duration::from_secs(30),
+            idle: Duration::from_secs(15),
+            min_body_rate: 512,
+        }
+    }
+}
+impl Timeouts {
+    /// Reads `--header-timeout`, `--body-timeout`, `--write-timeout` and
+    /// `--idle-timeout` in seconds, and `--min-body-rate` in bytes per second
+    pub fn from_args() -> Self {
+        let number = |flag: &str| crate::args::value(flag).and_then(|value| value.parse().ok());
+        let defaults = Self::default();
+        let seconds = |flag: &str, default: Duration| {
+            number(flag)
+                .filter(|secs| *secs > 0)
+                .map_or(default, Duration::from_secs)
+        };
+        Self {
+            header: seconds("--header-timeout", defaults.header),
+            body: seconds("--body-timeout", defaults.body),
+            write: seconds("--write-timeout", defaults.write),
+            idle: seconds("--idle-timeout", defaults.idle),
+            min_body_rate: number("--min-body-rate").unwrap_or(defaults.min_body_rate),
+        }
+    }
+}
+
+/// Buffers bytes read off a connection until whole requests can be parsed
+pub struct Reader<R> {
+    inner: R,
+    buffer: Vec<u8>,
+    timeouts: Timeouts,
+    /// Requests read so far, the first one waits under the header timeout instead of the idle one
+    requests: usize,
+    max_body_size: usize,
+}
+impl<R: Read + Socket> Reader<R> {
+    pub fn new(inner: R, timeouts: Timeouts) -> Self {
+        Self {
+            inner,
+            buffer: vec![],
+            timeouts,
+            requests: 0,
+            max_body_size: MAX_BODY_SIZE,
+        }
+    }
+    /// Rejects bodies larger than `max_body_size` bytes before reading them in
+    pub fn body_limit(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+    /// Reads the next request off the connection
+    ///
+    /// Returns `Ok(None)` once the client closes the connection between
+    /// requests or leaves it idle for too long, and `Ok(Some(Err(_)))` for
+    /// requests that could not be parsed, were not sent in time or announce
+    /// a body over the limit.
+    #[allow(dead_code)]
+    pub fn next_request(&mut self) -> io::Result<Option<Result<Request, Error>>> {
+        match self.next_head()? {
+            Some(Ok(body)) => self.read_body(body).map(Some),
+            Some(Err(e)) => Ok(Some(Err(e))),
+            None => Ok(None),
+        }
+    }
+    /// Reads the head of the next request, leaving its body on the connection
+    fn next_head(&mut self) -> io::Result<Option<Result<PendingBody, Error>>> {
+        let waiting_since = Instant::now();
+        let mut head_since = (!self.buffer.is_empty()).then_some(waiting_since);
+        loop {
+            match Request::parse_head(&self.buffer) {
+                Ok(Some((request, body_offset))) => {
+                    let body = request
+                        .framing()
+                        .map(|framing| PendingBody::new(request, body_offset, framing));
+                    return Ok(Some(body));
+                }
+                Ok(None) if self.buffer.len() > MAX_HEAD_SIZE => {
+                    let message = format!("head larger than {} bytes", MAX_HEAD_SIZE);
+                    return Ok(Some(Err(Error::ParseHead(message))));
+                }
+                Ok(None) => (),
+                Err(e) => return Ok(Some(Err(e))),
+            }
+            let now = Instant::now();
+            let deadline = match head_since {
+                Some(since) => since + self.timeouts.header,
+                None if self.requests == 0 => waiting_since + self.timeouts.header,
+                None => waiting_since + self.timeouts.idle,
+            };
+            match self.fill(deadline, READ_CHUNK_SIZE) {
+                Ok(0) if self.buffer.is_empty() => return Ok(None),
+                Ok(0) => {
+                    let message = String::from("connection closed mid request");
+                    return Ok(Some(Err(Error::Framing(message))));
+                }
+                Ok(_) => {
+                    head_since.get_or_insert(now);
+                }
+                Err(e) if is_timeout(&e) && self.buffer.is_empty() => {
+                    log_from_mod!(
+                        "closing connection, no request within",
+                        format!("{:?}", now - waiting_since)
+                    );
+                    return Ok(None);
+                }
+                Err(e) if is_timeout(&e) => {
+                    let message = String::from("request head not received in time");
+                    return Ok(Some(Err(Error::Timeout(message))));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    /// Reads the whole body of `body`, completing its request
+    fn read_body(&mut self, mut body: PendingBody) -> io::Result<Result<Request, Error>> {
+        loop {
+            match body.advance(&self.buffer, self.max_body_size) {
+                Ok(Some(end)) => {
+                    self.buffer.drain(..end);
+                    self.requests += 1;
+                    return Ok(Ok(body.finish()));
+                }
+                Ok(None) => (),
+                Err(e) => return Ok(Err(e)),
+            }
+            let received = self.buffer.len() - body.offset;
+            if let Some(e) = self.check_body_rate(body.since, received) {
+                return Ok(Err(e));
+            }
+            match self.fill(body.since + self.timeouts.body, READ_CHUNK_SIZE) {
+                Ok(0) => return Ok(Err(body_cut_short())),
+                Ok(_) => (),
+                Err(e) if is_timeout(&e) => return Ok(Err(body_not_in_time())),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    /// Passes the body starting at `offset` in the buffer on to `sender` as
+    /// it arrives, decoded, `since` being the end of its head
+    ///
+    /// Returns `Ok(true)` once the whole body was passed on, and `Ok(false)`
+    /// if its handler stopped taking it first, `done` telling it returned.
+    /// The rest of the body is then left unread. Failures are passed on
+    /// to the handler too, so that it does not take a body cut short as
+    /// complete.
+    fn stream_body(
+        &mut self,
+        (offset, framing, since): (usize, Framing, Instant),
+        sender: SyncSender<body::Piece>,
+        done: impl Fn() -> bool,
+    ) -> io::Result<Result<bool, Error>> {
+        let fail = |e: Error| {
+            let _ = sender.try_send(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                e.to_string(),
+            )));
+            Ok(Err(e))
+        };
+        if let Err(e) = check_length(framing, self.max_body_size) {
+            return fail(e);
+        }
+        // the head is not needed anymore, only what follows it is kept
+        self.buffer.drain(..offset);
+        let mut passed = 0usize;
+        let mut decoded = vec![];
+        // time spent waiting on the handler does not count against the client
+        let mut since = since;
+        loop {
+            let (consumed, complete) = match framing {
+                Framing::Empty => (0, true),
+                Framing::Length(length) => {
+                    let consumed = self.buffer.len().min(length - passed);
+                    decoded.extend_from_slice(&self.buffer[..consumed]);
+                    (consumed, passed + consumed == length)
+                }
+                Framing::Chunked => {
+                    let limit = self.max_body_size - passed;
+                    match request::decode_chunks(&self.buffer, &mut decoded, limit) {
+                        Ok(request::Chunks::Complete(consumed)) => (consumed, true),
+                        Ok(request::Chunks::Partial(consumed)) => (consumed, false),
+                        // the limit left is not the one to report
+                        Err(Error::ContentTooLarge(_)) => {
+                            return fail(Error::ContentTooLarge(self.max_body_size))
+                        }
+                        Err(e) => return fail(e),
+                    }
+                }
+            };
+            self.buffer.drain(..consumed);
+            if !decoded.is_empty() {
+                passed += decoded.len();
+                let waiting = Instant::now();
+                let mut piece = Ok(std::mem::take(&mut decoded));
+                loop {
+                    match sender.try_send(piece) {
+                        Ok(()) => break,
+                        Err(TrySendError::Full(_)) if done() => return Ok(Ok(false)),
+                        Err(TrySendError::Full(full)) => {
+                            piece = full;
+                            std::thread::sleep(HANDLER_WAIT);
+                        }
+                        Err(TrySendError::Disconnected(_)) => return Ok(Ok(false)),
+                    }
+                }
+                since += waiting.elapsed();
+            }
+            if complete {
+                self.requests += 1;
+                return Ok(Ok(true));
+            }
+            if done() {
+                return Ok(Ok(false));
+            }
+            if let Some(e) = self.check_body_rate(since, passed) {
+                return fail(e);
+            }
+            // reads are cut short to notice the handler returning without the whole body
+            let deadline = since + self.timeouts.body;
+            let tick = deadline.min(Instant::now() + HANDLER_TICK);
+            match self.fill(tick, STREAMED_CHUNK_SIZE) {
+                Ok(0) => return fail(body_cut_short()),
+                Ok(_) => (),
+                Err(e) if is_timeout(&e) && Instant::now() < deadline => (),
+                Err(e) if is_timeout(&e) => return fail(body_not_in_time()),
+                Err(e) => {
+                    let _ = sender.try_send(Err(io::Error::new(e.kind(), e.to_string())));
+                    return Err(e);
+                }
+            }
+        }
+    }
+    /// Reads up to `size` more bytes into the buffer, waiting until `deadline` at most
+    ///
+    /// Returns the number of bytes read, 0 once the client closed the connection.
+    fn fill(&mut self, deadline: Instant, size: usize) -> io::Result<usize> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::from(io::ErrorKind::TimedOut));
+        }
+        self.inner.set_read_timeout(Some(remaining))?;
+        let start = self.buffer.len();
+        self.buffer.resize(start + size, 0);
+        let read = self.inner.read(&mut self.buffer[start..]);
+        self.buffer.truncate(start + *read.as_ref().unwrap_or(&0));
+        if read.as_ref().is_ok_and(|read| *read > 0) {
+            crate::shutdown::busy();
+        }
+        read
+    }
+    /// Rejects bodies arriving slower than the minimum rate, once past the grace period
+    fn check_body_rate(&self, since: Instant, received: usize) -> Option<Error> {
+        let elapsed = since.elapsed();
+        let expected = self
+            .timeouts
+            .min_body_rate
+            .saturating_mul(elapsed.as_secs());
+        if elapsed > BODY_RATE_GRACE && (received as u64) < expected {
+            let message = format!(
+                "body arriving slower than {} bytes per second",
+                self.timeouts.min_body_rate
+            );
+            Some(Error::Timeout(message))
+        } else {
+            None
+        }
+    }
+    /// Returns true if the connection opens with the HTTP/2 preface
+    ///
+    /// Reads only as much as it takes to tell the preface apart from an HTTP/1 request line.
+    pub fn starts_with_preface(&mut self) -> io::Result<bool> {
+        self.inner.set_read_timeout(Some(self.timeouts.header))?;
+        while self.buffer.len() < h2::PREFACE.len() && h2::PREFACE.starts_with(&self.buffer) {
+            let mut chunk = [0u8; READ_CHUNK_SIZE];
+            match self.inner.read(&mut chunk)? {
+                0 => return Ok(false),
+                bytes_read => self.buffer.extend_from_slice(&chunk[..bytes_read]),
+            }
+        }
+        Ok(self.buffer.starts_with(h2::PREFACE))
+    }
+    fn inner_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+    /// Gives back the connection along with any bytes read but not yet parsed
+    pub fn into_parts(self) -> (R, Vec<u8>) {
+        (self.inner, self.buffer)
+    }
+}
+
+/// Rejects bodies announcing a length over `limit`, before any of them is read
+fn check_length(framing: Framing, limit: usize) -> Result<(), Error> {
+    match framing {
+        Framing::Length(length) if length > limit => Err(Error::ContentTooLarge(limit)),
+        _ => Ok(()),
+    }
+}
+
+/// Error for bodies whose connection closed before they were complete
+fn body_cut_short() -> Error {
+    Error::Framing(String::from("connection closed mid request"))
+}
+
+/// Error for bodies not received within the body timeout
+fn body_not_in_time() -> Error {
+    Error::Timeout(String::from("request body not received in time"))
+}
+
+/// Request whose head has been parsed, waiting for the rest of its body
+struct PendingBody {
+    request: Request,
+    /// Position of the body in the reader's buffer
+    offset: usize,
+    framing: Framing,
+    /// Chunks decoded so far, or the whole body once received
+    decoded: Vec<u8>,
+    /// Bytes of the body already decoded, chunked bodies resume after them
+    consumed: usize,
+    /// End of the head, from which the body timeout runs
+    since: Instant,
+}
+impl PendingBody {
+    fn new(request: Request, offset: usize, framing: Framing) -> Self {
+        Self {
+            request,
+            offset,
+            framing,
+            decoded: vec![],
+            consumed: 0,
+            since: Instant::now(),
+        }
+    }
+    /// Takes in the body bytes received so far
+    ///
+    /// Returns the end of the request in `buffer` once its body is complete,
+    /// or an error as soon as the declared length or the chunks decoded so
+    /// far exceed `limit`.
+    fn advance(&mut self, buffer: &[u8], limit: usize) -> Result<Option<usize>, Error> {
+        check_length(self.framing, limit)?;
+        let body = &buffer[self.offset..];
+        match self.framing {
+            Framing::Empty => Ok(Some(self.offset)),
+            Framing::Length(length) if body.len() >= length => {
+                self.decoded = body[..length].to_vec();
+                Ok(Some(self.offset + length))
+            }
+            Framing::Length(_) => Ok(None),
+            Framing::Chunked => {
+                match request::decode_chunks(&body[self.consumed..], &mut self.decoded, limit)? {
+                    request::Chunks::Complete(length) => {
+                        Ok(Some(self.offset + self.consumed + length))
+                    }
+                    request::Chunks::Partial(length) => {
+                        self.consumed += length;
+                        Ok(None)
+                    }
+                }
+            }
+        }
+    }
+    fn finish(mut self) -> Request {
+        self.request.set_body(self.decoded);
+        self.request
+    }
+}
+
+/// Turns a request into its response, whichever protocol version carried it
+pub type Handler = Arc<dyn Fn(Request) -> io::Result<Response> + Send + Sync>;
+
+/// Serves requests off `stream` until either side closes the connection
+///
+/// HTTP/1.1 connections are kept alive unless the client sends `Connection: close`,
+/// HTTP/1.0 connections are closed unless the client sends `Connection: keep-alive`.
+/// Connections opening with the HTTP/2 preface, or upgrading to h2c, are handed
+/// over to [`h2::Connection`], successful WebSocket handshakes to the
+/// endpoint registered on `router` and CONNECT requests to its forward proxy.
+/// Each request is served by the site of `router` its Host names, see
+/// [`Router::site`]; HTTP/1.1 requests without Host are answered with 400.
+pub fn serve<S: Read + Write + Socket>(
+    stream: S,
+    router: Arc<Router>,
+    timeouts: Timeouts,
+) -> io::Result<()> {
+    stream.set_write_timeout(Some(timeouts.write))?;
+    let mut reader = Reader::new(stream, timeouts).body_limit(router.max_body_size());
+    match reader.starts_with_preface() {
+        Ok(true) => {
+            let (stream, buffer) = reader.into_parts();
+            return h2::Connection::new(stream, buffer, router, timeouts).serve(None);
+        }
+        Ok(false) => (),
+        Err(e) if is_timeout(&e) => {
+            log_from_mod!("closing connection, nothing received in time");
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    }
+    let peer = reader
+        .inner_mut()
+        .peer_addr()
+        .ok()
+        .map(|address| address.ip());
+    let secure = reader.inner_mut().is_secure();
+    loop {
+        // log lines carry the id of the request from the moment its head is read
+        let (body, id) = match reader.next_head()? {
+            Some(Ok(mut body)) => {
+                body.request.peer = peer;
+                body.request.secure = secure;
+                let id = id::assign(&mut body.request);
+                (Ok(body), id)
+            }
+            Some(Err(e)) => (Err(e), id::generate()),
+            None => {
+                log_from_mod!("connection closed by client");
+                return Ok(());
+            }
+        };
+        let scope = id::enter(&id);
+        let mut chunked = true;
+        let mut head_only = false;
+        let (response, keep_alive) = match body.and_then(|body| {
+            body.request.validate_host()?;
+            Ok(body)
+        }) {
+            Ok(body) => {
+                let mut keep_alive = body.request.keep_alive();
+                let http_1_0 = body.request.start_line.version.eq(&Version::HTTP_1_0);
+                head_only = matches!(body.request.start_line.method, Method::Head);
+                let site = router.site(&body.request);
+                let streamed = body.framing.ne(&Framing::Empty) && site.streams_body(&body.request);
+                let responded = match streamed {
+                    true => respond_streamed(&mut reader, site, body, &id)?,
+                    false => match reader.read_body(body)? {
+                        Ok(request) => {
+                            let tunnel = site.connect_route(&request);
+                            let response = match (tunnel, site.websocket_route(&request)) {
+                                (Some(proxy), _) => {
+                                    let handshake =
+                                        move |request: &Request| match proxy.connect(request) {
+                                            Ok(destination) => (
+                                                Response::empty(response::Status::Ok),
+                                                Some(destination),
+                                            ),
+                                            Err(response) => (response, None),
+                                        };
+                                    match site.upgrade(request, handshake)? {
+                                        (_, Some((request, destination))) => {
+                                            log_from_mod!(
+                                                "opening tunnel to",
+                                                request.start_line.target.path
+                                            );
+                                            let (mut stream, buffer) = reader.into_parts();
+                                            return proxy::tunnel(
+                                                &mut stream,
+                                                &buffer,
+                                                destination,
+                                            );
+                                        }
+                                        (response, None) => response,
+                                    }
+                                }
+                                (None, Some(handler)) => {
+                                    let handshake = |request: &Request| {
+                                        let response = websocket::handshake(request);
+                                        let status = response.start_line.status;
+                                        let accepted =
+                                            matches!(status, response::Status::SwitchingProtocols);
+                                        (response, accepted.then_some(()))
+                                    };
+                                    match site.upgrade(request, handshake)? {
+                                        (response, Some((request, ()))) => {
+                                            log_from_mod!("upgrading connection to websocket");
+                                            write_response(reader.inner_mut(), response, true)?;
+                                            // silence for the idle timeout draws a ping, see WebSocket
+                                            reader
+                                                .inner_mut()
+                                                .set_read_timeout(Some(timeouts.idle))?;
+                                            let (stream, buffer) = reader.into_parts();
+                                            return handler(
+                                                request,
+                                                WebSocket::new(Box::new(stream), buffer),
+                                            );
+                                        }
+                                        (response, None) => response,
+                                    }
+                                }
+                                (None, None) => match h2::h2c_settings(&request) {
+                                    Some(settings) => {
+                                        // the streams of the connection get ids of their own
+                                        drop(scope);
+                                        return upgrade(
+                                            reader, request, settings, router, timeouts,
+                                        );
+                                    }
+                                    None => site.respond(request)?,
+                                },
+                            };
+                            Ok((response, true))
+                        }
+                        Err(e) => Err(e),
+                    },
+                };
+                let mut response = match responded {
+                    Ok((response, true)) => response,
+                    // the rest of the body is left unread, so the connection cannot go on
+                    Ok((response, false)) => {
+                        keep_alive = false;
+                        response
+                    }
+                    Err(e) => {
+                        keep_alive = false;
+                        rejection(&e, &id)
+                    }
+                };
+                // without chunked coding the end of the connection ends a streamed body
+                if http_1_0 && response.stream.is_some() {
+                    keep_alive = false;
+                    chunked = false;
+                }
+                if crate::shutdown::requested() {
+                    keep_alive = false;
+                }
+                match (keep_alive, http_1_0) {
+                    (true, true) => response
+                        .headers
+                        .insert_typed(&Connection(connection::Kind::KeepAlive)),
+                    (true, false) => (),
+                    (false, _) => response
+                        .headers
+                        .insert_typed(&Connection(connection::Kind::Close)),
+                }
+                (response, keep_alive)
+            }
+            Err(e) => (rejection(&e, &id), false),
+        };
+        let written = match head_only {
+            true => write_head_only(reader.inner_mut(), response, chunked),
+            false => write_response(reader.inner_mut(), response, chunked),
+        };
+        match written {
+            Ok(()) => (),
+            Err(e) if body::is_disconnect(&e) => {
+                elog_from_mod!("client disconnected", e);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+        if !keep_alive {
+            log_from_mod!("closing connection");
+            return Ok(());
+        }
+        if crate::shutdown::idle() {
+            log_from_mod!("closing idle connection, server stopping");
+            return Ok(());
+        }
+    }
+}
+
+/// Response to a request that failed before reaching a handler, tagged with its id
+fn rejection(error: &Error, id: &str) -> Response {
+    elog_from_mod!("rejecting request", error);
+    let mut response = Response::from_error(error);
+    response.headers.insert(id::HEADER, id);
+    response
+}
+
+/// Responds to the request of `body` with its handler running on a thread of
+/// its own, the body being passed on to it as it arrives
+///
+/// Returns the response and whether the whole body was read, or the error the
+/// body failed with, answered in place of whatever the handler returned.
+fn respond_streamed<S: Read + Socket>(
+    reader: &mut Reader<S>,
+    site: &Router,
+    body: PendingBody,
+    id: &str,
+) -> io::Result<Result<(Response, bool), Error>> {
+    let PendingBody {
+        mut request,
+        offset,
+        framing,
+        since,
+        ..
+    } = body;
+    let length = match framing {
+        Framing::Length(length) => Some(length as u64),
+        _ => None,
+    };
+    let (sender, receiver) = mpsc::sync_channel(STREAMED_CHUNKS);
+    request.source = Some(body::Source::streamed(receiver, length));
+    std::thread::scope(|scope| {
+        let responding = scope.spawn(move || {
+            let _scope = id::enter(id);
+            site.respond(request)
+        });
+        let streamed = reader.stream_body((offset, framing, since), sender, || {
+            responding.is_finished()
+        });
+        let responded = responding
+            .join()
+            .unwrap_or_else(|payload| std::panic::resume_unwind(payload));
+        match streamed? {
+            Ok(complete) => Ok(Ok((responded?, complete))),
+            Err(e) => Ok(Err(e)),
+        }
+    })
+}
+
+/// Turns a connection away without reading from it, asking the client to retry later
+pub fn refuse<W: Write>(stream: &mut W, retry_after: u64) -> io::Result<()> {
+    let response = Response::builder()
+        .status(response::Status::ServiceUnavailable)
+        .header("Retry-After", retry_after.to_string())
+        .typed(&Connection(connection::Kind::Close))
+        .empty();
+    write_response(stream, response, true)
+}
+
+/// Switches the connection to h2c, the response to `request` is sent on stream 1
+fn upgrade<S: Read + Write + Socket>(
+    mut reader: Reader<S>,
+    request: Request,
+    settings: Vec<u8>,
+    router: Arc<Router>,
+    timeouts: Timeouts,
+) -> io::Result<()> {
+    log_from_mod!("upgrading connection to h2c");
+    let switching = Response::builder()
+        .status(response::Status::SwitchingProtocols)
+        .typed(&Connection(connection::Kind::Upgrade))
+        .header("Upgrade", "h2c")
+        .empty();
+    write_response(reader.inner_mut(), switching, true)?;
+    let (stream, buffer) = reader.into_parts();
+    h2::Connection::new(stream, buffer, router, timeouts).serve(Some((request, settings)))
+}
+
+/// Writes `response`, with the fields derived from its body completed
+///
+/// Streamed bodies use the chunked coding if `chunked` is set, which it must
+/// not be for HTTP/1.0 clients.
+fn write_response<W: Write>(
+    stream: &mut W,
+    mut response: Response,
+    chunked: bool,
+) -> io::Result<()> {
+    // completed while the producer is still there, so no length is announced for it
+    response.complete_headers();
+    if let Some(producer) = response.stream.take() {
+        return write_streaming(stream, response, producer, chunked);
+    }
+    stream.write_all(&response.serialize())?;
+    stream.flush()
+}
+
+/// Writes the head `response` would be sent with, answering a HEAD request
+///
+/// The body is left out, the fields derived from it are kept as they would be sent.
+fn write_head_only<W: Write>(
+    stream: &mut W,
+    mut response: Response,
+    chunked: bool,
+) -> io::Result<()> {
+    response.complete_headers();
+    if response.stream.is_some() && chunked {
+        response
+            .headers
+            .insert_typed(&TransferEncoding(vec![String::from("chunked")]));
+    }
+    stream.write_all(response.head().as_bytes())?;
+    stream.flush()
+}
+
+/// Writes the head of `response`, then each chunk of the body as `producer` sends it
+///
+/// Without the chunked coding the body is sent as it is, and ended by closing
+/// the connection.
+fn write_streaming<W: Write>(
+    stream: &mut W,
+    mut response: Response,
+    producer: body::Producer,
+    chunked: bool,
+) -> io::Result<()> {
+    if chunked {
+        response
+            .headers
+            .insert_typed(&TransferEncoding(vec![String::from("chunked")]));
+    }
+    stream.write_all(response.head().as_bytes())?;
+    stream.flush()?;
+    producer(&mut Chunks { stream, chunked })?;
+    if chunked {
+        stream.write_all(b"0\r\n\r\n")?;
+        stream.flush()?;
+    }
+    Ok(())
+}
+
+/// Writes the chunks of a streamed body straight to the connection
+struct Chunks<'a, W> {
+    stream: &'a mut W,
+    chunked: bool,
+}
+impl<W: Write> Sink for Chunks<'_, W> {
+    fn send(&mut self, chunk: &[u8]) -> io::Result<()> {
+        // an empty chunk would end the body
+        if chunk.is_empty() {
+            return Ok(());
+        }
+        if self.chunked {
+            write!(self.stream, "{:x}\r\n", chunk.len())?;
+            self.stream.write_all(chunk)?;
+            self.stream.write_all(b"\r\n")?;
+        } else {
+            self.stream.write_all(chunk)?;
+        }
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{respond_streamed, write_head_only, write_response, Reader, Socket, Timeouts};
+    use crate::http::{
+        body::Sink, request::Method, response, router::Router, Error, Request, Response,
+    };
+    use std::{
+        cell::Cell,
+        collections::VecDeque,
+        io::{self, Read},
+        net::SocketAddr,
+        sync::Arc,
+        time::Duration,
+    };
+
+    /// Hands out scripted reads, timing out once they run out
+    struct Script {
+        reads: VecDeque<&'static [u8]>,
+        read_timeout: Cell<Option<Duration>>,
+    }
+    impl Script {
+        fn new(reads: &[&'static [u8]]) -> Self {
+            Self {
+                reads: reads.iter().copied().collect(),
+                read_timeout: Cell::new(None),
+            }
+        }
+        /// Returns true if the last read waited for about `timeout`
+        fn waited(&self, timeout: Duration) -> bool {
+            self.read_timeout
+                .get()
+                .is_some_and(|set| set <= timeout && set > timeout / 2)
+        }
+    }
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.reads.pop_front() {
+                Some(bytes) => {
+                    buf[..bytes.len()].copy_from_slice(bytes);
+                    Ok(bytes.len())
+                }
+                None => Err(io::Error::from(io::ErrorKind::WouldBlock)),
+            }
+        }
+    }
+    impl Socket for Script {
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            self.read_timeout.set(timeout);
+            Ok(())
+        }
+        fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+        fn peer_addr(&self) -> io::Result<SocketAddr> {
+            Err(io::Error::from(io::ErrorKind::NotConnected))
+        }
+    }
+
+    #[test]
+    fn silent_connections_are_closed() {
+        let mut reader = Reader::new(Script::new(&[]), Timeouts::default());
+        assert!(reader.next_request().unwrap().is_none());
+        assert!(reader.inner.waited(Timeouts::default().header));
+    }
+
+    #[test]
+    fn partial_heads_time_out() {
+        let script = Script::new(&[b"GET / HTTP/1.1\r\nHost:"]);
+        let mut reader = Reader::new(script, Timeouts::default());
+        match reader.next_request().unwrap() {
+            Some(Err(Error::Timeout(_))) => (),
+            _ => panic!("expected a timeout"),
+        }
+    }
+
+    #[test]
+    fn partial_bodies_time_out() {
+        let script =
+            Script::new(&[b"POST /files/a HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nab"]);
+        let mut reader = Reader::new(script, Timeouts::default());
+        match reader.next_request().unwrap() {
+            Some(Err(Error::Timeout(message))) => assert!(message.contains("body")),
+            _ => panic!("expected a timeout"),
+        }
+    }
+
+    #[test]
+    fn chunked_bodies_are_decoded_as_they_arrive() {
+        let script = Script::new(&[
+            b"POST /files/a HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nab",
+            b"c\r\n2\r\nde\r\n0\r\n",
+            b"\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n",
+        ]);
+        let mut reader = Reader::new(script, Timeouts::default());
+        match reader.next_request().unwrap() {
+            Some(Ok(request)) => assert_eq!(request.body.as_deref(), Some(&b"abcde"[..])),
+            _ => panic!("expected a request"),
+        }
+        match reader.next_request().unwrap() {
+            Some(Ok(request)) => assert!(request.body.is_none()),
+            _ => panic!("expected the pipelined request"),
+        }
+    }
+
+    #[test]
+    fn bodies_over_the_limit_are_rejected_before_they_are_read() {
+        let script =
+            Script::new(&[b"POST /files/a HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\n"]);
+        let mut reader = Reader::new(script, Timeouts::default()).body_limit(4);
+        assert!(matches!(
+            reader.next_request().unwrap(),
+            Some(Err(Error::ContentTooLarge(4)))
+        ));
+        let script = Script::new(&[
+            b"POST /files/a HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n",
+            b"3\r\nabc\r\n",
+            b"2\r\n",
+        ]);
+        let mut reader = Reader::new(script, Timeouts::default()).body_limit(4);
+        assert!(matches!(
+            reader.next_request().unwrap(),
+            Some(Err(Error::ContentTooLarge(4)))
+        ));
+    }
+
+    #[test]
+    fn streamed_bodies_are_read_while_the_handler_runs() {
+        let site = Router::new(Arc::new(|_| {
+            Ok(Response::empty(response::Status::NotFound))
+        }))
+        .stream_bodies("/upload")
+        .route(Method::Post, "/upload", |mut request: Request| {
+            let mut body = String::new();
+            request.take_body().read_to_string(&mut body)?;
+            Ok(Response::text(response::Status::Ok, body))
+        });
+        let script = Script::new(&[
+            b"POST /upload HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nab",
+            b"c\r\n2\r\nde\r\n0\r\n",
+            b"\r\n",
+        ]);
+        let mut reader = Reader::new(script, Timeouts::default());
+        let pending = match reader.next_head().unwrap() {
+            Some(Ok(pending)) => pending,
+            _ => panic!("expected a request"),
+        };
+        assert!(site.streams_body(&pending.request));
+        match respond_streamed(&mut reader, &site, pending, "1").unwrap() {
+            Ok((response, complete)) => {
+                assert!(complete);
+                assert_eq!(response.body.as_deref(), Some(&b"abcde"[..]));
+            }
+            Err(_) => panic!("expected a response"),
+        }
+        // bodies over the limit are cut off, and their handler sees them fail
+        let site = site.body_limit(4);
+        let script = Script::new(&[
+            b"POST /upload HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n",
+            b"2\r\nde\r\n",
+        ]);
+        let mut reader = Reader::new(script, Timeouts::default()).body_limit(4);
+        let pending = match reader.next_head().unwrap() {
+            Some(Ok(pending)) => pending,
+            _ => panic!("expected a request"),
+        };
+        assert!(matches!(
+            respond_streamed(&mut reader, &site, pending, "2").unwrap(),
+            Err(Error::ContentTooLarge(4))
+        ));
+    }
+
+    #[test]
+    fn kept_alive_connections_wait_under_the_idle_timeout() {
+        let script = Script::new(&[b"GET / HTTP/1.1\r\nHost: a\r\n\r\n"]);
+        let mut reader = Reader::new(script, Timeouts::default());
+        assert!(matches!(reader.next_request().unwrap(), Some(Ok(_))));
+        assert!(reader.next_request().unwrap().is_none());
+        assert!(reader.inner.waited(Timeouts::default().idle));
+    }
+
+    #[test]
+    fn streamed_responses_are_chunked_without_a_length() {
+        let response = Response::builder().stream(Box::new(|sink: &mut dyn Sink| sink.send(b"hi")));
+        let mut written = vec![];
+        write_response(&mut written, response, true).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(!written.contains("Content-Length"));
+        assert!(written.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(written.ends_with("\r\n\r\n2\r\nhi\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn head_responses_keep_the_length_of_the_body_they_leave_out() {
+        let mut written = vec![];
+        write_head_only(
+            &mut written,
+            Response::text(response::Status::Ok, "hello"),
+            true,
+        )
+        .unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.contains("Content-Length: 5\r\n"));
+        assert!(written.ends_with("\r\n\r\n"));
+    }
+}
@@ -0,0 +1,522 @@
+//! Cookies sent by clients and set by handlers, see RFC 6265
+//!
+//! Handlers read and change cookies through a [`Jar`]. Values can be signed,
+//! so tampering is detected, or encrypted, so clients can not read them
+//! either, with a [`Key`] derived from the server secret.
+use {
+    crate::http::{
+        base64,
+        header::{date, typed, HeaderMap, Typed},
+        Response,
+    },
+    ring::{
+        aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN},
+        hmac,
+        rand::{SecureRandom, SystemRandom},
+    },
+    std::{
+        fmt::{self, Display, Formatter},
+        io,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// Shortest secret accepted from `--cookie-secret`, in bytes
+pub const MIN_SECRET_LENGTH: usize = 32;
+
+/// Errors related to cookies
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Error {
+    /// Errors related to names that are not tokens
+    InvalidName(String),
+    /// Errors related to values holding characters cookies can not carry
+    InvalidValue(String, String),
+    /// Errors related to parsing Set-Cookie values
+    Parse(String),
+}
+impl std::error::Error for Error {}
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let source = module_path!();
+        let body = match self {
+            Self::InvalidName(name) => format!("invalid cookie name {}", name),
+            Self::InvalidValue(name, value) => format!("invalid value for {}: {}", name, value),
+            Self::Parse(message) => format!("parse error {}", message),
+        };
+        fmt::write(f, format_args!("[{}] {}", source, body))
+    }
+}
+
+/// Returns true for the characters a cookie value may hold unquoted
+fn is_cookie_octet(c: char) -> bool {
+    matches!(c, '!' | '#'..='+' | '-'..=':' | '<'..='[' | ']'..='~')
+}
+
+/// Whether a cookie is sent along with requests coming from other sites
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    /// Sent with every request, which browsers only allow for secure cookies
+    None,
+}
+impl Display for SameSite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let same_site = match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        };
+        f.write_str(same_site)
+    }
+}
+
+/// A cookie as set by a Set-Cookie header, built attribute by attribute
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    /// Seconds until the cookie expires, zero or less to remove it at once
+    pub max_age: Option<i64>,
+    pub expires: Option<SystemTime>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+impl Cookie {
+    /// Cookie with no attributes, kept until the browser is closed
+    pub fn new<N: Into<String>, V: Into<String>>(name: N, value: V) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+    pub fn path<P: Into<String>>(mut self, path: P) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+    pub fn domain<D: Into<String>>(mut self, domain: D) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age.as_secs() as i64);
+        self
+    }
+    pub fn expires(mut self, expires: SystemTime) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+    /// Checks that the name is a token and the value made of cookie octets
+    pub fn validate(&self) -> Result<(), Error> {
+        crate::http::header::validate_name(&self.name)
+            .map_err(|_| Error::InvalidName(self.name.escape_debug().to_string()))?;
+        match self.value.chars().find(|c| !is_cookie_octet(*c)) {
+            Some(c) => Err(Error::InvalidValue(
+                self.name.clone(),
+                format!("forbidden character {:?}", c),
+            )),
+            None => Ok(()),
+        }
+    }
+    /// Parses a Set-Cookie value, ignoring attributes that are unknown or malformed
+    pub fn parse(set_cookie: &str) -> Result<Self, Error> {
+        let mut attributes = set_cookie.split(';');
+        let (name, value) = attributes
+            .next()
+            .and_then(|pair| pair.split_once('='))
+            .ok_or_else(|| Error::Parse(format!("no name=value pair in {}", set_cookie)))?;
+        let mut cookie = Cookie::new(name.trim(), value.trim().trim_matches('"'));
+        cookie.validate()?;
+        for attribute in attributes {
+            let (name, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "path" if value.starts_with('/') => cookie.path = Some(value.to_string()),
+                "domain" if !value.is_empty() => {
+                    cookie.domain = Some(value.trim_start_matches('.').to_ascii_lowercase())
+                }
+                "max-age" => cookie.max_age = value.parse::<i64>().ok().or(cookie.max_age),
+                "expires" => cookie.expires = date::parse(value).or(cookie.expires),
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                "samesite" => match value.to_ascii_lowercase().as_str() {
+                    "strict" => cookie.same_site = Some(SameSite::Strict),
+                    "lax" => cookie.same_site = Some(SameSite::Lax),
+                    "none" => cookie.same_site = Some(SameSite::None),
+                    _ => (),
+                },
+                _ => (),
+            }
+        }
+        Ok(cookie)
+    }
+}
+impl Display for Cookie {
+    /// Formats the cookie as a Set-Cookie value
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age)?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", date::format(expires))?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits a Cookie header value into its name and value pairs, skipping malformed ones
+pub fn parse_pairs(value: &str) -> Vec<(String, String)> {
+    value
+        .split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| (name.trim(), value.trim().trim_matches('"')))
+        .filter(|(name, _)| crate::http::header::validate_name(name).is_ok())
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Keys signing and encrypting cookie values, derived from one server secret
+pub struct Key {
+    signing: hmac::Key,
+    #[allow(dead_code)]
+    encryption: LessSafeKey,
+    #[allow(dead_code)]
+    random: SystemRandom,
+}
+impl Key {
+    /// Derives a key for each use from `secret`, so no key serves two purposes
+    pub fn derive(secret: &[u8]) -> Self {
+        let master = hmac::Key::new(hmac::HMAC_SHA256, secret);
+        let derived = |label: &[u8]| hmac::sign(&master, label);
+        let signing = hmac::Key::new(hmac::HMAC_SHA256, derived(b"cookie signing").as_ref());
+        let encryption =
+            UnboundKey::new(&CHACHA20_POLY1305, derived(b"cookie encryption").as_ref())
+                .map(LessSafeKey::new)
+                .expect("a SHA-256 output is a valid ChaCha20-Poly1305 key");
+        Self {
+            signing,
+            encryption,
+            random: SystemRandom::new(),
+        }
+    }
+    /// Key from a random secret, which cookies set before a restart can not be read with
+    pub fn generate() -> io::Result<Self> {
+        let mut secret = [0u8; MIN_SECRET_LENGTH];
+        SystemRandom::new()
+            .fill(&mut secret)
+            .map_err(|_| io::Error::other("no randomness available"))?;
+        Ok(Self::derive(&secret))
+    }
+    /// Derives the key from `--cookie-secret`, or a random secret if none is given
+    pub fn from_args() -> io::Result<Self> {
+        match crate::args::value("--cookie-secret") {
+            Some(secret) if secret.len() < MIN_SECRET_LENGTH => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cookie secret shorter than {} bytes", MIN_SECRET_LENGTH),
+            )),
+            Some(secret) => Ok(Self::derive(secret.as_bytes())),
+            None => {
+                log_from_mod!("no --cookie-secret, signed cookies will not outlive this process");
+                Self::generate()
+            }
+        }
+    }
+    /// `value` followed by a tag binding it to the cookie `name`
+    fn sign(&self, name: &str, value: &str) -> String {
+        let tag = hmac::sign(&self.signing, format!("{}={}", name, value).as_bytes());
+        format!("{}.{}", value, base64::encode(tag.as_ref()))
+    }
+    /// The value of a signed cookie, or `None` if it was tampered with
+    fn verify(&self, name: &str, signed: &str) -> Option<String> {
+        let (value, tag) = signed.rsplit_once('.')?;
+        let tag = base64::decode(tag)?;
+        let message = format!("{}={}", name, value);
+        hmac::verify(&self.signing, message.as_bytes(), &tag).ok()?;
+        Some(value.to_string())
+    }
+    /// `value` encrypted and authenticated with the cookie `name`, as base64
+    #[allow(dead_code)]
+    fn encrypt(&self, name: &str, value: &str) -> Option<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.random.fill(&mut nonce).ok()?;
+        let mut sealed = value.as_bytes().to_vec();
+        self.encryption
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(name.as_bytes()),
+                &mut sealed,
+            )
+            .ok()?;
+        let mut encrypted = nonce.to_vec();
+        encrypted.extend(sealed);
+        Some(base64::encode(&encrypted))
+    }
+    /// The value of an encrypted cookie, or `None` if it was tampered with
+    #[allow(dead_code)]
+    fn decrypt(&self, name: &str, encrypted: &str) -> Option<String> {
+        let encrypted = base64::decode(encrypted)?;
+        if encrypted.len() < NONCE_LEN + aead::MAX_TAG_LEN {
+            return None;
+        }
+        let (nonce, sealed) = encrypted.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+        let mut sealed = sealed.to_vec();
+        let opened = self
+            .encryption
+            .open_in_place(nonce, Aad::from(name.as_bytes()), &mut sealed)
+            .ok()?;
+        String::from_utf8(opened.to_vec()).ok()
+    }
+}
+
+/// Cookies sent with a request, and the changes to send back with the response
+///
+/// Reads see the changes made so far, so a cookie added is read back at once
+/// and a removed one is gone.
+#[derive(Debug, Clone, Default)]
+pub struct Jar {
+    incoming: Vec<(String, String)>,
+    changes: Vec<Cookie>,
+}
+impl Jar {
+    /// Jar holding the cookies of every Cookie header in `headers`
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        Self {
+            incoming: headers
+                .typed::<typed::Cookie>()
+                .map(|cookie| cookie.0)
+                .unwrap_or_default(),
+            changes: vec![],
+        }
+    }
+    /// Value of the cookie `name`, the first one if the client sent several
+    pub fn get(&self, name: &str) -> Option<&str> {
+        match self
+            .changes
+            .iter()
+            .rev()
+            .find(|cookie| cookie.name.eq(name))
+        {
+            Some(cookie) if cookie.max_age.is_some_and(|max_age| max_age <= 0) => None,
+            Some(cookie) => Some(cookie.value.as_str()),
+            None => self
+                .incoming
+                .iter()
+                .find(|(incoming, _)| incoming.eq(name))
+                .map(|(_, value)| value.as_str()),
+        }
+    }
+    /// Sets `cookie` on the client once the response is sent
+    pub fn add(&mut self, cookie: Cookie) -> Result<(), Error> {
+        cookie.validate()?;
+        self.changes.push(cookie);
+        Ok(())
+    }
+    /// Removes `cookie` from the client, which must have the path and domain it was set with
+    pub fn remove(&mut self, cookie: Cookie) {
+        self.changes.push(Cookie {
+            value: String::new(),
+            max_age: Some(0),
+            expires: Some(UNIX_EPOCH),
+            ..cookie
+        });
+    }
+    /// View of the jar signing the cookies it adds and verifying those it reads
+    pub fn signed<'a>(&'a mut self, key: &'a Key) -> SignedJar<'a> {
+        SignedJar { jar: self, key }
+    }
+    /// View of the jar encrypting the cookies it adds and decrypting those it reads
+    #[allow(dead_code)]
+    pub fn private<'a>(&'a mut self, key: &'a Key) -> PrivateJar<'a> {
+        PrivateJar { jar: self, key }
+    }
+    /// Adds a Set-Cookie header to `response` for every change, in the order they were made
+    pub fn apply(self, response: &mut Response) {
+        self.changes.iter().for_each(|cookie| {
+            response
+                .headers
+                .append(typed::SetCookie::NAME, cookie.to_string())
+        });
+    }
+}
+
+/// Cookies of a [`Jar`] whose values carry a signature, see [`Jar::signed`]
+pub struct SignedJar<'a> {
+    jar: &'a mut Jar,
+    key: &'a Key,
+}
+impl SignedJar<'_> {
+    /// Value of the cookie `name`, or `None` if it is missing or was tampered with
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.key.verify(name, self.jar.get(name)?)
+    }
+    pub fn add(&mut self, mut cookie: Cookie) -> Result<(), Error> {
+        cookie.validate()?;
+        cookie.value = self.key.sign(&cookie.name, &cookie.value);
+        self.jar.add(cookie)
+    }
+}
+
+/// Cookies of a [`Jar`] whose values are encrypted, see [`Jar::private`]
+#[allow(dead_code)]
+pub struct PrivateJar<'a> {
+    jar: &'a mut Jar,
+    key: &'a Key,
+}
+#[allow(dead_code)]
+impl PrivateJar<'_> {
+    /// Value of the cookie `name`, or `None` if it is missing or was tampered with
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.key.decrypt(name, self.jar.get(name)?)
+    }
+    /// Adds `cookie`, whose value may hold any text as it is sent encrypted
+    pub fn add(&mut self, mut cookie: Cookie) -> Result<(), Error> {
+        cookie.value = self
+            .key
+            .encrypt(&cookie.name, &cookie.value)
+            .ok_or_else(|| {
+                Error::InvalidValue(cookie.name.clone(), String::from("encryption failed"))
+            })?;
+        self.jar.add(cookie)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_pairs, Cookie, Jar, Key, SameSite};
+    use crate::http::{header::HeaderMap, response::Status, Response};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn key() -> Key {
+        Key::derive(&[7u8; 32])
+    }
+
+    fn jar(cookie: &str) -> Jar {
+        let mut headers = HeaderMap::new();
+        headers.insert("Cookie", cookie);
+        Jar::from_headers(&headers)
+    }
+
+    #[test]
+    fn cookie_headers_are_split_into_pairs() {
+        let pairs = parse_pairs(r#"a=1; b="two"; broken; c=x=y"#);
+        let expected = [("a", "1"), ("b", "two"), ("c", "x=y")]
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+        assert_eq!(pairs, expected);
+    }
+
+    #[test]
+    fn set_cookie_round_trips() {
+        let cookie = Cookie::new("id", "abc")
+            .path("/")
+            .domain("example.com")
+            .max_age(Duration::from_secs(60))
+            .expires(UNIX_EPOCH + Duration::from_secs(784_111_777))
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+        let set_cookie = cookie.to_string();
+        assert_eq!(
+            set_cookie,
+            "id=abc; Path=/; Domain=example.com; Max-Age=60; \
+             Expires=Sun, 06 Nov 1994 08:49:37 GMT; Secure; HttpOnly; SameSite=Lax"
+        );
+        assert_eq!(Cookie::parse(&set_cookie), Ok(cookie));
+        assert!(Cookie::parse("no pair").is_err());
+        assert!(Cookie::new("a b", "1").validate().is_err());
+        assert!(Cookie::new("a", "x;y").validate().is_err());
+    }
+
+    #[test]
+    fn changes_are_read_back_and_sent() {
+        let mut jar = jar("a=1; b=2");
+        jar.add(Cookie::new("a", "3")).unwrap();
+        jar.remove(Cookie::new("b", "").path("/"));
+        assert_eq!(jar.get("a"), Some("3"));
+        assert_eq!(jar.get("b"), None);
+        let mut response = Response::empty(Status::Ok);
+        jar.apply(&mut response);
+        let set_cookies = response
+            .headers
+            .get_all("Set-Cookie")
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            set_cookies,
+            [
+                "a=3",
+                "b=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+            ]
+        );
+    }
+
+    #[test]
+    fn signed_cookies_detect_tampering() {
+        let key = key();
+        let mut sent = Jar::default();
+        sent.signed(&key).add(Cookie::new("user", "alice")).unwrap();
+        let value = sent.get("user").unwrap().to_string();
+        assert!(value.starts_with("alice."));
+        let mut received = jar(&format!("user={}", value));
+        assert_eq!(received.signed(&key).get("user"), Some("alice".to_string()));
+        let mut forged = jar(&format!("user={}", value.replace("alice", "admin")));
+        assert_eq!(forged.signed(&key).get("user"), None);
+        // a signature is only valid for the cookie it was made for
+        let mut renamed = jar(&format!("other={}", value));
+        assert_eq!(renamed.signed(&key).get("other"), None);
+        let mut other_key = jar(&format!("user={}", value));
+        assert_eq!(other_key.signed(&Key::derive(&[8u8; 32])).get("user"), None);
+    }
+
+    #[test]
+    fn private_cookies_are_unreadable_and_tamper_proof() {
+        let key = key();
+        let mut sent = Jar::default();
+        sent.private(&key).add(Cookie::new("cart", "a; b")).unwrap();
+        let value = sent.get("cart").unwrap().to_string();
+        assert!(!value.contains("; b"));
+        let mut received = jar(&format!("cart={}", value));
+        assert_eq!(received.private(&key).get("cart"), Some("a; b".to_string()));
+        let mut flipped = value.into_bytes();
+        flipped[20] = if flipped[20] == b'A' { b'B' } else { b'A' };
+        let mut forged = jar(&format!("cart={}", String::from_utf8(flipped).unwrap()));
+        assert_eq!(forged.private(&key).get("cart"), None);
+    }
+}
@@ -0,0 +1,348 @@
+//! Cross-origin resource sharing, see the Fetch Standard section 3.2
+//!
+//! Browsers only let scripts of other origins read a response that allows
+//! their origin, and ask first with an OPTIONS preflight before sending
+//! requests a form could not have sent. The [`Cors`] layer answers
+//! preflights itself and marks the responses of allowed origins.
+use {
+    crate::http::{
+        middleware::{Layer, Next},
+        request::Method,
+        response::Status,
+        Request, Response,
+    },
+    std::{io, time::Duration},
+};
+
+/// Methods allowed when no `--cors-methods` is given
+pub const DEFAULT_METHODS: [&str; 3] = ["GET", "HEAD", "POST"];
+
+/// Origins a policy allows
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Origin {
+    /// Every origin, written `*`
+    Any,
+    /// A single origin, like `https://example.com`
+    Exact(String),
+    /// Origins matching a pattern with one `*` standing for one or more
+    /// labels, like `https://*.example.com`
+    Pattern(String, String),
+}
+impl Origin {
+    pub fn parse(origin: &str) -> Self {
+        match origin.split_once('*') {
+            None => Origin::Exact(origin.to_ascii_lowercase()),
+            Some(("", "")) => Origin::Any,
+            Some((prefix, suffix)) => {
+                Origin::Pattern(prefix.to_ascii_lowercase(), suffix.to_ascii_lowercase())
+            }
+        }
+    }
+    pub fn matches(&self, origin: &str) -> bool {
+        let origin = origin.to_ascii_lowercase();
+        match self {
+            Origin::Any => true,
+            Origin::Exact(exact) => origin.eq(exact),
+            Origin::Pattern(prefix, suffix) => origin
+                .strip_prefix(prefix.as_str())
+                .and_then(|rest| rest.strip_suffix(suffix.as_str()))
+                .is_some_and(|labels| {
+                    !labels.is_empty()
+                        && labels
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+                }),
+        }
+    }
+}
+
+/// Which cross-origin requests are allowed, and what their scripts may see
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Policy {
+    pub origins: Vec<Origin>,
+    /// Methods, in upper case
+    pub methods: Vec<String>,
+    /// Request headers beyond the CORS-safelisted ones, `*` allowing any
+    pub headers: Vec<String>,
+    /// Response headers beyond the CORS-safelisted ones scripts may read
+    pub exposed_headers: Vec<String>,
+    /// Whether cookies and credentials may be sent along
+    pub credentials: bool,
+    /// How long browsers may cache the answer to a preflight
+    pub max_age: Option<Duration>,
+}
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            origins: vec![],
+            methods: DEFAULT_METHODS.map(String::from).to_vec(),
+            headers: vec![],
+            exposed_headers: vec![],
+            credentials: false,
+            max_age: None,
+        }
+    }
+}
+impl Policy {
+    /// Reads allowed origins from every `--cors-origin`, and the comma separated
+    /// `--cors-methods`, `--cors-headers` and `--cors-expose`, the flag
+    /// `--cors-credentials` and `--cors-max-age` in seconds
+    pub fn from_args() -> Self {
+        let list = |flag: &str| {
+            crate::args::value(flag)
+                .map(|list| {
+                    list.split(',')
+                        .map(str::trim)
+                        .filter(|item| !item.is_empty())
+                        .map(String::from)
+                        .collect::<Vec<String>>()
+                })
+                .unwrap_or_default()
+        };
+        let mut policy = Self {
+            origins: crate::args::values("--cors-origin")
+                .iter()
+                .map(|origin| Origin::parse(origin))
+                .collect(),
+            headers: list("--cors-headers"),
+            exposed_headers: list("--cors-expose"),
+            credentials: crate::args::flag("--cors-credentials"),
+            max_age: crate::args::value("--cors-max-age")
+                .and_then(|seconds| seconds.parse().ok())
+                .map(Duration::from_secs),
+            ..Self::default()
+        };
+        let methods = list("--cors-methods");
+        if !methods.is_empty() {
+            policy.methods = methods.iter().map(|m| m.to_ascii_uppercase()).collect();
+        }
+        policy
+    }
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.origins.iter().any(|allowed| allowed.matches(origin))
+    }
+    pub fn allows_method(&self, method: &str) -> bool {
+        self.methods.iter().any(|allowed| allowed.eq(method))
+    }
+    pub fn allows_header(&self, header: &str) -> bool {
+        self.headers
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(header))
+    }
+    /// Value of Access-Control-Allow-Origin for `origin`
+    ///
+    /// The origin is echoed back unless any origin is allowed without
+    /// credentials, as browsers refuse `*` along with credentials.
+    fn allow_origin<'a>(&self, origin: &'a str) -> &'a str {
+        match self.origins.contains(&Origin::Any) && !self.credentials {
+            true => "*",
+            false => origin,
+        }
+    }
+    /// Returns true if responses differ with the Origin of the request
+    fn varies(&self) -> bool {
+        self.origins.iter().any(|origin| origin.ne(&Origin::Any)) || self.credentials
+    }
+}
+
+/// Layer applying a CORS [`Policy`], doing nothing while it allows no origin
+pub struct Cors {
+    policy: Policy,
+}
+impl Cors {
+    pub fn new(policy: Policy) -> Self {
+        Self { policy }
+    }
+    pub fn from_args() -> Self {
+        Self::new(Policy::from_args())
+    }
+    /// Answers a preflight from `origin`, 403 if the request it announces is not allowed
+    fn preflight(&self, request: &Request, origin: &str, method: &str) -> Response {
+        let requested_headers = request
+            .headers
+            .get_all("Access-Control-Request-Headers")
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|header| !header.is_empty())
+            .map(|header| header.to_ascii_lowercase())
+            .collect::<Vec<String>>();
+        let allowed = self.policy.allows_origin(origin)
+            && self.policy.allows_method(method)
+            && requested_headers
+                .iter()
+                .all(|header| self.policy.allows_header(header));
+        let mut response = match allowed {
+            true => Response::empty(Status::NoContent),
+            false => {
+                elog_from_mod!("rejecting preflight from", origin.escape_debug());
+                Response::text(Status::Forbidden, "cross-origin request not allowed")
+            }
+        };
+        let headers = &mut response.headers;
+        headers.append(
+            "Vary",
+            "Origin, Access-Control-Request-Method, Access-Control-Request-Headers",
+        );
+        if !allowed {
+            return response;
+        }
+        headers.insert(
+            "Access-Control-Allow-Origin",
+            self.policy.allow_origin(origin),
+        );
+        headers.insert(
+            "Access-Control-Allow-Methods",
+            self.policy.methods.join(", "),
+        );
+        if !requested_headers.is_empty() {
+            headers.insert("Access-Control-Allow-Headers", requested_headers.join(", "));
+        }
+        if self.policy.credentials {
+            headers.insert("Access-Control-Allow-Credentials", "true");
+        }
+        if let Some(max_age) = self.policy.max_age {
+            headers.insert("Access-Control-Max-Age", max_age.as_secs().to_string());
+        }
+        response
+    }
+}
+impl Layer for Cors {
+    fn handle(&self, request: Request, next: Next<'_>) -> io::Result<Response> {
+        if self.policy.origins.is_empty() {
+            return next.run(request);
+        }
+        let origin = request.headers.get("Origin").map(str::to_string);
+        let preflight_method = request
+            .headers
+            .get("Access-Control-Request-Method")
+            .map(str::to_string);
+        if let (Method::Options, Some(origin), Some(method)) =
+            (&request.start_line.method, &origin, &preflight_method)
+        {
+            return Ok(self.preflight(&request, origin, method));
+        }
+        let mut response = next.run(request)?;
+        if self.policy.varies() {
+            response.headers.append("Vary", "Origin");
+        }
+        match origin {
+            Some(origin) if self.policy.allows_origin(&origin) => {
+                let headers = &mut response.headers;
+                headers.insert(
+                    "Access-Control-Allow-Origin",
+                    self.policy.allow_origin(&origin),
+                );
+                if self.policy.credentials {
+                    headers.insert("Access-Control-Allow-Credentials", "true");
+                }
+                if !self.policy.exposed_headers.is_empty() {
+                    let exposed = self.policy.exposed_headers.join(", ");
+                    headers.insert("Access-Control-Expose-Headers", exposed);
+                }
+            }
+            _ => (),
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cors, Origin, Policy};
+    use crate::http::{
+        connection::Handler,
+        middleware::{Layer, Next},
+        response::Status,
+        Request, Response,
+    };
+    use std::{sync::Arc, time::Duration};
+
+    fn send(policy: &Policy, raw: &str) -> Response {
+        let layers: Vec<Arc<dyn Layer>> = vec![Arc::new(Cors::new(policy.clone()))];
+        let handler: Handler = Arc::new(|_| Ok(Response::text(Status::Ok, "handled")));
+        let request = Request::try_construct(raw.as_bytes()).unwrap();
+        Next::new(&layers, &handler).run(request).unwrap()
+    }
+
+    fn policy() -> Policy {
+        Policy {
+            origins: vec![
+                Origin::parse("https://app.example.com"),
+                Origin::parse("https://*.example.org"),
+            ],
+            headers: vec!["x-token".to_string()],
+            exposed_headers: vec!["X-Request-Id".to_string()],
+            credentials: true,
+            max_age: Some(Duration::from_secs(600)),
+            ..Policy::default()
+        }
+    }
+
+    #[test]
+    fn origins_match_exactly_or_by_pattern() {
+        let pattern = Origin::parse("https://*.example.org");
+        assert!(pattern.matches("https://a.example.org"));
+        assert!(pattern.matches("HTTPS://a.b.example.org"));
+        assert!(!pattern.matches("https://.example.org"));
+        assert!(!pattern.matches("https://example.org"));
+        assert!(!pattern.matches("https://evil.com/.example.org"));
+        assert!(!pattern.matches("http://a.example.org"));
+        assert!(Origin::parse("*").matches("null"));
+        assert!(!Origin::parse("https://a.com").matches("https://a.com.evil"));
+    }
+
+    #[test]
+    fn preflights_are_answered_without_the_handler() {
+        let raw = "OPTIONS /files/a HTTP/1.1\r\nHost: a\r\nOrigin: https://x.example.org\r\n\
+                   Access-Control-Request-Method: POST\r\n\
+                   Access-Control-Request-Headers: X-Token, Content-Type\r\n\r\n";
+        let response = send(&policy(), raw);
+        assert_eq!(response.start_line.status as u16, 403);
+        assert_eq!(response.headers.get("Access-Control-Allow-Origin"), None);
+        let raw = raw.replace(", Content-Type", "");
+        let response = send(&policy(), &raw);
+        assert_eq!(response.start_line.status as u16, 204);
+        let header = |name: &str| response.headers.get(name).map(str::to_string);
+        let origin = header("Access-Control-Allow-Origin");
+        assert_eq!(origin.as_deref(), Some("https://x.example.org"));
+        let methods = header("Access-Control-Allow-Methods");
+        assert_eq!(methods.as_deref(), Some("GET, HEAD, POST"));
+        assert_eq!(
+            header("Access-Control-Allow-Headers").as_deref(),
+            Some("x-token")
+        );
+        assert_eq!(
+            header("Access-Control-Allow-Credentials").as_deref(),
+            Some("true")
+        );
+        assert_eq!(header("Access-Control-Max-Age").as_deref(), Some("600"));
+        let raw = raw.replace("Method: POST", "Method: DELETE");
+        assert_eq!(send(&policy(), &raw).start_line.status as u16, 403);
+    }
+
+    #[test]
+    fn responses_to_allowed_origins_are_marked() {
+        let raw = "GET /echo/a HTTP/1.1\r\nHost: a\r\nOrigin: https://app.example.com\r\n\r\n";
+        let response = send(&policy(), raw);
+        let header = |name: &str| response.headers.get(name).map(str::to_string);
+        let origin = header("Access-Control-Allow-Origin");
+        assert_eq!(origin.as_deref(), Some("https://app.example.com"));
+        let exposed = header("Access-Control-Expose-Headers");
+        assert_eq!(exposed.as_deref(), Some("X-Request-Id"));
+        assert_eq!(header("Vary").as_deref(), Some("Origin"));
+        let raw = raw.replace("app.example.com", "evil.com");
+        let response = send(&policy(), &raw);
+        assert_eq!(response.headers.get("Access-Control-Allow-Origin"), None);
+        assert_eq!(response.headers.get("Vary"), Some("Origin"));
+        let any = Policy {
+            origins: vec![Origin::Any],
+            ..Policy::default()
+        };
+        let response = send(&any, &raw);
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Origin"),
+            Some("*")
+        );
+        assert_eq!(response.headers.get("Vary"), None);
+    }
+}
@@ -1,9 +1,31 @@
+use crate::http::{header, Version};
 use std::fmt;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Error {
     ParseVersion(String, String),
     ParseVersionFormat(String),
+    /// Errors related to the request line or the shape of the head
+    ParseHead(String),
+    /// Errors related to a single header field
+    Header(header::Error),
+    /// Errors related to ambiguous or unsupported message framing
+    Framing(String),
+    /// Errors related to a missing or invalid Host header
+    Host(String),
+    /// Errors related to requests using a major version the server does not speak
+    VersionNotSupported(Version),
+    /// Errors related to requests the client did not send in time
+    Timeout(String),
+    /// Errors related to request methods the server does not know
+    MethodNotImplemented(String),
+    /// Errors related to request bodies larger than the server accepts
+    ContentTooLarge(usize),
+}
+impl From<header::Error> for Error {
+    fn from(value: header::Error) -> Self {
+        Self::Header(value)
+    }
 }
 
 impl std::error::Error for Error {}
@@ -15,6 +37,14 @@ impl fmt::Display for Error {
                 format!("parse version error {}/{}", message_1, message_2)
             }
             Self::ParseVersionFormat(message) => format!("parse version format error {}", message),
+            Self::ParseHead(message) => format!("parse head error {}", message),
+            Self::Header(error) => format!("header error {}", error),
+            Self::Framing(message) => format!("framing error {}", message),
+            Self::Host(message) => format!("host error {}", message),
+            Self::VersionNotSupported(version) => format!("version {} not supported", version),
+            Self::Timeout(message) => format!("timeout {}", message),
+            Self::MethodNotImplemented(method) => format!("method {} not implemented", method),
+            Self::ContentTooLarge(limit) => format!("body larger than {} bytes", limit),
         };
         fmt::write(f, format_args!("[{}] {}", source, body))
     }
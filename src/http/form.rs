@@ -0,0 +1,492 @@
+//! Form bodies, sent as `application/x-www-form-urlencoded` or `multipart/form-data`
+//!
+//! Multipart bodies are read as a stream: file parts are written to disk as
+//! their bytes arrive, and only text fields are kept in memory.
+use std::{
+    fmt, fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Largest head a part may have, its header fields included
+pub const MAX_PART_HEAD: usize = 8 * 1024;
+/// Longest boundary allowed, see RFC 2046 section 5.1.1
+const MAX_BOUNDARY: usize = 70;
+const READ_CHUNK_SIZE: usize = 16 * 1024;
+
+static UPLOADS: AtomicU64 = AtomicU64::new(0);
+
+/// Errors related to form bodies
+#[derive(Debug)]
+pub enum Error {
+    /// Errors related to bodies that do not follow their content type
+    Malformed(String),
+    /// Errors related to storing file parts
+    Io(io::Error),
+}
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+impl std::error::Error for Error {}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = module_path!();
+        let body = match self {
+            Self::Malformed(message) => format!("malformed form {}", message),
+            Self::Io(error) => format!("storing file failed {}", error),
+        };
+        fmt::write(f, format_args!("[{}] {}", source, body))
+    }
+}
+
+/// A file part, stored on disk once the whole form was read
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct File {
+    /// Name of the form field the file was sent under
+    pub name: String,
+    /// Name the client gave the file, which it is stored under
+    pub filename: String,
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// Fields of a form, in the order they were sent
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Form {
+    pub fields: Vec<(String, String)>,
+    pub files: Vec<File>,
+}
+
+/// Decodes `+` and percent escapes of a urlencoded name or value
+pub fn percent_decode(encoded: &str) -> Result<String, Error> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = [bytes.next(), bytes.next()];
+                let byte = match hex {
+                    [Some(high), Some(low)] => std::str::from_utf8(&[high, low])
+                        .ok()
+                        .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+                    _ => None,
+                };
+                let byte = byte.ok_or_else(|| {
+                    Error::Malformed(format!("invalid percent escape in {}", encoded))
+                })?;
+                decoded.push(byte);
+            }
+            byte => decoded.push(byte),
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| Error::Malformed(format!("{} is not UTF-8", encoded)))
+}
+
+/// Parses an `application/x-www-form-urlencoded` body
+pub fn urlencoded(body: &[u8]) -> Result<Form, Error> {
+    let body = std::str::from_utf8(body)
+        .map_err(|_| Error::Malformed(String::from("urlencoded body is not UTF-8")))?;
+    let fields = body
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((percent_decode(name)?, percent_decode(value)?))
+        })
+        .collect::<Result<Vec<(String, String)>, Error>>()?;
+    Ok(Form {
+        fields,
+        files: vec![],
+    })
+}
+
+/// Splits the parameters of a Content-Type or Content-Disposition value,
+/// unquoting quoted values
+///
+/// Names are lowercased, and the leading value is skipped.
+pub fn parameters(value: &str) -> Vec<(String, String)> {
+    let mut parameters = vec![];
+    let mut rest = value
+        .split_once(';')
+        .map(|(_, rest)| rest)
+        .unwrap_or_default();
+    while let Some((name, after)) = rest.split_once('=') {
+        // a parameter without a value is dropped
+        let name = name.rsplit(';').next().unwrap_or_default();
+        let after = after.trim_start();
+        let (value, remaining) = match after.strip_prefix('"') {
+            Some(quoted) => unquote(quoted),
+            None => {
+                let (value, remaining) = after.split_once(';').unwrap_or((after, ""));
+                (value.trim().to_string(), remaining)
+            }
+        };
+        parameters.push((name.trim().to_ascii_lowercase(), value));
+        rest = remaining;
+    }
+    parameters
+}
+
+/// The boundary parameter of a multipart Content-Type value
+pub fn boundary(content_type: &str) -> Option<String> {
+    parameters(content_type)
+        .into_iter()
+        .find(|(name, _)| name.eq("boundary"))
+        .map(|(_, boundary)| boundary)
+}
+
+/// Reads a quoted string up to its closing quote, returning it unescaped along
+/// with what follows the next `;`
+fn unquote(quoted: &str) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => {
+                let after = &quoted[index + 1..];
+                let remaining = after.split_once(';').map(|(_, rest)| rest);
+                return (value, remaining.unwrap_or_default());
+            }
+            '\\' => value.extend(chars.next().map(|(_, escaped)| escaped)),
+            c => value.push(c),
+        }
+    }
+    (value, "")
+}
+
+/// The part of a client supplied file name that is safe to store a file under
+///
+/// Directories are dropped, and names that would refer to something other
+/// than a file in the upload directory are rejected.
+fn safe_filename(filename: &str) -> Option<&str> {
+    let base = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+    match base {
+        "" | "." | ".." => None,
+        base if base.chars().any(char::is_control) => None,
+        base => Some(base),
+    }
+}
+
+/// A file part being written, removed on drop unless it was kept
+struct Upload {
+    temporary: PathBuf,
+    file: File,
+}
+impl Upload {
+    fn keep(mut self) -> io::Result<File> {
+        fs::rename(&self.temporary, &self.file.path)?;
+        self.temporary = PathBuf::new();
+        Ok(self.file.clone())
+    }
+}
+impl Drop for Upload {
+    fn drop(&mut self) {
+        if !self.temporary.as_os_str().is_empty() {
+            let _ = fs::remove_file(&self.temporary);
+        }
+    }
+}
+
+/// Streams `source` into the file at `path`, which only takes its name once
+/// the whole body was read
+///
+/// Returns the number of bytes stored, an empty body storing nothing.
+pub fn store<R: Read>(mut source: R, path: &Path) -> io::Result<u64> {
+    let directory = path.parent().unwrap_or(Path::new("."));
+    let temporary = temporary(directory);
+    let mut file = fs::File::create(&temporary)?;
+    let upload = Upload {
+        temporary,
+        file: File {
+            name: String::new(),
+            filename: String::new(),
+            path: path.to_path_buf(),
+            size: 0,
+        },
+    };
+    let size = io::copy(&mut source, &mut file)?;
+    if size > 0 {
+        file.sync_all()?;
+        upload.keep()?;
+    }
+    Ok(size)
+}
+
+/// Unique name for a file being written in `directory`
+fn temporary(directory: &Path) -> PathBuf {
+    let count = UPLOADS.fetch_add(1, Ordering::Relaxed);
+    directory.join(format!(".upload-{}-{}", std::process::id(), count))
+}
+
+/// Bytes read from the body but not yet parsed
+struct Stream<R> {
+    source: R,
+    buffer: Vec<u8>,
+}
+impl<R: Read> Stream<R> {
+    /// Reads the next chunk of the body, returning false at its end
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        loop {
+            match self.source.read(&mut chunk) {
+                Ok(bytes_read) => {
+                    self.buffer.extend_from_slice(&chunk[..bytes_read]);
+                    return Ok(bytes_read > 0);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    /// Hands the bytes before `delimiter` to `sink` as they arrive, then consumes the delimiter
+    fn copy_until(
+        &mut self,
+        delimiter: &[u8],
+        sink: &mut dyn FnMut(&[u8]) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        loop {
+            let found = self
+                .buffer
+                .windows(delimiter.len())
+                .position(|window| window == delimiter);
+            if let Some(at) = found {
+                sink(&self.buffer[..at])?;
+                self.buffer.drain(..at + delimiter.len());
+                return Ok(());
+            }
+            // what could be the start of the delimiter stays buffered
+            let kept = self.buffer.len().min(delimiter.len() - 1);
+            let flushed = self.buffer.len() - kept;
+            sink(&self.buffer[..flushed])?;
+            self.buffer.drain(..flushed);
+            if !self.fill()? {
+                return Err(Error::Malformed(String::from(
+                    "body ended before the closing boundary",
+                )));
+            }
+        }
+    }
+    /// Returns true if the next bytes are `prefix`, reading as many as needed
+    fn starts_with(&mut self, prefix: &[u8]) -> io::Result<bool> {
+        while self.buffer.len() < prefix.len() {
+            if !self.fill()? {
+                break;
+            }
+        }
+        Ok(self.buffer.starts_with(prefix))
+    }
+}
+
+/// Collects bytes into `into`, failing once there are more than `limit`
+fn limited<'a>(
+    into: &'a mut Vec<u8>,
+    limit: usize,
+    what: &'static str,
+) -> impl FnMut(&[u8]) -> Result<(), Error> + 'a {
+    move |bytes| {
+        if into.len() + bytes.len() > limit {
+            return Err(Error::Malformed(format!("{} too large", what)));
+        }
+        into.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// Parses a `multipart/form-data` body read from `source`, see RFC 7578
+///
+/// File parts are streamed into `directory` under the name the client gave
+/// them, replacing any file of that name. They are written to temporary files
+/// first, which only take their final name once the whole body was read. File
+/// inputs left empty, which browsers send without a file name, are skipped.
+pub fn multipart<R: Read>(source: R, boundary: &str, directory: &Path) -> Result<Form, Error> {
+    if boundary.is_empty() || boundary.len() > MAX_BOUNDARY {
+        return Err(Error::Malformed(format!("invalid boundary {}", boundary)));
+    }
+    let delimiter = format!("\r\n--{}", boundary).into_bytes();
+    // the first delimiter is not preceded by a line break of its own
+    let mut stream = Stream {
+        source,
+        buffer: b"\r\n".to_vec(),
+    };
+    stream.copy_until(&delimiter, &mut |_preamble| Ok(()))?;
+    let mut fields = vec![];
+    let mut uploads = vec![];
+    loop {
+        if stream.starts_with(b"--")? {
+            break;
+        }
+        let mut head = vec![];
+        stream.copy_until(
+            b"\r\n\r\n",
+            &mut limited(&mut head, MAX_PART_HEAD, "part head"),
+        )?;
+        let head = String::from_utf8(head)
+            .map_err(|_| Error::Malformed(String::from("part head is not UTF-8")))?;
+        let mut lines = head.split("\r\n");
+        // transport padding may follow the boundary
+        if !lines.next().unwrap_or_default().trim().is_empty() {
+            return Err(Error::Malformed(String::from("text after a boundary")));
+        }
+        let mut disposition = None;
+        for line in lines {
+            let (name, value) = crate::http::header::parse_field(line)
+                .map_err(|e| Error::Malformed(e.to_string()))?;
+            if name.eq_ignore_ascii_case("Content-Disposition") {
+                disposition = Some(parameters(value));
+            }
+        }
+        let disposition = disposition
+            .ok_or_else(|| Error::Malformed(String::from("part without Content-Disposition")))?;
+        let parameter = |wanted: &str| {
+            disposition
+                .iter()
+                .find(|(name, _)| name.eq(wanted))
+                .map(|(_, value)| value.clone())
+        };
+        let name = parameter("name")
+            .ok_or_else(|| Error::Malformed(String::from("part without a name")))?;
+        match parameter("filename") {
+            Some(filename) if filename.is_empty() => {
+                stream.copy_until(&delimiter, &mut |_| Ok(()))?;
+            }
+            Some(filename) => {
+                let filename = safe_filename(&filename)
+                    .ok_or_else(|| Error::Malformed(format!("unsafe file name {}", filename)))?;
+                let temporary = temporary(directory);
+                let mut file = fs::File::create(&temporary)?;
+                let mut upload = Upload {
+                    temporary,
+                    file: File {
+                        name,
+                        filename: filename.to_string(),
+                        path: directory.join(filename),
+                        size: 0,
+                    },
+                };
+                let mut size = 0u64;
+                stream.copy_until(&delimiter, &mut |bytes| {
+                    size += bytes.len() as u64;
+                    file.write_all(bytes).map_err(Error::from)
+                })?;
+                file.sync_all()?;
+                upload.file.size = size;
+                uploads.push(upload);
+            }
+            None => {
+                let mut value = vec![];
+                stream.copy_until(&delimiter, &mut |bytes| {
+                    value.extend_from_slice(bytes);
+                    Ok(())
+                })?;
+                let value = String::from_utf8(value)
+                    .map_err(|_| Error::Malformed(format!("field {} is not UTF-8", name)))?;
+                fields.push((name, value));
+            }
+        }
+    }
+    let files = uploads
+        .into_iter()
+        .map(Upload::keep)
+        .collect::<io::Result<Vec<File>>>()?;
+    Ok(Form { fields, files })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{boundary, multipart, parameters, percent_decode, store, urlencoded, Error};
+    use std::{fs, io::Read, path::PathBuf};
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("form-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    /// Hands out its bytes a few at a time, like a slow connection
+    struct Trickle<'a>(&'a [u8]);
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let length = self.0.len().min(buf.len()).min(3);
+            buf[..length].copy_from_slice(&self.0[..length]);
+            self.0 = &self.0[length..];
+            Ok(length)
+        }
+    }
+
+    #[test]
+    fn urlencoded_fields_are_decoded() {
+        let form = urlencoded(b"a=1+2&b=%C3%A9%26&c&&d=").unwrap();
+        let fields = [("a", "1 2"), ("b", "é&"), ("c", ""), ("d", "")]
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+        assert_eq!(form.fields, fields);
+        assert!(percent_decode("%zz").is_err());
+        assert!(percent_decode("%C3").is_err());
+        assert!(percent_decode("%4").is_err());
+    }
+
+    #[test]
+    fn parameters_are_unquoted() {
+        let parsed = parameters(r#"form-data; name="a;b"; filename="c\"d.txt" ; x=y"#);
+        let expected = [("name", "a;b"), ("filename", "c\"d.txt"), ("x", "y")]
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+        assert_eq!(parsed, expected);
+        assert_eq!(
+            boundary("multipart/form-data; charset=utf-8; boundary=\"a b\""),
+            Some("a b".to_string())
+        );
+    }
+
+    #[test]
+    fn file_parts_are_streamed_to_disk() {
+        let directory = directory("upload");
+        let body = "preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+            hello\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"upload\"; filename=\"../../a.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            line one\r\n-XyZ\r\n--XyZ \r\n\
+            Content-Disposition: form-data; name=\"empty\"; filename=\"\"\r\n\r\n\
+            \r\n--XyZ--\r\nepilogue";
+        let form = multipart(Trickle(body.as_bytes()), "XyZ", &directory).unwrap();
+        assert_eq!(form.fields, [("title".to_string(), "hello".to_string())]);
+        assert_eq!(form.files.len(), 1);
+        assert_eq!(form.files[0].filename, "a.txt");
+        assert_eq!(form.files[0].size, 14);
+        let stored = fs::read_to_string(directory.join("a.txt")).unwrap();
+        assert_eq!(stored, "line one\r\n-XyZ");
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn truncated_bodies_leave_no_files() {
+        let directory = directory("truncated");
+        let body =
+            "--b\r\nContent-Disposition: form-data; name=\"f\"; filename=\"a\"\r\n\r\npartial";
+        let result = multipart(body.as_bytes(), "b", &directory);
+        assert!(matches!(result, Err(Error::Malformed(_))));
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+        let body =
+            "--b\r\nContent-Disposition: form-data; name=\"f\"; filename=\"..\"\r\n\r\nx\r\n--b--";
+        assert!(multipart(body.as_bytes(), "b", &directory).is_err());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn stored_bodies_take_their_name_once_read() {
+        let directory = directory("store");
+        let path = directory.join("a.txt");
+        assert_eq!(store(Trickle(b"hello"), &path).unwrap(), 5);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello");
+        assert_eq!(store(Trickle(b""), &path).unwrap(), 0);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello");
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,50 @@
+use std::fmt;
+
+/// Error codes sent in RST_STREAM and GOAWAY frames, see RFC 9113 section 7
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[allow(dead_code)]
+pub enum Code {
+    NoError = 0x0,
+    ProtocolError = 0x1,
+    InternalError = 0x2,
+    FlowControlError = 0x3,
+    SettingsTimeout = 0x4,
+    StreamClosed = 0x5,
+    FrameSizeError = 0x6,
+    RefusedStream = 0x7,
+    Cancel = 0x8,
+    CompressionError = 0x9,
+    ConnectError = 0xa,
+    EnhanceYourCalm = 0xb,
+    InadequateSecurity = 0xc,
+    Http11Required = 0xd,
+}
+
+/// Errors related to the HTTP/2 connection layer
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Error {
+    /// Errors that end the whole connection with a GOAWAY frame
+    Connection(Code, String),
+    /// Errors that end a single stream with a RST_STREAM frame
+    Stream(u32, Code, String),
+}
+impl std::error::Error for Error {}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = module_path!();
+        let body = match self {
+            Self::Connection(code, message) => {
+                format!("connection error {:?}: {}", code, message)
+            }
+            Self::Stream(stream_id, code, message) => {
+                format!("stream {} error {:?}: {}", stream_id, code, message)
+            }
+        };
+        fmt::write(f, format_args!("[{}] {}", source, body))
+    }
+}
+impl From<crate::http::h2::hpack::Error> for Error {
+    fn from(value: crate::http::h2::hpack::Error) -> Self {
+        Self::Connection(Code::CompressionError, value.to_string())
+    }
+}
@@ -0,0 +1,188 @@
+use crate::http::h2::error::{Code, Error};
+
+/// Length of the fixed frame header
+pub const HEADER_LENGTH: usize = 9;
+
+/// Frame types, see RFC 9113 section 6
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Kind {
+    Data,
+    Headers,
+    Priority,
+    RstStream,
+    Settings,
+    PushPromise,
+    Ping,
+    GoAway,
+    WindowUpdate,
+    Continuation,
+    /// Frames of unknown types are ignored
+    Unknown(u8),
+}
+impl From<u8> for Kind {
+    fn from(value: u8) -> Self {
+        use Kind::*;
+        match value {
+            0x0 => Data,
+            0x1 => Headers,
+            0x2 => Priority,
+            0x3 => RstStream,
+            0x4 => Settings,
+            0x5 => PushPromise,
+            0x6 => Ping,
+            0x7 => GoAway,
+            0x8 => WindowUpdate,
+            0x9 => Continuation,
+            other => Unknown(other),
+        }
+    }
+}
+impl From<Kind> for u8 {
+    fn from(value: Kind) -> Self {
+        use Kind::*;
+        match value {
+            Data => 0x0,
+            Headers => 0x1,
+            Priority => 0x2,
+            RstStream => 0x3,
+            Settings => 0x4,
+            PushPromise => 0x5,
+            Ping => 0x6,
+            GoAway => 0x7,
+            WindowUpdate => 0x8,
+            Continuation => 0x9,
+            Unknown(other) => other,
+        }
+    }
+}
+
+/// Frame flags, their meaning depends on the frame type
+pub mod flags {
+    pub const END_STREAM: u8 = 0x1;
+    pub const ACK: u8 = 0x1;
+    pub const END_HEADERS: u8 = 0x4;
+    pub const PADDED: u8 = 0x8;
+    pub const PRIORITY: u8 = 0x20;
+}
+
+/// A single frame
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Frame {
+    pub kind: Kind,
+    pub flags: u8,
+    pub stream_id: u32,
+    pub payload: Vec<u8>,
+}
+impl Frame {
+    pub fn new(kind: Kind, flags: u8, stream_id: u32, payload: Vec<u8>) -> Self {
+        Self {
+            kind,
+            flags,
+            stream_id,
+            payload,
+        }
+    }
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+    /// Parses the first frame in `bytes`
+    ///
+    /// Returns the frame and the number of bytes it took up, or `Ok(None)` if
+    /// more bytes are needed to complete it.
+    pub fn parse(bytes: &[u8], max_frame_size: u32) -> Result<Option<(Self, usize)>, Error> {
+        if bytes.len() < HEADER_LENGTH {
+            return Ok(None);
+        }
+        let length = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        if length > max_frame_size {
+            return Err(Error::Connection(
+                Code::FrameSizeError,
+                format!("frame of {} bytes exceeds {}", length, max_frame_size),
+            ));
+        }
+        let end = HEADER_LENGTH + length as usize;
+        if bytes.len() < end {
+            return Ok(None);
+        }
+        // the reserved bit is ignored
+        let stream_id = u32::from_be_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]) & 0x7fff_ffff;
+        let frame = Self::new(
+            Kind::from(bytes[3]),
+            bytes[4],
+            stream_id,
+            bytes[HEADER_LENGTH..end].to_vec(),
+        );
+        Ok(Some((frame, end)))
+    }
+    pub fn encode(&self) -> Vec<u8> {
+        let length = (self.payload.len() as u32).to_be_bytes();
+        let mut bytes = Vec::with_capacity(HEADER_LENGTH + self.payload.len());
+        bytes.extend_from_slice(&length[1..]);
+        bytes.push(u8::from(self.kind));
+        bytes.push(self.flags);
+        bytes.extend_from_slice(&self.stream_id.to_be_bytes());
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+    /// Strips padding from DATA and HEADERS payloads, see RFC 9113 section 6.1
+    pub fn unpadded(&self) -> Result<&[u8], Error> {
+        if !self.has_flag(flags::PADDED) {
+            return Ok(&self.payload);
+        }
+        let padding = *self.payload.first().ok_or_else(|| {
+            Error::Connection(Code::FrameSizeError, String::from("missing pad length"))
+        })? as usize;
+        if padding >= self.payload.len() {
+            return Err(Error::Connection(
+                Code::ProtocolError,
+                String::from("padding exceeds payload"),
+            ));
+        }
+        Ok(&self.payload[1..self.payload.len() - padding])
+    }
+    /// A RST_STREAM frame ending `stream_id` with `code`
+    pub fn rst_stream(stream_id: u32, code: Code) -> Self {
+        let payload = (code as u32).to_be_bytes().to_vec();
+        Self::new(Kind::RstStream, 0, stream_id, payload)
+    }
+    /// A GOAWAY frame naming the last stream that was or will be processed
+    pub fn go_away(last_stream_id: u32, code: Code, debug: &str) -> Self {
+        let mut payload = last_stream_id.to_be_bytes().to_vec();
+        payload.extend_from_slice(&(code as u32).to_be_bytes());
+        payload.extend_from_slice(debug.as_bytes());
+        Self::new(Kind::GoAway, 0, 0, payload)
+    }
+    /// A WINDOW_UPDATE frame granting `increment` more bytes
+    pub fn window_update(stream_id: u32, increment: u32) -> Self {
+        let payload = increment.to_be_bytes().to_vec();
+        Self::new(Kind::WindowUpdate, 0, stream_id, payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{flags, Frame, Kind};
+
+    #[test]
+    fn round_trip() {
+        let frame = Frame::new(Kind::Data, flags::END_STREAM, 3, b"abc".to_vec());
+        let mut bytes = frame.encode();
+        bytes.extend_from_slice(b"next");
+        assert_eq!(Frame::parse(&bytes, 16384).unwrap(), Some((frame, 12)));
+        assert_eq!(Frame::parse(&bytes[..11], 16384).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_oversized_frames() {
+        let frame = Frame::new(Kind::Data, 0, 1, vec![0u8; 20]);
+        assert!(Frame::parse(&frame.encode(), 16).is_err());
+    }
+
+    #[test]
+    fn strips_padding() {
+        let frame = Frame::new(Kind::Data, flags::PADDED, 1, b"\x02abc\0\0".to_vec());
+        assert_eq!(frame.unpadded().unwrap(), b"abc");
+        let frame = Frame::new(Kind::Data, flags::PADDED, 1, b"\x05abc".to_vec());
+        assert!(frame.unpadded().is_err());
+    }
+}
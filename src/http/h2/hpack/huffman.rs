@@ -0,0 +1,157 @@
+use crate::http::h2::hpack::Error;
+
+/// Code length of every symbol of the HPACK Huffman code, see RFC 7541 appendix B
+///
+/// The code is canonical, so the codes themselves follow from the lengths:
+/// symbols are ordered by code length, then by value, and numbered upwards.
+/// Symbol 256 is the end-of-string marker.
+const CODE_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+    6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+    23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+    21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+    22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+    21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+    22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, 30,
+];
+/// Longest code in the table
+const MAX_CODE_LENGTH: usize = 30;
+/// End-of-string symbol, which must never appear in an encoded string
+const EOS: u16 = 256;
+
+/// Canonical coding tables built from `CODE_LENGTHS`
+struct Canonical {
+    /// Symbols ordered by code length, then by value
+    symbols: Vec<u16>,
+    /// Number of codes of each length
+    counts: [u32; MAX_CODE_LENGTH + 1],
+    /// Code of every symbol
+    codes: [u32; EOS as usize + 1],
+}
+impl Canonical {
+    fn new() -> Self {
+        let mut symbols = (0u16..=EOS).collect::<Vec<u16>>();
+        symbols.sort_by_key(|symbol| (CODE_LENGTHS[*symbol as usize], *symbol));
+        let mut counts = [0u32; MAX_CODE_LENGTH + 1];
+        CODE_LENGTHS
+            .iter()
+            .for_each(|length| counts[*length as usize] += 1);
+        let mut codes = [0u32; EOS as usize + 1];
+        let mut code = 0u32;
+        let mut previous_length = 0u8;
+        for symbol in symbols.iter() {
+            let length = CODE_LENGTHS[*symbol as usize];
+            code <<= length - previous_length;
+            codes[*symbol as usize] = code;
+            code += 1;
+            previous_length = length;
+        }
+        Self {
+            symbols,
+            counts,
+            codes,
+        }
+    }
+    /// Tables are built once, on first use
+    fn get() -> &'static Self {
+        static CANONICAL: std::sync::OnceLock<Canonical> = std::sync::OnceLock::new();
+        CANONICAL.get_or_init(Self::new)
+    }
+}
+
+/// Decodes a Huffman encoded string literal
+///
+/// Padding must be shorter than a byte and consist of the most significant bits of EOS.
+pub fn decode(encoded: &[u8]) -> Result<Vec<u8>, Error> {
+    let canonical = Canonical::get();
+    let mut decoded = vec![];
+    // code bits collected so far, and how many there are
+    let mut code = 0u32;
+    let mut length = 0usize;
+    // first code and first symbol index of the current length
+    let mut first = 0u32;
+    let mut index = 0u32;
+    for bit in encoded
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |shift| (byte >> shift) & 1))
+    {
+        code = (code << 1) | bit as u32;
+        length += 1;
+        let count = canonical.counts[length];
+        if code - first < count {
+            let symbol = canonical.symbols[(index + code - first) as usize];
+            if symbol == EOS {
+                return Err(Error::Huffman(String::from("end of string in literal")));
+            }
+            decoded.push(symbol as u8);
+            (code, length, first, index) = (0, 0, 0, 0);
+        } else if length == MAX_CODE_LENGTH {
+            return Err(Error::Huffman(String::from("invalid code")));
+        } else {
+            first = (first + count) << 1;
+            index += count;
+        }
+    }
+    // leftover bits are padding, which must be a prefix of EOS (all ones)
+    if length > 7 || code != (1u32 << length) - 1 {
+        return Err(Error::Huffman(String::from("invalid padding")));
+    }
+    Ok(decoded)
+}
+
+/// Encodes `raw` with the Huffman code, padding the last byte with ones
+pub fn encode(raw: &[u8]) -> Vec<u8> {
+    let codes = &Canonical::get().codes;
+    let mut encoded = vec![];
+    let mut pending = 0u64;
+    let mut pending_bits = 0u32;
+    for byte in raw {
+        let length = CODE_LENGTHS[*byte as usize] as u32;
+        pending = (pending << length) | codes[*byte as usize] as u64;
+        pending_bits += length;
+        while pending_bits >= 8 {
+            pending_bits -= 8;
+            encoded.push((pending >> pending_bits) as u8);
+        }
+    }
+    if pending_bits > 0 {
+        let padding = 8 - pending_bits;
+        encoded.push(((pending << padding) as u8) | ((1u8 << padding) - 1));
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode};
+
+    #[test]
+    fn decodes_rfc_7541_examples() {
+        // C.4.1 and C.4.2
+        let www = [
+            0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90, 0xf4, 0xff,
+        ];
+        assert_eq!(decode(&www).unwrap(), b"www.example.com");
+        assert_eq!(
+            decode(&[0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbf]).unwrap(),
+            b"no-cache"
+        );
+    }
+
+    #[test]
+    fn round_trips_every_byte() {
+        let raw = (0u8..=255).collect::<Vec<u8>>();
+        assert_eq!(decode(&encode(&raw)).unwrap(), raw);
+    }
+
+    #[test]
+    fn rejects_bad_padding() {
+        // a full byte of padding
+        assert!(decode(&[0xff]).is_err());
+        // padding that is not all ones
+        assert!(decode(&[0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbe]).is_err());
+    }
+}
@@ -0,0 +1,327 @@
+/// Module to handle the Huffman code used by string literals
+mod huffman;
+
+/// Module to handle the static and dynamic header tables
+mod table;
+
+use std::fmt;
+
+/// Errors related to decoding header blocks, all of them are fatal to the connection
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Error {
+    /// Errors related to the header block ending mid representation
+    Truncated,
+    /// Errors related to integers too large to represent
+    Integer,
+    /// Errors related to indices outside both tables
+    Index(usize),
+    /// Errors related to table size updates above the advertised limit or out of place
+    TableSize(usize),
+    /// Errors related to invalid Huffman encoded literals
+    Huffman(String),
+}
+impl std::error::Error for Error {}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = module_path!();
+        let body = match self {
+            Self::Truncated => String::from("truncated header block"),
+            Self::Integer => String::from("integer overflow"),
+            Self::Index(index) => format!("invalid table index {}", index),
+            Self::TableSize(size) => format!("invalid table size update {}", size),
+            Self::Huffman(message) => format!("huffman error {}", message),
+        };
+        fmt::write(f, format_args!("[{}] {}", source, body))
+    }
+}
+
+/// A decoded header field, names and values are raw octets
+pub type Field = (Vec<u8>, Vec<u8>);
+
+/// Decodes an integer with an `prefix` bit prefix, see RFC 7541 section 5.1
+///
+/// Returns the integer and the number of bytes it took up.
+fn decode_integer(block: &[u8], prefix: u8) -> Result<(usize, usize), Error> {
+    let mask = (1usize << prefix) - 1;
+    let first = *block.first().ok_or(Error::Truncated)? as usize & mask;
+    if first < mask {
+        return Ok((first, 1));
+    }
+    let mut value = mask;
+    for (i, byte) in block.iter().enumerate().skip(1) {
+        let shift = 7 * (i - 1);
+        if shift > 28 {
+            return Err(Error::Integer);
+        }
+        value += ((*byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(Error::Truncated)
+}
+
+/// Encodes `value` with an `prefix` bit prefix, or-ing `flags` into the first byte
+fn encode_integer(value: usize, prefix: u8, flags: u8, out: &mut Vec<u8>) {
+    let mask = (1usize << prefix) - 1;
+    if value < mask {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | mask as u8);
+    let mut remaining = value - mask;
+    while remaining >= 0x80 {
+        out.push((remaining & 0x7f) as u8 | 0x80);
+        remaining >>= 7;
+    }
+    out.push(remaining as u8);
+}
+
+/// Decodes a string literal, see RFC 7541 section 5.2
+///
+/// Returns the string and the number of bytes it took up.
+fn decode_string(block: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+    let huffman = block.first().ok_or(Error::Truncated)? & 0x80 != 0;
+    let (length, offset) = decode_integer(block, 7)?;
+    let end = offset.checked_add(length).ok_or(Error::Integer)?;
+    let raw = block.get(offset..end).ok_or(Error::Truncated)?;
+    let string = if huffman {
+        huffman::decode(raw)?
+    } else {
+        raw.to_vec()
+    };
+    Ok((string, end))
+}
+
+/// Encodes a string literal, Huffman coding it when that is shorter
+fn encode_string(raw: &[u8], out: &mut Vec<u8>) {
+    let encoded = huffman::encode(raw);
+    if encoded.len() < raw.len() {
+        encode_integer(encoded.len(), 7, 0x80, out);
+        out.extend_from_slice(&encoded);
+    } else {
+        encode_integer(raw.len(), 7, 0x00, out);
+        out.extend_from_slice(raw);
+    }
+}
+
+/// Decodes header blocks received from the peer
+pub struct Decoder {
+    table: table::Dynamic,
+    /// Table size advertised in our SETTINGS, updates above it are errors
+    max_table_size: usize,
+    /// Header list size advertised in our SETTINGS, see [`Decoder::list_limit`]
+    max_list_size: usize,
+}
+impl Decoder {
+    pub fn new(max_table_size: usize) -> Self {
+        Self {
+            table: table::Dynamic::new(max_table_size),
+            max_table_size,
+            max_list_size: usize::MAX,
+        }
+    }
+    /// Gives up on the fields of blocks decoding to a list larger than
+    /// `max_list_size`, counted as in RFC 9113 section 6.5.2
+    pub fn list_limit(mut self, max_list_size: usize) -> Self {
+        self.max_list_size = max_list_size;
+        self
+    }
+    /// Decodes a whole header block, updating the dynamic table as it goes
+    ///
+    /// Returns `Ok(None)` for blocks over the list limit, whose fields are
+    /// dropped as they are decoded while the table is still kept in step.
+    pub fn decode(&mut self, block: &[u8]) -> Result<Option<Vec<Field>>, Error> {
+        let mut fields = vec![];
+        let mut list_size = 0usize;
+        let mut oversized = false;
+        let mut offset = 0usize;
+        while offset < block.len() {
+            let rest = &block[offset..];
+            let first = rest[0];
+            let consumed = if first & 0x80 != 0 {
+                // indexed field
+                let (index, consumed) = decode_integer(rest, 7)?;
+                let field = self.table.get(index)?;
+                list_size = list_size.saturating_add(field_size(&field));
+                fields.push(field);
+                consumed
+            } else if first & 0xe0 == 0x20 {
+                // table size updates may only open a block
+                let (size, consumed) = decode_integer(rest, 5)?;
+                if size > self.max_table_size || list_size > 0 {
+                    return Err(Error::TableSize(size));
+                }
+                self.table.resize(size);
+                consumed
+            } else {
+                // literal field, indexed when the 0x40 bit is set
+                let indexing = first & 0xc0 == 0x40;
+                let prefix = if indexing { 6 } else { 4 };
+                let (index, mut consumed) = decode_integer(rest, prefix)?;
+                let name = if index == 0 {
+                    let (name, name_length) = decode_string(&rest[consumed..])?;
+                    consumed += name_length;
+                    name
+                } else {
+                    self.table.get(index)?.0
+                };
+                let (value, value_length) = decode_string(&rest[consumed..])?;
+                consumed += value_length;
+                if indexing {
+                    self.table.insert(name.clone(), value.clone());
+                }
+                let field = (name, value);
+                list_size = list_size.saturating_add(field_size(&field));
+                fields.push(field);
+                consumed
+            };
+            offset += consumed;
+            if list_size > self.max_list_size {
+                oversized = true;
+                fields.clear();
+            }
+        }
+        Ok((!oversized).then_some(fields))
+    }
+}
+
+/// Size of a field counted towards the header list size
+fn field_size((name, value): &Field) -> usize {
+    name.len() + value.len() + 32
+}
+
+/// Encodes a header block without touching the dynamic table
+///
+/// Fields matching a static entry exactly are indexed, the rest are sent
+/// as literals without indexing, reusing static names where possible.
+pub fn encode<'a, I>(fields: I) -> Vec<u8>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let mut block = vec![];
+    for (name, value) in fields {
+        let exact = table::STATIC
+            .iter()
+            .position(|entry| entry.0.eq(name) && entry.1.eq(value));
+        let named = table::STATIC.iter().position(|entry| entry.0.eq(name));
+        match (exact, named) {
+            (Some(index), _) => encode_integer(index + 1, 7, 0x80, &mut block),
+            (None, Some(index)) => {
+                encode_integer(index + 1, 4, 0x00, &mut block);
+                encode_string(value.as_bytes(), &mut block);
+            }
+            (None, None) => {
+                block.push(0x00);
+                encode_string(name.as_bytes(), &mut block);
+                encode_string(value.as_bytes(), &mut block);
+            }
+        }
+    }
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_integer, encode, encode_integer, Decoder};
+
+    fn fields(decoded: Vec<super::Field>) -> Vec<(String, String)> {
+        decoded
+            .into_iter()
+            .map(|(name, value)| {
+                (
+                    String::from_utf8(name).unwrap(),
+                    String::from_utf8(value).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn integer_round_trip() {
+        // RFC 7541 C.1.2, 1337 with a 5 bit prefix
+        let mut out = vec![];
+        encode_integer(1337, 5, 0, &mut out);
+        assert_eq!(out, vec![31, 154, 10]);
+        assert_eq!(decode_integer(&out, 5).unwrap(), (1337, 3));
+    }
+
+    #[test]
+    fn decodes_rfc_7541_request_examples_with_huffman() {
+        // C.4.1 to C.4.3 share one dynamic table
+        let mut decoder = Decoder::new(4096);
+        let first = [
+            0x82, 0x86, 0x84, 0x41, 0x8c, 0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab,
+            0x90, 0xf4, 0xff,
+        ];
+        assert_eq!(
+            fields(decoder.decode(&first).unwrap().unwrap()),
+            vec![
+                (":method".into(), "GET".into()),
+                (":scheme".into(), "http".into()),
+                (":path".into(), "/".into()),
+                (":authority".into(), "www.example.com".into()),
+            ]
+        );
+        let second = [
+            0x82, 0x86, 0x84, 0xbe, 0x58, 0x86, 0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbf,
+        ];
+        assert_eq!(
+            fields(decoder.decode(&second).unwrap().unwrap())[3..],
+            vec![
+                (":authority".into(), "www.example.com".into()),
+                ("cache-control".into(), "no-cache".into()),
+            ]
+        );
+        let third = [
+            0x82, 0x87, 0x85, 0xbf, 0x40, 0x88, 0x25, 0xa8, 0x49, 0xe9, 0x5b, 0xa9, 0x7d, 0x7f,
+            0x89, 0x25, 0xa8, 0x49, 0xe9, 0x5b, 0xb8, 0xe8, 0xb4, 0xbf,
+        ];
+        assert_eq!(
+            fields(decoder.decode(&third).unwrap().unwrap())[3..],
+            vec![
+                (":authority".into(), "www.example.com".into()),
+                ("custom-key".into(), "custom-value".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn encoded_blocks_decode_back() {
+        let sent = vec![
+            (":status", "200"),
+            ("content-type", "text/plain"),
+            ("x-request-id", "abc"),
+        ];
+        let block = encode(sent.clone());
+        let decoded = fields(Decoder::new(4096).decode(&block).unwrap().unwrap());
+        let expected = sent
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn blocks_over_the_list_limit_still_update_the_table() {
+        // a literal indexed under custom-key, then the entry it added
+        let mut block = vec![0x40];
+        super::encode_string(b"custom-key", &mut block);
+        super::encode_string(b"custom-value", &mut block);
+        let mut decoder = Decoder::new(4096).list_limit(60);
+        assert_eq!(decoder.decode(&block).unwrap().map(|f| f.len()), Some(1));
+        assert_eq!(decoder.decode(&[0xbe, 0xbe]).unwrap(), None);
+        assert_eq!(
+            fields(decoder.decode(&[0xbe]).unwrap().unwrap()),
+            vec![("custom-key".into(), "custom-value".into())]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_blocks() {
+        assert!(Decoder::new(4096).decode(&[0x80]).is_err());
+        assert!(Decoder::new(4096).decode(&[0xbe]).is_err());
+        assert!(Decoder::new(100).decode(&[0x3f, 0xe1, 0x1f]).is_err());
+        assert!(Decoder::new(4096).decode(&[0x41, 0x85, 0x61]).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use {crate::http::h2::hpack::Error, std::collections::VecDeque};
+
+/// Static table, see RFC 7541 appendix A, addressed from index 1
+pub const STATIC: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Per entry overhead counted towards the table size
+const ENTRY_OVERHEAD: usize = 32;
+
+/// Dynamic table shared by every header block of one direction of a connection
+#[derive(Debug, Default)]
+pub struct Dynamic {
+    /// Newest entry first
+    entries: VecDeque<(Vec<u8>, Vec<u8>)>,
+    size: usize,
+    max_size: usize,
+}
+impl Dynamic {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            ..Self::default()
+        }
+    }
+    /// Looks up `index` across the static table followed by the dynamic table
+    pub fn get(&self, index: usize) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        match index {
+            0 => Err(Error::Index(index)),
+            1..=61 => {
+                let (name, value) = STATIC[index - 1];
+                Ok((name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            }
+            _ => self
+                .entries
+                .get(index - STATIC.len() - 1)
+                .cloned()
+                .ok_or(Error::Index(index)),
+        }
+    }
+    /// Adds an entry, evicting the oldest entries to stay within the size limit
+    pub fn insert(&mut self, name: Vec<u8>, value: Vec<u8>) {
+        let entry_size = name.len() + value.len() + ENTRY_OVERHEAD;
+        self.size += entry_size;
+        self.entries.push_front((name, value));
+        self.evict();
+    }
+    /// Changes the size limit, evicting entries if it shrinks
+    pub fn resize(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict();
+    }
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            match self.entries.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + ENTRY_OVERHEAD,
+                None => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,991 @@
+/// Module to handle HTTP/2 error codes and errors
+pub(crate) mod error;
+
+/// Module to handle frames
+pub(crate) mod frame;
+
+/// Module to handle header compression
+pub(crate) mod hpack;
+
+/// Module to handle connection settings
+pub(crate) mod settings;
+
+use {
+    crate::http::{
+        base64,
+        body::{Producer, Sink},
+        connection::{self, Socket, Timeouts},
+        header,
+        request::{self, id},
+        response,
+        router::Router,
+        HeaderMap, Request, Response, Version,
+    },
+    error::{Code, Error},
+    frame::{flags, Frame, Kind},
+    settings::{Settings, MAX_WINDOW_SIZE},
+    std::{
+        collections::BTreeMap,
+        io::{self, Read, Write},
+        str::FromStr,
+        sync::{
+            mpsc::{self, Receiver, SyncSender, TryRecvError},
+            Arc,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+/// Connection preface every HTTP/2 client opens with
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Fields specific to an HTTP/1.1 connection, which are malformed in HTTP/2
+const CONNECTION_SPECIFIC: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Size of each read from the connection
+const READ_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Chunks a producer may get ahead of its stream before it blocks
+const PRODUCED_CHUNKS: usize = 4;
+
+/// Time reads wait for the client before the producers are looked at again
+const PRODUCER_TICK: Duration = Duration::from_millis(20);
+
+/// Returns the decoded HTTP2-Settings of a request asking to upgrade to h2c
+///
+/// The request must be HTTP/1.1, carry `Upgrade: h2c`, exactly one valid
+/// HTTP2-Settings field, and list both in its Connection field.
+pub fn h2c_settings(request: &Request) -> Option<Vec<u8>> {
+    let headers = request.headers();
+    let mut settings = headers.get_all("HTTP2-Settings");
+    let upgrade = request.start_line.version.eq(&Version::HTTP_1_1)
+        && headers.has_token("Upgrade", "h2c")
+        && headers.has_token("Connection", "Upgrade")
+        && headers.has_token("Connection", "HTTP2-Settings");
+    match (upgrade, settings.next(), settings.next()) {
+        (true, Some(encoded), None) => {
+            let decoded = base64::decode(encoded)?;
+            Settings::default().apply(&decoded).ok().map(|_| decoded)
+        }
+        _ => None,
+    }
+}
+
+/// Stream states, closed streams are dropped from the connection
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum State {
+    Open,
+    HalfClosedRemote,
+}
+
+/// A single request and response exchange
+struct Stream {
+    state: State,
+    /// Request fields, `None` until the first header block is complete
+    fields: Option<Vec<hpack::Field>>,
+    body: Vec<u8>,
+    send_window: i64,
+    recv_window: i64,
+    /// Response body waiting on flow control, `None` until the handler has run
+    pending: Option<Vec<u8>>,
+    /// Chunks of a streamed body still being produced
+    producer: Option<Receiver<Produced>>,
+}
+impl Stream {
+    fn new(state: State, send_window: u32, recv_window: u32) -> Self {
+        Self {
+            state,
+            fields: None,
+            body: vec![],
+            send_window: send_window as i64,
+            recv_window: recv_window as i64,
+            pending: None,
+            producer: None,
+        }
+    }
+}
+
+/// A header block spread over a HEADERS frame and its CONTINUATION frames
+struct Continuation {
+    stream_id: u32,
+    block: Vec<u8>,
+    end_stream: bool,
+}
+
+/// Server side of an HTTP/2 connection, see RFC 9113
+///
+/// Frames are read and answered on the calling thread. Requests are handed
+/// to the same handler as HTTP/1.1 as soon as their stream is half closed,
+/// and response bodies are sent as flow control allows. Streamed bodies are
+/// produced on a thread of their own, their chunks being taken in between
+/// frames, so that they hold up neither the other streams nor themselves.
+pub struct Connection<S> {
+    stream: S,
+    /// Bytes read but not yet parsed into frames
+    buffer: Vec<u8>,
+    /// Frames encoded but not yet written
+    outgoing: Vec<u8>,
+    decoder: hpack::Decoder,
+    local: Settings,
+    peer: Settings,
+    streams: BTreeMap<u32, Stream>,
+    /// Highest stream id the client has opened
+    last_stream_id: u32,
+    send_window: i64,
+    recv_window: i64,
+    continuation: Option<Continuation>,
+    going_away: bool,
+    router: Arc<Router>,
+    timeouts: Timeouts,
+}
+impl<S: Read + Write + Socket> Connection<S> {
+    /// Takes over `stream`, with `buffer` holding any bytes already read off it
+    ///
+    /// The connection is closed once it has no open stream for the idle
+    /// timeout, or open streams without a frame for the body timeout.
+    pub fn new(stream: S, buffer: Vec<u8>, router: Arc<Router>, timeouts: Timeouts) -> Self {
+        let local = Settings::local();
+        let peer = Settings::default();
+        Self {
+            stream,
+            buffer,
+            outgoing: vec![],
+            decoder: hpack::Decoder::new(local.header_table_size as usize)
+                .list_limit(local.max_header_list_size.unwrap_or(u32::MAX) as usize),
+            local,
+            peer,
+            streams: BTreeMap::new(),
+            last_stream_id: 0,
+            send_window: peer.initial_window_size as i64,
+            recv_window: local.initial_window_size as i64,
+            continuation: None,
+            going_away: false,
+            router,
+            timeouts,
+        }
+    }
+    /// Serves streams until the client closes the connection or an error ends it
+    ///
+    /// `upgraded` carries the request and HTTP2-Settings payload of an h2c
+    /// upgrade, whose response is sent on stream 1.
+    pub fn serve(mut self, upgraded: Option<(Request, Vec<u8>)>) -> io::Result<()> {
+        log_from_mod!("serving HTTP/2 connection");
+        self.queue(Frame::new(Kind::Settings, 0, 0, self.local.encode()));
+        if let Some((mut request, settings)) = upgraded {
+            if let Err(e) = self.peer.apply(&settings) {
+                return self.fail(e);
+            }
+            self.send_window = self.peer.initial_window_size as i64;
+            self.last_stream_id = 1;
+            let stream = Stream::new(
+                State::HalfClosedRemote,
+                self.peer.initial_window_size,
+                self.local.initial_window_size,
+            );
+            self.streams.insert(1, stream);
+            request.start_line.version = Version::HTTP_2;
+            if let Err(e) = self.respond(1, request) {
+                return self.fail(e);
+            }
+        }
+        self.flush()?;
+        if !self.read_preface()? {
+            let error = Error::Connection(Code::ProtocolError, String::from("invalid preface"));
+            return self.fail(error);
+        }
+        let mut settings_received = false;
+        let mut last_frame = Instant::now();
+        loop {
+            let stopping = match self.streams.is_empty() {
+                true => crate::shutdown::idle(),
+                false => crate::shutdown::requested(),
+            };
+            if stopping && !self.going_away {
+                self.going_away = true;
+                let message = "server stopping";
+                self.queue(Frame::go_away(self.last_stream_id, Code::NoError, message));
+            }
+            self.flush()?;
+            if self.going_away && self.streams.is_empty() {
+                log_from_mod!("HTTP/2 connection going away");
+                return Ok(());
+            }
+            // streams only being produced owe the client nothing
+            let timeout = match self.streams.is_empty() && self.continuation.is_none() {
+                true => Some(self.timeouts.idle),
+                false if self.waiting_on_client() => Some(self.timeouts.body),
+                false => None,
+            };
+            let producing = self
+                .streams
+                .values()
+                .any(|stream| stream.producer.is_some());
+            let now = Instant::now();
+            let remaining =
+                timeout.map(|timeout| (last_frame + timeout).saturating_duration_since(now));
+            let mut wait = remaining.unwrap_or(self.timeouts.body);
+            if producing {
+                wait = wait.min(PRODUCER_TICK);
+            }
+            let read = match wait.is_zero() {
+                true => Err(io::Error::from(io::ErrorKind::TimedOut)),
+                false => self
+                    .stream
+                    .set_read_timeout(Some(wait))
+                    .and_then(|()| self.read_frame()),
+            };
+            let frame = match read {
+                Err(e) if connection::is_timeout(&e) && producing && !wait.is_zero() => continue,
+                Err(e) if connection::is_timeout(&e) => {
+                    let error = Error::Connection(Code::NoError, String::from("timed out"));
+                    return self.fail(error);
+                }
+                read => read?,
+            };
+            let frame = match frame {
+                Some(Ok(frame)) => {
+                    crate::shutdown::busy();
+                    last_frame = Instant::now();
+                    frame
+                }
+                Some(Err(e)) => return self.fail(e),
+                None => {
+                    log_from_mod!("HTTP/2 connection closed by client");
+                    return Ok(());
+                }
+            };
+            if !settings_received {
+                if frame.kind.ne(&Kind::Settings) || frame.has_flag(flags::ACK) {
+                    let message = String::from("first frame is not SETTINGS");
+                    return self.fail(Error::Connection(Code::ProtocolError, message));
+                }
+                settings_received = true;
+            }
+            match self.handle_frame(frame) {
+                Ok(()) => (),
+                Err(Error::Stream(stream_id, code, message)) => {
+                    elog_from_mod!("resetting stream", message);
+                    self.streams.remove(&stream_id);
+                    self.queue(Frame::rst_stream(stream_id, code));
+                }
+                Err(e) => return self.fail(e),
+            }
+        }
+    }
+    /// Ends the connection with a GOAWAY frame carrying the error
+    fn fail(mut self, error: Error) -> io::Result<()> {
+        elog_from_mod!("closing HTTP/2 connection", error);
+        let (code, message) = match &error {
+            Error::Connection(code, message) => (*code, message.clone()),
+            Error::Stream(_, code, message) => (*code, message.clone()),
+        };
+        self.queue(Frame::go_away(self.last_stream_id, code, &message));
+        self.write_outgoing()
+    }
+    /// Reads until the buffer holds the preface, returning false on a mismatch
+    fn read_preface(&mut self) -> io::Result<bool> {
+        while self.buffer.len() < PREFACE.len() && PREFACE.starts_with(&self.buffer) {
+            if !self.fill()? {
+                return Ok(false);
+            }
+        }
+        if self.buffer.starts_with(PREFACE) {
+            self.buffer.drain(..PREFACE.len());
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+    /// Reads the next frame, returning `Ok(None)` once the client closed the connection
+    fn read_frame(&mut self) -> io::Result<Option<Result<Frame, Error>>> {
+        loop {
+            match Frame::parse(&self.buffer, self.local.max_frame_size) {
+                Ok(Some((frame, consumed))) => {
+                    self.buffer.drain(..consumed);
+                    return Ok(Some(Ok(frame)));
+                }
+                Ok(None) => (),
+                Err(e) => return Ok(Some(Err(e))),
+            }
+            if !self.fill()? {
+                return Ok(None);
+            }
+        }
+    }
+    /// Reads more bytes into the buffer, returning false at end of stream
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        let bytes_read = self.stream.read(&mut chunk)?;
+        self.buffer.extend_from_slice(&chunk[..bytes_read]);
+        Ok(bytes_read > 0)
+    }
+    fn queue(&mut self, frame: Frame) {
+        self.outgoing.extend_from_slice(&frame.encode());
+    }
+    fn write_outgoing(&mut self) -> io::Result<()> {
+        if !self.outgoing.is_empty() {
+            self.stream.write_all(&self.outgoing)?;
+            self.outgoing.clear();
+        }
+        self.stream.flush()
+    }
+    /// Sends as much pending response data as flow control allows, then writes everything queued
+    ///
+    /// Produced chunks are taken in as long as less than a frame is pending,
+    /// and streams whose producer failed are cancelled.
+    fn flush(&mut self) -> io::Result<()> {
+        let max_frame_size = self.peer.max_frame_size as i64;
+        let mut finished = vec![];
+        let mut cancelled = vec![];
+        let mut frames = vec![];
+        for (stream_id, stream) in self.streams.iter_mut() {
+            let pending = match stream.pending.as_mut() {
+                Some(pending) => pending,
+                None => continue,
+            };
+            while let Some(producer) = &stream.producer {
+                if pending.len() >= max_frame_size as usize {
+                    break;
+                }
+                match producer.try_recv() {
+                    Ok(Produced::Chunk(chunk)) => pending.extend_from_slice(&chunk),
+                    Ok(Produced::End(Ok(()))) => stream.producer = None,
+                    Ok(Produced::End(Err(e))) => {
+                        elog_from_mod!("streamed body ended early", e);
+                        cancelled.push(*stream_id);
+                        break;
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        elog_from_mod!("streamed body ended early, producer stopped");
+                        cancelled.push(*stream_id);
+                        break;
+                    }
+                }
+            }
+            if cancelled.contains(stream_id) {
+                continue;
+            }
+            let producing = stream.producer.is_some();
+            while !pending.is_empty() && self.send_window > 0 && stream.send_window > 0 {
+                let allowed = self.send_window.min(stream.send_window).min(max_frame_size) as usize;
+                let chunk = pending
+                    .drain(..allowed.min(pending.len()))
+                    .collect::<Vec<u8>>();
+                self.send_window -= chunk.len() as i64;
+                stream.send_window -= chunk.len() as i64;
+                let end_stream = if pending.is_empty() && !producing {
+                    finished.push(*stream_id);
+                    flags::END_STREAM
+                } else {
+                    0
+                };
+                frames.push(Frame::new(Kind::Data, end_stream, *stream_id, chunk));
+            }
+            // a body produced after its last chunk was sent ends with an empty frame
+            if pending.is_empty() && !producing && !finished.contains(stream_id) {
+                finished.push(*stream_id);
+                frames.push(Frame::new(
+                    Kind::Data,
+                    flags::END_STREAM,
+                    *stream_id,
+                    vec![],
+                ));
+            }
+        }
+        frames.into_iter().for_each(|frame| self.queue(frame));
+        finished.iter().for_each(|stream_id| {
+            self.streams.remove(stream_id);
+        });
+        cancelled.into_iter().for_each(|stream_id| {
+            self.streams.remove(&stream_id);
+            self.queue(Frame::rst_stream(stream_id, Code::Cancel));
+        });
+        self.write_outgoing()
+    }
+    /// Returns true if a stream can only move on once the client sends
+    /// more, the rest of its request or a window update
+    fn waiting_on_client(&self) -> bool {
+        self.streams.values().any(|stream| {
+            let blocked = (self.send_window <= 0 || stream.send_window <= 0)
+                && stream
+                    .pending
+                    .as_ref()
+                    .is_some_and(|pending| !pending.is_empty());
+            stream.state.eq(&State::Open) || blocked
+        })
+    }
+    fn handle_frame(&mut self, frame: Frame) -> Result<(), Error> {
+        if let Some(continuation) = &self.continuation {
+            if frame.kind.ne(&Kind::Continuation) || frame.stream_id.ne(&continuation.stream_id) {
+                let message = String::from("expected CONTINUATION");
+                return Err(Error::Connection(Code::ProtocolError, message));
+            }
+        }
+        let connection_frame = matches!(frame.kind, Kind::Settings | Kind::Ping | Kind::GoAway);
+        // WINDOW_UPDATE is valid on any stream, unknown frames are ignored on any
+        let any_stream = matches!(frame.kind, Kind::WindowUpdate | Kind::Unknown(_));
+        if !any_stream && connection_frame.ne(&(frame.stream_id == 0)) {
+            let message = format!("{:?} frame on stream {}", frame.kind, frame.stream_id);
+            return Err(Error::Connection(Code::ProtocolError, message));
+        }
+        match frame.kind {
+            Kind::Data => self.on_data(frame),
+            Kind::Headers => self.on_headers(frame),
+            Kind::Continuation => self.on_continuation(frame),
+            Kind::Priority if frame.payload.len() != 5 => Err(Error::Stream(
+                frame.stream_id,
+                Code::FrameSizeError,
+                String::from("PRIORITY frame size"),
+            )),
+            Kind::Priority => Ok(()),
+            Kind::RstStream if frame.payload.len() != 4 => Err(Error::Connection(
+                Code::FrameSizeError,
+                String::from("RST_STREAM frame size"),
+            )),
+            Kind::RstStream => {
+                self.check_not_idle(frame.stream_id)?;
+                self.streams.remove(&frame.stream_id);
+                Ok(())
+            }
+            Kind::Settings => self.on_settings(frame),
+            Kind::PushPromise => Err(Error::Connection(
+                Code::ProtocolError,
+                String::from("PUSH_PROMISE from client"),
+            )),
+            Kind::Ping if frame.payload.len() != 8 => Err(Error::Connection(
+                Code::FrameSizeError,
+                String::from("PING frame size"),
+            )),
+            Kind::Ping if frame.has_flag(flags::ACK) => Ok(()),
+            Kind::Ping => {
+                self.queue(Frame::new(Kind::Ping, flags::ACK, 0, frame.payload));
+                Ok(())
+            }
+            Kind::GoAway => {
+                log_from_mod!("client sent GOAWAY");
+                self.going_away = true;
+                Ok(())
+            }
+            Kind::WindowUpdate => self.on_window_update(frame),
+            Kind::Unknown(_) => Ok(()),
+        }
+    }
+    /// Frames other than HEADERS must not open streams
+    fn check_not_idle(&self, stream_id: u32) -> Result<(), Error> {
+        if stream_id > self.last_stream_id {
+            let message = format!("frame on idle stream {}", stream_id);
+            Err(Error::Connection(Code::ProtocolError, message))
+        } else {
+            Ok(())
+        }
+    }
+    fn on_headers(&mut self, frame: Frame) -> Result<(), Error> {
+        let stream_id = frame.stream_id;
+        let mut block = frame.unpadded()?;
+        if frame.has_flag(flags::PRIORITY) {
+            block = block.get(5..).ok_or_else(|| {
+                Error::Connection(Code::FrameSizeError, String::from("HEADERS priority"))
+            })?;
+        }
+        match self.streams.get(&stream_id) {
+            Some(stream) if stream.state.eq(&State::HalfClosedRemote) => {
+                let message = String::from("HEADERS on half closed stream");
+                return Err(Error::Stream(stream_id, Code::StreamClosed, message));
+            }
+            // trailers close the stream
+            Some(_) if !frame.has_flag(flags::END_STREAM) => {
+                let message = String::from("trailers without END_STREAM");
+                return Err(Error::Stream(stream_id, Code::ProtocolError, message));
+            }
+            Some(_) => (),
+            None if stream_id.is_multiple_of(2) || stream_id <= self.last_stream_id => {
+                let message = format!("HEADERS opening stream {}", stream_id);
+                return Err(Error::Connection(Code::ProtocolError, message));
+            }
+            None => {
+                self.last_stream_id = stream_id;
+                let open_streams = self.streams.len() as u32;
+                // refused streams are not tracked, but their block is still decoded
+                if !self.going_away
+                    && open_streams < self.local.max_concurrent_streams.unwrap_or(u32::MAX)
+                {
+                    let stream = Stream::new(
+                        State::Open,
+                        self.peer.initial_window_size,
+                        self.local.initial_window_size,
+                    );
+                    self.streams.insert(stream_id, stream);
+                }
+            }
+        }
+        let continuation = Continuation {
+            stream_id,
+            block: block.to_vec(),
+            end_stream: frame.has_flag(flags::END_STREAM),
+        };
+        if frame.has_flag(flags::END_HEADERS) {
+            self.on_header_block(continuation)
+        } else {
+            self.continuation = Some(continuation);
+            Ok(())
+        }
+    }
+    fn on_continuation(&mut self, frame: Frame) -> Result<(), Error> {
+        let mut continuation = self.continuation.take().ok_or_else(|| {
+            Error::Connection(Code::ProtocolError, String::from("unexpected CONTINUATION"))
+        })?;
+        continuation.block.extend_from_slice(&frame.payload);
+        // the block is decoded whole, so its size is capped before it is complete
+        let max_block_size = self.local.max_header_list_size.unwrap_or(u32::MAX) as usize;
+        if continuation.block.len() > max_block_size {
+            let message = format!("header block larger than {} bytes", max_block_size);
+            return Err(Error::Connection(Code::EnhanceYourCalm, message));
+        }
+        if frame.has_flag(flags::END_HEADERS) {
+            self.on_header_block(continuation)
+        } else {
+            self.continuation = Some(continuation);
+            Ok(())
+        }
+    }
+    fn on_header_block(&mut self, continuation: Continuation) -> Result<(), Error> {
+        let stream_id = continuation.stream_id;
+        let fields = self.decoder.decode(&continuation.block)?;
+        let stream = self.streams.get_mut(&stream_id).ok_or_else(|| {
+            let message = String::from("too many concurrent streams");
+            Error::Stream(stream_id, Code::RefusedStream, message)
+        })?;
+        let Some(fields) = fields else {
+            elog_from_mod!("rejecting header list over the advertised size");
+            return self.reject(stream_id, response::Status::RequestHeaderFieldsTooLarge);
+        };
+        // a second block holds trailers, which are dropped
+        if stream.fields.is_none() {
+            let max_body_size = self.router.max_body_size();
+            if declared_length(&fields).is_some_and(|length| length > max_body_size) {
+                elog_from_mod!("rejecting body over", max_body_size);
+                return self.reject(stream_id, response::Status::ContentTooLarge);
+            }
+            stream.fields = Some(fields);
+        }
+        if continuation.end_stream {
+            self.dispatch(stream_id)
+        } else {
+            Ok(())
+        }
+    }
+    /// Takes in the data of a stream, see RFC 9113 section 6.9
+    ///
+    /// Credit is only given back once the data is consumed, taken into the
+    /// body of the stream or dropped along with a stream that is gone, and a
+    /// stream is never credited past what its body may still take.
+    fn on_data(&mut self, frame: Frame) -> Result<(), Error> {
+        let stream_id = frame.stream_id;
+        let length = frame.payload.len();
+        self.recv_window -= length as i64;
+        if self.recv_window < 0 {
+            let message = String::from("connection receive window exceeded");
+            return Err(Error::Connection(Code::FlowControlError, message));
+        }
+        let data = frame.unpadded()?;
+        self.check_not_idle(stream_id)?;
+        let stream = match self.streams.get_mut(&stream_id) {
+            Some(stream) if stream.state.eq(&State::Open) && stream.fields.is_some() => stream,
+            _ => {
+                self.release(0, length);
+                let message = String::from("DATA on closed stream");
+                return Err(Error::Stream(stream_id, Code::StreamClosed, message));
+            }
+        };
+        stream.recv_window -= length as i64;
+        if stream.recv_window < 0 {
+            self.release(0, length);
+            let message = String::from("stream receive window exceeded");
+            return Err(Error::Stream(stream_id, Code::FlowControlError, message));
+        }
+        let max_body_size = self.router.max_body_size();
+        if stream.body.len().saturating_add(data.len()) > max_body_size {
+            self.release(0, length);
+            elog_from_mod!("rejecting body over", max_body_size);
+            return self.reject(stream_id, response::Status::ContentTooLarge);
+        }
+        stream.body.extend_from_slice(data);
+        // the window never reaches past the room left in the body
+        let room = (max_body_size - stream.body.len()) as i64;
+        let credit = (length as i64).min(room - stream.recv_window).max(0);
+        stream.recv_window += credit;
+        self.release(0, length);
+        if frame.has_flag(flags::END_STREAM) {
+            self.dispatch(stream_id)
+        } else {
+            self.release(stream_id, credit as usize);
+            Ok(())
+        }
+    }
+    /// Gives `length` bytes of receive window back to the client, on the
+    /// connection for stream 0
+    fn release(&mut self, stream_id: u32, length: usize) {
+        if length == 0 {
+            return;
+        }
+        if stream_id == 0 {
+            self.recv_window += length as i64;
+        }
+        self.queue(Frame::window_update(stream_id, length as u32));
+    }
+    fn on_settings(&mut self, frame: Frame) -> Result<(), Error> {
+        if frame.has_flag(flags::ACK) {
+            return match frame.payload.len() {
+                0 => Ok(()),
+                _ => Err(Error::Connection(
+                    Code::FrameSizeError,
+                    String::from("SETTINGS ACK with payload"),
+                )),
+            };
+        }
+        let previous_window = self.peer.initial_window_size as i64;
+        self.peer.apply(&frame.payload)?;
+        let delta = self.peer.initial_window_size as i64 - previous_window;
+        for stream in self.streams.values_mut() {
+            stream.send_window += delta;
+            if stream.send_window > MAX_WINDOW_SIZE as i64 {
+                let message = String::from("initial window size overflows a stream window");
+                return Err(Error::Connection(Code::FlowControlError, message));
+            }
+        }
+        self.queue(Frame::new(Kind::Settings, flags::ACK, 0, vec![]));
+        Ok(())
+    }
+    fn on_window_update(&mut self, frame: Frame) -> Result<(), Error> {
+        let stream_id = frame.stream_id;
+        let payload = <[u8; 4]>::try_from(frame.payload.as_slice()).map_err(|_| {
+            Error::Connection(Code::FrameSizeError, String::from("WINDOW_UPDATE size"))
+        })?;
+        let increment = (u32::from_be_bytes(payload) & 0x7fff_ffff) as i64;
+        let overflow = String::from("window exceeds maximum");
+        if stream_id == 0 {
+            if increment == 0 {
+                let message = String::from("zero connection window increment");
+                return Err(Error::Connection(Code::ProtocolError, message));
+            }
+            self.send_window += increment;
+            if self.send_window > MAX_WINDOW_SIZE as i64 {
+                return Err(Error::Connection(Code::FlowControlError, overflow));
+            }
+            return Ok(());
+        }
+        self.check_not_idle(stream_id)?;
+        match self.streams.get_mut(&stream_id) {
+            Some(_) if increment == 0 => {
+                let message = String::from("zero stream window increment");
+                Err(Error::Stream(stream_id, Code::ProtocolError, message))
+            }
+            Some(stream) => {
+                stream.send_window += increment;
+                if stream.send_window > MAX_WINDOW_SIZE as i64 {
+                    Err(Error::Stream(stream_id, Code::FlowControlError, overflow))
+                } else {
+                    Ok(())
+                }
+            }
+            None => Ok(()),
+        }
+    }
+    /// Half closes a stream whose request is complete and responds to it
+    fn dispatch(&mut self, stream_id: u32) -> Result<(), Error> {
+        let (fields, body) = match self.streams.get_mut(&stream_id) {
+            Some(stream) => {
+                stream.state = State::HalfClosedRemote;
+                (
+                    stream.fields.take().unwrap_or_default(),
+                    std::mem::take(&mut stream.body),
+                )
+            }
+            None => return Ok(()),
+        };
+        let request = build_request(fields, body).map_err(|message| {
+            let _scope = id::enter(&id::generate());
+            elog_from_mod!("rejecting malformed request", message);
+            Error::Stream(stream_id, Code::ProtocolError, message)
+        })?;
+        self.respond(stream_id, request)
+    }
+    /// Runs the handler of the site `request` is for and answers the stream with its response
+    fn respond(&mut self, stream_id: u32, mut request: Request) -> Result<(), Error> {
+        request.peer = self.stream.peer_addr().ok().map(|address| address.ip());
+        request.secure = self.stream.is_secure();
+        let id = id::assign(&mut request);
+        let _scope = id::enter(&id);
+        let head_only = matches!(request.start_line.method, request::Method::Head);
+        let response = self
+            .router
+            .site(&request)
+            .respond(request)
+            .map_err(|e| Error::Stream(stream_id, Code::InternalError, e.to_string()))?;
+        self.send_response(stream_id, response, head_only)
+    }
+    /// Answers a stream whose request is still arriving with `status`, then
+    /// resets it so that the client stops sending
+    fn reject(&mut self, stream_id: u32, status: response::Status) -> Result<(), Error> {
+        self.send_response(stream_id, Response::empty(status), false)?;
+        self.streams.remove(&stream_id);
+        self.queue(Frame::rst_stream(stream_id, Code::NoError));
+        Ok(())
+    }
+    /// Queues the header block of `response`, leaving its body pending
+    ///
+    /// Streamed bodies start being produced, see [`Connection::stream_body`].
+    fn send_response(
+        &mut self,
+        stream_id: u32,
+        response: Response,
+        head_only: bool,
+    ) -> Result<(), Error> {
+        let (fields, mut body, mut producer) = split_response(response);
+        // HEAD is answered with the fields of the body, without it
+        if head_only {
+            (body, producer) = (vec![], None);
+        }
+        let block = hpack::encode(
+            fields
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        );
+        let max_frame_size = self.peer.max_frame_size as usize;
+        let mut fragments = block.chunks(max_frame_size).collect::<Vec<&[u8]>>();
+        if fragments.is_empty() {
+            fragments.push(&[]);
+        }
+        let last = fragments.len() - 1;
+        let mut frames = vec![];
+        for (i, fragment) in fragments.into_iter().enumerate() {
+            let mut frame_flags = if i == last { flags::END_HEADERS } else { 0 };
+            let kind = if i == 0 {
+                if body.is_empty() && producer.is_none() {
+                    frame_flags |= flags::END_STREAM;
+                }
+                Kind::Headers
+            } else {
+                Kind::Continuation
+            };
+            frames.push(Frame::new(kind, frame_flags, stream_id, fragment.to_vec()));
+        }
+        frames.into_iter().for_each(|frame| self.queue(frame));
+        if let Some(producer) = producer {
+            return self.stream_body(stream_id, producer);
+        }
+        if body.is_empty() {
+            self.streams.remove(&stream_id);
+        } else if let Some(stream) = self.streams.get_mut(&stream_id) {
+            stream.pending = Some(body);
+        }
+        Ok(())
+    }
+    /// Runs `producer` on a thread of its own, its chunks being sent as
+    /// DATA frames as they come in and flow control allows
+    ///
+    /// The producer learns that the client reset the stream, or the
+    /// connection ended, when sending its next chunk fails.
+    fn stream_body(&mut self, stream_id: u32, producer: Producer) -> Result<(), Error> {
+        let (sender, receiver) = mpsc::sync_channel(PRODUCED_CHUNKS);
+        let id = id::current();
+        std::thread::spawn(move || {
+            let _scope = id.as_deref().map(id::enter);
+            let result = producer(&mut ChannelSink(sender.clone()));
+            let _ = sender.send(Produced::End(result));
+        });
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            stream.pending = Some(vec![]);
+            stream.producer = Some(receiver);
+        }
+        Ok(())
+    }
+}
+
+/// What the thread running a producer hands the connection
+enum Produced {
+    Chunk(Vec<u8>),
+    /// The producer returned, with the error it gave up on if any
+    End(io::Result<()>),
+}
+
+/// Hands the chunks of a streamed body over to the connection
+struct ChannelSink(SyncSender<Produced>);
+impl Sink for ChannelSink {
+    fn send(&mut self, chunk: &[u8]) -> io::Result<()> {
+        if chunk.is_empty() {
+            return Ok(());
+        }
+        self.0
+            .send(Produced::Chunk(chunk.to_vec()))
+            .map_err(|_| io::Error::new(io::ErrorKind::ConnectionAborted, "stream closed"))
+    }
+}
+
+/// Maps header block octets onto a string one character per byte, as HTTP/1 parsing does
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| *b as char).collect()
+}
+
+/// Body length a decoded header block announces with content-length, if any
+fn declared_length(fields: &[hpack::Field]) -> Option<usize> {
+    fields
+        .iter()
+        .find(|(name, _)| name.eq(b"content-length"))
+        .and_then(|(_, value)| latin1(value).trim().parse().ok())
+}
+
+/// Builds a request out of a decoded header block, see RFC 9113 section 8.3.1
+fn build_request(fields: Vec<hpack::Field>, body: Vec<u8>) -> Result<Request, String> {
+    let mut method = None;
+    let mut scheme = None;
+    let mut path = None;
+    let mut authority = None;
+    let mut headers = HeaderMap::new();
+    for (name, value) in fields {
+        let (name, value) = (latin1(&name), latin1(&value));
+        if let Some(pseudo) = name.strip_prefix(':') {
+            let slot = match pseudo {
+                "method" => &mut method,
+                "scheme" => &mut scheme,
+                "path" => &mut path,
+                "authority" => &mut authority,
+                _ => return Err(format!("unknown pseudo header {}", name)),
+            };
+            if !headers.is_empty() || slot.is_some() {
+                return Err(format!("misplaced or repeated pseudo header {}", name));
+            }
+            *slot = Some(value);
+            continue;
+        }
+        header::validate_name(&name).map_err(|e| e.to_string())?;
+        header::validate_value(&name, &value).map_err(|e| e.to_string())?;
+        if name.bytes().any(|b| b.is_ascii_uppercase()) {
+            return Err(format!("uppercase field name {}", name));
+        }
+        if CONNECTION_SPECIFIC.contains(&name.as_str())
+            || (name.eq("te") && !value.eq_ignore_ascii_case("trailers"))
+        {
+            return Err(format!("connection specific field {}", name));
+        }
+        headers.append(name, value);
+    }
+    let (method, _scheme, path) = match (method, scheme, path) {
+        (Some(method), Some(scheme), Some(path)) if !path.is_empty() => (method, scheme, path),
+        _ => return Err(String::from("missing pseudo headers")),
+    };
+    let method = request::Method::from_str(&method).map_err(|e| e.to_string())?;
+    if let (Some(authority), false) = (authority, headers.contains("host")) {
+        headers.append("host", authority);
+    }
+    match headers.try_typed::<header::typed::ContentLength>() {
+        Ok(Some(header::typed::ContentLength(length))) if length != body.len() => {
+            return Err(String::from("content-length does not match body"))
+        }
+        Err(e) => return Err(e.to_string()),
+        _ => (),
+    }
+    Ok(Request {
+        start_line: request::Startline {
+            method,
+            target: request::Target::parse(&path),
+            version: Version::HTTP_2,
+        },
+        headers,
+        body: if body.is_empty() { None } else { Some(body) },
+        peer: None,
+        secure: false,
+        session: None,
+        source: None,
+    })
+}
+
+/// Splits a response into the fields of its header block, its body and the
+/// producer of a streamed body
+///
+/// Connection specific fields are dropped, and those derived from the body
+/// completed as they are for HTTP/1.x.
+fn split_response(mut response: Response) -> (Vec<(String, String)>, Vec<u8>, Option<Producer>) {
+    response.complete_headers();
+    let body = response.body.unwrap_or_default();
+    let status = (response.start_line.status as u16).to_string();
+    let mut fields = vec![(String::from(":status"), status)];
+    fields.extend(
+        response
+            .headers
+            .iter()
+            .map(|field| (field.name.to_ascii_lowercase(), field.value.clone()))
+            .filter(|(name, _)| !CONNECTION_SPECIFIC.contains(&name.as_str())),
+    );
+    (fields, body, response.stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_request, h2c_settings};
+    use crate::http::{Request, Version};
+
+    fn field(name: &str, value: &str) -> (Vec<u8>, Vec<u8>) {
+        (name.as_bytes().to_vec(), value.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn pseudo_headers_build_a_request() {
+        let fields = vec![
+            field(":method", "GET"),
+            field(":scheme", "http"),
+            field(":path", "/echo/abc"),
+            field(":authority", "example.com"),
+            field("user-agent", "curl/8.0"),
+        ];
+        let request = build_request(fields, vec![]).unwrap();
+        assert_eq!(request.start_line.target.path, "/echo/abc");
+        assert_eq!(request.start_line.version, Version::HTTP_2);
+        assert_eq!(request.headers().get("host"), Some("example.com"));
+    }
+
+    #[test]
+    fn malformed_header_blocks_are_rejected() {
+        let base = || {
+            vec![
+                field(":method", "GET"),
+                field(":scheme", "http"),
+                field(":path", "/"),
+            ]
+        };
+        assert!(build_request(base()[..2].to_vec(), vec![]).is_err());
+        for extra in [
+            field("Host", "a"),
+            field("connection", "keep-alive"),
+            field("te", "gzip"),
+            field(":status", "200"),
+        ] {
+            let mut fields = base();
+            fields.push(extra);
+            assert!(build_request(fields, vec![]).is_err());
+        }
+        let mut fields = base();
+        fields.push(field("content-length", "3"));
+        assert!(build_request(fields, b"ab".to_vec()).is_err());
+    }
+
+    #[test]
+    fn h2c_upgrade_requires_every_field() {
+        let upgrade = "GET / HTTP/1.1\r\nHost: a\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAoAAAAAIAAAAA\r\n\r\n";
+        let request = Request::try_construct(upgrade.as_bytes()).unwrap();
+        assert_eq!(
+            h2c_settings(&request).map(|settings| settings.len()),
+            Some(18)
+        );
+        let missing = upgrade.replace("Connection: Upgrade, HTTP2-Settings", "Connection: Upgrade");
+        let request = Request::try_construct(missing.as_bytes()).unwrap();
+        assert!(h2c_settings(&request).is_none());
+    }
+}
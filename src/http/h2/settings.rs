@@ -0,0 +1,134 @@
+use crate::http::h2::error::{Code, Error};
+
+/// Largest flow control window allowed
+pub const MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+
+/// Connection settings of one endpoint, see RFC 9113 section 6.5.2
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Settings {
+    pub header_table_size: u32,
+    pub enable_push: bool,
+    pub max_concurrent_streams: Option<u32>,
+    pub initial_window_size: u32,
+    pub max_frame_size: u32,
+    pub max_header_list_size: Option<u32>,
+}
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            header_table_size: 4096,
+            enable_push: true,
+            max_concurrent_streams: None,
+            initial_window_size: 65535,
+            max_frame_size: 16384,
+            max_header_list_size: None,
+        }
+    }
+}
+impl Settings {
+    /// Settings this server advertises
+    pub fn local() -> Self {
+        Self {
+            enable_push: false,
+            max_concurrent_streams: Some(100),
+            max_header_list_size: Some(16384),
+            ..Self::default()
+        }
+    }
+    /// Applies the parameters of a SETTINGS payload on top of these settings
+    ///
+    /// Unknown parameters are ignored.
+    pub fn apply(&mut self, payload: &[u8]) -> Result<(), Error> {
+        if !payload.len().is_multiple_of(6) {
+            return Err(Error::Connection(
+                Code::FrameSizeError,
+                format!("settings payload of {} bytes", payload.len()),
+            ));
+        }
+        for parameter in payload.chunks(6) {
+            let identifier = u16::from_be_bytes([parameter[0], parameter[1]]);
+            let value =
+                u32::from_be_bytes([parameter[2], parameter[3], parameter[4], parameter[5]]);
+            match identifier {
+                0x1 => self.header_table_size = value,
+                0x2 if value <= 1 => self.enable_push = value == 1,
+                0x2 => {
+                    return Err(Error::Connection(
+                        Code::ProtocolError,
+                        format!("enable push of {}", value),
+                    ))
+                }
+                0x3 => self.max_concurrent_streams = Some(value),
+                0x4 if value <= MAX_WINDOW_SIZE => self.initial_window_size = value,
+                0x4 => {
+                    return Err(Error::Connection(
+                        Code::FlowControlError,
+                        format!("initial window size of {}", value),
+                    ))
+                }
+                0x5 if (16384..=16_777_215).contains(&value) => self.max_frame_size = value,
+                0x5 => {
+                    return Err(Error::Connection(
+                        Code::ProtocolError,
+                        format!("max frame size of {}", value),
+                    ))
+                }
+                0x6 => self.max_header_list_size = Some(value),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+    /// Encodes the parameters that differ from the protocol defaults
+    pub fn encode(&self) -> Vec<u8> {
+        let defaults = Self::default();
+        let mut parameters = vec![];
+        if self.header_table_size != defaults.header_table_size {
+            parameters.push((0x1u16, self.header_table_size));
+        }
+        if self.enable_push != defaults.enable_push {
+            parameters.push((0x2, self.enable_push as u32));
+        }
+        if let Some(max_concurrent_streams) = self.max_concurrent_streams {
+            parameters.push((0x3, max_concurrent_streams));
+        }
+        if self.initial_window_size != defaults.initial_window_size {
+            parameters.push((0x4, self.initial_window_size));
+        }
+        if self.max_frame_size != defaults.max_frame_size {
+            parameters.push((0x5, self.max_frame_size));
+        }
+        if let Some(max_header_list_size) = self.max_header_list_size {
+            parameters.push((0x6, max_header_list_size));
+        }
+        parameters
+            .into_iter()
+            .flat_map(|(identifier, value)| {
+                let mut parameter = identifier.to_be_bytes().to_vec();
+                parameter.extend_from_slice(&value.to_be_bytes());
+                parameter
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Settings;
+
+    #[test]
+    fn round_trip() {
+        let mut settings = Settings::default();
+        settings.apply(&Settings::local().encode()).unwrap();
+        assert_eq!(settings, Settings::local());
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let mut settings = Settings::default();
+        assert!(settings.apply(&[0, 2, 0, 0, 0, 2]).is_err());
+        assert!(settings.apply(&[0, 4, 0x80, 0, 0, 0]).is_err());
+        assert!(settings.apply(&[0, 5, 0, 0, 0, 1]).is_err());
+        assert!(settings.apply(&[0, 5, 0, 0]).is_err());
+    }
+}
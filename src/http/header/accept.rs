@@ -0,0 +1,341 @@
+//! Media and language ranges of the Accept and Accept-Language headers, and
+//! negotiation against what a handler can send, see RFC 9110 section 12
+use {
+    crate::http::header::{content_type, typed, Error, HeaderMap, Kind},
+    std::fmt::{self, Display, Formatter},
+};
+
+/// Weight of a range, in thousandths: 1000 unless a `q` parameter says otherwise
+pub type Quality = u16;
+
+/// Parses a `q` parameter value, from `0` to `1` with at most three decimals
+fn quality(value: &str) -> Option<Quality> {
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let digits = fraction.len() <= 3 && fraction.bytes().all(|b| b.is_ascii_digit());
+    let thousandths = match (whole, digits) {
+        ("0", true) => format!("{:0<3}", fraction).parse::<Quality>().ok()?,
+        ("1", true) if fraction.bytes().all(|b| b == b'0') => 1000,
+        _ => return None,
+    };
+    Some(thousandths)
+}
+
+/// Formats a weight as a `q` parameter value, with trailing zeros dropped
+fn format_quality(quality: Quality) -> String {
+    match quality {
+        0 => String::from("0"),
+        1000.. => String::from("1"),
+        quality => format!("0.{:03}", quality)
+            .trim_end_matches('0')
+            .to_string(),
+    }
+}
+
+/// Leading value of a list element, its parameters and its weight
+type Element<'a> = (&'a str, Vec<(String, String)>, Quality);
+
+/// Splits a list header into its elements, each split into its leading value
+/// and its parameters
+///
+/// The weight is taken out of the parameters, along with any extension
+/// parameters following it.
+fn elements<'a>(
+    values: &'a str,
+    kind: impl Fn() -> Kind + 'a,
+) -> impl Iterator<Item = Result<Element<'a>, Error>> + 'a {
+    values
+        .split(',')
+        .map(str::trim)
+        .filter(|element| !element.is_empty())
+        .map(move |element| {
+            let mut parts = element.split(';').map(str::trim);
+            let leading = parts.next().unwrap_or_default();
+            let mut parameters = vec![];
+            for parameter in parts {
+                let (name, value) = parameter.split_once('=').ok_or_else(|| {
+                    Error::Parse(kind(), format!("parameter without value {}", parameter))
+                })?;
+                let name = name.trim().to_ascii_lowercase();
+                let value = value.trim().trim_matches('"');
+                if name.eq("q") {
+                    let weight = quality(value)
+                        .ok_or_else(|| Error::Parse(kind(), format!("invalid weight {}", value)))?;
+                    return Ok((leading, parameters, weight));
+                }
+                parameters.push((name, value.to_string()));
+            }
+            Ok((leading, parameters, 1000))
+        })
+}
+
+/// A media range like `text/*;q=0.5`, with type and subtype lowercased
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MediaRange {
+    pub kind: String,
+    pub subtype: String,
+    pub parameters: Vec<(String, String)>,
+    pub quality: Quality,
+}
+impl MediaRange {
+    /// Parses every range of an Accept value
+    pub fn parse_list(value: &str) -> Result<Vec<Self>, Error> {
+        let kind = || Kind::Accept(value.to_string());
+        elements(value, kind)
+            .map(|element| {
+                let (range, parameters, quality) = element?;
+                let (media_type, subtype) = range
+                    .split_once('/')
+                    .filter(|(media_type, subtype)| {
+                        super::validate_name(media_type).is_ok()
+                            && super::validate_name(subtype).is_ok()
+                            && (media_type.ne(&"*") || subtype.eq(&"*"))
+                    })
+                    .ok_or_else(|| {
+                        Error::Parse(kind(), format!("invalid media range {}", range))
+                    })?;
+                Ok(Self {
+                    kind: media_type.to_ascii_lowercase(),
+                    subtype: subtype.to_ascii_lowercase(),
+                    parameters,
+                    quality,
+                })
+            })
+            .collect()
+    }
+    /// How closely the range matches `media_type`, or `None` if it does not
+    ///
+    /// Offered types carry no parameters, so ranges with parameters are taken
+    /// to match them, only ranking as more specific.
+    fn specificity(&self, media_type: &str) -> Option<usize> {
+        let (kind, subtype) = media_type.split_once('/')?;
+        match (self.kind.as_str(), self.subtype.as_str()) {
+            ("*", "*") => Some(0),
+            (range_kind, "*") if range_kind.eq_ignore_ascii_case(kind) => Some(1),
+            (range_kind, range_subtype)
+                if range_kind.eq_ignore_ascii_case(kind)
+                    && range_subtype.eq_ignore_ascii_case(subtype) =>
+            {
+                Some(2 + self.parameters.len())
+            }
+            _ => None,
+        }
+    }
+}
+impl Display for MediaRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.kind, self.subtype)?;
+        self.parameters
+            .iter()
+            .try_for_each(|(name, value)| write!(f, ";{}={}", name, value))?;
+        match self.quality {
+            1000 => Ok(()),
+            quality => write!(f, ";q={}", format_quality(quality)),
+        }
+    }
+}
+
+/// A language range like `en-GB;q=0.8`, lowercased
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LanguageRange {
+    pub tag: String,
+    pub quality: Quality,
+}
+impl LanguageRange {
+    /// Parses every range of an Accept-Language value
+    pub fn parse_list(value: &str) -> Result<Vec<Self>, Error> {
+        let kind = || Kind::AcceptLanguage(value.to_string());
+        elements(value, kind)
+            .map(|element| {
+                let (tag, _, quality) = element?;
+                let valid = tag.eq("*")
+                    || tag.split('-').all(|subtag| {
+                        (1..=8).contains(&subtag.len())
+                            && subtag.bytes().all(|b| b.is_ascii_alphanumeric())
+                    });
+                match valid {
+                    true => Ok(Self {
+                        tag: tag.to_ascii_lowercase(),
+                        quality,
+                    }),
+                    false => Err(Error::Parse(
+                        kind(),
+                        format!("invalid language range {}", tag),
+                    )),
+                }
+            })
+            .collect()
+    }
+    /// How closely the range matches `tag`, or `None` if it does not
+    ///
+    /// A range matches the tags it is equal to or a prefix of, ending at a
+    /// subtag boundary, see RFC 4647 section 3.3.1.
+    fn specificity(&self, tag: &str) -> Option<usize> {
+        let tag = tag.to_ascii_lowercase();
+        match tag.strip_prefix(self.tag.as_str()) {
+            _ if self.tag.eq("*") => Some(0),
+            Some(rest) if rest.is_empty() || rest.starts_with('-') => Some(self.tag.len()),
+            _ => None,
+        }
+    }
+}
+impl Display for LanguageRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.quality {
+            1000 => write!(f, "{}", self.tag),
+            quality => write!(f, "{};q={}", self.tag, format_quality(quality)),
+        }
+    }
+}
+
+/// Picks the offer given the highest weight by the most specific range matching it
+///
+/// Offers are listed in the server's order of preference, which breaks ties.
+/// Offers no range matches, or matched with a weight of zero, are not acceptable.
+fn best<T: Copy>(offers: &[T], weight: impl Fn(T) -> Option<Quality>) -> Option<T> {
+    offers
+        .iter()
+        .filter_map(|offer| Some((*offer, weight(*offer).filter(|weight| *weight > 0)?)))
+        .fold(
+            None,
+            |best: Option<(T, Quality)>, (offer, weight)| match best {
+                Some((_, best_weight)) if best_weight >= weight => best,
+                _ => Some((offer, weight)),
+            },
+        )
+        .map(|(offer, _)| offer)
+}
+
+/// Weight the most specific of `ranges` gives an offer, by the specificity `matching` computes
+fn weight<R>(
+    ranges: &[R],
+    matching: impl Fn(&R) -> Option<usize>,
+    quality: impl Fn(&R) -> Quality,
+) -> Option<Quality> {
+    ranges
+        .iter()
+        .filter_map(|range| Some((matching(range)?, quality(range))))
+        .max_by_key(|(specificity, _)| *specificity)
+        .map(|(_, quality)| quality)
+}
+
+impl typed::Accept {
+    /// The offer the client prefers, or `None` if none is acceptable
+    pub fn preferred(&self, offers: &[content_type::Kind]) -> Option<content_type::Kind> {
+        best(offers, |offer| {
+            let media_type = offer.to_string();
+            weight(
+                &self.0,
+                |range| range.specificity(&media_type),
+                |range| range.quality,
+            )
+        })
+    }
+}
+impl typed::AcceptLanguage {
+    /// The language tag the client prefers, or `None` if none is acceptable
+    pub fn preferred<'a>(&self, offers: &[&'a str]) -> Option<&'a str> {
+        best(offers, |offer| {
+            weight(
+                &self.0,
+                |range| range.specificity(offer),
+                |range| range.quality,
+            )
+        })
+    }
+}
+
+/// The media type to answer with, among `offers` in the server's order of preference
+///
+/// Requests without an Accept header, or with one that can not be parsed,
+/// accept anything and get the first offer.
+pub fn negotiate(headers: &HeaderMap, offers: &[content_type::Kind]) -> Option<content_type::Kind> {
+    match headers.typed::<typed::Accept>() {
+        Some(accept) => accept.preferred(offers),
+        None => offers.first().copied(),
+    }
+}
+
+/// The language to answer with, among `offers` in the server's order of preference
+///
+/// Requests without an Accept-Language header, or with one that can not be
+/// parsed, accept any language and get the first offer.
+#[allow(dead_code)]
+pub fn negotiate_language<'a>(headers: &HeaderMap, offers: &[&'a str]) -> Option<&'a str> {
+    match headers.typed::<typed::AcceptLanguage>() {
+        Some(accept_language) => accept_language.preferred(offers),
+        None => offers.first().copied(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{negotiate, LanguageRange, MediaRange};
+    use crate::http::header::{
+        content_type::Kind::{Json, Plaintext},
+        typed::{Accept, AcceptLanguage},
+        HeaderMap, Typed,
+    };
+
+    fn accept(value: &str) -> Accept {
+        Accept::decode(&[value]).unwrap()
+    }
+
+    #[test]
+    fn ranges_are_parsed_with_weights_and_parameters() {
+        let ranges = MediaRange::parse_list("text/HTML;level=1 ; q=0.7, */*;q=0").unwrap();
+        assert_eq!(ranges[0].kind, "text");
+        assert_eq!(ranges[0].subtype, "html");
+        assert_eq!(
+            ranges[0].parameters,
+            [("level".to_string(), "1".to_string())]
+        );
+        assert_eq!(ranges[0].quality, 700);
+        assert_eq!(ranges[1].quality, 0);
+        assert_eq!(
+            accept("text/plain;q=0.50, application/json").encode(),
+            "text/plain;q=0.5, application/json"
+        );
+        assert!(MediaRange::parse_list("text").is_err());
+        assert!(MediaRange::parse_list("*/json").is_err());
+        assert!(MediaRange::parse_list("text/plain;q=1.5").is_err());
+        assert!(MediaRange::parse_list("text/plain;q=0.1234").is_err());
+        let languages = LanguageRange::parse_list("en-GB, fr;q=0.8, *;q=0.1").unwrap();
+        assert_eq!(languages[0].tag, "en-gb");
+        assert_eq!(languages[2].quality, 100);
+        assert!(LanguageRange::parse_list("en_GB").is_err());
+    }
+
+    #[test]
+    fn the_most_specific_range_decides() {
+        let offers = [Plaintext, Json];
+        assert_eq!(accept("application/json").preferred(&offers), Some(Json));
+        assert_eq!(accept("*/*").preferred(&offers), Some(Plaintext));
+        assert_eq!(
+            accept("text/*;q=0.5, application/json;q=0.6").preferred(&offers),
+            Some(Json)
+        );
+        assert_eq!(accept("*/*, text/plain;q=0").preferred(&offers), Some(Json));
+        assert_eq!(accept("image/png").preferred(&offers), None);
+    }
+
+    #[test]
+    fn missing_or_malformed_accept_allows_anything() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(negotiate(&headers, &[Json, Plaintext]), Some(Json));
+        headers.insert("Accept", "not a range");
+        assert_eq!(negotiate(&headers, &[Plaintext, Json]), Some(Plaintext));
+    }
+
+    #[test]
+    fn languages_match_by_prefix() {
+        let accept_language = AcceptLanguage::decode(&["en;q=0.5, fr-CA, *;q=0.1"]).unwrap();
+        assert_eq!(
+            accept_language.preferred(&["en-US", "fr-CA"]),
+            Some("fr-CA")
+        );
+        assert_eq!(accept_language.preferred(&["en-US", "fr"]), Some("en-US"));
+        assert_eq!(accept_language.preferred(&["de"]), Some("de"));
+        let accept_language = AcceptLanguage::decode(&["en"]).unwrap();
+        assert_eq!(accept_language.preferred(&["eng", "de"]), None);
+    }
+}
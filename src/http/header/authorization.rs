@@ -0,0 +1,142 @@
+//! Authorization credentials and WWW-Authenticate challenges, see RFC 9110 section 11
+use {
+    crate::http::{base64, header::Error},
+    std::{
+        fmt::{self, Display, Formatter},
+        str::FromStr,
+    },
+};
+
+/// Returns true for values made of token68 characters, see RFC 9110 section 11.2
+fn is_token68(value: &str) -> bool {
+    let value = value.trim_end_matches('=');
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._~+/".contains(c))
+}
+
+/// Credentials sent by a client in an Authorization header
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Credentials {
+    /// User and password, see RFC 7617
+    Basic { user: String, password: String },
+    /// Token given to the client beforehand, see RFC 6750
+    Bearer(String),
+    /// Any other scheme, with its credentials as they were sent
+    Other { scheme: String, credentials: String },
+}
+impl Display for Credentials {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Credentials::Basic { user, password } => {
+                let encoded = base64::encode(format!("{}:{}", user, password).as_bytes());
+                write!(f, "Basic {}", encoded)
+            }
+            Credentials::Bearer(token) => write!(f, "Bearer {}", token),
+            Credentials::Other {
+                scheme,
+                credentials,
+            } => write!(f, "{} {}", scheme, credentials),
+        }
+    }
+}
+impl FromStr for Credentials {
+    type Err = Error;
+    /// Parses `scheme credentials`, the scheme being matched case-insensitively
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |message: &str| Error::Invalid("Authorization", message.to_string());
+        let (scheme, credentials) = s.trim().split_once(' ').unwrap_or((s.trim(), ""));
+        let credentials = credentials.trim();
+        match scheme.to_ascii_lowercase().as_str() {
+            "basic" => {
+                let decoded = base64::decode(credentials)
+                    .ok_or_else(|| invalid("basic credentials are not base64"))?;
+                let decoded = String::from_utf8(decoded)
+                    .map_err(|_| invalid("basic credentials are not UTF-8"))?;
+                let (user, password) = decoded
+                    .split_once(':')
+                    .ok_or_else(|| invalid("basic credentials without a colon"))?;
+                Ok(Credentials::Basic {
+                    user: user.to_string(),
+                    password: password.to_string(),
+                })
+            }
+            "bearer" if is_token68(credentials) => Ok(Credentials::Bearer(credentials.to_string())),
+            "bearer" => Err(invalid("bearer token is not token68")),
+            _ if scheme.is_empty() => Err(invalid("no scheme")),
+            _ => Ok(Credentials::Other {
+                scheme: scheme.to_string(),
+                credentials: credentials.to_string(),
+            }),
+        }
+    }
+}
+
+/// Challenge sent in a WWW-Authenticate header, naming the scheme a client should authenticate with
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Challenge {
+    pub scheme: &'static str,
+    /// Parameters like `realm`, sent as quoted strings in the order they were added
+    pub parameters: Vec<(&'static str, String)>,
+}
+impl Challenge {
+    pub fn new(scheme: &'static str, realm: &str) -> Self {
+        Self {
+            scheme,
+            parameters: vec![("realm", realm.to_string())],
+        }
+    }
+    pub fn parameter(mut self, name: &'static str, value: &str) -> Self {
+        self.parameters.push((name, value.to_string()));
+        self
+    }
+}
+impl Display for Challenge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.scheme)?;
+        for (index, (name, value)) in self.parameters.iter().enumerate() {
+            let separator = if index == 0 { " " } else { ", " };
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            write!(f, "{}{}=\"{}\"", separator, name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Challenge, Credentials};
+    use std::str::FromStr;
+
+    #[test]
+    fn credentials_round_trip() {
+        let basic = Credentials::from_str("basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==").unwrap();
+        assert_eq!(
+            basic,
+            Credentials::Basic {
+                user: "Aladdin".to_string(),
+                password: "open sesame".to_string()
+            }
+        );
+        assert_eq!(basic.to_string(), "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+        let bearer = Credentials::from_str("Bearer mF_9.B5f-4.1JqM").unwrap();
+        assert_eq!(bearer, Credentials::Bearer("mF_9.B5f-4.1JqM".to_string()));
+        assert!(Credentials::from_str("Bearer a b").is_err());
+        assert!(Credentials::from_str("Basic bm9jb2xvbg==").is_err());
+        assert!(Credentials::from_str("").is_err());
+        assert!(matches!(
+            Credentials::from_str("Digest username=\"a\""),
+            Ok(Credentials::Other { .. })
+        ));
+    }
+
+    #[test]
+    fn challenges_quote_their_parameters() {
+        let challenge = Challenge::new("Bearer", "say \"hi\"").parameter("error", "invalid_token");
+        assert_eq!(
+            challenge.to_string(),
+            r#"Bearer realm="say \"hi\"", error="invalid_token""#
+        );
+    }
+}
@@ -10,6 +10,10 @@ use {
 pub enum Kind {
     /// Connection keep alive
     KeepAlive,
+    /// Connection close after the current message
+    Close,
+    /// Connection switching to the protocol named in the Upgrade header
+    Upgrade,
     /// Any unrecognized or unimplemented connection kind
     #[allow(dead_code)]
     Unrecognized,
@@ -19,6 +23,8 @@ impl Display for Kind {
         use Kind::*;
         let connection_string = match self {
             KeepAlive => String::from("keep-alive"),
+            Close => String::from("close"),
+            Upgrade => String::from("Upgrade"),
             Unrecognized => String::from("unrecognized"),
         };
         fmt::write(f, format_args!("{}", connection_string))
@@ -28,8 +34,10 @@ impl FromStr for Kind {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         use Kind::*;
-        match s {
+        match s.to_ascii_lowercase().as_str() {
             "keep-alive" => Ok(KeepAlive),
+            "close" => Ok(Close),
+            "upgrade" => Ok(Upgrade),
             other => {
                 let unknown_connection = format!("unknown connection kind value {}", other);
                 Err(Error::Unrecognized(unknown_connection))
@@ -48,4 +56,9 @@ mod tests {
         let s = "keep-alive";
         assert_eq!(Kind::from_str(s).unwrap(), Kind::KeepAlive)
     }
+    #[test]
+    fn close_from_str_ignores_case() {
+        let s = "Close";
+        assert_eq!(Kind::from_str(s).unwrap(), Kind::Close)
+    }
 }
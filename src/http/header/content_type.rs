@@ -6,12 +6,20 @@ use {
     },
 };
 /// Content types
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Kind {
     /// Plain text content
     Plaintext,
     /// Octet stream
     Appbytestream,
+    /// Server-sent events
+    EventStream,
+    /// JSON document
+    Json,
+    /// Form fields, percent encoded
+    FormUrlencoded,
+    /// Form fields and files, each in a part of its own
+    FormData,
 }
 impl Display for Kind {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
@@ -19,16 +27,26 @@ impl Display for Kind {
         let content_type_string = match self {
             Plaintext => String::from("text/plain"),
             Appbytestream => String::from("application/octet-stream"),
+            EventStream => String::from("text/event-stream"),
+            Json => String::from("application/json"),
+            FormUrlencoded => String::from("application/x-www-form-urlencoded"),
+            FormData => String::from("multipart/form-data"),
         };
         fmt::write(f, format_args!("{}", content_type_string))
     }
 }
 impl FromStr for Kind {
     type Err = Error;
+    /// Parses a media type, ignoring case and any parameters
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
+        let media_type = s.split(';').next().unwrap_or_default().trim();
+        match media_type.to_ascii_lowercase().as_str() {
             "text/plain" => Ok(Self::Plaintext),
             "application/octet-stream" => Ok(Self::Appbytestream),
+            "text/event-stream" => Ok(Self::EventStream),
+            "application/json" => Ok(Self::Json),
+            "application/x-www-form-urlencoded" => Ok(Self::FormUrlencoded),
+            "multipart/form-data" => Ok(Self::FormData),
             other => Err(Error::Unrecognized(other.to_string())),
         }
     }
@@ -0,0 +1,187 @@
+//! HTTP dates, see RFC 9110 section 5.6.7
+//!
+//! Dates are sent as IMF-fixdate, like `Sun, 06 Nov 1994 08:49:37 GMT`, and
+//! read in that format or either of the obsolete ones recipients must accept.
+use std::{
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const LONG_DAYS: [&str; 7] = [
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Second the cached date was formatted for, and the formatted date
+static CACHE: Mutex<Option<(u64, String)>> = Mutex::new(None);
+
+/// Year, month and day of the civil date `days` after 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // shifts the epoch to 0000-03-01 so leap days end each 400 year era
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Days from 1970-01-01 to the civil date, the inverse of [`civil_from_days`]
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = i64::from((month + 9) % 12);
+    let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Formats `time` as an IMF-fixdate, dropping fractions of a second
+pub fn format(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() as i64)
+        .unwrap_or_default();
+    let (days, second_of_day) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        DAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        second_of_day / 3600,
+        second_of_day / 60 % 60,
+        second_of_day % 60
+    )
+}
+
+/// The current time as an IMF-fixdate, formatted at most once a second
+pub fn now() -> String {
+    let now = SystemTime::now();
+    let second = now
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default();
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    match cache.as_ref() {
+        Some((cached, date)) if *cached == second => date.clone(),
+        _ => {
+            let date = format(now);
+            *cache = Some((second, date.clone()));
+            date
+        }
+    }
+}
+
+/// Parses a date in any of the formats of RFC 9110, returning `None` if it is in none
+///
+/// The day name is checked against the date, which must be a valid one.
+pub fn parse(s: &str) -> Option<SystemTime> {
+    let (day_name, rest) = s.split_once(' ')?;
+    let fields = rest.split(' ').collect::<Vec<&str>>();
+    let (day_name, day, month, year, time) = match (day_name.strip_suffix(','), fields.as_slice()) {
+        // IMF-fixdate: Sun, 06 Nov 1994 08:49:37 GMT
+        (Some(day_name), &[day, month, year, time, "GMT"]) if year.len() == 4 => {
+            (day_name, day, month, year.parse::<i64>().ok()?, time)
+        }
+        // RFC 850: Sunday, 06-Nov-94 08:49:37 GMT
+        (Some(day_name), &[date, time, "GMT"]) => {
+            let mut parts = date.split('-');
+            let (day, month, year) = (parts.next()?, parts.next()?, parts.next()?);
+            if year.len() != 2 || parts.next().is_some() {
+                return None;
+            }
+            let two_digits = year.parse::<i64>().ok()?;
+            // read as the most recent year ending in those digits, see RFC 9110 section 5.6.7
+            let current = civil_from_days(
+                SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as i64 / 86_400,
+            )
+            .0;
+            let mut year = current - current.rem_euclid(100) + two_digits;
+            if year > current + 50 {
+                year -= 100;
+            }
+            (day_name, day, month, year, time)
+        }
+        // asctime: Sun Nov  6 08:49:37 1994
+        (None, &[month, "", day, time, year] | &[month, day, time, year]) if year.len() == 4 => {
+            (day_name, day, month, year.parse::<i64>().ok()?, time)
+        }
+        _ => return None,
+    };
+    let month = MONTHS.iter().position(|name| *name == month)? as u32 + 1;
+    let day = day.parse::<u32>().ok().filter(|day| day.ge(&1))?;
+    let mut clock = time.split(':').map(|part| {
+        Some(part)
+            .filter(|part| part.len() == 2)?
+            .parse::<i64>()
+            .ok()
+    });
+    let (hour, minute, second) = (clock.next()??, clock.next()??, clock.next()??);
+    if clock.next().is_some() || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    if civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+    let weekday = days.rem_euclid(7) as usize;
+    if day_name != DAYS[weekday] && day_name != LONG_DAYS[weekday] {
+        return None;
+    }
+    let seconds = days * 86_400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(seconds).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format, now, parse};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn dates_are_formatted_as_imf_fixdate() {
+        let at = |seconds: u64| format(UNIX_EPOCH + Duration::from_secs(seconds));
+        assert_eq!(at(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(at(784_111_777), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(at(951_782_400), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(parse(&now()).map(format), Some(now()));
+    }
+
+    #[test]
+    fn every_format_is_parsed() {
+        let expected = Some(UNIX_EPOCH + Duration::from_secs(784_111_777));
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(parse("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+        assert_eq!(parse("Sun Nov  6 08:49:37 1994"), expected);
+    }
+
+    #[test]
+    fn invalid_dates_are_rejected() {
+        assert_eq!(parse("Mon, 06 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 UTC"), None);
+        assert_eq!(parse("Thu, 30 Feb 2023 00:00:00 GMT"), None);
+        assert_eq!(parse("Sun, 06 Nov 1994 24:00:00 GMT"), None);
+        assert_eq!(parse("Sun, 06 Nov 1994 8:49:37 GMT"), None);
+        assert_eq!(parse("yesterday"), None);
+    }
+}
@@ -2,7 +2,7 @@ use crate::http::Header;
 use std::fmt;
 
 /// Errors related to headers
-#[derive(Debug)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Error {
     /// Errors related to parsing headers
     Parse(Header, String),
@@ -11,6 +11,13 @@ pub enum Error {
     ParseFormat(Header, String),
     /// Errors related to unrecognized headers
     Unrecognized(String),
+    /// Errors related to decoding the value of a named header
+    #[allow(dead_code)]
+    Invalid(&'static str, String),
+    /// Errors related to field names that are not valid tokens
+    InvalidName(String),
+    /// Errors related to field values carrying forbidden characters
+    InvalidValue(String, String),
 }
 impl std::error::Error for Error {}
 impl fmt::Display for Error {
@@ -21,14 +28,17 @@ impl fmt::Display for Error {
             // format!("{}: {}", "PARSE USER AGENT", message)
             // }
             Self::Parse(header_kind, message) => {
-                format!("{} parse error: {}", header_kind.to_string(), message)
+                format!("{} parse error: {}", header_kind.name(), message)
+            }
+            Self::ParseFormat(header_kind, message) => {
+                format!("{} format parse error: {}", header_kind.name(), message)
             }
-            Self::ParseFormat(header_kind, message) => format!(
-                "{} format parse error: {}",
-                header_kind.to_string(),
-                message
-            ),
             Self::Unrecognized(message) => format!("unrecognized header {}", message),
+            Self::Invalid(name, message) => format!("{} invalid value: {}", name, message),
+            Self::InvalidName(name) => format!("invalid field name {}", name),
+            Self::InvalidValue(name, message) => {
+                format!("{} invalid field value: {}", name, message)
+            }
         };
         fmt::write(f, format_args!("[{}] {}", source, body))
     }
@@ -0,0 +1,212 @@
+use {
+    crate::http::header::{Error, Kind, Typed},
+    std::fmt::{self, Display, Formatter},
+};
+
+/// A single header field, as it was received or pushed
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Field {
+    /// Field name, kept in its original case
+    pub name: String,
+    /// Field value, with surrounding whitespace trimmed
+    pub value: String,
+}
+impl Display for Field {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fmt::write(f, format_args!("{}: {}\r\n", self.name, self.value))
+    }
+}
+
+/// Case-insensitive, order-preserving, multi-valued collection of header fields
+///
+/// Every field of a message is stored, whether or not it is known to `Kind`.
+/// Known fields can be read back through `HeaderMap::typed`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct HeaderMap {
+    fields: Vec<Field>,
+}
+impl HeaderMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Number of fields, counting repeated names separately
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+    /// Returns true if at least one field is named `name`
+    pub fn contains(&self, name: &str) -> bool {
+        self.fields
+            .iter()
+            .any(|field| field.name.eq_ignore_ascii_case(name))
+    }
+    /// Returns the first value of the fields named `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|field| field.name.eq_ignore_ascii_case(name))
+            .map(|field| field.value.as_str())
+    }
+    /// Returns every value of the fields named `name`, in the order they were added
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |field| field.name.eq_ignore_ascii_case(name))
+            .map(|field| field.value.as_str())
+    }
+    /// Returns true if any field named `name` lists `token` in its comma separated value
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|listed| listed.trim().eq_ignore_ascii_case(token))
+    }
+    /// Adds a field, keeping any existing fields with the same name
+    pub fn append<N, V>(&mut self, name: N, value: V)
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        self.fields.push(Field {
+            name: name.into(),
+            value: value.into(),
+        })
+    }
+    /// Adds a field, replacing any existing fields with the same name
+    ///
+    /// The new field takes the position of the first replaced field, if any.
+    pub fn insert<N, V>(&mut self, name: N, value: V)
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        let name = name.into();
+        let value = value.into();
+        match self
+            .fields
+            .iter()
+            .position(|field| field.name.eq_ignore_ascii_case(&name))
+        {
+            Some(index) => {
+                self.remove(&name);
+                self.fields.insert(index, Field { name, value });
+            }
+            None => self.fields.push(Field { name, value }),
+        }
+    }
+    /// Removes every field named `name`, returning their values
+    pub fn remove(&mut self, name: &str) -> Vec<String> {
+        let mut removed = vec![];
+        self.fields.retain(|field| {
+            if field.name.eq_ignore_ascii_case(name) {
+                removed.push(field.value.clone());
+                false
+            } else {
+                true
+            }
+        });
+        removed
+    }
+    /// Iterates over every field in order
+    pub fn iter(&self) -> std::slice::Iter<'_, Field> {
+        self.fields.iter()
+    }
+    /// Adds a known header, keeping any existing fields with the same name
+    pub fn push(&mut self, kind: Kind) {
+        self.append(kind.name(), kind.value())
+    }
+    /// Decodes the typed header `T`, returning `None` if it is absent or malformed
+    pub fn typed<T: Typed>(&self) -> Option<T> {
+        self.try_typed::<T>().ok().flatten()
+    }
+    /// Decodes the typed header `T`, returning `Ok(None)` if it is absent
+    pub fn try_typed<T: Typed>(&self) -> Result<Option<T>, Error> {
+        let values = self.get_all(T::NAME).collect::<Vec<&str>>();
+        if values.is_empty() {
+            Ok(None)
+        } else {
+            T::decode(&values).map(Some)
+        }
+    }
+    /// Encodes `header`, replacing any existing fields with the same name
+    pub fn insert_typed<T: Typed>(&mut self, header: &T) {
+        self.insert(T::NAME, header.encode())
+    }
+    /// Encodes `header`, keeping any existing fields with the same name
+    pub fn append_typed<T: Typed>(&mut self, header: &T) {
+        self.append(T::NAME, header.encode())
+    }
+}
+impl Display for HeaderMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.fields.iter().try_for_each(|field| field.fmt(f))
+    }
+}
+impl<'a> IntoIterator for &'a HeaderMap {
+    type Item = &'a Field;
+    type IntoIter = std::slice::Iter<'a, Field>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+impl Extend<Kind> for HeaderMap {
+    fn extend<I: IntoIterator<Item = Kind>>(&mut self, iter: I) {
+        iter.into_iter().for_each(|kind| self.push(kind))
+    }
+}
+impl FromIterator<Kind> for HeaderMap {
+    fn from_iter<I: IntoIterator<Item = Kind>>(iter: I) -> Self {
+        let mut map = Self::new();
+        map.extend(iter);
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HeaderMap;
+    use crate::http::header::{typed::ContentLength, Kind};
+
+    #[test]
+    fn lookup_ignores_case() {
+        let mut map = HeaderMap::new();
+        map.append("X-Request-Id", "abc");
+        assert_eq!(map.get("x-request-id"), Some("abc"));
+        assert!(map.contains("X-REQUEST-ID"));
+    }
+
+    #[test]
+    fn keeps_order_and_repeated_fields() {
+        let mut map = HeaderMap::new();
+        map.append("Cookie", "a=1");
+        map.append("Authorization", "Bearer t");
+        map.append("cookie", "b=2");
+        assert_eq!(
+            map.get_all("Cookie").collect::<Vec<_>>(),
+            vec!["a=1", "b=2"]
+        );
+        assert_eq!(
+            map.to_string(),
+            "Cookie: a=1\r\nAuthorization: Bearer t\r\ncookie: b=2\r\n"
+        );
+    }
+
+    #[test]
+    fn insert_replaces_in_place() {
+        let mut map = [Kind::ContentLength(1), Kind::Accept("*/*".to_string())]
+            .into_iter()
+            .collect::<HeaderMap>();
+        map.insert_typed(&ContentLength(3));
+        assert_eq!(map.to_string(), "Content-Length: 3\r\nAccept: */*\r\n");
+    }
+
+    #[test]
+    fn typed_accessor() {
+        let mut map = HeaderMap::new();
+        map.append("content-length", "42");
+        assert_eq!(map.typed::<ContentLength>(), Some(ContentLength(42)));
+        map.insert("Content-Length", "nope");
+        assert!(map.try_typed::<ContentLength>().is_err());
+    }
+}
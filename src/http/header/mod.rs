@@ -1,5 +1,11 @@
 /// Module to handle errors related to headers
-mod error;
+pub(crate) mod error;
+
+/// Module to handle Accept and Accept-Language headers
+pub(crate) mod accept;
+
+/// Module to handle Authorization and WWW-Authenticate headers
+pub(crate) mod authorization;
 
 /// Module to handle User-Agent headers
 pub(crate) mod user_agent;
@@ -10,15 +16,27 @@ pub(crate) mod connection;
 /// Module to handle Content-Type headers
 pub(crate) mod content_type;
 
-use {
-    error::Error,
-    std::{
-        fmt::{self, Display, Formatter},
-        str::FromStr,
-    },
+/// Module to handle the generic header map
+pub(crate) mod map;
+
+/// Module to format and parse HTTP dates
+pub(crate) mod date;
+
+/// Module to handle Server headers
+pub(crate) mod server;
+
+/// Module to handle typed access to header values
+pub(crate) mod typed;
+
+pub use {error::Error, map::HeaderMap, typed::Typed};
+
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+    time::SystemTime,
 };
 /// Headers
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Kind {
     /// Request Header
     Host(String, Option<u16>),
@@ -30,10 +48,24 @@ pub enum Kind {
     AcceptLanguage(String),
     /// Request Header
     AcceptEncoding(String),
+    /// Request Header
+    Cookie(String),
+    /// Request Header
+    Authorization(String),
     /// General Header
     Connection(connection::Kind),
     /// General Header
     UpgradeInsecureRequests(u32),
+    /// General Header
+    TransferEncoding(String),
+    /// General Header
+    Date(SystemTime),
+    /// Response Header
+    Server(String),
+    /// Response Header
+    SetCookie(String),
+    /// Response Header
+    WwwAuthenticate(String),
     /// Representation Header
     ContentType(content_type::Kind),
     /// Representation Header
@@ -41,104 +73,37 @@ pub enum Kind {
 }
 impl Display for Kind {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        use Kind::*;
-        let header_string = match self {
-            // Request Headers
-            Host(host, None) => format!("Host: {}", host),
-            Host(host, Some(port)) => format!("Host: {}:{}", host, port),
-            UserAgent(user_agent) => format!("User-Agent: {}", user_agent),
-            Accept(accepted) => format!("Accept: {}", accepted),
-            AcceptLanguage(accepted_language) => {
-                format!("Accept-Language: {}", accepted_language)
-            }
-            AcceptEncoding(accepted_encoding) => {
-                format!("Accept-Encoding: {}", accepted_encoding)
-            }
-            // General Headers
-            Connection(connection) => format!("Connection: {}", connection),
-            UpgradeInsecureRequests(count) => {
-                format!("Upgrade-Insecure-Requests: {}", count)
-            }
-            // Representation Headers
-            ContentType(content_type) => format!("Content-Type: {}", content_type),
-            ContentLength(content_length) => {
-                format!("Content-Length: {}", content_length)
-            }
-        };
-        fmt::write(f, format_args!("{}\r\n", header_string))
+        fmt::write(f, format_args!("{}: {}\r\n", self.name(), self.value()))
     }
 }
 impl FromStr for Kind {
     type Err = Error;
     fn from_str(value: &str) -> Result<Self, Self::Err> {
-        use Kind::*;
-        let (key, value) = value.split_once(':').unwrap_or(("", ""));
-        match (key.trim(), value.trim()) {
+        let (key, value) = parse_field(value)?;
+        let values = [value];
+        match key.to_ascii_lowercase().as_str() {
             // Request Headers
-            ("Host", host_address) => match host_address.split_once(':') {
-                Some((address, port_string)) => match port_string.parse::<u16>() {
-                    Ok(port) => Ok(Host(address.to_string(), Some(port))),
-                    Err(e) => {
-                        let kind = Host(host_address.to_string(), None);
-                        let message =
-                            format!("{} error on trying to parse port {}", e, port_string);
-                        let error = Error::Parse(kind, message);
-                        Err(error)
-                    }
-                },
-                None => Ok(Host(host_address.to_string(), None)),
-            },
-            ("User-Agent", user_agent_string) => {
-                match user_agent::Kind::from_str(user_agent_string) {
-                    Ok(user_agent_kind) => Ok(UserAgent(user_agent_kind)),
-                    Err(e) => Err(e),
-                }
-            }
-            ("Accept", accepted) => Ok(Accept(accepted.to_string())),
-            ("Accept-Language", accepted_language) => {
-                Ok(AcceptLanguage(accepted_language.to_string()))
-            }
-            ("Accept-Encoding", accepted_encoding) => {
-                Ok(AcceptEncoding(accepted_encoding.to_string()))
-            }
+            "host" => typed::Host::decode(&values).map(Kind::from),
+            "user-agent" => typed::UserAgent::decode(&values).map(Kind::from),
+            "accept" => typed::Accept::decode(&values).map(Kind::from),
+            "accept-language" => typed::AcceptLanguage::decode(&values).map(Kind::from),
+            "accept-encoding" => typed::AcceptEncoding::decode(&values).map(Kind::from),
+            "cookie" => typed::Cookie::decode(&values).map(Kind::from),
+            "authorization" => typed::Authorization::decode(&values).map(Kind::from),
             // General Headers
-            ("Connection", connection_string) => {
-                match connection::Kind::from_str(connection_string) {
-                    Ok(connection_kind) => Ok(Connection(connection_kind)),
-                    Err(e) => Err(e),
-                }
+            "connection" => typed::Connection::decode(&values).map(Kind::from),
+            "upgrade-insecure-requests" => {
+                typed::UpgradeInsecureRequests::decode(&values).map(Kind::from)
             }
-            ("Upgrade-Insecure-Requests", count_string) => match count_string.parse::<u32>() {
-                Ok(count) => Ok(UpgradeInsecureRequests(count)),
-                Err(e) => {
-                    let kind = UpgradeInsecureRequests(0u32);
-                    let message =
-                        format!("{} error on trying to parse UIR count: {}", e, count_string);
-                    let error = Error::Parse(kind, message);
-                    Err(error)
-                }
-            },
+            "transfer-encoding" => typed::TransferEncoding::decode(&values).map(Kind::from),
+            "date" => typed::Date::decode(&values).map(Kind::from),
+            // Response Headers
+            "server" => typed::Server::decode(&values).map(Kind::from),
+            "set-cookie" => typed::SetCookie::decode(&values).map(Kind::from),
+            "www-authenticate" => typed::WwwAuthenticate::decode(&values).map(Kind::from),
             // Representation Headers
-            ("Content-Type", content_type_string) => {
-                match content_type::Kind::from_str(content_type_string) {
-                    Ok(content_kind) => Ok(ContentType(content_kind)),
-                    Err(e) => Err(e),
-                }
-            }
-            ("Content-Length", content_length_string) => {
-                match content_length_string.parse::<usize>() {
-                    Ok(content_length) => Ok(ContentLength(content_length)),
-                    Err(e) => {
-                        let kind = ContentLength(0usize);
-                        let message = format!(
-                            "{} error on trying to parse content length {}",
-                            e, content_length_string
-                        );
-                        let error = Error::Parse(kind, message);
-                        Err(error)
-                    }
-                }
-            }
+            "content-type" => typed::ContentType::decode(&values).map(Kind::from),
+            "content-length" => typed::ContentLength::decode(&values).map(Kind::from),
             _ => Err(Error::Unrecognized(format!(
                 "unknown header {}: {}",
                 key, value
@@ -147,20 +112,90 @@ impl FromStr for Kind {
     }
 }
 impl Kind {
+    /// Field name of the header
+    pub fn name(&self) -> &'static str {
+        use Kind::*;
+        match self {
+            // Request Headers
+            Host(_, _) => typed::Host::NAME,
+            UserAgent(_) => typed::UserAgent::NAME,
+            Accept(_) => typed::Accept::NAME,
+            AcceptLanguage(_) => typed::AcceptLanguage::NAME,
+            AcceptEncoding(_) => typed::AcceptEncoding::NAME,
+            Cookie(_) => typed::Cookie::NAME,
+            Authorization(_) => typed::Authorization::NAME,
+            // General Headers
+            Connection(_) => typed::Connection::NAME,
+            UpgradeInsecureRequests(_) => typed::UpgradeInsecureRequests::NAME,
+            TransferEncoding(_) => typed::TransferEncoding::NAME,
+            Date(_) => typed::Date::NAME,
+            // Response Headers
+            Server(_) => typed::Server::NAME,
+            SetCookie(_) => typed::SetCookie::NAME,
+            WwwAuthenticate(_) => typed::WwwAuthenticate::NAME,
+            // Representation Headers
+            ContentType(_) => typed::ContentType::NAME,
+            ContentLength(_) => typed::ContentLength::NAME,
+        }
+    }
+    /// Field value of the header
+    pub fn value(&self) -> String {
+        use Kind::*;
+        match self {
+            // Request Headers
+            Host(host, None) => host.clone(),
+            Host(host, Some(port)) => format!("{}:{}", host, port),
+            UserAgent(user_agent) => user_agent.to_string(),
+            Accept(accepted) => accepted.clone(),
+            AcceptLanguage(accepted_language) => accepted_language.clone(),
+            AcceptEncoding(accepted_encoding) => accepted_encoding.clone(),
+            Cookie(pairs) => pairs.clone(),
+            Authorization(credentials) => credentials.clone(),
+            // General Headers
+            Connection(connection) => connection.to_string(),
+            UpgradeInsecureRequests(count) => count.to_string(),
+            TransferEncoding(codings) => codings.clone(),
+            Date(time) => date::format(*time),
+            // Response Headers
+            Server(product) => product.clone(),
+            SetCookie(cookie) => cookie.clone(),
+            WwwAuthenticate(challenges) => challenges.clone(),
+            // Representation Headers
+            ContentType(content_type) => content_type.to_string(),
+            ContentLength(content_length) => content_length.to_string(),
+        }
+    }
     /// Returns true for headers that modify the request by specifying it further, by giving context, or by conditionally restricting it
     #[allow(dead_code)]
     pub fn is_request_header(&self) -> bool {
         use Kind::*;
         matches!(
             self,
-            Host(_, _) | UserAgent(_) | Accept(_) | AcceptLanguage(_) | AcceptEncoding(_)
+            Host(_, _)
+                | UserAgent(_)
+                | Accept(_)
+                | AcceptLanguage(_)
+                | AcceptEncoding(_)
+                | Cookie(_)
+                | Authorization(_)
         )
     }
     /// Returns true for headers that apply to the message as a whole
     #[allow(dead_code)]
     pub fn is_general_header(&self) -> bool {
         use Kind::*;
-        matches!(self, Connection(_) | UpgradeInsecureRequests(_))
+        matches!(
+            self,
+            Connection(_) | UpgradeInsecureRequests(_) | TransferEncoding(_) | Date(_)
+        )
+    }
+    /// Returns true for headers that give context about the response or the server sending it
+    #[allow(dead_code)]
+    pub fn is_response_header(&self) -> bool {
+        matches!(
+            self,
+            Kind::Server(_) | Kind::SetCookie(_) | Kind::WwwAuthenticate(_)
+        )
     }
     /// Returns true for headers that describe the original format of the message data and any encoding applied (only present if the message has a body)
     #[allow(dead_code)]
@@ -169,3 +204,112 @@ impl Kind {
         matches!(self, ContentType(_) | ContentLength(_))
     }
 }
+
+/// Returns true for the characters allowed in a field name, see RFC 9110 section 5.6.2
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+/// Returns true for non-empty tokens, like field names and methods
+pub fn is_token(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(is_token_char)
+}
+/// Checks that `name` is a non-empty token
+pub fn validate_name(name: &str) -> Result<(), Error> {
+    if is_token(name) {
+        Ok(())
+    } else {
+        Err(Error::InvalidName(name.escape_debug().to_string()))
+    }
+}
+/// Checks that `value` carries no bare CR, LF or NUL
+pub fn validate_value(name: &str, value: &str) -> Result<(), Error> {
+    match value.chars().find(|c| matches!(c, '\r' | '\n' | '\0')) {
+        Some(c) => Err(Error::InvalidValue(
+            name.to_string(),
+            format!("forbidden character {:?}", c),
+        )),
+        None => Ok(()),
+    }
+}
+/// Splits a single field line into its validated name and trimmed value
+///
+/// Whitespace is not allowed between the name and the colon, and lines
+/// continuing a previous field (obsolete line folding) are rejected.
+pub fn parse_field(line: &str) -> Result<(&str, &str), Error> {
+    if line.starts_with([' ', '\t']) {
+        return Err(Error::InvalidName(format!(
+            "obsolete line folding in {}",
+            line.escape_debug()
+        )));
+    }
+    match line.split_once(':') {
+        Some((name, value)) => {
+            let value = value.trim_matches([' ', '\t']);
+            validate_name(name)?;
+            validate_value(name, value)?;
+            Ok((name, value))
+        }
+        None => Err(Error::InvalidName(format!(
+            "missing colon in {}",
+            line.escape_debug()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_field, Kind};
+    use std::{
+        str::FromStr,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    #[test]
+    fn kind_from_str_ignores_case() {
+        assert_eq!(
+            Kind::from_str("content-length: 5").unwrap(),
+            Kind::ContentLength(5)
+        );
+        assert_eq!(
+            Kind::from_str("HOST: example.com:80").unwrap(),
+            Kind::Host("example.com".to_string(), Some(80))
+        );
+        let date = Kind::from_str("date: Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        assert_eq!(
+            date,
+            Kind::Date(UNIX_EPOCH + Duration::from_secs(784_111_777))
+        );
+        assert_eq!(date.value(), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert!(Kind::from_str("Date: tomorrow").is_err());
+        assert_eq!(
+            Kind::from_str("Server: a/1").unwrap(),
+            Kind::Server("a/1".to_string())
+        );
+    }
+
+    #[test]
+    fn host_ports_follow_bracketed_ipv6_literals() {
+        use super::typed::{Host, Typed};
+        let host = |value: &str| Host::decode(&[value]).map(|host| (host.host, host.port));
+        assert_eq!(
+            host("[::1]:8080").unwrap(),
+            ("[::1]".to_string(), Some(8080))
+        );
+        assert_eq!(host("[::1]").unwrap(), ("[::1]".to_string(), None));
+        assert_eq!(host("a.test:80").unwrap(), ("a.test".to_string(), Some(80)));
+        assert_eq!(host("a.test").unwrap(), ("a.test".to_string(), None));
+        for invalid in ["::1", "[::1]:x", "a.test:", "a.test:99999", "[::1"] {
+            assert!(host(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parse_field_rejects_space_before_colon() {
+        assert!(parse_field("Host : example.com").is_err());
+        assert!(parse_field(" Host: example.com").is_err());
+        assert_eq!(
+            parse_field("Host:\texample.com ").unwrap(),
+            ("Host", "example.com")
+        );
+    }
+}
@@ -0,0 +1,20 @@
+//! Product sent in the Server header of every response
+use std::sync::OnceLock;
+
+/// Product sent unless `--server-header` names another one
+pub const DEFAULT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+static PRODUCT: OnceLock<Option<String>> = OnceLock::new();
+
+/// Product to send, read once from `--server-header`, or `None` if `--no-server-header` is given
+pub fn product() -> Option<&'static str> {
+    PRODUCT
+        .get_or_init(|| {
+            if crate::args::flag("--no-server-header") {
+                None
+            } else {
+                Some(crate::args::value("--server-header").unwrap_or_else(|| DEFAULT.to_string()))
+            }
+        })
+        .as_deref()
+}
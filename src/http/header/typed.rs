@@ -0,0 +1,448 @@
+use {
+    crate::http::header::{
+        accept::{LanguageRange, MediaRange},
+        authorization::{Challenge, Credentials},
+        connection, content_type, date, user_agent, Error, Kind,
+    },
+    crate::http::{cookie, request},
+    std::{
+        str::FromStr,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// Headers that can be decoded from, and encoded into, raw field values
+///
+/// Implement this for your own types to read them with `HeaderMap::typed`
+/// and write them with `HeaderMap::insert_typed`.
+pub trait Typed: Sized {
+    /// Field name, matched case-insensitively
+    const NAME: &'static str;
+    /// Decodes the header from every value sent under `NAME`, in order
+    ///
+    /// `values` is never empty.
+    fn decode(values: &[&str]) -> Result<Self, Error>;
+    /// Encodes the header into a single field value
+    fn encode(&self) -> String;
+}
+
+/// Value of a header that may only appear once; the first one wins
+fn single<'a>(values: &[&'a str]) -> &'a str {
+    values.first().copied().unwrap_or_default()
+}
+
+/// Value of a comma separated list header, with repeated fields combined
+fn combined(values: &[&str]) -> String {
+    values.join(", ")
+}
+
+/// Request Header
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Host {
+    pub host: String,
+    pub port: Option<u16>,
+}
+impl Typed for Host {
+    const NAME: &'static str = "Host";
+    fn decode(values: &[&str]) -> Result<Self, Error> {
+        let host_address = single(values);
+        // IPv6 hosts are bracketed, only a colon past the brackets starts the port
+        if let Some((host, port)) = request::split_authority(host_address) {
+            return Ok(Self {
+                host: host.to_string(),
+                port: Some(port),
+            });
+        }
+        let bracketed = host_address.starts_with('[') && host_address.ends_with(']');
+        if bracketed || !host_address.contains(':') {
+            return Ok(Self {
+                host: host_address.to_string(),
+                port: None,
+            });
+        }
+        let kind = Kind::Host(host_address.to_string(), None);
+        let message = format!("invalid host or port in {}", host_address);
+        Err(Error::Parse(kind, message))
+    }
+    fn encode(&self) -> String {
+        match self.port {
+            Some(port) => format!("{}:{}", self.host, port),
+            None => self.host.clone(),
+        }
+    }
+}
+
+/// Request Header
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UserAgent(pub user_agent::Kind);
+impl Typed for UserAgent {
+    const NAME: &'static str = "User-Agent";
+    fn decode(values: &[&str]) -> Result<Self, Error> {
+        user_agent::Kind::from_str(single(values)).map(Self)
+    }
+    fn encode(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// Request Header
+///
+/// Ranges are kept in the order they were sent, see [`Accept::preferred`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Accept(pub Vec<MediaRange>);
+impl Typed for Accept {
+    const NAME: &'static str = "Accept";
+    fn decode(values: &[&str]) -> Result<Self, Error> {
+        MediaRange::parse_list(&combined(values)).map(Self)
+    }
+    fn encode(&self) -> String {
+        let ranges = self.0.iter().map(MediaRange::to_string);
+        ranges.collect::<Vec<String>>().join(", ")
+    }
+}
+
+/// Request Header
+///
+/// Ranges are kept in the order they were sent, see [`AcceptLanguage::preferred`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AcceptLanguage(pub Vec<LanguageRange>);
+impl Typed for AcceptLanguage {
+    const NAME: &'static str = "Accept-Language";
+    fn decode(values: &[&str]) -> Result<Self, Error> {
+        LanguageRange::parse_list(&combined(values)).map(Self)
+    }
+    fn encode(&self) -> String {
+        let ranges = self.0.iter().map(LanguageRange::to_string);
+        ranges.collect::<Vec<String>>().join(", ")
+    }
+}
+
+/// Request Header
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AcceptEncoding(pub String);
+impl Typed for AcceptEncoding {
+    const NAME: &'static str = "Accept-Encoding";
+    fn decode(values: &[&str]) -> Result<Self, Error> {
+        Ok(Self(combined(values)))
+    }
+    fn encode(&self) -> String {
+        self.0.clone()
+    }
+}
+
+/// General Header
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Connection(pub connection::Kind);
+impl Typed for Connection {
+    const NAME: &'static str = "Connection";
+    fn decode(values: &[&str]) -> Result<Self, Error> {
+        connection::Kind::from_str(single(values)).map(Self)
+    }
+    fn encode(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// General Header
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct UpgradeInsecureRequests(pub u32);
+impl Typed for UpgradeInsecureRequests {
+    const NAME: &'static str = "Upgrade-Insecure-Requests";
+    fn decode(values: &[&str]) -> Result<Self, Error> {
+        let count_string = single(values);
+        match count_string.parse::<u32>() {
+            Ok(count) => Ok(Self(count)),
+            Err(e) => {
+                let kind = Kind::UpgradeInsecureRequests(0u32);
+                let message = format!("{} error on trying to parse UIR count: {}", e, count_string);
+                Err(Error::Parse(kind, message))
+            }
+        }
+    }
+    fn encode(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// Representation Header
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ContentType(pub content_type::Kind);
+impl Typed for ContentType {
+    const NAME: &'static str = "Content-Type";
+    fn decode(values: &[&str]) -> Result<Self, Error> {
+        content_type::Kind::from_str(single(values)).map(Self)
+    }
+    fn encode(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// Representation Header
+///
+/// Repeated fields and comma separated lists are accepted only when every
+/// value is the same, anything else is rejected as a possible smuggling attempt.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ContentLength(pub usize);
+impl Typed for ContentLength {
+    const NAME: &'static str = "Content-Length";
+    fn decode(values: &[&str]) -> Result<Self, Error> {
+        let mut content_length = None;
+        for content_length_string in values.iter().flat_map(|value| value.split(',')) {
+            let content_length_string = content_length_string.trim();
+            let parsed = if content_length_string.is_empty()
+                || !content_length_string.bytes().all(|b| b.is_ascii_digit())
+            {
+                None
+            } else {
+                content_length_string.parse::<usize>().ok()
+            };
+            match (parsed, content_length) {
+                (None, _) => {
+                    let kind = Kind::ContentLength(0usize);
+                    let message = format!(
+                        "error on trying to parse content length {}",
+                        content_length_string
+                    );
+                    return Err(Error::Parse(kind, message));
+                }
+                (Some(parsed), Some(previous)) if parsed.ne(&previous) => {
+                    let kind = Kind::ContentLength(previous);
+                    let message = format!("conflicting content lengths {}", values.join(", "));
+                    return Err(Error::Parse(kind, message));
+                }
+                (Some(parsed), _) => content_length = Some(parsed),
+            }
+        }
+        Ok(Self(content_length.unwrap_or_default()))
+    }
+    fn encode(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// General Header
+///
+/// Codings are lowercased and listed in the order they were applied.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TransferEncoding(pub Vec<String>);
+impl TransferEncoding {
+    /// Returns true if chunked is the final coding, which is required for requests
+    pub fn is_chunked(&self) -> bool {
+        self.0.last().is_some_and(|coding| coding.eq("chunked"))
+    }
+}
+impl Typed for TransferEncoding {
+    const NAME: &'static str = "Transfer-Encoding";
+    fn decode(values: &[&str]) -> Result<Self, Error> {
+        let codings = values
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|coding| coding.trim().to_ascii_lowercase())
+            .filter(|coding| !coding.is_empty())
+            .collect::<Vec<String>>();
+        if codings.is_empty() {
+            let kind = Kind::TransferEncoding(String::new());
+            Err(Error::Parse(
+                kind,
+                String::from("empty transfer coding list"),
+            ))
+        } else {
+            Ok(Self(codings))
+        }
+    }
+    fn encode(&self) -> String {
+        self.0.join(", ")
+    }
+}
+
+/// General Header
+///
+/// Sent as an IMF-fixdate, read in any of the formats of RFC 9110.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Date(pub SystemTime);
+impl Typed for Date {
+    const NAME: &'static str = "Date";
+    fn decode(values: &[&str]) -> Result<Self, Error> {
+        let value = single(values);
+        date::parse(value).map(Self).ok_or_else(|| {
+            let kind = Kind::Date(UNIX_EPOCH);
+            let message = format!("error on trying to parse date {}", value);
+            Error::Parse(kind, message)
+        })
+    }
+    fn encode(&self) -> String {
+        date::format(self.0)
+    }
+}
+
+/// Response Header
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Server(pub String);
+impl Typed for Server {
+    const NAME: &'static str = "Server";
+    fn decode(values: &[&str]) -> Result<Self, Error> {
+        Ok(Self(single(values).to_string()))
+    }
+    fn encode(&self) -> String {
+        self.0.clone()
+    }
+}
+
+/// Request Header
+///
+/// Name and value pairs of every Cookie field, in order; HTTP/2 clients may
+/// split them over several fields.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Cookie(pub Vec<(String, String)>);
+impl Typed for Cookie {
+    const NAME: &'static str = "Cookie";
+    fn decode(values: &[&str]) -> Result<Self, Error> {
+        Ok(Self(
+            values
+                .iter()
+                .flat_map(|value| cookie::parse_pairs(value))
+                .collect(),
+        ))
+    }
+    fn encode(&self) -> String {
+        let pairs = self
+            .0
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value));
+        pairs.collect::<Vec<String>>().join("; ")
+    }
+}
+
+/// Request Header
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Authorization(pub Credentials);
+impl Typed for Authorization {
+    const NAME: &'static str = "Authorization";
+    fn decode(values: &[&str]) -> Result<Self, Error> {
+        single(values).parse::<Credentials>().map(Self)
+    }
+    fn encode(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// Response Header
+///
+/// Challenges are kept as they were sent, see [`Challenge`] to build them.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WwwAuthenticate(pub String);
+impl Typed for WwwAuthenticate {
+    const NAME: &'static str = "WWW-Authenticate";
+    fn decode(values: &[&str]) -> Result<Self, Error> {
+        Ok(Self(combined(values)))
+    }
+    fn encode(&self) -> String {
+        self.0.clone()
+    }
+}
+impl From<Vec<Challenge>> for WwwAuthenticate {
+    fn from(challenges: Vec<Challenge>) -> Self {
+        let challenges = challenges.iter().map(Challenge::to_string);
+        Self(challenges.collect::<Vec<String>>().join(", "))
+    }
+}
+
+/// Response Header
+///
+/// Never combined: each cookie is set by its own field.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SetCookie(pub cookie::Cookie);
+impl Typed for SetCookie {
+    const NAME: &'static str = "Set-Cookie";
+    fn decode(values: &[&str]) -> Result<Self, Error> {
+        let value = single(values);
+        cookie::Cookie::parse(value).map(Self).map_err(|error| {
+            let kind = Kind::SetCookie(value.to_string());
+            Error::Parse(kind, error.to_string())
+        })
+    }
+    fn encode(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+impl From<Host> for Kind {
+    fn from(value: Host) -> Self {
+        Kind::Host(value.host, value.port)
+    }
+}
+impl From<UserAgent> for Kind {
+    fn from(value: UserAgent) -> Self {
+        Kind::UserAgent(value.0)
+    }
+}
+impl From<Accept> for Kind {
+    fn from(value: Accept) -> Self {
+        Kind::Accept(value.encode())
+    }
+}
+impl From<AcceptLanguage> for Kind {
+    fn from(value: AcceptLanguage) -> Self {
+        Kind::AcceptLanguage(value.encode())
+    }
+}
+impl From<AcceptEncoding> for Kind {
+    fn from(value: AcceptEncoding) -> Self {
+        Kind::AcceptEncoding(value.0)
+    }
+}
+impl From<Connection> for Kind {
+    fn from(value: Connection) -> Self {
+        Kind::Connection(value.0)
+    }
+}
+impl From<UpgradeInsecureRequests> for Kind {
+    fn from(value: UpgradeInsecureRequests) -> Self {
+        Kind::UpgradeInsecureRequests(value.0)
+    }
+}
+impl From<TransferEncoding> for Kind {
+    fn from(value: TransferEncoding) -> Self {
+        Kind::TransferEncoding(value.encode())
+    }
+}
+impl From<ContentType> for Kind {
+    fn from(value: ContentType) -> Self {
+        Kind::ContentType(value.0)
+    }
+}
+impl From<ContentLength> for Kind {
+    fn from(value: ContentLength) -> Self {
+        Kind::ContentLength(value.0)
+    }
+}
+impl From<Date> for Kind {
+    fn from(value: Date) -> Self {
+        Kind::Date(value.0)
+    }
+}
+impl From<Server> for Kind {
+    fn from(value: Server) -> Self {
+        Kind::Server(value.0)
+    }
+}
+impl From<Cookie> for Kind {
+    fn from(value: Cookie) -> Self {
+        Kind::Cookie(value.encode())
+    }
+}
+impl From<SetCookie> for Kind {
+    fn from(value: SetCookie) -> Self {
+        Kind::SetCookie(value.encode())
+    }
+}
+impl From<Authorization> for Kind {
+    fn from(value: Authorization) -> Self {
+        Kind::Authorization(value.encode())
+    }
+}
+impl From<WwwAuthenticate> for Kind {
+    fn from(value: WwwAuthenticate) -> Self {
+        Kind::WwwAuthenticate(value.0)
+    }
+}
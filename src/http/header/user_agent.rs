@@ -5,7 +5,7 @@ use {
         str::FromStr,
     },
 };
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 /// Various types of user-agents
 pub enum Kind {
     /// Curl user agent
@@ -0,0 +1,407 @@
+//! JSON values, parsed from request bodies and serialized into response bodies, see RFC 8259
+use std::fmt::{self, Display, Formatter};
+
+/// Deepest nesting of arrays and objects accepted, keeping the parser's recursion bounded
+pub const MAX_DEPTH: usize = 128;
+
+/// A JSON value
+///
+/// Object members keep the order they were parsed or built in. Duplicate
+/// names are kept too, [`Value::get`] returns the first.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+impl Value {
+    /// Object with the given members
+    pub fn object<N: Into<String>, I: IntoIterator<Item = (N, Value)>>(members: I) -> Self {
+        Value::Object(
+            members
+                .into_iter()
+                .map(|(name, value)| (name.into(), value))
+                .collect(),
+        )
+    }
+    /// Value of the first member named `name`, if this is an object
+    #[allow(dead_code)]
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members
+                .iter()
+                .find(|(member, _)| member.eq(name))
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+    /// Parses a complete JSON text, surrounded by nothing but whitespace
+    pub fn parse(text: &[u8]) -> Result<Self, Error> {
+        let mut parser = Parser { text, offset: 0 };
+        let value = parser.value(0)?;
+        parser.whitespace();
+        match parser.offset == text.len() {
+            true => Ok(value),
+            false => Err(parser.error("trailing characters")),
+        }
+    }
+}
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+impl From<u16> for Value {
+    fn from(value: u16) -> Self {
+        Value::Number(value.into())
+    }
+}
+impl Display for Value {
+    /// Serializes the value compactly, non-finite numbers becoming null
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(number) if !number.is_finite() => f.write_str("null"),
+            Value::Number(number) => write!(f, "{}", number),
+            Value::String(string) => write_string(f, string),
+            Value::Array(values) => {
+                f.write_str("[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("]")
+            }
+            Value::Object(members) => {
+                f.write_str("{")?;
+                for (index, (name, value)) in members.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+/// Writes `string` quoted, escaping what JSON requires to be
+fn write_string(f: &mut Formatter<'_>, string: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in string.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c < ' ' => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+/// Errors related to malformed JSON texts
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Error {
+    /// Byte offset the error was found at
+    pub offset: usize,
+    pub message: &'static str,
+}
+impl std::error::Error for Error {}
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let source = module_path!();
+        fmt::write(
+            f,
+            format_args!("[{}] {} at byte {}", source, self.message, self.offset),
+        )
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    offset: usize,
+}
+impl Parser<'_> {
+    fn error(&self, message: &'static str) -> Error {
+        Error {
+            offset: self.offset,
+            message,
+        }
+    }
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.offset).copied()
+    }
+    fn whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.offset += 1;
+        }
+    }
+    /// Consumes `literal` if the text continues with it
+    fn eat(&mut self, literal: &[u8]) -> bool {
+        let matched = self.text[self.offset..].starts_with(literal);
+        if matched {
+            self.offset += literal.len();
+        }
+        matched
+    }
+    fn expect(&mut self, byte: u8, message: &'static str) -> Result<(), Error> {
+        match self.eat(&[byte]) {
+            true => Ok(()),
+            false => Err(self.error(message)),
+        }
+    }
+    fn value(&mut self, depth: usize) -> Result<Value, Error> {
+        self.whitespace();
+        match self.peek() {
+            Some(b'{') | Some(b'[') if depth >= MAX_DEPTH => Err(self.error("nesting too deep")),
+            Some(b'{') => self.object(depth + 1),
+            Some(b'[') => self.array(depth + 1),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ if self.eat(b"true") => Ok(Value::Bool(true)),
+            _ if self.eat(b"false") => Ok(Value::Bool(false)),
+            _ if self.eat(b"null") => Ok(Value::Null),
+            None => Err(self.error("unexpected end of text")),
+            Some(_) => Err(self.error("expected a value")),
+        }
+    }
+    fn array(&mut self, depth: usize) -> Result<Value, Error> {
+        self.offset += 1;
+        let mut values = vec![];
+        self.whitespace();
+        if self.eat(b"]") {
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value(depth)?);
+            self.whitespace();
+            if self.eat(b"]") {
+                return Ok(Value::Array(values));
+            }
+            self.expect(b',', "expected , or ]")?;
+        }
+    }
+    fn object(&mut self, depth: usize) -> Result<Value, Error> {
+        self.offset += 1;
+        let mut members = vec![];
+        self.whitespace();
+        if self.eat(b"}") {
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a member name"));
+            }
+            let name = self.string()?;
+            self.whitespace();
+            self.expect(b':', "expected :")?;
+            members.push((name, self.value(depth)?));
+            self.whitespace();
+            if self.eat(b"}") {
+                return Ok(Value::Object(members));
+            }
+            self.expect(b',', "expected , or }")?;
+        }
+    }
+    fn string(&mut self) -> Result<String, Error> {
+        self.offset += 1;
+        let mut string = String::new();
+        loop {
+            let start = self.offset;
+            while matches!(self.peek(), Some(byte) if byte != b'"' && byte != b'\\' && byte >= 0x20)
+            {
+                self.offset += 1;
+            }
+            let run = std::str::from_utf8(&self.text[start..self.offset]).map_err(|e| Error {
+                offset: start + e.valid_up_to(),
+                message: "invalid UTF-8",
+            })?;
+            string.push_str(run);
+            match self.peek() {
+                Some(b'"') => {
+                    self.offset += 1;
+                    return Ok(string);
+                }
+                Some(b'\\') => {
+                    self.offset += 1;
+                    string.push(self.escape()?);
+                }
+                Some(_) => return Err(self.error("control character in string")),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+    /// Decodes the escape sequence following a backslash
+    fn escape(&mut self) -> Result<char, Error> {
+        let escaped = self
+            .peek()
+            .ok_or_else(|| self.error("unterminated string"))?;
+        self.offset += 1;
+        let c = match escaped {
+            b'"' => '"',
+            b'\\' => '\\',
+            b'/' => '/',
+            b'b' => '\u{8}',
+            b'f' => '\u{c}',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => {
+                let unit = self.code_unit()?;
+                let code_point = match unit {
+                    0xd800..=0xdbff => {
+                        if !self.eat(b"\\u") {
+                            return Err(self.error("unpaired surrogate"));
+                        }
+                        let low = self.code_unit()?;
+                        if !(0xdc00..=0xdfff).contains(&low) {
+                            return Err(self.error("unpaired surrogate"));
+                        }
+                        0x10000 + ((unit - 0xd800) << 10) + (low - 0xdc00)
+                    }
+                    0xdc00..=0xdfff => return Err(self.error("unpaired surrogate")),
+                    unit => unit,
+                };
+                char::from_u32(code_point).ok_or_else(|| self.error("invalid code point"))?
+            }
+            _ => return Err(self.error("invalid escape")),
+        };
+        Ok(c)
+    }
+    /// Reads the four hex digits of a `\u` escape
+    fn code_unit(&mut self) -> Result<u32, Error> {
+        let digits = self
+            .text
+            .get(self.offset..self.offset + 4)
+            .filter(|digits| digits.iter().all(u8::is_ascii_hexdigit))
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.offset += 4;
+        let digits = std::str::from_utf8(digits).unwrap_or_default();
+        Ok(u32::from_str_radix(digits, 16).unwrap_or_default())
+    }
+    fn number(&mut self) -> Result<Value, Error> {
+        let start = self.offset;
+        self.eat(b"-");
+        let digits = |parser: &mut Self| {
+            let from = parser.offset;
+            while matches!(parser.peek(), Some(b'0'..=b'9')) {
+                parser.offset += 1;
+            }
+            parser.offset - from
+        };
+        match self.peek() {
+            Some(b'0') => self.offset += 1,
+            Some(b'1'..=b'9') => {
+                digits(self);
+            }
+            _ => return Err(self.error("expected a digit")),
+        }
+        if self.eat(b".") && digits(self) == 0 {
+            return Err(self.error("expected a digit"));
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.offset += 1;
+            if !self.eat(b"+") {
+                self.eat(b"-");
+            }
+            if digits(self) == 0 {
+                return Err(self.error("expected a digit"));
+            }
+        }
+        std::str::from_utf8(&self.text[start..self.offset])
+            .ok()
+            .and_then(|number| number.parse::<f64>().ok())
+            .map(Value::Number)
+            .ok_or_else(|| self.error("invalid number"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Value, MAX_DEPTH};
+
+    fn parse(text: &str) -> Result<Value, super::Error> {
+        Value::parse(text.as_bytes())
+    }
+
+    #[test]
+    fn values_are_parsed() {
+        let value =
+            parse(r#" {"a": [1, -2.5e1, true, null], "b": "x\"\u00e9\ud83d\ude00"} "#).unwrap();
+        assert_eq!(
+            value.get("a"),
+            Some(&Value::Array(vec![
+                Value::Number(1.0),
+                Value::Number(-25.0),
+                Value::Bool(true),
+                Value::Null
+            ]))
+        );
+        assert_eq!(value.get("b"), Some(&Value::from("x\"é😀")));
+    }
+
+    #[test]
+    fn malformed_texts_are_rejected() {
+        for text in [
+            "",
+            "{",
+            "[1,]",
+            "{\"a\" 1}",
+            "01",
+            "1.",
+            "-",
+            "\"\\x\"",
+            "\"\\ud800\"",
+            "nul",
+            "[] []",
+            "\"a\nb\"",
+            "{1: 2}",
+        ] {
+            assert!(parse(text).is_err(), "{:?} parsed", text);
+        }
+        let error = parse("[1, x]").unwrap_err();
+        assert_eq!(error.offset, 4);
+        assert!(parse(&"[".repeat(MAX_DEPTH + 1)).is_err());
+        assert!(parse(&format!(
+            "{}{}",
+            "[".repeat(MAX_DEPTH),
+            "]".repeat(MAX_DEPTH)
+        ))
+        .is_ok());
+    }
+
+    #[test]
+    fn values_are_serialized() {
+        let value = Value::object([
+            ("s", Value::from("a\"b\\\n\u{1}")),
+            (
+                "n",
+                Value::Array(vec![Value::Number(3.0), Value::Number(0.5)]),
+            ),
+            ("o", Value::object([("nan", Value::Number(f64::NAN))])),
+        ]);
+        let text = value.to_string();
+        assert_eq!(
+            text,
+            r#"{"s":"a\"b\\\n\u0001","n":[3,0.5],"o":{"nan":null}}"#
+        );
+        assert_eq!(parse(&text).unwrap().get("s"), value.get("s"));
+    }
+}
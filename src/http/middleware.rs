@@ -0,0 +1,278 @@
+//! Layers wrapping request handling, run in the order they are declared on the router
+use {
+    crate::http::{
+        connection::Handler,
+        header::{
+            accept,
+            content_type::Kind::{Json, Plaintext},
+            typed::{self, ContentType},
+            Kind, Typed,
+        },
+        json::Value,
+        request::id,
+        response::Status,
+        Request, Response,
+    },
+    std::{
+        io,
+        panic::{self, AssertUnwindSafe},
+        sync::Arc,
+        time::Instant,
+    },
+};
+
+/// Wraps the handling of a request
+///
+/// Code before `next.run` acts as a before hook and code after it as an after
+/// hook, while returning without calling it short-circuits the rest of the
+/// pipeline, handler included.
+pub trait Layer: Send + Sync {
+    fn handle(&self, request: Request, next: Next<'_>) -> io::Result<Response>;
+}
+
+/// The layers following the current one, and the handler they wrap
+pub struct Next<'a> {
+    layers: &'a [Arc<dyn Layer>],
+    handler: &'a Handler,
+}
+impl<'a> Next<'a> {
+    pub fn new(layers: &'a [Arc<dyn Layer>], handler: &'a Handler) -> Self {
+        Self { layers, handler }
+    }
+    /// Passes `request` on to the next layer, or to the handler after the last one
+    pub fn run(self, request: Request) -> io::Result<Response> {
+        match self.layers.split_first() {
+            Some((layer, layers)) => layer.handle(request, Next::new(layers, self.handler)),
+            None => (self.handler)(request),
+        }
+    }
+}
+
+/// Writes the access log: each request line and the status it was answered with
+///
+/// Lines carry the request id when a [`RequestId`] layer runs before this one.
+pub struct Logging;
+impl Layer for Logging {
+    fn handle(&self, request: Request, next: Next<'_>) -> io::Result<Response> {
+        let line = format!(
+            "{} {} {}",
+            request.start_line.method, request.start_line.target.path, request.start_line.version
+        );
+        log_from_mod!("request", line);
+        match next.run(request) {
+            Ok(response) => {
+                log_from_mod!(
+                    "response",
+                    format!("{} -> {}", line, response.start_line.status)
+                );
+                Ok(response)
+            }
+            Err(e) => {
+                elog_from_mod!("request failed", e);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Measures how long the rest of the pipeline takes, reported in a Server-Timing header
+pub struct Timing;
+impl Layer for Timing {
+    fn handle(&self, request: Request, next: Next<'_>) -> io::Result<Response> {
+        let start = Instant::now();
+        let mut response = next.run(request)?;
+        let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+        log_from_mod!("handled in ms", format!("{:.3}", elapsed));
+        response
+            .headers
+            .append("Server-Timing", format!("app;dur={:.3}", elapsed));
+        Ok(response)
+    }
+}
+
+/// Tags every request with an X-Request-Id, visible to handlers and echoed in the response
+///
+/// Valid ids sent by the client are adopted, others replaced by a generated
+/// one, see [`id::assign`]. Requests read off a connection already carry the
+/// id it gave them. Log lines written while the rest of the pipeline runs
+/// carry the id.
+pub struct RequestId;
+impl Layer for RequestId {
+    fn handle(&self, mut request: Request, next: Next<'_>) -> io::Result<Response> {
+        let id = id::assign(&mut request);
+        let _scope = id::enter(&id);
+        let mut response = next.run(request)?;
+        response.headers.insert(id::HEADER, id);
+        Ok(response)
+    }
+}
+
+/// Gives error responses a JSON body when the client prefers JSON to plain text
+///
+/// Only responses without a body or with a plain text one are changed, the
+/// text becoming the message of the error. Other bodies are left as they are.
+pub struct JsonErrors;
+impl Layer for JsonErrors {
+    fn handle(&self, request: Request, next: Next<'_>) -> io::Result<Response> {
+        let prefers_json = accept::negotiate(request.headers(), &[Plaintext, Json]) == Some(Json);
+        let id = request.id().map(str::to_string);
+        let mut response = next.run(request)?;
+        let status = response.start_line.status;
+        if !prefers_json || !status.is_error() || response.stream.is_some() {
+            return Ok(response);
+        }
+        let message = match (response.headers.typed::<ContentType>(), &response.body) {
+            (None, None) => None,
+            (None, Some(body)) if body.is_empty() => None,
+            (Some(ContentType(Plaintext)), body) => body
+                .as_deref()
+                .map(|body| String::from_utf8_lossy(body).to_string()),
+            _ => return Ok(response),
+        };
+        let mut error = vec![
+            ("status", Value::from(status as u16)),
+            ("reason", Value::from(status.reason())),
+        ];
+        error.extend(message.map(|message| ("message", Value::from(message))));
+        error.extend(id.map(|id| ("request_id", Value::from(id))));
+        response.headers.remove(typed::ContentType::NAME);
+        response.headers.push(Kind::ContentType(Json));
+        response.headers.append("Vary", typed::Accept::NAME);
+        response.body = Some(
+            Value::object([("error", Value::object(error))])
+                .to_string()
+                .into_bytes(),
+        );
+        Ok(response)
+    }
+}
+
+/// Answers with 500 when the rest of the pipeline panics, keeping the connection alive
+pub struct CatchPanic;
+impl Layer for CatchPanic {
+    fn handle(&self, request: Request, next: Next<'_>) -> io::Result<Response> {
+        match panic::catch_unwind(AssertUnwindSafe(|| next.run(request))) {
+            Ok(result) => result,
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                elog_from_mod!("handler panicked", message);
+                Ok(Response::empty(Status::InternalServerError))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CatchPanic, JsonErrors, Layer, Next, RequestId};
+    use crate::http::{connection::Handler, request::id, response::Status, Request, Response};
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    /// Records its name before and after the rest of the pipeline runs
+    struct Trace(&'static str, Arc<Mutex<Vec<String>>>);
+    impl Layer for Trace {
+        fn handle(&self, request: Request, next: Next<'_>) -> io::Result<Response> {
+            self.1.lock().unwrap().push(format!("before {}", self.0));
+            let response = next.run(request);
+            self.1.lock().unwrap().push(format!("after {}", self.0));
+            response
+        }
+    }
+
+    fn request(raw: &str) -> Request {
+        Request::try_construct(raw.as_bytes()).unwrap()
+    }
+
+    fn ok() -> Handler {
+        Arc::new(|_| Ok(Response::empty(Status::Ok)))
+    }
+
+    #[test]
+    fn layers_run_in_declared_order() {
+        let trace = Arc::new(Mutex::new(vec![]));
+        let layers: Vec<Arc<dyn Layer>> = vec![
+            Arc::new(Trace("outer", trace.clone())),
+            Arc::new(Trace("inner", trace.clone())),
+        ];
+        let handler = ok();
+        Next::new(&layers, &handler)
+            .run(request("GET / HTTP/1.1\r\nHost: a\r\n\r\n"))
+            .unwrap();
+        assert_eq!(
+            *trace.lock().unwrap(),
+            ["before outer", "before inner", "after inner", "after outer"]
+        );
+    }
+
+    #[test]
+    fn panics_become_internal_server_errors() {
+        let layers: Vec<Arc<dyn Layer>> = vec![Arc::new(CatchPanic)];
+        let handler: Handler = Arc::new(|_| panic!("boom"));
+        let response = Next::new(&layers, &handler)
+            .run(request("GET / HTTP/1.1\r\nHost: a\r\n\r\n"))
+            .unwrap();
+        assert_eq!(response.start_line.status as u16, 500);
+    }
+
+    #[test]
+    fn errors_get_json_bodies_when_preferred() {
+        let layers: Vec<Arc<dyn Layer>> = vec![Arc::new(JsonErrors)];
+        let handler: Handler = Arc::new(|_| Ok(Response::text(Status::NotFound, "no \"a\"")));
+        let raw = "GET /a HTTP/1.1\r\nHost: a\r\nAccept: application/json\r\n\r\n";
+        let response = Next::new(&layers, &handler).run(request(raw)).unwrap();
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("application/json")
+        );
+        assert_eq!(
+            response.body.unwrap(),
+            br#"{"error":{"status":404,"reason":"Not Found","message":"no \"a\""}}"#
+        );
+
+        let raw = "GET /a HTTP/1.1\r\nHost: a\r\nAccept: text/plain, application/json\r\n\r\n";
+        let response = Next::new(&layers, &handler).run(request(raw)).unwrap();
+        assert_eq!(response.headers.get("Content-Type"), Some("text/plain"));
+
+        let handler = ok();
+        let raw = "GET /a HTTP/1.1\r\nHost: a\r\nAccept: application/json\r\n\r\n";
+        let response = Next::new(&layers, &handler).run(request(raw)).unwrap();
+        assert_eq!(response.body, None);
+    }
+
+    #[test]
+    fn request_ids_reach_the_handler_and_the_response() {
+        let layers: Vec<Arc<dyn Layer>> = vec![Arc::new(RequestId)];
+        let handler: Handler = Arc::new(|request| {
+            let mut response = Response::empty(Status::Ok);
+            response
+                .headers
+                .insert("Seen", request.id().unwrap_or_default());
+            response
+                .headers
+                .insert("Logged", id::current().unwrap_or_default());
+            Ok(response)
+        });
+        let response = Next::new(&layers, &handler)
+            .run(request("GET / HTTP/1.1\r\nHost: a\r\n\r\n"))
+            .unwrap();
+        let generated = response.headers.get(id::HEADER).unwrap();
+        assert_eq!(response.headers.get("Seen"), Some(generated));
+        assert_eq!(response.headers.get("Logged"), Some(generated));
+        assert_eq!(id::current(), None);
+
+        let raw = "GET / HTTP/1.1\r\nHost: a\r\nX-Request-Id: abc-123\r\n\r\n";
+        let response = Next::new(&layers, &handler).run(request(raw)).unwrap();
+        assert_eq!(response.headers.get(id::HEADER), Some("abc-123"));
+
+        let raw = "GET / HTTP/1.1\r\nHost: a\r\nX-Request-Id: a b\r\n\r\n";
+        let response = Next::new(&layers, &handler).run(request(raw)).unwrap();
+        assert_ne!(response.headers.get(id::HEADER), Some("a b"));
+    }
+}
@@ -1,43 +1,91 @@
-use error::Error;
+pub use error::Error;
+use header::Typed;
 #[allow(unused_imports)]
-use std::{fmt, io::Write, path::PathBuf, str::FromStr};
+use std::{
+    fmt,
+    io::{Read, Write},
+    path::PathBuf,
+    str::FromStr,
+};
 
+pub(crate) mod auth;
+pub(crate) mod base64;
+pub(crate) mod body;
+#[cfg(test)]
+mod conformance;
+pub(crate) mod connection;
+pub(crate) mod cookie;
+pub(crate) mod cors;
 mod error;
+pub(crate) mod form;
+pub(crate) mod h2;
 pub(crate) mod header;
+pub(crate) mod json;
+pub(crate) mod middleware;
+pub(crate) mod proxy;
+pub(crate) mod ratelimit;
 pub(crate) mod request;
 pub(crate) mod response;
+pub(crate) mod router;
+pub(crate) mod session;
+pub(crate) mod sse;
+pub(crate) mod websocket;
 
 #[allow(dead_code)]
 const OK: &str = "HTTP/1.1 200 OK\r\n";
 #[allow(dead_code)]
 const NOT_FOUND: &str = "HTTP/1.1 404 Not Found\r\n\r\n";
-const MAX_BUFFER_SIZE: usize = 1024;
 
 /// Struct to handle HTTP version
-#[derive(Copy, Clone, Debug)]
+///
+/// The minor version is `None` for versions that have none, like HTTP/2.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct Version(u8, Option<u8>);
+impl Version {
+    pub const HTTP_1_0: Version = Version(1u8, Some(0u8));
+    pub const HTTP_1_1: Version = Version(1u8, Some(1u8));
+    /// Version of requests received over HTTP/2 frames, never negotiated from a start line
+    pub const HTTP_2: Version = Version(2u8, None);
+    /// Versions this server can answer with, lowest first
+    const SUPPORTED: [Version; 2] = [Version::HTTP_1_0, Version::HTTP_1_1];
+
+    /// Picks the version to respond with: the highest supported version within
+    /// the major version of the request, or `None` if that major is not supported
+    pub fn negotiate(&self) -> Option<Version> {
+        Self::SUPPORTED
+            .into_iter()
+            .filter(|supported| supported.0.eq(&self.0))
+            .max_by_key(|supported| supported.1)
+    }
+    /// Returns true if connections stay open unless the client asks to close them
+    pub fn persistent_by_default(&self) -> bool {
+        (self.0, self.1.unwrap_or_default()) >= (1u8, 1u8)
+    }
+}
 impl fmt::Display for Version {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.1 {
-            Some(1u8) => fmt::write(f, format_args!("HTTP/1.1")),
-            None => fmt::write(f, format_args!("HTTP/2")),
-            _ => todo!(),
+            Some(minor) => fmt::write(f, format_args!("HTTP/{}.{}", self.0, minor)),
+            None => fmt::write(f, format_args!("HTTP/{}", self.0)),
         }
     }
 }
 impl FromStr for Version {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digit = |digits: &str| match digits.as_bytes() {
+            [d] if d.is_ascii_digit() => Some(d - b'0'),
+            _ => None,
+        };
         match s.split_once('/') {
             Some(("HTTP", version)) => {
-                let (major, minor) = match version.split_once('.') {
-                    Some((major_string, minor_string)) => (
-                        major_string.parse::<u8>().unwrap(),
-                        minor_string.parse::<u8>().ok(),
-                    ),
-                    _ => (version.parse::<u8>().unwrap(), None),
+                let parsed = match version.split_once('.') {
+                    Some((major_string, minor_string)) => digit(major_string)
+                        .zip(digit(minor_string))
+                        .map(|(major, minor)| Self(major, Some(minor))),
+                    None => digit(version).map(|major| Self(major, None)),
                 };
-                Ok(Self(major, minor))
+                parsed.ok_or_else(|| Error::ParseVersionFormat(s.to_string()))
             }
             Some((first, secnd)) => Err(Error::ParseVersion(first.to_string(), secnd.to_string())),
             _ => Err(Error::ParseVersionFormat(s.to_string())),
@@ -46,93 +94,210 @@ impl FromStr for Version {
 }
 /// Typedef for Headers
 pub type Header = crate::http::header::Kind;
+/// Typedef for the map holding every header field of a message
+pub type HeaderMap = crate::http::header::HeaderMap;
 
 /// HTTP requests are messages sent by the client to initiate an action on the server
 #[allow(dead_code)]
 pub struct Request {
     start_line: request::Startline,
-    headers: Vec<Header>,
+    headers: HeaderMap,
     body: Option<Vec<u8>>,
+    /// Address of the client on the other end of the connection, once known
+    peer: Option<std::net::IpAddr>,
+    /// Whether the request arrived over TLS
+    secure: bool,
+    /// Session of the client, when a [`session::Sessions`] layer runs
+    session: Option<session::Handle>,
+    /// Body still arriving when the route streams it, see [`Request::take_body`]
+    source: Option<body::Source>,
 }
 impl Request {
-    pub fn try_construct(
-        request_buffer: &[u8; MAX_BUFFER_SIZE],
-        bytes_read: usize,
-    ) -> Option<Self> {
-        log_from_mod!("Request::try_construct [enter]");
-        let request_str = request_buffer[0..bytes_read]
-            .iter()
-            .map(|x| *x as char)
-            .collect::<String>();
-        let request_lines = request_str
-            .lines()
-            .filter(|s| !s.is_empty())
-            .collect::<Vec<&str>>();
-        let start_line =
-            request::Startline::from_str(request_lines.get(0usize).unwrap_or(&"")).ok();
-        let headers = request_lines
-            .iter()
-            .filter_map(
-                |request_line| match str::parse::<header::Kind>(request_line) {
-                    Ok(h) => {
-                        log_from_mod!("Header: ", h.to_string());
-                        Some(h)
-                    }
-                    Err(_e) => None,
-                },
-            )
-            .collect::<Vec<header::Kind>>();
-        // number of header lines plus 1 for start line
-        let body_offset = headers.len() + 1;
-        let body = request_lines
-            .iter()
-            .filter_map_i(|(i, e)| {
-                if i.ge(&body_offset) {
-                    log_from_mod!("Body: ", e);
-                    Some(e.as_bytes().to_vec())
-                } else {
-                    None
-                }
-            })
-            .flatten()
-            .collect::<Vec<u8>>();
-        if let Some(start_line) = start_line {
-            let request = if body.len() > 0usize {
-                Request {
-                    start_line,
-                    headers,
-                    body: Some(body),
-                }
-            } else {
-                Request {
-                    start_line,
-                    headers,
-                    body: None,
-                }
-            };
-            Some(request)
+    /// Parses a complete request from the bytes read off the connection
+    ///
+    /// Field names and values are validated strictly, and requests whose
+    /// framing is ambiguous (conflicting Content-Length values, or both
+    /// Content-Length and Transfer-Encoding) are rejected.
+    #[allow(dead_code)]
+    pub fn try_construct(request_bytes: &[u8]) -> Result<Self, Error> {
+        match Self::parse(request_bytes)? {
+            Some((request, _consumed)) => Ok(request),
+            None if Self::head_end(request_bytes).is_none() => {
+                Err(Error::ParseHead(String::from("missing end of head")))
+            }
+            None => Err(Error::Framing(String::from("incomplete body"))),
+        }
+    }
+    /// Parses the first request in `request_bytes`
+    ///
+    /// Returns the request and the number of bytes it took up, or `Ok(None)`
+    /// if more bytes are needed to complete it.
+    pub fn parse(request_bytes: &[u8]) -> Result<Option<(Self, usize)>, Error> {
+        let (mut request, body_offset) = match Self::parse_head(request_bytes)? {
+            Some(parsed) => parsed,
+            None => return Ok(None),
+        };
+        let body = &request_bytes[body_offset..];
+        let (body, body_length) = match request.framing()? {
+            request::Framing::Empty => (vec![], 0),
+            request::Framing::Length(length) if body.len() >= length => {
+                (body[..length].to_vec(), length)
+            }
+            request::Framing::Length(_) => return Ok(None),
+            request::Framing::Chunked => match request::decode_chunked(body)? {
+                Some(decoded) => decoded,
+                None => return Ok(None),
+            },
+        };
+        request.set_body(body);
+        Ok(Some((request, body_offset + body_length)))
+    }
+    /// Parses the head of the first request in `request_bytes`, leaving the body unset
+    ///
+    /// Returns the request and the offset its body starts at, or `Ok(None)`
+    /// if the head has not been fully received.
+    pub fn parse_head(request_bytes: &[u8]) -> Result<Option<(Self, usize)>, Error> {
+        // the head ends at the first empty line, everything after it is body
+        let (head, body_offset) = match Self::head_end(request_bytes) {
+            Some(head_end) => (&request_bytes[..head_end], head_end + 4),
+            None => return Ok(None),
+        };
+        let head_str = head.iter().map(|x| *x as char).collect::<String>();
+        let mut head_lines = head_str.split("\r\n");
+        let start_line_str = head_lines.next().unwrap_or("");
+        let method = start_line_str.split(' ').next().unwrap_or_default();
+        if header::is_token(method) && request::Method::from_str(method).is_err() {
+            return Err(Error::MethodNotImplemented(method.to_string()));
+        }
+        let start_line = request::Startline::from_str(start_line_str)
+            .map_err(|e| Error::ParseHead(format!("{} in {}", e, start_line_str.escape_debug())))?;
+        if start_line.version.negotiate().is_none() {
+            return Err(Error::VersionNotSupported(start_line.version));
+        }
+        let mut headers = HeaderMap::new();
+        for header_line in head_lines {
+            let (name, value) = header::parse_field(header_line)?;
+            headers.append(name, value);
+        }
+        let request = Request {
+            start_line,
+            headers,
+            body: None,
+            peer: None,
+            secure: false,
+            session: None,
+            source: None,
+        };
+        Ok(Some((request, body_offset)))
+    }
+    /// Position of the empty line ending the head, if it has been received
+    fn head_end(request_bytes: &[u8]) -> Option<usize> {
+        request_bytes.windows(4).position(|w| w.eq(b"\r\n\r\n"))
+    }
+    /// Determines how the message body is delimited, see RFC 9112 section 6.3
+    ///
+    /// Conflicting Content-Length values, both Content-Length and
+    /// Transfer-Encoding, or a final transfer coding other than chunked are
+    /// rejected.
+    pub fn framing(&self) -> Result<request::Framing, Error> {
+        use header::typed::{ContentLength, TransferEncoding};
+        let content_length = self.headers.try_typed::<ContentLength>()?;
+        let transfer_encoding = self.headers.try_typed::<TransferEncoding>()?;
+        match (content_length, transfer_encoding) {
+            (Some(_), Some(_)) => Err(Error::Framing(String::from(
+                "both Content-Length and Transfer-Encoding present",
+            ))),
+            (None, Some(transfer_encoding)) if transfer_encoding.is_chunked() => {
+                Ok(request::Framing::Chunked)
+            }
+            (None, Some(transfer_encoding)) => Err(Error::Framing(format!(
+                "final transfer coding is not chunked: {}",
+                transfer_encoding.encode()
+            ))),
+            (Some(ContentLength(0)), None) | (None, None) => Ok(request::Framing::Empty),
+            (Some(ContentLength(content_length)), None) => {
+                Ok(request::Framing::Length(content_length))
+            }
+        }
+    }
+    /// Sets the body read after the head, an empty body counts as none
+    fn set_body(&mut self, body: Vec<u8>) {
+        self.body = match body.is_empty() {
+            true => None,
+            false => Some(body),
+        };
+    }
+    /// Takes the body to read it, as it arrives for the routes streaming
+    /// bodies and from memory otherwise, see [`router::Router::stream_bodies`]
+    pub fn take_body(&mut self) -> body::Source {
+        match self.source.take() {
+            Some(source) => source,
+            None => body::Source::buffered(self.body.take().unwrap_or_default()),
+        }
+    }
+    /// Returns true if the connection should stay open after responding
+    pub fn keep_alive(&self) -> bool {
+        if self.headers.has_token("Connection", "close") {
+            false
+        } else if self.start_line.version.persistent_by_default() {
+            true
         } else {
-            None
+            self.headers.has_token("Connection", "keep-alive")
         }
     }
-}
-impl fmt::Display for Response {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let headers = self
-            .headers
-            .clone()
-            .into_iter()
-            .fold(String::new(), |acc, elem| format!("{}{}", acc, elem));
-        match self.body.clone() {
-            Some(content) => std::fmt::write(
-                f,
-                format_args!("{}{}\r\n{}", self.start_line, headers, content),
-            ),
-            None => std::fmt::write(f, format_args!("{}{}\r\n", self.start_line, headers)),
+    /// Checks the Host header, which HTTP/1.1 requests must carry exactly once
+    pub fn validate_host(&self) -> Result<(), Error> {
+        match self.headers.get_all(header::typed::Host::NAME).count() {
+            0 if !self.start_line.version.persistent_by_default() => Ok(()),
+            0 => Err(Error::Host(String::from("missing Host header"))),
+            1 => self
+                .headers
+                .try_typed::<header::typed::Host>()
+                .map(|_| ())
+                .map_err(Error::from),
+            _ => Err(Error::Host(String::from("more than one Host header"))),
+        }
+    }
+    /// Every header field sent with the request
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+    /// Method, target and version of the request
+    pub fn start_line(&self) -> &request::Startline {
+        &self.start_line
+    }
+    /// Address of the client that sent the request, without regard to proxies
+    pub fn peer(&self) -> Option<std::net::IpAddr> {
+        self.peer
+    }
+    /// Returns true if the request arrived over TLS
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+    /// Id tying the request to its log lines, set before handlers run
+    pub fn id(&self) -> Option<&str> {
+        self.headers.get(request::id::HEADER)
+    }
+    /// Body parsed as JSON, or `None` if its Content-Type is not application/json
+    ///
+    /// Requests without a body parse as an empty text would, failing.
+    pub fn json(&self) -> Option<Result<json::Value, json::Error>> {
+        match self.headers.typed::<header::typed::ContentType>() {
+            Some(header::typed::ContentType(header::content_type::Kind::Json)) => {
+                Some(json::Value::parse(self.body.as_deref().unwrap_or_default()))
+            }
+            _ => None,
         }
     }
+    /// Session of the client, `None` unless a [`session::Sessions`] layer runs
+    pub fn session(&self) -> Option<session::Handle> {
+        self.session.clone()
+    }
+    /// Cookies sent with the request, to read and change for the response
+    pub fn cookies(&self) -> cookie::Jar {
+        cookie::Jar::from_headers(&self.headers)
+    }
 }
-
 // #[derive(Clone)]
 // pub struct FilterMapI<I, F, A, B>
 // where
@@ -161,6 +326,7 @@ impl fmt::Display for Response {
 //     }
 // }
 
+#[allow(dead_code)]
 pub trait IteratorExtensions {
     fn for_each_i<F>(self, f: F)
     where
@@ -205,22 +371,188 @@ impl<I: Iterator + Sized> IteratorExtensions for I {}
 // }
 pub struct Response {
     start_line: response::Startline,
-    headers: Vec<header::Kind>,
-    body: Option<String>,
+    headers: HeaderMap,
+    body: Option<Vec<u8>>,
+    /// Body produced while it is being sent, in place of `body`
+    stream: Option<body::Producer>,
+    /// Length of the body a response without one stands for, see [`response::Builder::length_only`]
+    announced: Option<usize>,
+}
+impl Response {
+    /// Starts building a 200 response, see [`response::Builder`]
+    pub fn builder() -> response::Builder {
+        response::Builder::new()
+    }
+    /// Response for requests that could not be accepted, the connection is closed afterwards
+    pub fn from_error(error: &Error) -> Self {
+        use header::{connection::Kind::Close, Kind::*};
+        let status = match error {
+            Error::VersionNotSupported(_) => response::Status::HttpVersionNotSupported,
+            Error::Timeout(_) => response::Status::RequestTimeout,
+            Error::MethodNotImplemented(_) => response::Status::NotImplemented,
+            Error::ContentTooLarge(_) => response::Status::ContentTooLarge,
+            _ => response::Status::BadRequest,
+        };
+        Self::builder()
+            .status(status)
+            .kind(Connection(Close))
+            .empty()
+    }
+    /// Response with `status` and no body
+    pub fn empty(status: response::Status) -> Self {
+        Self::builder().status(status).empty()
+    }
+    /// Response with a plain text body
+    pub fn text<B: Into<String>>(status: response::Status, body: B) -> Self {
+        Self::builder()
+            .status(status)
+            .kind(header::Kind::ContentType(
+                header::content_type::Kind::Plaintext,
+            ))
+            .body(body.into())
+    }
+    /// Response with a binary body
+    pub fn bytes<B: Into<Vec<u8>>>(status: response::Status, body: B) -> Self {
+        Self::builder()
+            .status(status)
+            .kind(header::Kind::ContentType(
+                header::content_type::Kind::Appbytestream,
+            ))
+            .body(body)
+    }
+    /// Response with `value` serialized as its JSON body
+    pub fn json(status: response::Status, value: json::Value) -> Self {
+        Self::builder()
+            .status(status)
+            .kind(header::Kind::ContentType(header::content_type::Kind::Json))
+            .body(value.to_string())
+    }
+    /// Response sending the client to `location`, `status` telling for how long and with which method
+    pub fn redirect(status: response::Status, location: &str) -> Self {
+        Self::builder()
+            .status(status)
+            .header("Location", location)
+            .empty()
+    }
+    /// Response with the content of the file at `path`, or 404 if there is none
+    pub fn file<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+        match std::fs::read(path) {
+            Ok(content) => Ok(Self::bytes(response::Status::Ok, content)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Self::empty(response::Status::NotFound))
+            }
+            Err(e) => Err(e),
+        }
+    }
+    /// Response with `text` as plain text or `value` as JSON, whichever the client prefers
+    ///
+    /// Clients accepting neither get 406, with the types on offer listed.
+    fn negotiated(
+        headers: &HeaderMap,
+        status: response::Status,
+        text: String,
+        value: json::Value,
+    ) -> Self {
+        use header::content_type::Kind::{Json, Plaintext};
+        const OFFERS: [header::content_type::Kind; 2] = [Plaintext, Json];
+        let mut response = match header::accept::negotiate(headers, &OFFERS) {
+            Some(Json) => Self::json(status, value),
+            Some(_) => Self::text(status, text),
+            None => Self::text(
+                response::Status::NotAcceptable,
+                format!("available: {}, {}", OFFERS[0], OFFERS[1]),
+            ),
+        };
+        response.headers.append("Vary", header::typed::Accept::NAME);
+        response
+    }
+    /// Response listing the fields and stored files of a form, 201 if it carried files
+    fn form_received(headers: &HeaderMap, form: form::Form) -> Self {
+        let status = match form.files.is_empty() {
+            true => response::Status::Ok,
+            false => response::Status::Created,
+        };
+        let mut lines = vec![];
+        lines.extend(
+            form.files
+                .iter()
+                .map(|file| format!("file {} {} {}", file.name, file.filename, file.size)),
+        );
+        lines.extend(
+            form.fields
+                .iter()
+                .map(|(name, value)| format!("field {}={}", name, value)),
+        );
+        let files = form.files.into_iter().map(|file| {
+            json::Value::object([
+                ("field", json::Value::from(file.name)),
+                ("filename", json::Value::from(file.filename)),
+                ("size", json::Value::Number(file.size as f64)),
+            ])
+        });
+        let fields = form
+            .fields
+            .into_iter()
+            .map(|(name, value)| (name, json::Value::from(value)));
+        let object = json::Value::object([
+            ("files", json::Value::Array(files.collect())),
+            ("fields", json::Value::object(fields)),
+        ]);
+        Self::negotiated(headers, status, lines.join("\n"), object)
+    }
+    /// Sets the fields derived from the rest of the response, just before it is sent
+    ///
+    /// Content-Length always matches the body: it is left out of interim
+    /// responses and of streamed bodies, whose length is not known upfront.
+    /// Date and Server are added to final responses that do not carry them yet.
+    pub fn complete_headers(&mut self) {
+        use header::{typed, Kind::ContentLength};
+        self.headers.remove("Content-Length");
+        if self.start_line.status.is_informational() {
+            return;
+        }
+        // 204 responses never have a body, so they may not announce one either
+        let no_content = matches!(self.start_line.status, response::Status::NoContent);
+        if self.stream.is_none() && !no_content {
+            let length = self.body.as_ref().map(Vec::len).unwrap_or_default();
+            self.headers
+                .push(ContentLength(self.announced.unwrap_or(length)));
+        }
+        if !self.headers.contains(typed::Date::NAME) {
+            self.headers.insert(typed::Date::NAME, header::date::now());
+        }
+        if let Some(product) = header::server::product() {
+            if !self.headers.contains(typed::Server::NAME) {
+                self.headers.insert(typed::Server::NAME, product);
+            }
+        }
+    }
+    /// Status line, header fields and the empty line ending them
+    pub fn head(&self) -> String {
+        format!("{}{}\r\n", self.start_line, self.headers)
+    }
+    /// Complete HTTP/1.x message, ready to be written to the connection
+    pub fn serialize(mut self) -> Vec<u8> {
+        self.complete_headers();
+        let mut message = self.head().into_bytes();
+        message.extend(self.body.unwrap_or_default());
+        message
+    }
 }
 impl TryFrom<Request> for Response {
     type Error = std::io::Error;
     fn try_from(value: Request) -> Result<Self, Self::Error> {
-        use header::{content_type::Kind::*, Kind::*};
+        Self::builtin(value, crate::args::value("--directory"))
+    }
+}
+impl Response {
+    /// Answers `value` with the built-in handlers, `/files` being served from `directory`
+    pub fn builtin(mut value: Request, directory: Option<String>) -> std::io::Result<Self> {
         use request::Method::*;
-        log_from_mod!("request startline", value.start_line);
+        use response::Status;
 
-        let version = value.start_line.version;
-        let mut status = response::Status::NotFound;
-        let mut headers: Vec<header::Kind> = vec![];
-        let mut body = None;
         let request_path = value.start_line.target.path.clone();
-        let request_path_components = request_path.split('/').into_iter().collect::<Vec<&str>>();
+        let request_path_components = request_path.split('/').collect::<Vec<&str>>();
         let request_path_root = request_path_components.get(1usize);
         let request_path_remainder = request_path_components
             .clone()
@@ -231,154 +563,137 @@ impl TryFrom<Request> for Response {
         if let Some(root) = request_path_root.cloned() {
             log_from_mod!("root", root);
         };
+        // methods the built-in handlers answer at a path, for the whole server with `*`
+        let allow = match request_path_root {
+            Some(&"files") | Some(&"echo") | None => "GET, HEAD, POST, OPTIONS",
+            _ => "GET, HEAD, OPTIONS",
+        };
+        // HEAD is answered as GET, the connection leaves the body out
         match (value.start_line.method, request_path_root) {
-            (Get, _) if request_path.eq("/") => {
+            (Get | Head, _) if request_path.eq("/") => {
                 log_from_mod!("get index");
-                status = response::Status::Ok;
-                let start_line = response::Startline { version, status };
-                Ok(Self {
-                    start_line,
-                    headers,
-                    body,
-                })
+                Ok(Self::empty(Status::Ok))
             }
-            (Get, Some(&"echo")) => {
+            (Get | Head, Some(&"echo")) => {
                 log_from_mod!("get echo");
-                // let content = request_path_components
-                //     .into_iter()
-                //     .filter(|e| e.ne(&"echo"))
-                //     // .filter_map_i(|(i, e)| if i.gt(&1usize) { Some(e) } else { None })
-                //     // .enumerate()
-                //     // .filter_map(|(i, e)| if i.ne(&0usize) { Some(e) } else { None })
-                //     .collect::<Vec<&str>>()
-                //     .join("/");
                 let content = request_path_remainder.join("/");
-                Ok(Self {
-                    start_line: response::Startline {
-                        version,
-                        status: response::Status::Ok,
-                    },
-                    headers: vec![ContentType(Plaintext), ContentLength(content.len())],
-                    body: Some(content),
-                })
+                let object = json::Value::object([("echo", json::Value::from(content.as_str()))]);
+                Ok(Self::negotiated(
+                    &value.headers,
+                    Status::Ok,
+                    content,
+                    object,
+                ))
             }
-            (Get, Some(&"user-agent")) => {
+            (Get | Head, Some(&"user-agent")) => {
                 log_from_mod!("get user agent");
                 let content = value
                     .headers
-                    .iter()
-                    .filter_map(|x| match x {
-                        Header::UserAgent(user_agent) => Some(user_agent.to_string()),
-                        _ => None,
-                    })
-                    .nth(0usize)
-                    .unwrap();
-                headers.push(ContentType(Plaintext));
-                headers.push(ContentLength(content.len()));
-                body = Some(content);
-                status = response::Status::Ok;
-                let start_line = response::Startline { version, status };
-                Ok(Self {
-                    start_line,
-                    headers,
-                    body,
-                })
+                    .typed::<header::typed::UserAgent>()
+                    .map(|user_agent| user_agent.encode())
+                    .unwrap_or_default();
+                let object =
+                    json::Value::object([("user-agent", json::Value::from(content.as_str()))]);
+                Ok(Self::negotiated(
+                    &value.headers,
+                    Status::Ok,
+                    content,
+                    object,
+                ))
+            }
+            // files are only served and stored under the directory
+            (Get | Head | Post, Some(&"files")) if request_path_remainder.contains(&"..") => {
+                Ok(Self::empty(Status::Forbidden))
             }
-            (Get, Some(&"files")) => match std::env::args().nth(2usize) {
+            (Get | Head, Some(&"files")) => match directory {
                 Some(directory) => {
                     log_from_mod!("get files");
-                    // let content = request_path_components
-                    //     .into_iter()
-                    //     .enumerate()
-                    //     .filter_map(|(i, e)| if i.ne(&0usize) { Some(e) } else { None })
-                    //     .collect::<Vec<&str>>()
-                    //     .join("/");
                     let content = request_path_remainder.join("/");
-                    let file_string = vec![directory, content].join("/");
+                    let file_string = [directory, content].join("/");
                     log_from_mod!("{}", file_string.clone());
-                    let path = std::path::PathBuf::from(file_string);
-                    if path.exists() {
-                        let buf = std::fs::read(path.clone())?;
-                        let buf_str = String::from_utf8(buf).unwrap_or_default();
-                        log_from_mod!("{}", buf_str);
-                        headers.push(ContentType(header::content_type::Kind::Appbytestream));
-                        headers.push(ContentLength(buf_str.len()));
-                        body = Some(buf_str);
-                        status = response::Status::Ok;
-                    } else {
-                        log_from_mod!("path not found");
-                        status = response::Status::NotFound;
-                    };
-                    let start_line = response::Startline { version, status };
-                    Ok(Self {
-                        start_line,
-                        headers,
-                        body,
-                    })
-                }
-                None => {
-                    panic!();
+                    Self::file(PathBuf::from(file_string))
                 }
+                None => Ok(Self::empty(Status::NotFound)),
             },
-            (Get, Some(unknown)) => {
+            (Get | Head, Some(unknown)) => {
                 log_from_mod!("get unknown", unknown);
-                status = response::Status::NotFound;
-                let start_line = response::Startline { version, status };
-                log_from_mod!("responding with", start_line.to_string());
-                Ok(Self {
-                    start_line: response::Startline { version, status },
-                    headers: value.headers,
-                    body: None,
-                })
+                Ok(Self::empty(Status::NotFound))
             }
-            (Get, None) => todo!(),
-            (Post, Some(&"files")) => match std::env::args().nth(2usize) {
+            (Get | Head, None) => Ok(Self::empty(Status::NotFound)),
+            (Post, Some(&"files")) => match directory {
                 Some(directory) => {
                     log_from_mod!("post files");
-                    // let content = request_path_components
-                    //     .into_iter()
-                    //     .filter_map_i(|(i, e)| if i.ne(&0usize) { Some(e) } else { None })
-                    //     .collect::<Vec<&str>>()
-                    //     .join("/");
                     let content = request_path_remainder.join("/");
-                    let file_string = vec![directory, content].join("/");
+                    let file_string = [directory, content].join("/");
                     log_from_mod!("post path", file_string.clone());
-                    let path = std::path::PathBuf::from(file_string);
-                    match value.body {
-                        Some(body) => {
-                            let mut file = std::fs::File::create(path)?;
-                            let bytes_written = file.write(&body)?;
-                            // std::fs::write(path, body.clone())?;
-                            // headers.push(ContentType(header::content_type::Kind::Plaintext));
-                            // headers.push(ContentLength(body.len()));
-                            // let start_line = response::Startline { version, status };
-                            Ok(Self {
-                                start_line: response::Startline {
-                                    version,
-                                    status: response::Status::Created,
-                                },
-                                headers: vec![
-                                    ContentType(header::content_type::Kind::Plaintext),
-                                    ContentLength(bytes_written),
-                                ],
-                                body: None,
-                            })
+                    let path = PathBuf::from(file_string);
+                    use header::{
+                        content_type::Kind::{FormData, FormUrlencoded},
+                        typed::ContentType,
+                    };
+                    let mut body = value.take_body();
+                    // forms are posted to a directory, other bodies are stored as the file named
+                    let content_type = match path.is_dir() {
+                        true => value.headers.typed::<ContentType>(),
+                        false => None,
+                    };
+                    let form = match content_type {
+                        Some(ContentType(FormData)) => {
+                            let content_type = value.headers.get(ContentType::NAME);
+                            let boundary =
+                                content_type.and_then(form::boundary).unwrap_or_default();
+                            Some(form::multipart(&mut body, &boundary, &path))
                         }
-                        None => Ok(Self {
-                            start_line: response::Startline { version, status },
-                            headers: vec![],
-                            body: None,
-                        }),
+                        Some(ContentType(FormUrlencoded)) => {
+                            let mut content = vec![];
+                            body.read_to_end(&mut content)?;
+                            Some(form::urlencoded(&content))
+                        }
+                        _ => None,
+                    };
+                    match form {
+                        Some(Ok(form)) => Ok(Self::form_received(&value.headers, form)),
+                        Some(Err(form::Error::Malformed(message))) => {
+                            Ok(Self::text(Status::BadRequest, message))
+                        }
+                        Some(Err(form::Error::Io(e))) => Err(e),
+                        None => match form::store(body, &path)? {
+                            0 => Ok(Self::empty(Status::NotFound)),
+                            _ => Ok(Self::builder()
+                                .status(Status::Created)
+                                .kind(header::Kind::ContentType(
+                                    header::content_type::Kind::Plaintext,
+                                ))
+                                .empty()),
+                        },
                     }
                 }
-                None => {
-                    panic!()
-                }
+                None => Ok(Self::empty(Status::NotFound)),
             },
-            (Post, _) => todo!(),
-            (Put, _) => todo!(),
-            (Options, _) => todo!(),
-            (Head, _) => todo!(),
+            (Post, Some(&"echo")) => {
+                log_from_mod!("post echo");
+                match value.json() {
+                    Some(Ok(parsed)) => Ok(Self::json(Status::Ok, parsed)),
+                    Some(Err(e)) => Ok(Self::text(
+                        Status::BadRequest,
+                        format!("invalid JSON: {} at byte {}", e.message, e.offset),
+                    )),
+                    None => Ok(Self::text(
+                        Status::UnsupportedMediaType,
+                        "expected application/json",
+                    )),
+                }
+            }
+            (Options, _) => Ok(Self::builder()
+                .status(Status::NoContent)
+                .header("Allow", allow)
+                .empty()),
+            // tunnels are opened by the connection when the forward proxy is on
+            (Post | Put | Connect, _) => {
+                let mut response = Self::text(Status::MethodNotAllowed, "method not allowed");
+                response.headers.insert("Allow", allow);
+                Ok(response)
+            }
         }
     }
 }
@@ -0,0 +1,1036 @@
+//! Reverse proxy forwarding the requests under a prefix to an upstream server over HTTP/1.1
+//!
+//! Hop-by-hop fields are dropped in both directions, and the client is
+//! described to the upstream with Forwarded and X-Forwarded-* fields.
+//! Bodies are streamed in both directions: request bodies are sent upstream
+//! as the client sends them, chunked when their length is not known
+//! beforehand, and response bodies sent to the client as they arrive.
+//! Upstream connections are kept alive and reused between requests.
+//!
+//! The same forwarding serves [`ForwardProxy`], which clients address with
+//! absolute form targets and CONNECT requests instead of a prefix.
+use {
+    crate::http::{
+        auth::Htpasswd,
+        body::{is_disconnect, Producer, Sink, Source},
+        connection::{is_timeout, Socket},
+        header::{
+            self,
+            authorization::{Challenge, Credentials},
+            HeaderMap,
+        },
+        middleware::{Layer, Next},
+        request::{self, target, Method},
+        response::Status,
+        Request, Response,
+    },
+    std::{
+        collections::HashMap,
+        io::{self, BufRead, BufReader, Read, Write},
+        net::{Shutdown, TcpStream, ToSocketAddrs},
+        path::Path,
+        str::FromStr,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+};
+
+/// Fields that only concern a single connection, see RFC 9110 section 7.6.1
+pub const HOP_BY_HOP: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+/// Idle connections kept open to each upstream
+pub const MAX_IDLE: usize = 8;
+/// Time to connect to an upstream
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time an upstream gets to start its response, and between reads of it,
+/// when no `--proxy-timeout` is given
+pub const READ_TIMEOUT: Duration = Duration::from_secs(30);
+/// Destinations a forward proxy keeps connections to before it starts over
+const MAX_UPSTREAMS: usize = 64;
+/// Time a tunnel may stay without traffic in either direction
+pub const TUNNEL_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+/// Longest a tunnel waits on one side before checking the other
+pub const TUNNEL_TICK: Duration = Duration::from_millis(20);
+/// Largest response head accepted from an upstream
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+/// Size of the reads copying a response body
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Removes the hop-by-hop fields of `headers`, and those the Connection field names
+pub fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let named = headers
+        .get_all("Connection")
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<String>>();
+    for name in HOP_BY_HOP
+        .iter()
+        .copied()
+        .chain(named.iter().map(String::as_str))
+    {
+        headers.remove(name);
+    }
+}
+
+/// Connects to `address`, bounding reads and writes by `timeout`
+fn open(address: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} resolves to no address", address),
+    );
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                stream.set_nodelay(true)?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Server requests are forwarded to, with the connections left open to it
+pub struct Upstream {
+    address: String,
+    read_timeout: Duration,
+    idle: Mutex<Vec<TcpStream>>,
+}
+impl Upstream {
+    pub fn new<A: Into<String>>(address: A, read_timeout: Duration) -> Self {
+        Self {
+            address: address.into(),
+            read_timeout,
+            idle: Mutex::new(vec![]),
+        }
+    }
+    /// An idle connection if one is left, `true` telling it was used before, or a new one
+    fn checkout(&self) -> io::Result<(TcpStream, bool)> {
+        let idle = self.idle.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        if let Some(stream) = idle {
+            return Ok((stream, true));
+        }
+        open(&self.address, self.read_timeout).map(|stream| (stream, false))
+    }
+    /// Keeps `stream` for a later request, unless enough connections are idle
+    fn checkin(&self, stream: TcpStream) {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        if idle.len() < MAX_IDLE {
+            idle.push(stream);
+        }
+    }
+}
+
+/// How the end of a response body is found
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Framing {
+    Empty,
+    Length(u64),
+    Chunked,
+    /// The upstream closes the connection after the body
+    Close,
+}
+
+/// Head of a response read from an upstream
+struct Head {
+    status: Status,
+    headers: HeaderMap,
+    framing: Framing,
+    /// Whether the connection can be reused once the body is read
+    keep_alive: bool,
+}
+
+/// Reads a line ending with CRLF or LF, failing once `limit` bytes are read without one
+fn read_line<R: BufRead>(reader: &mut R, limit: &mut usize) -> io::Result<String> {
+    let mut line = vec![];
+    let read = reader
+        .by_ref()
+        .take(*limit as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "upstream closed the connection",
+        ));
+    }
+    if !line.ends_with(b"\n") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "upstream response head too large",
+        ));
+    }
+    *limit -= read;
+    let line = line.strip_suffix(b"\n").unwrap_or(&line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    Ok(line.iter().map(|b| *b as char).collect())
+}
+
+/// Reads the head of the final response to a request, skipping interim ones
+fn read_head<R: BufRead>(reader: &mut R, method: &Method) -> io::Result<Head> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    loop {
+        let mut limit = MAX_HEAD_SIZE;
+        let status_line = read_line(reader, &mut limit)?;
+        let mut parts = status_line.splitn(3, ' ');
+        let version = parts.next().unwrap_or_default();
+        let code = parts.next().and_then(|code| code.parse::<u16>().ok());
+        let (http_1_1, code) = match (version, code) {
+            ("HTTP/1.1", Some(code)) => (true, code),
+            ("HTTP/1.0", Some(code)) => (false, code),
+            _ => return Err(invalid(format!("invalid status line {}", status_line))),
+        };
+        let mut headers = HeaderMap::new();
+        loop {
+            let line = read_line(reader, &mut limit)?;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = header::parse_field(&line).map_err(|e| invalid(e.to_string()))?;
+            headers.append(name, value);
+        }
+        let status =
+            Status::from_code(code).ok_or_else(|| invalid(format!("unknown status {}", code)))?;
+        if status.is_informational() {
+            if let Status::SwitchingProtocols = status {
+                return Err(invalid(String::from("upstream switched protocols")));
+            }
+            continue;
+        }
+        let keep_alive = match http_1_1 {
+            true => !headers.has_token("Connection", "close"),
+            false => headers.has_token("Connection", "keep-alive"),
+        };
+        let no_body = matches!(method, Method::Head)
+            || matches!(status, Status::NoContent | Status::NotModified);
+        let framing = if no_body {
+            Framing::Empty
+        } else if headers.has_token("Transfer-Encoding", "chunked") {
+            Framing::Chunked
+        } else if let Some(length) = headers.get("Content-Length") {
+            let length = length
+                .parse::<u64>()
+                .map_err(|_| invalid(format!("invalid content length {}", length)))?;
+            Framing::Length(length)
+        } else {
+            Framing::Close
+        };
+        return Ok(Head {
+            status,
+            headers,
+            framing,
+            keep_alive: keep_alive && framing != Framing::Close,
+        });
+    }
+}
+
+/// Copies `length` bytes of `reader` to `sink`
+fn copy<R: Read>(reader: &mut R, sink: &mut dyn Sink, length: u64) -> io::Result<()> {
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut left = length;
+    while left > 0 {
+        let wanted = left.min(CHUNK_SIZE as u64) as usize;
+        let read = reader.read(&mut buffer[..wanted])?;
+        if read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "upstream body ended early",
+            ));
+        }
+        sink.send(&buffer[..read])?;
+        left -= read as u64;
+    }
+    Ok(())
+}
+
+/// Sends `body` to `stream`, chunked if its length is not known beforehand
+fn send_body<W: Write>(stream: &mut W, body: &mut Source) -> io::Result<()> {
+    if body.length().is_some() {
+        return io::copy(body, stream).map(|_| ());
+    }
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        let read = body.read(&mut buffer)?;
+        if read == 0 {
+            return stream.write_all(b"0\r\n\r\n");
+        }
+        write!(stream, "{:x}\r\n", read)?;
+        stream.write_all(&buffer[..read])?;
+        stream.write_all(b"\r\n")?;
+    }
+}
+
+/// Copies a chunked body of `reader` to `sink`, see RFC 9112 section 7.1
+fn copy_chunked<R: BufRead>(reader: &mut R, sink: &mut dyn Sink) -> io::Result<()> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    loop {
+        let line = read_line(reader, &mut MAX_HEAD_SIZE.clone())?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = u64::from_str_radix(size, 16).map_err(|_| invalid("invalid chunk size"))?;
+        if size == 0 {
+            // trailer fields are dropped, they end with an empty line
+            let mut limit = MAX_HEAD_SIZE;
+            while !read_line(reader, &mut limit)?.is_empty() {}
+            return Ok(());
+        }
+        copy(reader, sink, size)?;
+        if !read_line(reader, &mut 2)?.is_empty() {
+            return Err(invalid("chunk longer than its size"));
+        }
+    }
+}
+
+/// Forwards the requests under `prefix` to its upstream
+pub struct Proxy {
+    pub prefix: String,
+    upstream: Arc<Upstream>,
+}
+impl Proxy {
+    pub fn new(prefix: &str, upstream: Upstream) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            upstream: Arc::new(upstream),
+        }
+    }
+    /// Reads proxies from every `--proxy prefix=host:port`, and how long
+    /// upstreams get to respond from `--proxy-timeout` in seconds
+    pub fn from_args() -> io::Result<Vec<Self>> {
+        let read_timeout = crate::args::value("--proxy-timeout")
+            .and_then(|seconds| seconds.parse().ok())
+            .map_or(READ_TIMEOUT, Duration::from_secs);
+        crate::args::values("--proxy")
+            .iter()
+            .map(|proxy| match proxy.split_once('=') {
+                Some((prefix, address)) if prefix.starts_with('/') && address.contains(':') => {
+                    Ok(Self::new(prefix, Upstream::new(address, read_timeout)))
+                }
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("proxy {} is not prefix=host:port", proxy),
+                )),
+            })
+            .collect()
+    }
+    /// Forwards `request` to the upstream, its target left as it is
+    pub fn forward(&self, request: Request) -> io::Result<Response> {
+        self.upstream.forward(request)
+    }
+}
+impl Upstream {
+    /// Head of the request sent upstream, with the fields describing the
+    /// client added and those framing a body `length` bytes long, or chunked
+    fn request_head(&self, request: &Request, length: Option<u64>) -> String {
+        let mut headers = request.headers.clone();
+        strip_hop_by_hop(&mut headers);
+        let host = headers.remove("Host").into_iter().next();
+        headers.remove("Content-Length");
+        let proto = if request.is_secure() { "https" } else { "http" };
+        let mut forwarded = vec![];
+        if let Some(peer) = request.peer() {
+            // IPv6 addresses hold colons, so they are quoted and bracketed
+            match peer {
+                std::net::IpAddr::V4(peer) => forwarded.push(format!("for={}", peer)),
+                std::net::IpAddr::V6(peer) => forwarded.push(format!("for=\"[{}]\"", peer)),
+            }
+            let forwarded_for = headers
+                .remove("X-Forwarded-For")
+                .into_iter()
+                .chain([peer.to_string()])
+                .collect::<Vec<String>>();
+            headers.insert("X-Forwarded-For", forwarded_for.join(", "));
+        }
+        if let Some(host) = &host {
+            forwarded.push(format!("host=\"{}\"", host.replace(['"', '\\'], "")));
+            headers.insert("X-Forwarded-Host", host.as_str());
+        }
+        forwarded.push(format!("proto={}", proto));
+        headers.append("Forwarded", forwarded.join(";"));
+        headers.insert("X-Forwarded-Proto", proto);
+        // absolute form targets name the host the request is for, see RFC 9112 section 3.2.2
+        let authority = request.start_line.target.authority();
+        headers.insert("Host", authority.unwrap_or(&self.address));
+        let method = &request.start_line.method;
+        match length {
+            Some(0) if !matches!(method, Method::Post | Method::Put) => (),
+            Some(length) => headers.insert("Content-Length", length.to_string()),
+            None => headers.insert("Transfer-Encoding", "chunked"),
+        }
+        format!(
+            "{} {} HTTP/1.1\r\n{}\r\n",
+            method,
+            request.start_line.target.origin(),
+            headers
+        )
+    }
+    /// Sends the request on a pooled connection, its body read from `body`
+    ///
+    /// Requests found to be sent on a reused connection the upstream had
+    /// closed are sent again on a fresh one, as long as their method is
+    /// idempotent and none of their body was sent, so that the upstream
+    /// does not act on them twice.
+    fn exchange(
+        &self,
+        request: &Request,
+        body: &mut Source,
+    ) -> io::Result<(Head, BufReader<TcpStream>)> {
+        let head = self.request_head(request, body.length());
+        let method = &request.start_line.method;
+        loop {
+            let (mut stream, reused) = self.checkout()?;
+            let sent = stream
+                .write_all(head.as_bytes())
+                .and_then(|()| send_body(&mut stream, body))
+                .and_then(|()| stream.flush());
+            let mut reader = BufReader::new(stream);
+            let result = sent.and_then(|()| read_head(&mut reader, method));
+            match result {
+                Ok(head) => return Ok((head, reader)),
+                // the upstream closed an idle connection before it got the request
+                Err(e)
+                    if reused && method.is_idempotent() && !body.started() && !is_timeout(&e) =>
+                {
+                    log_from_mod!("retrying on a new upstream connection", e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    /// Forwards `request` upstream, answering 502 if the upstream fails and 504 if it is too slow
+    pub fn forward(self: &Arc<Self>, mut request: Request) -> io::Result<Response> {
+        let mut body = request.take_body();
+        let (head, mut reader) = match self.exchange(&request, &mut body) {
+            Ok(exchanged) => exchanged,
+            Err(e) if is_timeout(&e) => {
+                elog_from_mod!("upstream timed out", self.address);
+                return Ok(Response::text(Status::GatewayTimeout, "upstream timed out"));
+            }
+            Err(e) => {
+                elog_from_mod!("upstream failed", e);
+                return Ok(Response::text(Status::BadGateway, "upstream unavailable"));
+            }
+        };
+        let Head {
+            status,
+            mut headers,
+            framing,
+            keep_alive,
+        } = head;
+        strip_hop_by_hop(&mut headers);
+        let length = headers
+            .remove("Content-Length")
+            .into_iter()
+            .next()
+            .and_then(|length| length.parse::<usize>().ok());
+        // HEAD requests and 304 keep the length of the body they leave out
+        let length_only = matches!(request.start_line.method, Method::Head)
+            || matches!(status, Status::NotModified);
+        let response = Response::builder().status(status).headers(headers);
+        let upstream = self.clone();
+        let release = move |reader: BufReader<TcpStream>| {
+            if keep_alive && reader.buffer().is_empty() {
+                upstream.checkin(reader.into_inner());
+            }
+        };
+        match framing {
+            Framing::Empty | Framing::Length(0) => {
+                release(reader);
+                match length.filter(|_| length_only) {
+                    Some(length) => Ok(response.length_only(length)),
+                    None => Ok(response.empty()),
+                }
+            }
+            framing => {
+                let producer: Producer = Box::new(move |sink: &mut dyn Sink| {
+                    match framing {
+                        Framing::Length(length) => copy(&mut reader, sink, length)?,
+                        Framing::Chunked => copy_chunked(&mut reader, sink)?,
+                        _ => copy(&mut reader, sink, u64::MAX).or_else(|e| match e.kind() {
+                            io::ErrorKind::UnexpectedEof => Ok(()),
+                            _ => Err(e),
+                        })?,
+                    }
+                    release(reader);
+                    Ok(())
+                });
+                Ok(response.stream(producer))
+            }
+        }
+    }
+}
+
+/// Destination a forward proxy may reach, `*` standing for any port, any
+/// host or the subdomains of a host as in `*.example.com`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Destination {
+    host: String,
+    port: Option<u16>,
+}
+impl Destination {
+    /// Parses `host:port`, returning `None` for anything else
+    pub fn parse(destination: &str) -> Option<Self> {
+        let (host, port) = destination.rsplit_once(':')?;
+        let port = match port {
+            "*" => None,
+            port => Some(port.parse::<u16>().ok().filter(|port| *port != 0)?),
+        };
+        let host = host.to_ascii_lowercase();
+        (!host.is_empty()).then_some(Self { host, port })
+    }
+    pub fn allows(&self, host: &str, port: u16) -> bool {
+        let host = host.to_ascii_lowercase();
+        let host_allowed = match self.host.strip_prefix('*') {
+            Some("") => true,
+            Some(suffix) if suffix.starts_with('.') => host.ends_with(suffix),
+            _ => self.host.eq(&host),
+        };
+        host_allowed && self.port.is_none_or(|allowed| allowed == port)
+    }
+}
+
+/// Opt-in forward proxy, forwarding absolute form requests and opening
+/// CONNECT tunnels to the destinations it allows
+///
+/// As a layer it takes absolute form requests before they are routed, tunnels
+/// are opened by the connection, see [`ForwardProxy::connect`] and [`tunnel`].
+pub struct ForwardProxy {
+    allowed: Vec<Destination>,
+    /// Users that must authenticate with Proxy-Authorization, anyone may use the proxy without
+    users: Option<Htpasswd>,
+    read_timeout: Duration,
+    /// Pools of the destinations reached so far
+    upstreams: Mutex<HashMap<String, Arc<Upstream>>>,
+}
+impl ForwardProxy {
+    pub fn new(allowed: Vec<Destination>, users: Option<Htpasswd>, read_timeout: Duration) -> Self {
+        Self {
+            allowed,
+            users,
+            read_timeout,
+            upstreams: Mutex::new(HashMap::new()),
+        }
+    }
+    /// Enabled by `--forward-proxy`, reads the destinations allowed from every
+    /// `--proxy-allow host:port`, the users from the htpasswd file `--proxy-users`
+    /// and how long destinations get to respond from `--proxy-timeout` in seconds
+    pub fn from_args() -> io::Result<Option<Self>> {
+        if !crate::args::flag("--forward-proxy") {
+            return Ok(None);
+        }
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        let allowed = crate::args::values("--proxy-allow")
+            .iter()
+            .map(|destination| {
+                Destination::parse(destination)
+                    .ok_or_else(|| invalid(format!("destination {} is not host:port", destination)))
+            })
+            .collect::<io::Result<Vec<Destination>>>()?;
+        if allowed.is_empty() {
+            return Err(invalid(String::from(
+                "forward proxy without any --proxy-allow destination",
+            )));
+        }
+        let users = crate::args::value("--proxy-users")
+            .map(|file| Htpasswd::load(Path::new(&file)))
+            .transpose()?;
+        let read_timeout = crate::args::value("--proxy-timeout")
+            .and_then(|seconds| seconds.parse().ok())
+            .map_or(READ_TIMEOUT, Duration::from_secs);
+        Ok(Some(Self::new(allowed, users, read_timeout)))
+    }
+    /// Checks the credentials of the client and the destination of `request`,
+    /// returning the host and port to reach or the response refusing it
+    fn admit(&self, request: &Request) -> Result<(String, u16), Response> {
+        if let Some(users) = &self.users {
+            let credentials = request
+                .headers
+                .get("Proxy-Authorization")
+                .and_then(|value| Credentials::from_str(value).ok());
+            let authenticated = match credentials {
+                Some(Credentials::Basic { user, password }) => users.verify(&user, &password),
+                _ => false,
+            };
+            if !authenticated {
+                elog_from_mod!("rejecting proxy credentials");
+                let mut response = Response::text(
+                    Status::ProxyAuthenticationRequired,
+                    "proxy authentication required",
+                );
+                let challenge = Challenge::new("Basic", "proxy");
+                response
+                    .headers
+                    .insert("Proxy-Authenticate", challenge.to_string());
+                return Err(response);
+            }
+        }
+        let target = &request.start_line.target;
+        let authority = target.authority().unwrap_or_default();
+        let destination = match target.form {
+            target::Form::Authority => request::split_authority(authority),
+            _ if target.scheme().as_deref() != Some("http") => None,
+            _ if authority.contains(['@']) => None,
+            _ => match request::split_authority(authority) {
+                Some(destination) => Some(destination),
+                None if !authority.is_empty() && !authority.ends_with(':') => Some((authority, 80)),
+                None => None,
+            },
+        };
+        let (host, port) = destination.ok_or_else(|| {
+            let message = format!("cannot proxy to {}", target.path);
+            Response::text(Status::BadRequest, message)
+        })?;
+        if !self
+            .allowed
+            .iter()
+            .any(|allowed| allowed.allows(host, port))
+        {
+            elog_from_mod!("destination not allowed", authority.escape_debug());
+            return Err(Response::text(Status::Forbidden, "destination not allowed"));
+        }
+        Ok((host.to_string(), port))
+    }
+    /// Forwards an absolute form request to its destination, over a pooled connection
+    pub fn forward(&self, request: Request) -> io::Result<Response> {
+        let (host, port) = match self.admit(&request) {
+            Ok(destination) => destination,
+            Err(response) => return Ok(response),
+        };
+        let address = format!("{}:{}", host, port);
+        let upstream = {
+            let mut upstreams = self.upstreams.lock().unwrap_or_else(|e| e.into_inner());
+            if upstreams.len() >= MAX_UPSTREAMS && !upstreams.contains_key(&address) {
+                upstreams.clear();
+            }
+            let upstream = Upstream::new(address.as_str(), self.read_timeout);
+            upstreams
+                .entry(address)
+                .or_insert_with(|| Arc::new(upstream))
+                .clone()
+        };
+        upstream.forward(request)
+    }
+    /// Connects to the destination of a CONNECT request, or returns the
+    /// response refusing it, 502 and 504 if the destination cannot be reached
+    pub fn connect(&self, request: &Request) -> Result<TcpStream, Response> {
+        let (host, port) = self.admit(request)?;
+        let address = format!("{}:{}", host, port);
+        open(&address, TUNNEL_IDLE_TIMEOUT).map_err(|e| {
+            elog_from_mod!("cannot open tunnel", e);
+            match is_timeout(&e) {
+                true => Response::text(Status::GatewayTimeout, "destination timed out"),
+                false => Response::text(Status::BadGateway, "destination unavailable"),
+            }
+        })
+    }
+}
+impl Layer for ForwardProxy {
+    fn handle(&self, request: Request, next: Next<'_>) -> io::Result<Response> {
+        match request.start_line.target.form {
+            target::Form::Absolute => self.forward(request),
+            _ => next.run(request),
+        }
+    }
+}
+
+/// Moves whatever is read from `from` to `to`, `None` telling `from` is closed
+fn pump<R: Read, W: Write>(
+    from: &mut R,
+    to: &mut W,
+    buffer: &mut [u8],
+) -> io::Result<Option<usize>> {
+    match from.read(buffer) {
+        Ok(0) => Ok(None),
+        Ok(read) => to
+            .write_all(&buffer[..read])
+            .and(to.flush())
+            .map(|()| Some(read)),
+        Err(e) if is_timeout(&e) => Ok(Some(0)),
+        Err(e) if is_disconnect(&e) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Confirms a CONNECT request to `client`, then copies bytes both ways between
+/// it and `destination` until the destination closes the tunnel or it stays idle
+///
+/// `buffered` holds what the client sent past its request. Both sides are read
+/// in turn for at most [`TUNNEL_TICK`], so that a single thread serves the tunnel.
+pub fn tunnel<S: Read + Write + Socket>(
+    client: &mut S,
+    buffered: &[u8],
+    mut destination: TcpStream,
+) -> io::Result<()> {
+    // a tunnel is established by a 2xx with neither Content-Length nor Transfer-Encoding
+    let mut established = Response::empty(Status::Ok);
+    established.complete_headers();
+    established.headers.remove("Content-Length");
+    client.write_all(established.head().as_bytes())?;
+    client.flush()?;
+    destination.write_all(buffered)?;
+    client.set_read_timeout(Some(TUNNEL_TICK))?;
+    destination.set_read_timeout(Some(TUNNEL_TICK))?;
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut client_open = true;
+    let mut active = Instant::now();
+    loop {
+        let mut moved = 0;
+        if client_open {
+            match pump(client, &mut destination, &mut buffer)? {
+                Some(sent) => moved += sent,
+                // the destination sees the end of the stream, and may still answer
+                None => {
+                    client_open = false;
+                    let _ = destination.shutdown(Shutdown::Write);
+                }
+            }
+        }
+        match pump(&mut destination, client, &mut buffer)? {
+            Some(received) => moved += received,
+            None => return Ok(()),
+        }
+        if moved > 0 {
+            active = Instant::now();
+        } else if active.elapsed() > TUNNEL_IDLE_TIMEOUT {
+            log_from_mod!("closing idle tunnel");
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strip_hop_by_hop, tunnel, Destination, ForwardProxy, Proxy, Upstream};
+    use crate::http::{
+        auth::Htpasswd,
+        body::{Sink, Source},
+        header::HeaderMap,
+        Request, Response,
+    };
+    use std::{
+        io::{self, BufRead, BufReader, Read, Write},
+        net::{Shutdown, TcpListener, TcpStream},
+        sync::mpsc,
+        thread,
+        time::Duration,
+    };
+
+    struct Collect(Vec<u8>);
+    impl Sink for Collect {
+        fn send(&mut self, chunk: &[u8]) -> io::Result<()> {
+            self.0.extend_from_slice(chunk);
+            Ok(())
+        }
+    }
+
+    /// Status code, a header and the whole body of `response`
+    fn read(mut response: Response, header: &str) -> (u16, Option<String>, String) {
+        let mut body = Collect(response.body.take().unwrap_or_default());
+        if let Some(producer) = response.stream.take() {
+            producer(&mut body).unwrap();
+        }
+        let header = response.headers.get(header).map(String::from);
+        let body = String::from_utf8(body.0).unwrap();
+        (response.start_line.status.code(), header, body)
+    }
+
+    fn request(raw: &str) -> Request {
+        let mut request = Request::try_construct(raw.as_bytes()).unwrap();
+        request.peer = Some([10, 0, 0, 7].into());
+        request
+    }
+
+    #[test]
+    fn hop_by_hop_fields_are_stripped() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Connection", "keep-alive, X-Secret");
+        headers.insert("X-Secret", "1");
+        headers.insert("Keep-Alive", "timeout=5");
+        headers.insert("Accept", "*/*");
+        strip_hop_by_hop(&mut headers);
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers.get("Accept"), Some("*/*"));
+    }
+
+    #[test]
+    fn requests_are_forwarded_over_one_kept_alive_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (heads, received) = mpsc::channel();
+        // answers two requests over the only connection it accepts
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            for _ in 0..2 {
+                let mut head = String::new();
+                while !head.ends_with("\r\n\r\n") {
+                    reader.read_line(&mut head).unwrap();
+                }
+                let length = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .map_or(0, |length| length.parse().unwrap());
+                let mut body = vec![0u8; length];
+                reader.read_exact(&mut body).unwrap();
+                heads.send(head).unwrap();
+                writer
+                    .write_all(
+                        b"HTTP/1.1 100 Continue\r\n\r\n\
+                          HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\n\
+                          Connection: X-Hop\r\nX-Hop: 1\r\nX-Upstream: yes\r\n\r\n\
+                          5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\n",
+                    )
+                    .unwrap();
+            }
+        });
+        let proxy = Proxy::new(
+            "/api",
+            Upstream::new(address.clone(), Duration::from_secs(5)),
+        );
+        let raw = "POST /api/items?x=1 HTTP/1.1\r\nHost: example.com\r\n\
+                   Connection: keep-alive\r\nX-Forwarded-For: 192.0.2.1\r\n\
+                   Content-Length: 2\r\n\r\nhi";
+        for _ in 0..2 {
+            let response = proxy.forward(request(raw)).unwrap();
+            assert_eq!(response.headers.get("X-Hop"), None);
+            let (status, upstream, body) = read(response, "X-Upstream");
+            assert_eq!((status, upstream.as_deref()), (201, Some("yes")));
+            assert_eq!(body, "hello world");
+        }
+        let head = received.recv().unwrap();
+        assert!(head.starts_with("POST /api/items?x=1 HTTP/1.1\r\n"));
+        for field in [
+            format!("Host: {}", address),
+            "X-Forwarded-For: 192.0.2.1, 10.0.0.7".to_string(),
+            "X-Forwarded-Host: example.com".to_string(),
+            "X-Forwarded-Proto: http".to_string(),
+            "Forwarded: for=10.0.0.7;host=\"example.com\";proto=http".to_string(),
+            "Content-Length: 2".to_string(),
+        ] {
+            assert!(
+                head.contains(&format!("{}\r\n", field)),
+                "{} in {}",
+                field,
+                head
+            );
+        }
+        assert!(!head.contains("Connection"));
+        assert!(received.recv().is_ok());
+    }
+
+    #[test]
+    fn upstream_failures_become_gateway_errors() {
+        let raw = "GET /api HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        // a listener that never answers
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = silent.local_addr().unwrap().to_string();
+        let proxy = Proxy::new("/api", Upstream::new(address, Duration::from_millis(50)));
+        assert_eq!(read(proxy.forward(request(raw)).unwrap(), "").0, 504);
+        // a port nothing listens on anymore
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let proxy = Proxy::new("/api", Upstream::new(address, Duration::from_secs(1)));
+        assert_eq!(read(proxy.forward(request(raw)).unwrap(), "").0, 502);
+    }
+
+    #[test]
+    fn bodies_of_unknown_length_are_sent_chunked() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let upstream = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut received = String::new();
+            while !received.ends_with("0\r\n\r\n") {
+                reader.read_line(&mut received).unwrap();
+            }
+            stream
+                .write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            received
+        });
+        let proxy = Proxy::new("/api", Upstream::new(address, Duration::from_secs(5)));
+        let mut request = request("POST /api HTTP/1.1\r\nHost: a\r\n\r\n");
+        let (sender, receiver) = mpsc::sync_channel(2);
+        request.source = Some(Source::streamed(receiver, None));
+        let sending = thread::spawn(move || {
+            for piece in ["hello", " world"] {
+                sender.send(Ok(piece.as_bytes().to_vec())).unwrap();
+            }
+        });
+        assert_eq!(read(proxy.forward(request).unwrap(), "").0, 201);
+        sending.join().unwrap();
+        let received = upstream.join().unwrap();
+        let (head, body) = received.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("\r\nTransfer-Encoding: chunked"), "{}", head);
+        assert!(!head.contains("Content-Length"));
+        assert_eq!(body, "5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn responses_without_body_keep_the_upstream_length() {
+        for (raw, reply) in [
+            (
+                "HEAD /api HTTP/1.1\r\nHost: a\r\n\r\n",
+                &b"HTTP/1.1 200 OK\r\nContent-Length: 42\r\n\r\n"[..],
+            ),
+            (
+                "GET /api HTTP/1.1\r\nHost: a\r\nIf-None-Match: \"x\"\r\n\r\n",
+                &b"HTTP/1.1 304 Not Modified\r\nContent-Length: 42\r\n\r\n"[..],
+            ),
+        ] {
+            let (address, _) = serve_once(reply);
+            let proxy = Proxy::new("/api", Upstream::new(address, Duration::from_secs(5)));
+            let mut response = proxy.forward(request(raw)).unwrap();
+            response.complete_headers();
+            assert_eq!(response.headers.get("Content-Length"), Some("42"));
+            assert_eq!(read(response, "").2, "");
+        }
+    }
+
+    #[test]
+    fn only_idempotent_requests_are_retried() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        // answers a single request on each of two connections, then closes them
+        thread::spawn(move || {
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut head = String::new();
+                while !head.ends_with("\r\n\r\n") {
+                    reader.read_line(&mut head).unwrap();
+                }
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                    .unwrap();
+            }
+        });
+        let proxy = Proxy::new("/api", Upstream::new(address, Duration::from_secs(2)));
+        let get = "GET /api HTTP/1.1\r\nHost: a\r\n\r\n";
+        assert_eq!(read(proxy.forward(request(get)).unwrap(), "").0, 200);
+        // the closed connection is replaced for the GET, but not for the POST
+        assert_eq!(read(proxy.forward(request(get)).unwrap(), "").0, 200);
+        let post = "POST /api HTTP/1.1\r\nHost: a\r\nContent-Length: 0\r\n\r\n";
+        assert_eq!(read(proxy.forward(request(post)).unwrap(), "").0, 502);
+    }
+
+    /// Address of an upstream answering `reply` to the one request it takes,
+    /// and the head of that request
+    fn serve_once(reply: &'static [u8]) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (heads, received) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut head = String::new();
+            while !head.ends_with("\r\n\r\n") {
+                reader.read_line(&mut head).unwrap();
+            }
+            stream.write_all(reply).unwrap();
+            heads.send(head).unwrap();
+        });
+        (address, received)
+    }
+
+    fn forward_proxy(users: Option<Htpasswd>) -> ForwardProxy {
+        let allowed = vec![Destination::parse("127.0.0.1:*").unwrap()];
+        ForwardProxy::new(allowed, users, Duration::from_secs(5))
+    }
+
+    #[test]
+    fn destinations_match_hosts_and_ports() {
+        let subdomains = Destination::parse("*.example.com:*").unwrap();
+        assert!(subdomains.allows("API.example.com", 8443));
+        assert!(!subdomains.allows("example.com", 80));
+        assert!(!subdomains.allows("badexample.com", 80));
+        let exact = Destination::parse("localhost:8080").unwrap();
+        assert!(exact.allows("localhost", 8080));
+        assert!(!exact.allows("localhost", 8081));
+        assert!(Destination::parse("*:443").unwrap().allows("a", 443));
+        assert!(Destination::parse("host").is_none());
+        assert!(Destination::parse("host:x").is_none());
+    }
+
+    #[test]
+    fn forward_proxy_admits_authenticated_clients_to_allowed_destinations() {
+        let users = Htpasswd::parse("bob:{SHA}87u9ZqY9S/F0eUBXjsPQEDUw4h0=").unwrap();
+        let proxy = forward_proxy(Some(users));
+        let (address, received) = serve_once(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        let raw = |credentials: &str, authority: &str| {
+            format!(
+                "GET http://{0}/a?b HTTP/1.1\r\nHost: {0}\r\n\
+                 Proxy-Authorization: Basic {1}\r\n\r\n",
+                authority, credentials
+            )
+        };
+        let anonymous = format!("GET http://{0}/ HTTP/1.1\r\nHost: {0}\r\n\r\n", address);
+        let (status, challenge, _) = read(
+            proxy.forward(request(&anonymous)).unwrap(),
+            "Proxy-Authenticate",
+        );
+        assert_eq!(
+            (status, challenge.as_deref()),
+            (407, Some("Basic realm=\"proxy\""))
+        );
+        let wrong = raw("Ym9iOmh1bnRlcjM=", &address);
+        assert_eq!(read(proxy.forward(request(&wrong)).unwrap(), "").0, 407);
+        let elsewhere = raw("Ym9iOmh1bnRlcjI=", "192.0.2.1:80");
+        assert_eq!(read(proxy.forward(request(&elsewhere)).unwrap(), "").0, 403);
+        let allowed = raw("Ym9iOmh1bnRlcjI=", &address);
+        let (status, _, body) = read(proxy.forward(request(&allowed)).unwrap(), "");
+        assert_eq!((status, body.as_str()), (200, "ok"));
+        let head = received.recv().unwrap();
+        assert!(head.starts_with("GET /a?b HTTP/1.1\r\n"), "{}", head);
+        assert!(head.contains(&format!("Host: {}\r\n", address)));
+        assert!(!head.contains("Proxy-Authorization"));
+    }
+
+    #[test]
+    fn tunnels_copy_bytes_both_ways() {
+        // a destination echoing what it reads until the end of the stream
+        let echo = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = echo.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = echo.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            io::copy(&mut stream, &mut writer).unwrap();
+        });
+        let proxy = forward_proxy(None);
+        let denied = request("CONNECT 192.0.2.1:22 HTTP/1.1\r\nHost: 192.0.2.1:22\r\n\r\n");
+        assert!(
+            matches!(proxy.connect(&denied), Err(response) if response.start_line.status.code() == 403)
+        );
+        let connect = request(&format!(
+            "CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n",
+            address
+        ));
+        let destination = proxy.connect(&connect).ok().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut served, _) = listener.accept().unwrap();
+        let tunnel = thread::spawn(move || tunnel(&mut served, b"early ", destination));
+        client.write_all(b"bytes").unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert!(received.starts_with("HTTP/1.1 200 OK\r\n"), "{}", received);
+        assert!(!received.contains("Content-Length"));
+        assert!(received.ends_with("\r\n\r\nearly bytes"));
+        tunnel.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+//! Token bucket rate limiting keyed by client address
+//!
+//! Each rule covers its prefix and the paths under it, matched by whole
+//! segments as [`router::is_under`] does, and gives every client
+//! a bucket of `limit` tokens, refilled evenly over `window`. A request takes
+//! a token, or is answered with 429 once the bucket is empty.
+use {
+    crate::http::{
+        middleware::{Layer, Next},
+        response::Status,
+        router, Request, Response,
+    },
+    std::{
+        collections::HashMap,
+        io,
+        net::IpAddr,
+        str::FromStr,
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+};
+
+/// Rules applied when no `--rate-limit` is given
+pub const DEFAULT_RULES: [(&str, u32, u64); 1] = [("/files", 60, 60)];
+/// How often buckets left untouched long enough to be full again are dropped
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Limit on the requests a client may send to paths under `prefix`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Rule {
+    pub prefix: String,
+    pub limit: u32,
+    pub window: Duration,
+}
+impl Rule {
+    /// Tokens added back to a bucket each second
+    fn rate(&self) -> f64 {
+        self.limit as f64 / self.window.as_secs_f64()
+    }
+    /// Returns true if `path` is the prefix or lies under it
+    fn covers(&self, path: &str) -> bool {
+        router::is_under(path, &self.prefix)
+    }
+}
+impl FromStr for Rule {
+    type Err = String;
+    /// Parses `prefix=limit/seconds`, like `/files=60/60`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("rate limit {} is not prefix=limit/seconds", s);
+        let (prefix, quota) = s.split_once('=').ok_or_else(invalid)?;
+        let (limit, seconds) = quota.split_once('/').ok_or_else(invalid)?;
+        let limit = limit.parse::<u32>().map_err(|_| invalid())?;
+        let seconds = seconds.parse::<u64>().map_err(|_| invalid())?;
+        if !prefix.starts_with('/') || limit == 0 || seconds == 0 {
+            return Err(invalid());
+        }
+        Ok(Self {
+            prefix: prefix.to_string(),
+            limit,
+            window: Duration::from_secs(seconds),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Outcome of taking a token
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Decision {
+    allowed: bool,
+    remaining: u32,
+    /// Seconds until the bucket is full again
+    reset: u64,
+    /// Seconds until the next token, when none is left
+    retry_after: u64,
+}
+
+struct Buckets {
+    by_key: HashMap<(usize, IpAddr), Bucket>,
+    swept: Instant,
+}
+
+/// Layer answering 429 to clients that ran out of tokens, with RateLimit headers on every response it limits
+///
+/// The client is the peer of the connection, unless the peer is a trusted
+/// proxy: X-Forwarded-For is then read from the right, and the first address
+/// not itself trusted is the client.
+pub struct RateLimit {
+    rules: Vec<Rule>,
+    trusted_proxies: Vec<IpAddr>,
+    buckets: Mutex<Buckets>,
+}
+impl RateLimit {
+    pub fn new(rules: Vec<Rule>, trusted_proxies: Vec<IpAddr>) -> Self {
+        Self {
+            rules,
+            trusted_proxies,
+            buckets: Mutex::new(Buckets {
+                by_key: HashMap::new(),
+                swept: Instant::now(),
+            }),
+        }
+    }
+    /// Reads rules from every `--rate-limit prefix=limit/seconds`, and the
+    /// proxies trusted to set X-Forwarded-For from every `--trusted-proxy`
+    pub fn from_args() -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        let mut rules = crate::args::values("--rate-limit")
+            .iter()
+            .map(|rule| rule.parse::<Rule>().map_err(invalid))
+            .collect::<io::Result<Vec<Rule>>>()?;
+        if rules.is_empty() {
+            rules = DEFAULT_RULES
+                .iter()
+                .map(|(prefix, limit, seconds)| Rule {
+                    prefix: prefix.to_string(),
+                    limit: *limit,
+                    window: Duration::from_secs(*seconds),
+                })
+                .collect();
+        }
+        let trusted_proxies = crate::args::values("--trusted-proxy")
+            .iter()
+            .map(|proxy| {
+                proxy
+                    .parse::<IpAddr>()
+                    .map_err(|e| invalid(format!("trusted proxy {}: {}", proxy, e)))
+            })
+            .collect::<io::Result<Vec<IpAddr>>>()?;
+        Ok(Self::new(rules, trusted_proxies))
+    }
+    /// Address the request is counted against
+    fn client(&self, request: &Request) -> Option<IpAddr> {
+        let peer = request.peer()?;
+        if !self.trusted_proxies.contains(&peer) {
+            return Some(peer);
+        }
+        let forwarded = request
+            .headers()
+            .get_all("X-Forwarded-For")
+            .flat_map(|value| value.split(','))
+            .map(|hop| hop.trim().parse::<IpAddr>().ok())
+            .collect::<Vec<Option<IpAddr>>>();
+        let mut client = peer;
+        for hop in forwarded.into_iter().rev() {
+            match hop {
+                Some(hop) if self.trusted_proxies.contains(&hop) => client = hop,
+                Some(hop) => return Some(hop),
+                // an unparsable hop can not be trusted further
+                None => break,
+            }
+        }
+        Some(client)
+    }
+    /// Takes a token from the bucket of `client` under the rule at `index`
+    fn take(&self, index: usize, client: IpAddr, now: Instant) -> Decision {
+        let rule = &self.rules[index];
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if now.saturating_duration_since(buckets.swept) >= SWEEP_INTERVAL {
+            let rules = &self.rules;
+            buckets.by_key.retain(|(index, _), bucket| {
+                now.saturating_duration_since(bucket.updated) < rules[*index].window
+            });
+            buckets.swept = now;
+        }
+        let bucket = buckets.by_key.entry((index, client)).or_insert(Bucket {
+            tokens: rule.limit as f64,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rule.rate()).min(rule.limit as f64);
+        bucket.updated = now;
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        let seconds_until = |tokens: f64| (tokens.max(0.0) / rule.rate()).ceil() as u64;
+        Decision {
+            allowed,
+            remaining: bucket.tokens.floor() as u32,
+            reset: seconds_until(rule.limit as f64 - bucket.tokens),
+            retry_after: seconds_until(1.0 - bucket.tokens).max(1),
+        }
+    }
+}
+impl Layer for RateLimit {
+    fn handle(&self, request: Request, next: Next<'_>) -> io::Result<Response> {
+        let path = request.start_line.target.path.as_str();
+        let index = self.rules.iter().position(|rule| rule.covers(path));
+        let (index, client) = match (index, self.client(&request)) {
+            (Some(index), Some(client)) => (index, client),
+            _ => return next.run(request),
+        };
+        let rule = &self.rules[index];
+        let decision = self.take(index, client, Instant::now());
+        let mut response = if decision.allowed {
+            next.run(request)?
+        } else {
+            elog_from_mod!("rate limited", client);
+            Response::builder()
+                .status(Status::TooManyRequests)
+                .header("Retry-After", decision.retry_after.to_string())
+                .empty()
+        };
+        let headers = &mut response.headers;
+        headers.insert("RateLimit-Limit", rule.limit.to_string());
+        headers.insert("RateLimit-Remaining", decision.remaining.to_string());
+        headers.insert("RateLimit-Reset", decision.reset.to_string());
+        headers.insert(
+            "RateLimit-Policy",
+            format!("{};w={}", rule.limit, rule.window.as_secs()),
+        );
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimit, Rule};
+    use crate::http::Request;
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, Instant},
+    };
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    fn limiter(trusted_proxies: Vec<IpAddr>) -> RateLimit {
+        RateLimit::new(vec!["/files=2/10".parse().unwrap()], trusted_proxies)
+    }
+
+    #[test]
+    fn rules_are_parsed() {
+        let rule = "/files=60/30".parse::<Rule>().unwrap();
+        assert_eq!(rule.limit, 60);
+        assert_eq!(rule.window, Duration::from_secs(30));
+        assert!("files=1/1".parse::<Rule>().is_err());
+        assert!("/files=0/1".parse::<Rule>().is_err());
+        assert!("/files=1".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn buckets_empty_and_refill() {
+        let limiter = limiter(vec![]);
+        let now = Instant::now();
+        assert!(limiter.take(0, ip(1), now).allowed);
+        let decision = limiter.take(0, ip(1), now);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        let decision = limiter.take(0, ip(1), now);
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after, 5);
+        assert!(limiter.take(0, ip(2), now).allowed);
+        assert!(limiter.take(0, ip(1), now + Duration::from_secs(5)).allowed);
+    }
+
+    #[test]
+    fn idle_buckets_are_evicted() {
+        let limiter = limiter(vec![]);
+        let now = Instant::now();
+        limiter.take(0, ip(1), now);
+        limiter.take(0, ip(2), now + Duration::from_secs(55));
+        limiter.take(0, ip(3), now + Duration::from_secs(61));
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(!buckets.by_key.contains_key(&(0, ip(1))));
+        assert!(buckets.by_key.contains_key(&(0, ip(2))));
+    }
+
+    #[test]
+    fn forwarded_addresses_are_trusted_only_from_proxies() {
+        let raw = "GET /files/a HTTP/1.1\r\nHost: a\r\nX-Forwarded-For: 10.0.0.9, 10.0.0.7, 10.0.0.8\r\n\r\n";
+        let mut request = Request::try_construct(raw.as_bytes()).unwrap();
+        request.peer = Some(ip(1));
+        assert_eq!(limiter(vec![]).client(&request), Some(ip(1)));
+        assert_eq!(limiter(vec![ip(1)]).client(&request), Some(ip(8)));
+        assert_eq!(limiter(vec![ip(1), ip(8)]).client(&request), Some(ip(7)));
+    }
+
+    #[test]
+    fn rules_cover_whole_segments() {
+        let rule = "/files=60/30".parse::<Rule>().unwrap();
+        for path in ["/files", "/files/", "/files/a/b", "/files?x=1"] {
+            assert!(rule.covers(path), "{}", path);
+        }
+        assert!(!rule.covers("/filesystem"));
+        assert!(!rule.covers("/"));
+    }
+}
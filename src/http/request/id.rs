@@ -0,0 +1,117 @@
+//! Request ids, tying log lines to the request being handled when they were written
+use {
+    crate::http::Request,
+    std::{
+        cell::RefCell,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            OnceLock,
+        },
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// Header carrying the id, both in requests and responses
+pub const HEADER: &str = "X-Request-Id";
+/// Longest id adopted from a client
+pub const MAX_LENGTH: usize = 128;
+
+thread_local! {
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Distinguishes ids of this process from those of earlier runs
+static PREFIX: OnceLock<u32> = OnceLock::new();
+static COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Returns true for ids safe to adopt from a client and write to logs
+pub fn is_valid(id: &str) -> bool {
+    (1..=MAX_LENGTH).contains(&id.len())
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:/+=".contains(&b))
+}
+
+/// Returns a new id, unique within this process
+pub fn generate() -> String {
+    let prefix = PREFIX.get_or_init(|| {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.subsec_nanos())
+            .unwrap_or_default();
+        nanos ^ std::process::id().rotate_left(16)
+    });
+    let count = COUNT.fetch_add(1, Ordering::Relaxed);
+    format!("{:08x}-{:08x}", prefix, count)
+}
+
+/// Gives `request` its id, returning it
+///
+/// A valid id sent by the client is adopted, any other replaced by a generated one.
+pub fn assign(request: &mut Request) -> String {
+    let id = match request.id() {
+        Some(id) if is_valid(id) => id.to_string(),
+        Some(id) => {
+            elog_from_mod!("replacing invalid request id", id.escape_debug());
+            generate()
+        }
+        None => generate(),
+    };
+    request.headers.insert(HEADER, id.as_str());
+    id
+}
+
+/// Marks the current thread as handling the request `id` until dropped
+pub struct Scope {
+    previous: Option<String>,
+}
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Marks the current thread as handling the request `id`, scopes may nest
+pub fn enter(id: &str) -> Scope {
+    let previous = CURRENT.with(|current| current.replace(Some(id.to_string())));
+    Scope { previous }
+}
+
+/// Id of the request the current thread is handling, if any
+pub fn current() -> Option<String> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Prefix for log lines written while handling a request, empty otherwise
+pub fn tag() -> String {
+    current().map(|id| format!("[{}] ", id)).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{current, enter, is_valid};
+
+    #[test]
+    fn ids_are_validated() {
+        assert!(is_valid("0a42b13e-00000001"));
+        assert!(is_valid("trace:abc/def+1="));
+        assert!(!is_valid(""));
+        assert!(!is_valid("has space"));
+        assert!(!is_valid("line\nbreak"));
+        assert!(!is_valid(&"a".repeat(129)));
+    }
+
+    #[test]
+    fn scopes_nest_and_restore() {
+        assert_eq!(current(), None);
+        let outer = enter("outer");
+        {
+            let _inner = enter("inner");
+            assert_eq!(current().as_deref(), Some("inner"));
+        }
+        assert_eq!(current().as_deref(), Some("outer"));
+        drop(outer);
+        assert_eq!(current(), None);
+    }
+}
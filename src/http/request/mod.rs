@@ -1,14 +1,18 @@
+/// Module to handle request ids
+pub(crate) mod id;
+
 use std::{
     fmt::{self, Display, Formatter},
     str::FromStr,
 };
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Method {
     Get,
     Post,
     Put,
     Head,
     Options,
+    Connect,
 }
 impl Display for Method {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
@@ -19,10 +23,18 @@ impl Display for Method {
             Put => "PUT",
             Head => "HEAD",
             Options => "OPTIONS",
+            Connect => "CONNECT",
         };
         fmt::write(f, format_args!("{}", method_string))
     }
 }
+impl Method {
+    /// Returns true for methods whose effect is the same whether a request is
+    /// sent once or several times, see RFC 9110 section 9.2.2
+    pub fn is_idempotent(&self) -> bool {
+        !matches!(self, Method::Post | Method::Connect)
+    }
+}
 #[derive(Debug, Eq, PartialEq)]
 pub struct ParseRequestMethodError(String);
 impl Display for ParseRequestMethodError {
@@ -49,6 +61,7 @@ impl FromStr for Method {
             "PUT" => Ok(Put),
             "HEAD" => Ok(Head),
             "OPTIONS" => Ok(Options),
+            "CONNECT" => Ok(Connect),
             _ => Err(ParseRequestMethodError::new(format!(
                 "{} is not a recognized request method",
                 s
@@ -57,8 +70,7 @@ impl FromStr for Method {
     }
 }
 pub mod target {
-    #[derive(Debug, Copy, Clone)]
-    #[allow(dead_code)]
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
     pub enum Form {
         Origin,
         Absolute,
@@ -71,13 +83,71 @@ pub struct Target {
     pub path: String,
     pub form: target::Form,
 }
+impl Target {
+    /// Tells the form of `target` from its shape, see RFC 9112 section 3.2
+    ///
+    /// Targets that are neither `*`, a path nor a URL with a scheme are taken
+    /// for the authority form, which only CONNECT requests may use.
+    pub fn parse(target: &str) -> Self {
+        let form = if target.eq("*") {
+            target::Form::Asterisk
+        } else if target.starts_with('/') {
+            target::Form::Origin
+        } else if target.contains("://") {
+            target::Form::Absolute
+        } else {
+            target::Form::Authority
+        };
+        Target {
+            path: target.to_string(),
+            form,
+        }
+    }
+    /// Scheme of an absolute form target, lowercased
+    pub fn scheme(&self) -> Option<String> {
+        match self.form {
+            target::Form::Absolute => self
+                .path
+                .split_once("://")
+                .map(|(scheme, _)| scheme.to_ascii_lowercase()),
+            _ => None,
+        }
+    }
+    /// Host and optional port named by an absolute or authority form target
+    pub fn authority(&self) -> Option<&str> {
+        match self.form {
+            target::Form::Absolute => {
+                let (_, rest) = self.path.split_once("://")?;
+                let end = rest.find(['/', '?']).unwrap_or(rest.len());
+                Some(&rest[..end])
+            }
+            target::Form::Authority => Some(&self.path),
+            _ => None,
+        }
+    }
+    /// Path and query of the target, as an origin server expects them
+    pub fn origin(&self) -> String {
+        match self.form {
+            target::Form::Absolute => {
+                let authority = self.authority().unwrap_or_default();
+                let start = self.path.find("://").unwrap_or_default() + 3 + authority.len();
+                match &self.path[start..] {
+                    "" => String::from("/"),
+                    rest if rest.starts_with('?') => format!("/{}", rest),
+                    rest => rest.to_string(),
+                }
+            }
+            _ => self.path.clone(),
+        }
+    }
+}
 /// The start-line contains three elements:
 ///   1. An HTTP `Method`, either a verb or a noun, that describes the action to be performed
 ///   2. The request target, usually a URL, or the absolute path of the protocol, port, and domain are usually characterized between different HTTP `Method`s. It can be:
-///     - An absolute path, ultimately followed by a ? and query string. This is the most common form, known as the origin form.
-///     - A complete URL, known as the absolute form, is mostuly used with `Method::Get` when connected to a proxy.
-///     - The authority component of a URL, consisting of the domain name and optionally the port (prefixed by a :) is called the authority form. It is only used with `Method::Connect` when setting up an HTTP tunnel.
-///     - The asterisk form, a simple asterisk is used with `Method::Options`, representing the server as a whole.
+///      - An absolute path, ultimately followed by a ? and query string. This is the most common form, known as the origin form.
+///      - A complete URL, known as the absolute form, is mostuly used with `Method::Get` when connected to a proxy.
+///      - The authority component of a URL, consisting of the domain name and optionally the port (prefixed by a :) is called the authority form. It is only used with `Method::Connect` when setting up an HTTP tunnel.
+///      - The asterisk form, a simple asterisk is used with `Method::Options`, representing the server as a whole.
 ///   3. The HTTP version, which defines the structure of the remaining message, acting as an indicator of the expected version to use for the response
 #[derive(Clone)]
 pub struct Startline {
@@ -100,87 +170,146 @@ impl Display for Startline {
 impl FromStr for Startline {
     type Err = std::io::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut components = s.split_whitespace();
-        if components.clone().count() != 3 {
-            let error = std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "component count longer not equal to 3",
-            );
-            Err(error)
-        } else {
-            let method = match components.nth(0usize).unwrap() {
-                "GET" => Method::Get,
-                "POST" => Method::Post,
-                _ => unimplemented!(),
-            };
-            let target_component = components.nth(0usize).unwrap();
-            let target = if target_component.contains('*') {
-                Target {
-                    path: "*".to_string(),
-                    form: target::Form::Asterisk,
+        let invalid_input =
+            |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+        let components = s.split(' ').collect::<Vec<&str>>();
+        match components.as_slice() {
+            [method_component, target_component, version_component] => {
+                let method =
+                    Method::from_str(method_component).map_err(|e| invalid_input(e.to_string()))?;
+                let target = Target::parse(target_component);
+                // CONNECT names where to tunnel to, and nothing else may
+                let connect = matches!(method, Method::Connect);
+                if connect != matches!(target.form, target::Form::Authority) {
+                    let message = format!("{} not valid with {}", target_component, method);
+                    return Err(invalid_input(message));
                 }
-            } else {
-                Target {
-                    path: target_component.to_string(),
-                    form: target::Form::Absolute,
+                if connect && split_authority(target_component).is_none() {
+                    let message = format!("{} is not host:port", target_component);
+                    return Err(invalid_input(message));
                 }
-            };
-            let version = match components.nth(0usize).unwrap() {
-                "HTTP/1.1" => super::Version(1u8, Some(1u8)),
-                "HTTP/2" => super::Version(2u8, None),
-                _ => unimplemented!(),
-            };
-            let line = Self {
-                method,
-                target,
-                version,
-            };
-            Ok(line)
+                let version = super::Version::from_str(version_component)
+                    .map_err(|e| invalid_input(e.to_string()))?;
+                Ok(Self {
+                    method,
+                    target,
+                    version,
+                })
+            }
+            _ => Err(invalid_input(String::from(
+                "component count not equal to 3",
+            ))),
         }
     }
 }
 impl Startline {
     #[allow(dead_code)]
     pub(super) fn try_parse(start_line: &str) -> Option<Self> {
-        let mut components = start_line.split_whitespace();
-        if components.clone().count() != 3 {
-            None
-        } else {
-            let method = match components.nth(0usize).unwrap() {
-                "GET" => Method::Get,
-                "POST" => Method::Post,
-                _ => unimplemented!(),
-            };
-            let target_component = components.nth(0usize).unwrap();
-            let target = if target_component.contains('*') {
-                Target {
-                    path: "*".to_string(),
-                    form: target::Form::Asterisk,
+        Self::from_str(start_line).ok()
+    }
+}
+/// Splits an authority into its host and port, both required
+///
+/// IPv6 hosts are bracketed, the brackets are kept.
+pub fn split_authority(authority: &str) -> Option<(&str, u16)> {
+    let (host, port) = authority.rsplit_once(':')?;
+    let bracketed = host.starts_with('[') && host.ends_with(']');
+    let valid_host = !host.is_empty()
+        && (bracketed || !host.contains(['[', ']', ':', '/', '@']))
+        && !host.contains(char::is_whitespace);
+    let port = port.parse::<u16>().ok().filter(|port| *port != 0)?;
+    valid_host.then_some((host, port))
+}
+/// How the body of a request is delimited, see RFC 9112 section 6.3
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Framing {
+    Empty,
+    Length(usize),
+    Chunked,
+}
+/// Longest chunk size line accepted, chunk extensions included
+pub const MAX_CHUNK_LINE: usize = 4 * 1024;
+/// Largest trailer section accepted, its final empty line included
+pub const MAX_TRAILER_SIZE: usize = 8 * 1024;
+/// Progress of [`decode_chunks`] through a chunked body
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Chunks {
+    /// The body ended after this many bytes, trailer fields included
+    Complete(usize),
+    /// This many bytes held whole chunks, decoding resumes after them
+    Partial(usize),
+}
+/// Decodes the whole chunks at the start of `body` into `decoded`, see RFC 9112 section 7.1
+///
+/// Decoding stops before the first chunk not fully received, so that it can
+/// resume there once more bytes arrive, and as soon as a chunk size would
+/// take the decoded body past `limit`. Chunk extensions are ignored and
+/// trailer fields are discarded, size lines longer than [`MAX_CHUNK_LINE`]
+/// and trailer sections larger than [`MAX_TRAILER_SIZE`] being rejected
+/// before they end.
+pub fn decode_chunks(
+    body: &[u8],
+    decoded: &mut Vec<u8>,
+    limit: usize,
+) -> Result<Chunks, super::Error> {
+    use super::Error::{ContentTooLarge, Framing};
+    let line_end = |bytes: &[u8]| bytes.windows(2).position(|w| w.eq(b"\r\n"));
+    let mut offset = 0usize;
+    loop {
+        let size_end = match line_end(&body[offset..]) {
+            Some(size_end) if size_end <= MAX_CHUNK_LINE => offset + size_end,
+            None if body.len() - offset <= MAX_CHUNK_LINE => return Ok(Chunks::Partial(offset)),
+            _ => {
+                let message = format!("chunk size line longer than {} bytes", MAX_CHUNK_LINE);
+                return Err(Framing(message));
+            }
+        };
+        let size_line = String::from_utf8_lossy(&body[offset..size_end]).to_string();
+        let size_str = size_line.split(';').next().unwrap_or("").trim();
+        let size = match usize::from_str_radix(size_str, 16) {
+            Ok(size) if size_str.bytes().all(|b| b.is_ascii_hexdigit()) => size,
+            _ => return Err(Framing(format!("invalid chunk size {}", size_line))),
+        };
+        if decoded.len().saturating_add(size) > limit {
+            return Err(ContentTooLarge(limit));
+        }
+        let data_start = size_end + 2;
+        if size == 0 {
+            // trailer fields run until an empty line
+            let mut trailer_start = data_start;
+            loop {
+                let trailer_end = line_end(&body[trailer_start..]);
+                let read = trailer_end.map_or(body.len(), |end| trailer_start + end + 2);
+                if read - data_start > MAX_TRAILER_SIZE {
+                    let message = format!("trailer section larger than {} bytes", MAX_TRAILER_SIZE);
+                    return Err(Framing(message));
                 }
-            } else {
-                Target {
-                    path: target_component.to_string(),
-                    form: target::Form::Absolute,
+                match trailer_end {
+                    Some(0) => return Ok(Chunks::Complete(trailer_start + 2)),
+                    Some(trailer_end) => trailer_start += trailer_end + 2,
+                    None => return Ok(Chunks::Partial(offset)),
                 }
-            };
-            // let path =
-            //  components
-            //     .nth(0usize)
-            //     .unwrap()
-            //     .split('/')
-            //     .filter(!String::is_empty)
-            //     .collect::<Vec<String>>();
-            let version = match components.nth(0usize).unwrap() {
-                "HTTP/1.1" => super::Version(1u8, Some(1u8)),
-                "HTTP/2" => super::Version(2u8, None),
-                _ => unimplemented!(),
-            };
-            let line = Self {
-                method,
-                target,
-                version,
-            };
-            Some(line)
+            }
+        }
+        let chunk_end = data_start.saturating_add(size);
+        if body.len() < chunk_end.saturating_add(2) {
+            return Ok(Chunks::Partial(offset));
         }
+        if !body[chunk_end..chunk_end + 2].eq(b"\r\n") {
+            return Err(Framing(format!("chunk of size {} not terminated", size)));
+        }
+        decoded.extend_from_slice(&body[data_start..chunk_end]);
+        offset = chunk_end + 2;
+    }
+}
+/// Decodes a body sent with the chunked transfer coding
+///
+/// Returns the decoded body and the number of bytes it took up, or `Ok(None)`
+/// if `body` does not hold the whole message yet.
+pub fn decode_chunked(body: &[u8]) -> Result<Option<(Vec<u8>, usize)>, super::Error> {
+    let mut decoded = vec![];
+    match decode_chunks(body, &mut decoded, usize::MAX)? {
+        Chunks::Complete(length) => Ok(Some((decoded, length))),
+        Chunks::Partial(_) => Ok(None),
     }
 }
@@ -0,0 +1,153 @@
+use crate::http::{
+    body::Producer,
+    header::{HeaderMap, Kind, Typed},
+    response::{Startline, Status},
+    Response, Version,
+};
+
+/// Builds a [`Response`] from its status and header fields, finished by its body
+///
+/// The status line always reads HTTP/1.1, the highest version a HTTP/1.x
+/// client can be answered with, and one HTTP/2 ignores.
+/// Content-Length and Date are left out on purpose: they are computed when
+/// the response is sent, see [`Response::complete_headers`].
+pub struct Builder {
+    status: Status,
+    headers: HeaderMap,
+}
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            status: Status::Ok,
+            headers: HeaderMap::new(),
+        }
+    }
+}
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Status of the response, 200 unless set
+    pub fn status(mut self, status: Status) -> Self {
+        self.status = status;
+        self
+    }
+    /// Adds a field, keeping any earlier field with the same name
+    pub fn header<N, V>(mut self, name: N, value: V) -> Self
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        self.headers.append(name, value);
+        self
+    }
+    /// Adds a known header, keeping any earlier field with the same name
+    pub fn kind(mut self, kind: Kind) -> Self {
+        self.headers.push(kind);
+        self
+    }
+    /// Encodes `header`, replacing any earlier field with the same name
+    pub fn typed<T: Typed>(mut self, header: &T) -> Self {
+        self.headers.insert_typed(header);
+        self
+    }
+    /// Adds every field of `headers`, after those already set
+    pub fn headers(mut self, headers: HeaderMap) -> Self {
+        headers.iter().for_each(|field| {
+            self.headers
+                .append(field.name.as_str(), field.value.as_str())
+        });
+        self
+    }
+    /// Finishes the response with `body`
+    pub fn body<B: Into<Vec<u8>>>(self, body: B) -> Response {
+        let mut response = self.empty();
+        response.body = Some(body.into());
+        response
+    }
+    /// Finishes the response without a body
+    pub fn empty(self) -> Response {
+        Response {
+            start_line: Startline {
+                version: Version::HTTP_1_1,
+                status: self.status,
+            },
+            headers: self.headers,
+            body: None,
+            stream: None,
+            announced: None,
+        }
+    }
+    /// Finishes the response without a body, announcing the `length` of the
+    /// one it stands for, as responses to HEAD requests and 304 responses do
+    pub fn length_only(self, length: usize) -> Response {
+        let mut response = self.empty();
+        response.announced = Some(length);
+        response
+    }
+    /// Finishes the response with a body produced while it is being sent
+    ///
+    /// HTTP/1.1 sends it chunked, HTTP/1.0 closes the connection after it,
+    /// and HTTP/2 sends each chunk as DATA frames.
+    pub fn stream(self, producer: Producer) -> Response {
+        let mut response = self.empty();
+        response.stream = Some(producer);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::{json::Value, response::Status, Response};
+
+    fn serialized(response: Response) -> String {
+        String::from_utf8(response.serialize()).unwrap()
+    }
+
+    #[test]
+    fn content_length_follows_the_body() {
+        let response = Response::builder()
+            .status(Status::Created)
+            .header("Content-Length", "99")
+            .header("X-Custom", "a")
+            .body("hello");
+        let message = serialized(response);
+        assert!(message.starts_with("HTTP/1.1 201 Created\r\nX-Custom: a\r\n"));
+        assert!(message.contains("Content-Length: 5\r\n"));
+        assert!(!message.contains("99"));
+        assert!(message.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn date_and_server_are_added_unless_set() {
+        let message = serialized(Response::empty(Status::NotFound));
+        assert!(message.contains("Content-Length: 0\r\nDate: "));
+        assert!(message.contains(" GMT\r\nServer: "));
+        let response = Response::builder()
+            .header("Date", "Sun, 06 Nov 1994 08:49:37 GMT")
+            .empty();
+        assert_eq!(serialized(response).matches("Date: ").count(), 1);
+    }
+
+    #[test]
+    fn interim_responses_carry_neither() {
+        let response = Response::builder()
+            .status(Status::SwitchingProtocols)
+            .header("Upgrade", "h2c")
+            .empty();
+        assert_eq!(
+            serialized(response),
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: h2c\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn convenience_constructors_set_the_content_type() {
+        let message = serialized(Response::json(Status::Ok, Value::Null));
+        assert!(message.contains("Content-Type: application/json\r\n"));
+        let message = serialized(Response::redirect(Status::SeeOther, "/a"));
+        assert!(message.starts_with("HTTP/1.1 303 See Other\r\nLocation: /a\r\n"));
+        let response = Response::file("/nonexistent/file").unwrap();
+        assert_eq!(response.start_line.status as u16, 404);
+    }
+}
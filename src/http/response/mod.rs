@@ -1,19 +1,185 @@
+/// Module to build responses field by field
+pub(crate) mod builder;
+
 use std::fmt;
+
+pub use builder::Builder;
 #[derive(Clone, Copy, Debug)]
 #[allow(dead_code)]
 pub enum Status {
+    Continue = 100,
+    SwitchingProtocols = 101,
+    EarlyHints = 103,
     Ok = 200,
     Created = 201,
+    Accepted = 202,
+    NonAuthoritativeInformation = 203,
+    NoContent = 204,
+    ResetContent = 205,
+    PartialContent = 206,
+    MultipleChoices = 300,
+    MovedPermanently = 301,
+    Found = 302,
+    SeeOther = 303,
+    NotModified = 304,
+    TemporaryRedirect = 307,
+    PermanentRedirect = 308,
+    BadRequest = 400,
+    Unauthorized = 401,
+    PaymentRequired = 402,
+    Forbidden = 403,
     NotFound = 404,
+    MethodNotAllowed = 405,
+    NotAcceptable = 406,
+    ProxyAuthenticationRequired = 407,
+    RequestTimeout = 408,
+    Conflict = 409,
+    Gone = 410,
+    LengthRequired = 411,
+    PreconditionFailed = 412,
+    ContentTooLarge = 413,
+    UriTooLong = 414,
+    UnsupportedMediaType = 415,
+    RangeNotSatisfiable = 416,
+    ExpectationFailed = 417,
+    MisdirectedRequest = 421,
+    UnprocessableContent = 422,
+    UpgradeRequired = 426,
+    PreconditionRequired = 428,
+    TooManyRequests = 429,
+    RequestHeaderFieldsTooLarge = 431,
+    UnavailableForLegalReasons = 451,
+    InternalServerError = 500,
+    NotImplemented = 501,
+    BadGateway = 502,
+    ServiceUnavailable = 503,
+    GatewayTimeout = 504,
+    HttpVersionNotSupported = 505,
 }
 impl fmt::Display for Status {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let s = match self {
-            Status::Ok => format!("{} OK", *self as isize),
-            Status::Created => format!("{} Created", *self as isize),
-            Status::NotFound => format!("{} NotFound", *self as isize),
-        };
-        fmt::write(f, format_args!("{}", s))
+        fmt::write(f, format_args!("{} {}", self.code(), self.reason()))
+    }
+}
+impl Status {
+    /// Every status, to look codes up in
+    const ALL: [Status; 48] = [
+        Status::Continue,
+        Status::SwitchingProtocols,
+        Status::EarlyHints,
+        Status::Ok,
+        Status::Created,
+        Status::Accepted,
+        Status::NonAuthoritativeInformation,
+        Status::NoContent,
+        Status::ResetContent,
+        Status::PartialContent,
+        Status::MultipleChoices,
+        Status::MovedPermanently,
+        Status::Found,
+        Status::SeeOther,
+        Status::NotModified,
+        Status::TemporaryRedirect,
+        Status::PermanentRedirect,
+        Status::BadRequest,
+        Status::Unauthorized,
+        Status::PaymentRequired,
+        Status::Forbidden,
+        Status::NotFound,
+        Status::MethodNotAllowed,
+        Status::NotAcceptable,
+        Status::ProxyAuthenticationRequired,
+        Status::RequestTimeout,
+        Status::Conflict,
+        Status::Gone,
+        Status::LengthRequired,
+        Status::PreconditionFailed,
+        Status::ContentTooLarge,
+        Status::UriTooLong,
+        Status::UnsupportedMediaType,
+        Status::RangeNotSatisfiable,
+        Status::ExpectationFailed,
+        Status::MisdirectedRequest,
+        Status::UnprocessableContent,
+        Status::UpgradeRequired,
+        Status::PreconditionRequired,
+        Status::TooManyRequests,
+        Status::RequestHeaderFieldsTooLarge,
+        Status::UnavailableForLegalReasons,
+        Status::InternalServerError,
+        Status::NotImplemented,
+        Status::BadGateway,
+        Status::ServiceUnavailable,
+        Status::GatewayTimeout,
+        Status::HttpVersionNotSupported,
+    ];
+    /// Status with the numeric `code`, if it is a registered one
+    pub fn from_code(code: u16) -> Option<Self> {
+        Self::ALL.into_iter().find(|status| status.code() == code)
+    }
+    pub fn code(&self) -> u16 {
+        *self as u16
+    }
+    /// Returns true for interim responses, which carry no body
+    pub fn is_informational(&self) -> bool {
+        self.code() < 200
+    }
+    /// Returns true for client and server errors
+    pub fn is_error(&self) -> bool {
+        self.code() >= 400
+    }
+    /// Reason phrase of the status, like `Not Found`
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Status::Continue => "Continue",
+            Status::SwitchingProtocols => "Switching Protocols",
+            Status::EarlyHints => "Early Hints",
+            Status::Ok => "OK",
+            Status::Created => "Created",
+            Status::Accepted => "Accepted",
+            Status::NonAuthoritativeInformation => "Non-Authoritative Information",
+            Status::NoContent => "No Content",
+            Status::ResetContent => "Reset Content",
+            Status::PartialContent => "Partial Content",
+            Status::MultipleChoices => "Multiple Choices",
+            Status::MovedPermanently => "Moved Permanently",
+            Status::Found => "Found",
+            Status::SeeOther => "See Other",
+            Status::NotModified => "Not Modified",
+            Status::TemporaryRedirect => "Temporary Redirect",
+            Status::PermanentRedirect => "Permanent Redirect",
+            Status::BadRequest => "Bad Request",
+            Status::Unauthorized => "Unauthorized",
+            Status::PaymentRequired => "Payment Required",
+            Status::Forbidden => "Forbidden",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::NotAcceptable => "Not Acceptable",
+            Status::ProxyAuthenticationRequired => "Proxy Authentication Required",
+            Status::RequestTimeout => "Request Timeout",
+            Status::Conflict => "Conflict",
+            Status::Gone => "Gone",
+            Status::LengthRequired => "Length Required",
+            Status::PreconditionFailed => "Precondition Failed",
+            Status::ContentTooLarge => "Content Too Large",
+            Status::UriTooLong => "URI Too Long",
+            Status::UnsupportedMediaType => "Unsupported Media Type",
+            Status::RangeNotSatisfiable => "Range Not Satisfiable",
+            Status::ExpectationFailed => "Expectation Failed",
+            Status::MisdirectedRequest => "Misdirected Request",
+            Status::UnprocessableContent => "Unprocessable Content",
+            Status::UpgradeRequired => "Upgrade Required",
+            Status::PreconditionRequired => "Precondition Required",
+            Status::TooManyRequests => "Too Many Requests",
+            Status::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            Status::UnavailableForLegalReasons => "Unavailable For Legal Reasons",
+            Status::InternalServerError => "Internal Server Error",
+            Status::NotImplemented => "Not Implemented",
+            Status::BadGateway => "Bad Gateway",
+            Status::ServiceUnavailable => "Service Unavailable",
+            Status::GatewayTimeout => "Gateway Timeout",
+            Status::HttpVersionNotSupported => "HTTP Version Not Supported",
+        }
     }
 }
 pub struct Startline {
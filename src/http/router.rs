@@ -0,0 +1,405 @@
+use {
+    crate::http::{
+        auth::Auth,
+        connection::{Handler, MAX_BODY_SIZE},
+        cors::Cors,
+        header::typed::Host,
+        middleware::{self, Layer, Next},
+        proxy::{ForwardProxy, Proxy},
+        ratelimit::RateLimit,
+        request::{target, Method},
+        session::{self, Sessions},
+        sse,
+        websocket::{self, WebSocket},
+        Request, Response,
+    },
+    std::{
+        io,
+        sync::{Arc, Mutex},
+    },
+};
+
+/// Takes over a connection once its WebSocket handshake succeeded
+pub type WebSocketHandler = Arc<dyn Fn(Request, WebSocket<'_>) -> io::Result<()> + Send + Sync>;
+
+/// Dispatches requests to the handler registered for their path
+///
+/// Routes match the request path exactly, without its query, HEAD requests
+/// taking GET routes, and mounts every path under their prefix, whatever the
+/// method. Requests matching neither go to the fallback handler. Every
+/// handler is wrapped by the layers, the first declared outermost, WebSocket
+/// handshakes and CONNECT requests going through them too, see
+/// [`Router::upgrade`].
+///
+/// Routers may hold sites, other routers serving the requests for a host
+/// name, see [`Router::host`]. Requests for other hosts are left to this one.
+pub struct Router {
+    fallback: Handler,
+    routes: Vec<(Method, String, Handler)>,
+    mounts: Vec<(String, Handler)>,
+    websockets: Vec<(String, WebSocketHandler)>,
+    forward_proxy: Option<Arc<ForwardProxy>>,
+    layers: Vec<Arc<dyn Layer>>,
+    sites: Vec<(String, Router)>,
+    max_body_size: usize,
+    /// Prefixes whose handlers read bodies as they arrive
+    streamed: Vec<String>,
+}
+impl Router {
+    pub fn new(fallback: Handler) -> Self {
+        Self {
+            fallback,
+            routes: vec![],
+            mounts: vec![],
+            websockets: vec![],
+            forward_proxy: None,
+            layers: vec![],
+            sites: vec![],
+            max_body_size: MAX_BODY_SIZE,
+            streamed: vec![],
+        }
+    }
+    /// Limits request bodies to `max_body_size` bytes, larger ones being
+    /// answered with 413 while they are read off the connection
+    pub fn body_limit(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+    /// Largest request body accepted, on every site of the connection
+    pub fn max_body_size(&self) -> usize {
+        self.max_body_size
+    }
+    /// Hands the requests under `prefix` to their handler while their body is
+    /// still arriving, see [`Request::take_body`]
+    pub fn stream_bodies(mut self, prefix: &str) -> Self {
+        self.streamed.push(prefix.to_string());
+        self
+    }
+    /// Returns true if the body of `request` is read while its handler runs,
+    /// as it is under the prefixes streaming bodies and for the forward proxy
+    pub fn streams_body(&self, request: &Request) -> bool {
+        let path = path(request);
+        let forwarded = request.start_line.target.form.eq(&target::Form::Absolute);
+        let streamed = || self.streamed.iter().any(|prefix| is_under(path, prefix));
+        match request.start_line.method {
+            Method::Connect => false,
+            _ => (forwarded && self.forward_proxy.is_some()) || (!forwarded && streamed()),
+        }
+    }
+    /// Wraps every handler in `layer`, inside the layers declared before it,
+    /// the layer keeping its state across the routers it is shared with
+    pub fn layer(mut self, layer: Arc<dyn Layer>) -> Self {
+        self.layers.push(layer);
+        self
+    }
+    /// Registers `handler` for requests with `method` at `path`
+    pub fn route<F>(mut self, method: Method, path: &str, handler: F) -> Self
+    where
+        F: Fn(Request) -> io::Result<Response> + Send + Sync + 'static,
+    {
+        self.routes
+            .push((method, path.to_string(), Arc::new(handler)));
+        self
+    }
+    /// Registers `handler` for requests of any method at `prefix` or under it,
+    /// routes registered for a path taking precedence
+    pub fn mount<F>(mut self, prefix: &str, handler: F) -> Self
+    where
+        F: Fn(Request) -> io::Result<Response> + Send + Sync + 'static,
+    {
+        self.mounts.push((prefix.to_string(), Arc::new(handler)));
+        self
+    }
+    /// Registers a WebSocket endpoint at `path`
+    pub fn websocket<F>(mut self, path: &str, handler: F) -> Self
+    where
+        F: Fn(Request, WebSocket<'_>) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.websockets.push((path.to_string(), Arc::new(handler)));
+        self
+    }
+    /// Serves the requests for `pattern` with `site`, a host name or `*.`
+    /// followed by a domain for any of its subdomains
+    pub fn host(mut self, pattern: &str, site: Router) -> Self {
+        let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+        self.sites.push((pattern, site));
+        self
+    }
+    /// Returns the router of the site `request` is for, this one for unknown hosts
+    ///
+    /// Sites named exactly take precedence over wildcards, which are tried
+    /// in the order they were added.
+    pub fn site(&self, request: &Request) -> &Router {
+        let Some(Host { host, .. }) = request.headers.typed::<Host>() else {
+            return self;
+        };
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let exact = self.sites.iter().find(|(pattern, _)| pattern.eq(&host));
+        let wildcard = || {
+            self.sites.iter().find(|(pattern, _)| {
+                pattern
+                    .strip_prefix('*')
+                    .is_some_and(|suffix| suffix.starts_with('.') && host.ends_with(suffix))
+            })
+        };
+        exact.or_else(wildcard).map_or(self, |(_, site)| site)
+    }
+    /// Returns the WebSocket endpoint registered for the request path, if any
+    pub fn websocket_route(&self, request: &Request) -> Option<WebSocketHandler> {
+        let path = path(request);
+        self.websockets
+            .iter()
+            .find(|(route, _)| route.eq(path))
+            .map(|(_, handler)| handler.clone())
+    }
+    /// Serves as `proxy` for the clients that address it as one, its layer
+    /// taking absolute form requests inside the layers declared before it
+    pub fn forward_proxy(mut self, proxy: Arc<ForwardProxy>) -> Self {
+        self.layers.push(proxy.clone());
+        self.forward_proxy = Some(proxy);
+        self
+    }
+    /// Returns the forward proxy to open the tunnel of a CONNECT request, if any
+    pub fn connect_route(&self, request: &Request) -> Option<Arc<ForwardProxy>> {
+        match request.start_line.method {
+            Method::Connect => self.forward_proxy.clone(),
+            _ => None,
+        }
+    }
+    /// Runs the layers on a request about to take over the connection, with
+    /// `handshake` answering it in place of a handler
+    ///
+    /// Returns the response, and the request along with what `handshake`
+    /// gave to take the connection over with, if any. Requests a layer turned
+    /// away, unauthenticated or rate limited, never reach `handshake`.
+    pub fn upgrade<T, F>(
+        &self,
+        request: Request,
+        handshake: F,
+    ) -> io::Result<(Response, Option<(Request, T)>)>
+    where
+        T: Send + 'static,
+        F: Fn(&Request) -> (Response, Option<T>) + Send + Sync + 'static,
+    {
+        let upgraded = Arc::new(Mutex::new(None));
+        let handler: Handler = {
+            let upgraded = upgraded.clone();
+            Arc::new(move |request| {
+                let (response, upgrade) = handshake(&request);
+                let mut upgraded = upgraded.lock().unwrap_or_else(|e| e.into_inner());
+                *upgraded = upgrade.map(|upgrade| (request, upgrade));
+                Ok(response)
+            })
+        };
+        let response = Next::new(&self.layers, &handler).run(request)?;
+        let upgraded = upgraded.lock().unwrap_or_else(|e| e.into_inner()).take();
+        Ok((response, upgraded))
+    }
+    /// Responds to a request that is not taking over the connection
+    pub fn respond(&self, request: Request) -> io::Result<Response> {
+        let path = path(&request);
+        let mount = || {
+            self.mounts
+                .iter()
+                .find(|(prefix, _)| is_under(path, prefix))
+                .map(|(_, handler)| handler)
+        };
+        let handler = self
+            .routes
+            .iter()
+            .find(|(method, route, _)| {
+                // HEAD is answered wherever GET is
+                let requested = match request.start_line.method {
+                    Method::Head => Method::Get,
+                    method => method,
+                };
+                method.eq(&requested) && route.eq(path)
+            })
+            .map(|(_, _, handler)| handler)
+            .or_else(mount)
+            .unwrap_or(&self.fallback);
+        Next::new(&self.layers, handler).run(request)
+    }
+}
+
+/// Returns true if `path` is `prefix` or lies under it, comparing whole
+/// segments and ignoring empty ones and the query
+pub fn is_under(path: &str, prefix: &str) -> bool {
+    let segments = |path: &str| {
+        let path = path
+            .split_once('?')
+            .map_or(path, |(path, _query)| path)
+            .to_string();
+        path.split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(String::from)
+            .collect::<Vec<String>>()
+    };
+    let (path, prefix) = (segments(path), segments(prefix));
+    path.starts_with(&prefix)
+}
+
+/// Path of the request target without its query
+fn path(request: &Request) -> &str {
+    let path = request.start_line.target.path.as_str();
+    path.split_once('?').map_or(path, |(path, _query)| path)
+}
+
+/// Routes served by default: the built-in handlers, ticking server-sent
+/// events at `/events`, a WebSocket echo at `/ws/echo`, the session of
+/// the client at `/session` and the upstreams [`Proxy::from_args`] reads,
+/// serving as the forward proxy [`ForwardProxy::from_args`] reads if enabled
+///
+/// Bodies are limited to `--max-body-size` bytes, or [`MAX_BODY_SIZE`],
+/// clients are rate limited as [`RateLimit::from_args`] reads, other origins
+/// allowed as [`Cors::from_args`] reads, clients authenticated as
+/// [`Auth::from_args`] reads and sessions kept as [`Sessions::from_args`] reads.
+///
+/// Files are served from `--directory`, and every `--vhost host=directory`
+/// adds a site with the same routes serving its own files, see [`Router::host`].
+/// Sites share their layers and proxies, so that a client is rate limited and
+/// its session kept across them.
+pub fn routes() -> io::Result<Router> {
+    let max_body_size = crate::args::value("--max-body-size")
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(MAX_BODY_SIZE);
+    let shared = Shared::from_args()?;
+    let mut router = site(crate::args::value("--directory"), &shared).body_limit(max_body_size);
+    for vhost in crate::args::values("--vhost") {
+        match vhost.split_once('=') {
+            Some((host, directory)) if !host.is_empty() && !directory.is_empty() => {
+                router = router.host(host, site(Some(directory.to_string()), &shared));
+            }
+            _ => {
+                let message = format!("vhost {} is not host=directory", vhost);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+            }
+        }
+    }
+    Ok(router)
+}
+
+/// Layers and proxies read from the arguments once for every site
+struct Shared {
+    /// Layers declared before the forward proxy, outermost first
+    outer: Vec<Arc<dyn Layer>>,
+    forward_proxy: Option<Arc<ForwardProxy>>,
+    /// Layers declared after the forward proxy, outermost first
+    inner: Vec<Arc<dyn Layer>>,
+    proxies: Vec<Arc<Proxy>>,
+}
+impl Shared {
+    fn from_args() -> io::Result<Self> {
+        Ok(Self {
+            outer: vec![
+                Arc::new(middleware::RequestId),
+                Arc::new(middleware::JsonErrors),
+                Arc::new(middleware::Logging),
+                Arc::new(RateLimit::from_args()?),
+                Arc::new(middleware::Timing),
+                Arc::new(middleware::CatchPanic),
+            ],
+            forward_proxy: ForwardProxy::from_args()?.map(Arc::new),
+            inner: vec![
+                Arc::new(Cors::from_args()),
+                Arc::new(Auth::from_args()?),
+                Arc::new(Sessions::from_args()?),
+            ],
+            proxies: Proxy::from_args()?.into_iter().map(Arc::new).collect(),
+        })
+    }
+}
+
+/// Routes of a site whose built-in handlers serve files from `directory`
+fn site(directory: Option<String>, shared: &Shared) -> Router {
+    let builtin = move |request| Response::builtin(request, directory.clone());
+    let mut router = Router::new(Arc::new(builtin));
+    for layer in &shared.outer {
+        router = router.layer(layer.clone());
+    }
+    if let Some(forward_proxy) = &shared.forward_proxy {
+        router = router.forward_proxy(forward_proxy.clone());
+    }
+    for layer in &shared.inner {
+        router = router.layer(layer.clone());
+    }
+    router = router
+        .route(Method::Get, "/events", sse::ticks)
+        .route(Method::Get, "/session", session::show)
+        .route(Method::Post, "/session", session::update)
+        .route(Method::Post, "/session/end", session::end)
+        .websocket("/ws/echo", websocket::echo)
+        .stream_bodies("/files");
+    for proxy in &shared.proxies {
+        let proxy = proxy.clone();
+        let prefix = proxy.prefix.clone();
+        router = router
+            .mount(&prefix, move |request| proxy.forward(request))
+            .stream_bodies(&prefix);
+    }
+    router
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Router;
+    use crate::http::{
+        middleware::{Layer, Next},
+        response::Status,
+        Request, Response,
+    };
+    use std::{io, sync::Arc};
+
+    /// Router answering every request with `name`
+    fn named(name: &'static str) -> Router {
+        Router::new(Arc::new(move |_| Ok(Response::text(Status::Ok, name))))
+    }
+
+    #[test]
+    fn sites_are_chosen_by_host() {
+        let router = named("default")
+            .host("*.example.com", named("wildcard"))
+            .host("API.example.com.", named("api"))
+            .host("example.org", named("org"));
+        for (host, site) in [
+            ("api.example.com", "api"),
+            ("www.Example.com:8080", "wildcard"),
+            ("example.com", "default"),
+            ("example.org.", "org"),
+            ("other.test", "default"),
+        ] {
+            let raw = format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", host);
+            let request = Request::try_construct(raw.as_bytes()).unwrap();
+            let response = router.site(&request).respond(request).unwrap();
+            assert_eq!(response.body.as_deref(), Some(site.as_bytes()), "{}", host);
+        }
+        let request = Request::try_construct(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        let response = router.site(&request).respond(request).unwrap();
+        assert_eq!(response.body.as_deref(), Some(&b"default"[..]));
+    }
+
+    /// Turns away the requests under `/private`
+    struct Private;
+    impl Layer for Private {
+        fn handle(&self, request: Request, next: Next<'_>) -> io::Result<Response> {
+            match super::is_under(&request.start_line.target.path, "/private") {
+                true => Ok(Response::empty(Status::Forbidden)),
+                false => next.run(request),
+            }
+        }
+    }
+
+    #[test]
+    fn upgrades_go_through_the_layers() {
+        let router = named("default").layer(Arc::new(Private));
+        for (path, upgraded) in [("/ws", true), ("/private/ws", false)] {
+            let raw = format!("GET {} HTTP/1.1\r\nHost: a\r\n\r\n", path);
+            let request = Request::try_construct(raw.as_bytes()).unwrap();
+            let handshake = |_: &Request| (Response::empty(Status::SwitchingProtocols), Some(()));
+            let (response, upgrade) = router.upgrade(request, handshake).unwrap();
+            assert_eq!(upgrade.is_some(), upgraded, "{}", path);
+            let status = if upgraded { 101 } else { 403 };
+            assert_eq!(response.start_line.status as u16, status, "{}", path);
+        }
+    }
+}
@@ -0,0 +1,607 @@
+//! Sessions tracking clients across requests through a signed id cookie
+//!
+//! The [`Sessions`] layer loads the session named by the cookie before the
+//! handler runs and saves it afterwards. Handlers reach it through
+//! [`Request::session`]. A session is only stored, and its cookie only set,
+//! once it holds data.
+use {
+    crate::http::{
+        cookie::{Cookie, Jar, Key, SameSite},
+        form, json,
+        middleware::{Layer, Next},
+        response::Status,
+        Request, Response,
+    },
+    ring::rand::{SecureRandom, SystemRandom},
+    std::{
+        collections::{BTreeMap, HashMap},
+        fs, io,
+        path::PathBuf,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// Name of the session cookie when no `--session-cookie` is given
+pub const COOKIE_NAME: &str = "sid";
+/// Time a session lasts without requests when no `--session-idle-timeout` is given
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// Time a session lasts at most when no `--session-timeout` is given
+pub const ABSOLUTE_TIMEOUT: Duration = Duration::from_secs(8 * 60 * 60);
+/// Random bytes in a session id, written as twice as many hex digits
+const ID_LENGTH: usize = 32;
+/// How often expired sessions are dropped from the store
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Returns a new random session id
+fn generate_id() -> io::Result<String> {
+    let mut bytes = [0u8; ID_LENGTH];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| io::Error::other("no randomness available"))?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Returns true for ids this server could have generated, the only ones used as file names
+fn is_valid_id(id: &str) -> bool {
+    id.len() == 2 * ID_LENGTH && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Data of a session as it is stored, with the times its timeouts count from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub data: BTreeMap<String, String>,
+    pub created: SystemTime,
+    pub accessed: SystemTime,
+}
+impl Record {
+    fn new(now: SystemTime) -> Self {
+        Self {
+            data: BTreeMap::new(),
+            created: now,
+            accessed: now,
+        }
+    }
+    fn to_json(&self) -> json::Value {
+        let seconds = |time: SystemTime| {
+            let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+            json::Value::Number(since.as_secs() as f64)
+        };
+        let data = self
+            .data
+            .iter()
+            .map(|(name, value)| (name.as_str(), json::Value::from(value.as_str())));
+        json::Value::object([
+            ("created", seconds(self.created)),
+            ("accessed", seconds(self.accessed)),
+            ("data", json::Value::object(data)),
+        ])
+    }
+    fn from_json(value: &json::Value) -> Option<Self> {
+        let time = |name: &str| match value.get(name)? {
+            json::Value::Number(seconds) if *seconds >= 0.0 => {
+                Some(UNIX_EPOCH + Duration::from_secs(*seconds as u64))
+            }
+            _ => None,
+        };
+        let data = match value.get("data")? {
+            json::Value::Object(members) => members
+                .iter()
+                .map(|(name, value)| match value {
+                    json::Value::String(value) => Some((name.clone(), value.clone())),
+                    _ => None,
+                })
+                .collect::<Option<BTreeMap<String, String>>>()?,
+            _ => return None,
+        };
+        Some(Self {
+            data,
+            created: time("created")?,
+            accessed: time("accessed")?,
+        })
+    }
+}
+
+/// When sessions expire: after `idle` without requests, and `absolute` after they were created
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Timeouts {
+    pub idle: Duration,
+    pub absolute: Duration,
+}
+impl Timeouts {
+    /// Reads `--session-idle-timeout` and `--session-timeout`, both in seconds
+    pub fn from_args() -> Self {
+        let seconds = |flag: &str| {
+            crate::args::value(flag)
+                .and_then(|seconds| seconds.parse::<u64>().ok())
+                .map(Duration::from_secs)
+        };
+        Self {
+            idle: seconds("--session-idle-timeout").unwrap_or(IDLE_TIMEOUT),
+            absolute: seconds("--session-timeout").unwrap_or(ABSOLUTE_TIMEOUT),
+        }
+    }
+    pub fn is_expired(&self, record: &Record, now: SystemTime) -> bool {
+        let elapsed = |since: SystemTime| now.duration_since(since).unwrap_or_default();
+        elapsed(record.accessed) >= self.idle || elapsed(record.created) >= self.absolute
+    }
+}
+
+/// Where sessions are kept between requests
+///
+/// Implement this to keep them elsewhere, [`Sessions::new`] takes any store.
+pub trait Store: Send + Sync {
+    /// The session `id`, or `None` if there is none by that id
+    fn load(&self, id: &str) -> io::Result<Option<Record>>;
+    fn save(&self, id: &str, record: &Record) -> io::Result<()>;
+    /// Removes the session `id`, doing nothing if there is none
+    fn remove(&self, id: &str) -> io::Result<()>;
+    /// Removes every session `expired` returns true for
+    fn remove_expired(&self, expired: &dyn Fn(&Record) -> bool) -> io::Result<()>;
+}
+
+/// Sessions kept in memory, lost when the process exits
+#[derive(Default)]
+pub struct MemoryStore {
+    records: Mutex<HashMap<String, Record>>,
+}
+impl Store for MemoryStore {
+    fn load(&self, id: &str) -> io::Result<Option<Record>> {
+        let records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(records.get(id).cloned())
+    }
+    fn save(&self, id: &str, record: &Record) -> io::Result<()> {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.insert(id.to_string(), record.clone());
+        Ok(())
+    }
+    fn remove(&self, id: &str) -> io::Result<()> {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.remove(id);
+        Ok(())
+    }
+    fn remove_expired(&self, expired: &dyn Fn(&Record) -> bool) -> io::Result<()> {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.retain(|_, record| !expired(record));
+        Ok(())
+    }
+}
+
+/// Sessions kept as one JSON file each under a directory, surviving restarts
+///
+/// Files are replaced whole by renaming, so a session is never read half written.
+pub struct FileStore {
+    directory: PathBuf,
+}
+impl FileStore {
+    /// Store under `directory`, created if missing
+    pub fn new<P: Into<PathBuf>>(directory: P) -> io::Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+        Ok(Self { directory })
+    }
+    fn path(&self, id: &str) -> io::Result<PathBuf> {
+        match is_valid_id(id) {
+            true => Ok(self.directory.join(format!("{}.json", id))),
+            false => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid session id {}", id.escape_debug()),
+            )),
+        }
+    }
+    /// The record stored at `path`, or `None` if it was removed or is not a record
+    fn read(path: &PathBuf) -> io::Result<Option<Record>> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(json::Value::parse(&bytes)
+                .ok()
+                .as_ref()
+                .and_then(Record::from_json)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+impl Store for FileStore {
+    fn load(&self, id: &str) -> io::Result<Option<Record>> {
+        Self::read(&self.path(id)?)
+    }
+    fn save(&self, id: &str, record: &Record) -> io::Result<()> {
+        let path = self.path(id)?;
+        let temporary = self.directory.join(format!(".{}.tmp", id));
+        fs::write(&temporary, record.to_json().to_string())?;
+        fs::rename(&temporary, path)
+    }
+    fn remove(&self, id: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(id)?) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+    fn remove_expired(&self, expired: &dyn Fn(&Record) -> bool) -> io::Result<()> {
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            let is_session = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(is_valid_id)
+                && path
+                    .extension()
+                    .is_some_and(|extension| extension == "json");
+            if is_session && Self::read(&path)?.is_some_and(|record| expired(&record)) {
+                fs::remove_file(&path).or_else(|e| match e.kind() {
+                    io::ErrorKind::NotFound => Ok(()),
+                    _ => Err(e),
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Session of the request being handled, as the handler changed it
+#[derive(Debug, Clone)]
+struct Session {
+    /// Id the session is stored under, `None` until it is first saved
+    id: Option<String>,
+    record: Record,
+    rotate: bool,
+    destroyed: bool,
+}
+
+/// Handle on the session of a request, given to handlers by [`Request::session`]
+#[derive(Debug, Clone)]
+pub struct Handle(Arc<Mutex<Session>>);
+impl Handle {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Session> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.lock().record.data.get(name).cloned()
+    }
+    pub fn insert<N: Into<String>, V: Into<String>>(&self, name: N, value: V) {
+        let mut session = self.lock();
+        session.record.data.insert(name.into(), value.into());
+    }
+    #[allow(dead_code)]
+    pub fn remove(&self, name: &str) -> Option<String> {
+        self.lock().record.data.remove(name)
+    }
+    /// Every name and value held by the session
+    pub fn data(&self) -> BTreeMap<String, String> {
+        self.lock().record.data.clone()
+    }
+    /// Moves the session to a new id once the response is sent
+    ///
+    /// Call this whenever the privileges of the client change, like on login,
+    /// so an id learned before, or planted by someone else, grants nothing.
+    pub fn rotate(&self) {
+        self.lock().rotate = true;
+    }
+    /// Removes the session from the store and its cookie from the client
+    pub fn destroy(&self) {
+        let mut session = self.lock();
+        session.record.data.clear();
+        session.destroyed = true;
+    }
+}
+
+/// Layer giving every request a session, see the [module documentation](self)
+///
+/// The cookie is signed so forged ids are turned away before the store is
+/// consulted, and sent with HttpOnly and SameSite=Lax, and Secure if asked to.
+pub struct Sessions {
+    store: Box<dyn Store>,
+    key: Key,
+    cookie_name: String,
+    secure: bool,
+    timeouts: Timeouts,
+    swept: Mutex<Instant>,
+}
+impl Sessions {
+    pub fn new<S: Store + 'static>(store: S, key: Key, timeouts: Timeouts) -> Self {
+        Self {
+            store: Box::new(store),
+            key,
+            cookie_name: COOKIE_NAME.to_string(),
+            secure: false,
+            timeouts,
+            swept: Mutex::new(Instant::now()),
+        }
+    }
+    /// Reads the store from `--session-dir`, sessions staying in memory without it,
+    /// the cookie name from `--session-cookie` and whether it is only sent over
+    /// HTTPS from `--session-secure`
+    ///
+    /// Ids are signed with the key [`Key::from_args`] reads.
+    pub fn from_args() -> io::Result<Self> {
+        let key = Key::from_args()?;
+        let timeouts = Timeouts::from_args();
+        let mut sessions = match crate::args::value("--session-dir") {
+            Some(directory) => Self::new(FileStore::new(directory)?, key, timeouts),
+            None => Self::new(MemoryStore::default(), key, timeouts),
+        };
+        if let Some(cookie_name) = crate::args::value("--session-cookie") {
+            sessions.cookie_name = cookie_name;
+        }
+        sessions.secure = crate::args::flag("--session-secure");
+        Ok(sessions)
+    }
+    /// Drops expired sessions from the store, at most once every [`SWEEP_INTERVAL`]
+    fn sweep(&self, now: SystemTime) -> io::Result<()> {
+        {
+            let mut swept = self.swept.lock().unwrap_or_else(|e| e.into_inner());
+            if swept.elapsed() < SWEEP_INTERVAL {
+                return Ok(());
+            }
+            *swept = Instant::now();
+        }
+        self.store
+            .remove_expired(&|record| self.timeouts.is_expired(record, now))
+    }
+    /// The unexpired session named by the cookie, with its id
+    fn load(&self, jar: &mut Jar, now: SystemTime) -> io::Result<Option<(String, Record)>> {
+        let id = match jar.signed(&self.key).get(&self.cookie_name) {
+            Some(id) if is_valid_id(&id) => id,
+            _ => return Ok(None),
+        };
+        match self.store.load(&id)? {
+            Some(record) if self.timeouts.is_expired(&record, now) => {
+                self.store.remove(&id)?;
+                Ok(None)
+            }
+            Some(record) => Ok(Some((id, record))),
+            None => Ok(None),
+        }
+    }
+    fn cookie(&self, id: &str) -> Cookie {
+        Cookie::new(self.cookie_name.as_str(), id)
+            .path("/")
+            .max_age(self.timeouts.absolute)
+            .secure(self.secure)
+            .http_only(true)
+            .same_site(SameSite::Lax)
+    }
+    /// Saves `session` as the handler left it, changing the cookie in `jar` to match
+    fn save(&self, session: Session, jar: &mut Jar, now: SystemTime) -> io::Result<()> {
+        let Session {
+            id,
+            mut record,
+            rotate,
+            destroyed,
+        } = session;
+        if let Some(id) = &id {
+            if destroyed || rotate || record.data.is_empty() {
+                self.store.remove(id)?;
+            }
+        }
+        if destroyed || record.data.is_empty() {
+            if id.is_some() || jar.get(&self.cookie_name).is_some() {
+                jar.remove(Cookie::new(self.cookie_name.as_str(), "").path("/"));
+            }
+            return Ok(());
+        }
+        record.accessed = now;
+        let id = match id {
+            Some(id) if !rotate => id,
+            _ => {
+                let id = generate_id()?;
+                jar.signed(&self.key)
+                    .add(self.cookie(&id))
+                    .map_err(io::Error::other)?;
+                id
+            }
+        };
+        self.store.save(&id, &record)
+    }
+}
+impl Layer for Sessions {
+    fn handle(&self, mut request: Request, next: Next<'_>) -> io::Result<Response> {
+        let now = SystemTime::now();
+        self.sweep(now)?;
+        let mut jar = request.cookies();
+        let (id, record) = match self.load(&mut jar, now)? {
+            Some((id, record)) => (Some(id), record),
+            None => (None, Record::new(now)),
+        };
+        let handle = Handle(Arc::new(Mutex::new(Session {
+            id,
+            record,
+            rotate: false,
+            destroyed: false,
+        })));
+        request.session = Some(handle.clone());
+        let mut response = next.run(request)?;
+        let session = handle.lock().clone();
+        self.save(session, &mut jar, now)?;
+        jar.apply(&mut response);
+        Ok(response)
+    }
+}
+
+/// Shows the data of the session as JSON
+pub fn show(request: Request) -> io::Result<Response> {
+    let data = request
+        .session()
+        .map(|session| session.data())
+        .unwrap_or_default();
+    let data = data
+        .iter()
+        .map(|(name, value)| (name.as_str(), json::Value::from(value.as_str())));
+    Ok(Response::json(Status::Ok, json::Value::object(data)))
+}
+
+/// Stores the fields of an urlencoded form in the session, rotating it when `user` changes
+pub fn update(request: Request) -> io::Result<Response> {
+    let session = match request.session() {
+        Some(session) => session,
+        None => return Ok(Response::text(Status::InternalServerError, "no sessions")),
+    };
+    let fields = match form::urlencoded(request.body.as_deref().unwrap_or_default()) {
+        Ok(form) => form.fields,
+        Err(e) => return Ok(Response::text(Status::BadRequest, e.to_string())),
+    };
+    for (name, value) in fields {
+        if name == "user" && session.get("user").as_ref() != Some(&value) {
+            session.rotate();
+        }
+        session.insert(name, value);
+    }
+    show(request)
+}
+
+/// Ends the session
+pub fn end(request: Request) -> io::Result<Response> {
+    if let Some(session) = request.session() {
+        session.destroy();
+    }
+    Ok(Response::empty(Status::NoContent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_id, FileStore, MemoryStore, Record, Sessions, Store, Timeouts};
+    use crate::http::{
+        connection::Handler,
+        cookie::Key,
+        middleware::{Layer, Next},
+        response::Status,
+        Request, Response,
+    };
+    use std::{
+        sync::Arc,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    fn record(created: u64, accessed: u64) -> Record {
+        let mut record = Record::new(UNIX_EPOCH + Duration::from_secs(created));
+        record.accessed = UNIX_EPOCH + Duration::from_secs(accessed);
+        record
+            .data
+            .insert("user".to_string(), "alice \"a\"".to_string());
+        record
+    }
+
+    fn exercise(store: &dyn Store) {
+        let (live, stale) = (generate_id().unwrap(), generate_id().unwrap());
+        assert_ne!(live, stale);
+        assert_eq!(store.load(&live).unwrap(), None);
+        store.save(&live, &record(100, 150)).unwrap();
+        store.save(&stale, &record(100, 100)).unwrap();
+        assert_eq!(store.load(&live).unwrap(), Some(record(100, 150)));
+        let now = UNIX_EPOCH + Duration::from_secs(160);
+        let timeouts = Timeouts {
+            idle: Duration::from_secs(30),
+            absolute: Duration::from_secs(100),
+        };
+        store
+            .remove_expired(&|record| timeouts.is_expired(record, now))
+            .unwrap();
+        assert_eq!(store.load(&stale).unwrap(), None);
+        assert!(store.load(&live).unwrap().is_some());
+        store.remove(&live).unwrap();
+        store.remove(&live).unwrap();
+        assert_eq!(store.load(&live).unwrap(), None);
+    }
+
+    #[test]
+    fn timeouts_count_from_access_and_creation() {
+        let timeouts = Timeouts {
+            idle: Duration::from_secs(10),
+            absolute: Duration::from_secs(60),
+        };
+        let at = |seconds: u64| UNIX_EPOCH + Duration::from_secs(seconds);
+        assert!(!timeouts.is_expired(&record(0, 50), at(55)));
+        assert!(timeouts.is_expired(&record(0, 40), at(55)));
+        assert!(timeouts.is_expired(&record(0, 59), at(60)));
+        assert!(!timeouts.is_expired(&record(0, 0), SystemTime::UNIX_EPOCH));
+    }
+
+    #[test]
+    fn memory_store_keeps_and_expires_sessions() {
+        exercise(&MemoryStore::default());
+    }
+
+    #[test]
+    fn file_store_keeps_and_expires_sessions() {
+        let directory = std::env::temp_dir().join(format!("sessions-{}", std::process::id()));
+        let store = FileStore::new(&directory).unwrap();
+        exercise(&store);
+        assert!(store.load("../../etc/passwd").is_err());
+        let _ = std::fs::remove_dir_all(directory);
+    }
+
+    /// Sends `path` through the layers with the session cookie `sid`, returning
+    /// the user the handler saw and the Set-Cookie it was answered with
+    fn send(
+        layers: &[Arc<dyn Layer>],
+        path: &str,
+        sid: Option<&str>,
+    ) -> (Option<String>, Option<String>) {
+        let handler: Handler = Arc::new(|request: Request| {
+            let session = request.session().unwrap();
+            let user = session.get("user");
+            match request.start_line.target.path.as_str() {
+                "/login" => {
+                    session.insert("user", "alice");
+                    session.rotate();
+                }
+                "/logout" => session.destroy(),
+                _ => (),
+            }
+            Ok(Response::text(Status::Ok, user.unwrap_or_default()))
+        });
+        let cookie = sid.map(|sid| format!("Cookie: sid={}\r\n", sid));
+        let raw = format!(
+            "GET {} HTTP/1.1\r\nHost: a\r\n{}\r\n",
+            path,
+            cookie.unwrap_or_default()
+        );
+        let request = Request::try_construct(raw.as_bytes()).unwrap();
+        let response = Next::new(layers, &handler).run(request).unwrap();
+        let user = String::from_utf8(response.body.clone().unwrap_or_default()).unwrap();
+        let set_cookie = response.headers.get("Set-Cookie").map(str::to_string);
+        (Some(user).filter(|user| !user.is_empty()), set_cookie)
+    }
+
+    /// Value of the cookie set by `set_cookie`
+    fn value(set_cookie: &str) -> String {
+        let (pair, _) = set_cookie.split_once(';').unwrap();
+        pair.trim_start_matches("sid=").to_string()
+    }
+
+    #[test]
+    fn sessions_are_created_rotated_and_destroyed() {
+        let timeouts = Timeouts {
+            idle: Duration::from_secs(60),
+            absolute: Duration::from_secs(600),
+        };
+        let sessions = Sessions::new(MemoryStore::default(), Key::derive(&[1u8; 32]), timeouts);
+        let layers: Vec<Arc<dyn Layer>> = vec![Arc::new(sessions)];
+        // nothing is stored, and no cookie set, until the session holds data
+        assert_eq!(send(&layers, "/", None), (None, None));
+        let (_, set_cookie) = send(&layers, "/login", None);
+        let set_cookie = set_cookie.unwrap();
+        assert!(set_cookie.ends_with("; Path=/; Max-Age=600; HttpOnly; SameSite=Lax"));
+        let first = value(&set_cookie);
+        assert_eq!(
+            send(&layers, "/", Some(&first)),
+            (Some("alice".to_string()), None)
+        );
+        // logging in again moves the session to a new id, the old one is forgotten
+        let (_, set_cookie) = send(&layers, "/login", Some(&first));
+        let second = value(&set_cookie.unwrap());
+        assert_ne!(first, second);
+        assert_eq!(
+            send(&layers, "/", Some(&second)).0.as_deref(),
+            Some("alice")
+        );
+        assert_eq!(send(&layers, "/", Some(&first)).0, None);
+        // forged ids are not looked up
+        let flipped = if second.starts_with('0') { "1" } else { "0" };
+        let forged = format!("{}{}", flipped, &second[1..]);
+        assert_eq!(send(&layers, "/", Some(&forged)).0, None);
+        let (_, removal) = send(&layers, "/logout", Some(&second));
+        assert!(removal.unwrap().starts_with("sid=; Path=/; Max-Age=0"));
+        assert_eq!(send(&layers, "/", Some(&second)).0, None);
+    }
+}
@@ -0,0 +1,175 @@
+//! Server-sent events, see the HTML Living Standard section 9.2
+use {
+    crate::http::{
+        body::{Producer, Sink},
+        header::{content_type, Kind},
+        request::id,
+        HeaderMap, Request, Response,
+    },
+    std::{
+        fmt, io,
+        sync::mpsc::{self, RecvTimeoutError, Sender},
+        time::Duration,
+    },
+};
+
+/// Time without events after which a comment is sent to keep the connection open
+pub const HEARTBEAT: Duration = Duration::from_secs(15);
+
+/// A single event, written as `field: value` lines ended by a blank line
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+impl Event {
+    /// Event of the default `message` type carrying `data`, which may span lines
+    pub fn new<D: Into<String>>(data: D) -> Self {
+        Self {
+            data: data.into(),
+            ..Self::default()
+        }
+    }
+    /// Sets the id clients send back in Last-Event-ID when reconnecting
+    pub fn id<I: Into<String>>(mut self, id: I) -> Self {
+        self.id = Some(single_line(id.into()));
+        self
+    }
+    /// Sets the event type
+    pub fn event<E: Into<String>>(mut self, event: E) -> Self {
+        self.event = Some(single_line(event.into()));
+        self
+    }
+    /// Sets how long clients wait before reconnecting
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+}
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(id) = &self.id {
+            fmt::write(f, format_args!("id: {}\n", id))?;
+        }
+        if let Some(event) = &self.event {
+            fmt::write(f, format_args!("event: {}\n", event))?;
+        }
+        if let Some(retry) = &self.retry {
+            fmt::write(f, format_args!("retry: {}\n", retry.as_millis()))?;
+        }
+        for line in self
+            .data
+            .split("\r\n")
+            .flat_map(|line| line.split(['\r', '\n']))
+        {
+            fmt::write(f, format_args!("data: {}\n", line))?;
+        }
+        fmt::write(f, format_args!("\n"))
+    }
+}
+
+/// Drops the characters that would end a field early
+fn single_line(value: String) -> String {
+    value.replace(['\r', '\n', '\0'], "")
+}
+
+/// Id of the last event a reconnecting client received
+pub fn last_event_id(request: &Request) -> Option<String> {
+    request
+        .headers()
+        .get("Last-Event-ID")
+        .filter(|id| !id.is_empty())
+        .map(String::from)
+}
+
+/// Streams the events `source` sends until either side stops
+///
+/// `source` runs on a thread of its own with the Last-Event-ID of the request,
+/// and learns that the client went away when sending an event fails. A comment
+/// is sent whenever `heartbeat` passes without an event.
+pub fn response<F>(request: &Request, heartbeat: Duration, source: F) -> Response
+where
+    F: FnOnce(Option<String>, Sender<Event>) + Send + 'static,
+{
+    let last_event_id = last_event_id(request);
+    let mut headers = HeaderMap::new();
+    headers.push(Kind::ContentType(content_type::Kind::EventStream));
+    headers.insert("Cache-Control", "no-cache");
+    let producer: Producer = Box::new(move |sink: &mut dyn Sink| {
+        let (sender, receiver) = mpsc::channel();
+        let id = id::current();
+        std::thread::spawn(move || {
+            let _scope = id.as_deref().map(id::enter);
+            source(last_event_id, sender)
+        });
+        // a first comment gets the head through any buffering proxy
+        sink.send(b": stream open\n\n")?;
+        loop {
+            match receiver.recv_timeout(heartbeat) {
+                Ok(event) => sink.send(event.to_string().as_bytes())?,
+                Err(RecvTimeoutError::Timeout) => sink.send(b": heartbeat\n\n")?,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    });
+    Response::builder().headers(headers).stream(producer)
+}
+
+/// Sends a `tick` event every second, counting on from the Last-Event-ID of the request
+pub fn ticks(request: Request) -> io::Result<Response> {
+    Ok(response(&request, HEARTBEAT, |last_event_id, sender| {
+        let start = last_event_id
+            .and_then(|id| id.parse::<u64>().ok())
+            .map_or(0, |id| id + 1);
+        for count in start.. {
+            let mut event = Event::new(count.to_string())
+                .id(count.to_string())
+                .event("tick");
+            if count == start {
+                event = event.retry(Duration::from_secs(3));
+            }
+            if sender.send(event).is_err() {
+                log_from_mod!("event stream closed", count);
+                return;
+            }
+            std::thread::sleep(Duration::from_secs(1));
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{last_event_id, Event};
+    use crate::http::Request;
+    use std::time::Duration;
+
+    #[test]
+    fn events_are_written_field_by_field() {
+        let event = Event::new("first\nsecond")
+            .id("7")
+            .event("tick")
+            .retry(Duration::from_secs(3));
+        assert_eq!(
+            event.to_string(),
+            "id: 7\nevent: tick\nretry: 3000\ndata: first\ndata: second\n\n"
+        );
+        assert_eq!(Event::new("").to_string(), "data: \n\n");
+    }
+
+    #[test]
+    fn ids_can_not_break_lines() {
+        assert_eq!(
+            Event::new("a").id("1\n2").to_string(),
+            "id: 12\ndata: a\n\n"
+        );
+    }
+
+    #[test]
+    fn last_event_id_is_read_from_the_request() {
+        let raw = "GET /events HTTP/1.1\r\nHost: a\r\nLast-Event-ID: 41\r\n\r\n";
+        let request = Request::try_construct(raw.as_bytes()).unwrap();
+        assert_eq!(last_event_id(&request), Some(String::from("41")));
+    }
+}
@@ -0,0 +1,34 @@
+use std::fmt;
+
+/// Errors that end a WebSocket connection, each maps to a close code
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Error {
+    /// Errors related to frames breaking RFC 6455
+    Protocol(String),
+    /// Errors related to text messages that are not valid UTF-8
+    InvalidData(String),
+    /// Errors related to frames or messages above the size limit
+    TooBig(u64),
+}
+impl Error {
+    /// Close code sent to the client, see RFC 6455 section 7.4.1
+    pub fn close_code(&self) -> u16 {
+        match self {
+            Self::Protocol(_) => 1002,
+            Self::InvalidData(_) => 1007,
+            Self::TooBig(_) => 1009,
+        }
+    }
+}
+impl std::error::Error for Error {}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = module_path!();
+        let body = match self {
+            Self::Protocol(message) => format!("protocol error {}", message),
+            Self::InvalidData(message) => format!("invalid data {}", message),
+            Self::TooBig(size) => format!("message of {} bytes too big", size),
+        };
+        fmt::write(f, format_args!("[{}] {}", source, body))
+    }
+}
@@ -0,0 +1,186 @@
+use crate::http::websocket::error::Error;
+
+/// Largest payload accepted in a single frame or reassembled message
+pub const MAX_PAYLOAD_SIZE: usize = 16 * 1024 * 1024;
+
+/// Frame opcodes, see RFC 6455 section 5.2
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+impl Opcode {
+    /// Returns true for close, ping and pong, which may not be fragmented
+    pub fn is_control(&self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+impl TryFrom<u8> for Opcode {
+    type Error = Error;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        use Opcode::*;
+        match value {
+            0x0 => Ok(Continuation),
+            0x1 => Ok(Text),
+            0x2 => Ok(Binary),
+            0x8 => Ok(Close),
+            0x9 => Ok(Ping),
+            0xa => Ok(Pong),
+            other => Err(Error::Protocol(format!("reserved opcode {:#x}", other))),
+        }
+    }
+}
+impl From<Opcode> for u8 {
+    fn from(value: Opcode) -> Self {
+        use Opcode::*;
+        match value {
+            Continuation => 0x0,
+            Text => 0x1,
+            Binary => 0x2,
+            Close => 0x8,
+            Ping => 0x9,
+            Pong => 0xa,
+        }
+    }
+}
+
+/// A single WebSocket frame, payloads are kept unmasked
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
+}
+impl Frame {
+    pub fn new(opcode: Opcode, payload: Vec<u8>) -> Self {
+        Self {
+            fin: true,
+            opcode,
+            payload,
+        }
+    }
+    /// Parses a frame sent by a client, which must be masked
+    ///
+    /// Returns `Ok(None)` until `bytes` holds the whole frame, and the frame
+    /// along with the number of bytes it took up otherwise.
+    pub fn parse(bytes: &[u8]) -> Result<Option<(Self, usize)>, Error> {
+        let (first, second) = match bytes {
+            [first, second, ..] => (*first, *second),
+            _ => return Ok(None),
+        };
+        if first & 0x70 != 0 {
+            return Err(Error::Protocol(String::from("reserved bits set")));
+        }
+        let fin = first & 0x80 != 0;
+        let opcode = Opcode::try_from(first & 0x0f)?;
+        if second & 0x80 == 0 {
+            return Err(Error::Protocol(String::from("unmasked client frame")));
+        }
+        let (length, mut offset) = match second & 0x7f {
+            126 => match bytes.get(2..4) {
+                Some(length) => (u16::from_be_bytes([length[0], length[1]]) as u64, 4),
+                None => return Ok(None),
+            },
+            127 => match bytes.get(2..10) {
+                Some(length) => {
+                    let mut buffer = [0u8; 8];
+                    buffer.copy_from_slice(length);
+                    (u64::from_be_bytes(buffer), 10)
+                }
+                None => return Ok(None),
+            },
+            length => (length as u64, 2),
+        };
+        if opcode.is_control() && (!fin || length > 125) {
+            return Err(Error::Protocol(String::from(
+                "fragmented or oversized control frame",
+            )));
+        }
+        if length > MAX_PAYLOAD_SIZE as u64 {
+            return Err(Error::TooBig(length));
+        }
+        let mask = match bytes.get(offset..offset + 4) {
+            Some(mask) => [mask[0], mask[1], mask[2], mask[3]],
+            None => return Ok(None),
+        };
+        offset += 4;
+        let end = offset + length as usize;
+        let payload = match bytes.get(offset..end) {
+            Some(masked) => masked
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4])
+                .collect(),
+            None => return Ok(None),
+        };
+        Ok(Some((
+            Self {
+                fin,
+                opcode,
+                payload,
+            },
+            end,
+        )))
+    }
+    /// Encodes the frame unmasked, as servers send them
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(self.payload.len() + 10);
+        let fin = if self.fin { 0x80 } else { 0x00 };
+        encoded.push(fin | u8::from(self.opcode));
+        match self.payload.len() {
+            length @ 0..=125 => encoded.push(length as u8),
+            length @ 126..=0xffff => {
+                encoded.push(126);
+                encoded.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                encoded.push(127);
+                encoded.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+        encoded.extend_from_slice(&self.payload);
+        encoded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Frame, Opcode};
+
+    #[test]
+    fn parses_rfc_6455_masked_hello() {
+        let bytes = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        let (frame, consumed) = Frame::parse(&bytes).unwrap().unwrap();
+        assert_eq!(consumed, bytes.len());
+        assert_eq!(frame, Frame::new(Opcode::Text, b"Hello".to_vec()));
+        assert!(Frame::parse(&bytes[..6]).unwrap().is_none());
+    }
+
+    #[test]
+    fn encodes_unmasked_with_extended_lengths() {
+        let hello = Frame::new(Opcode::Text, b"Hello".to_vec()).encode();
+        assert_eq!(hello, vec![0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+        let medium = Frame::new(Opcode::Binary, vec![0; 256]).encode();
+        assert_eq!(medium[..4], [0x82, 0x7e, 0x01, 0x00]);
+        let large = Frame::new(Opcode::Binary, vec![0; 65536]).encode();
+        assert_eq!(large[..10], [0x82, 0x7f, 0, 0, 0, 0, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn rejects_invalid_frames() {
+        // unmasked
+        assert!(Frame::parse(&[0x81, 0x00]).is_err());
+        // reserved bit
+        assert!(Frame::parse(&[0xc1, 0x80, 0, 0, 0, 0]).is_err());
+        // reserved opcode
+        assert!(Frame::parse(&[0x83, 0x80, 0, 0, 0, 0]).is_err());
+        // fragmented ping
+        assert!(Frame::parse(&[0x09, 0x80, 0, 0, 0, 0]).is_err());
+    }
+}
@@ -0,0 +1,416 @@
+/// Module to handle errors that close WebSocket connections
+pub(crate) mod error;
+
+/// Module to handle frames
+pub(crate) mod frame;
+
+/// Module to compute the SHA-1 digest used by the handshake
+mod sha1;
+
+use {
+    crate::http::{
+        base64, connection::is_timeout, request::Method, response, HeaderMap, Request, Response,
+    },
+    error::Error,
+    frame::{Frame, Opcode, MAX_PAYLOAD_SIZE},
+    std::io::{self, Read, Write},
+};
+
+/// Appended to Sec-WebSocket-Key before hashing, see RFC 6455 section 1.3
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The only protocol version defined, see RFC 6455 section 4.1
+const VERSION: &str = "13";
+
+/// Size of each read from the connection
+const READ_CHUNK_SIZE: usize = 4 * 1024;
+
+/// Derives Sec-WebSocket-Accept from the client's Sec-WebSocket-Key
+pub fn accept_key(key: &str) -> String {
+    base64::encode(&sha1::digest(format!("{}{}", key, GUID).as_bytes()))
+}
+
+/// Answers an opening handshake, see RFC 6455 section 4.2
+///
+/// Returns 101 Switching Protocols if the connection may be upgraded, 426
+/// Upgrade Required for plain requests or other protocol versions, and 400
+/// for malformed handshakes.
+pub fn handshake(request: &Request) -> Response {
+    let headers = request.headers();
+    let mut response_headers = HeaderMap::new();
+    let status = if !headers.has_token("Upgrade", "websocket")
+        || !headers.get("Sec-WebSocket-Version").eq(&Some(VERSION))
+    {
+        response_headers.insert("Upgrade", "websocket");
+        response_headers.insert("Connection", "Upgrade");
+        response_headers.insert("Sec-WebSocket-Version", VERSION);
+        response::Status::UpgradeRequired
+    } else {
+        let mut keys = headers.get_all("Sec-WebSocket-Key");
+        let key = match (keys.next(), keys.next()) {
+            (Some(key), None) => Some(key),
+            _ => None,
+        };
+        let valid = request.start_line.method.eq(&Method::Get)
+            && request.start_line.version.persistent_by_default()
+            && headers.has_token("Connection", "Upgrade")
+            && key
+                .and_then(base64::decode)
+                .is_some_and(|nonce| nonce.len() == 16);
+        match (valid, key) {
+            (true, Some(key)) => {
+                response_headers.insert("Upgrade", "websocket");
+                response_headers.insert("Connection", "Upgrade");
+                response_headers.insert("Sec-WebSocket-Accept", accept_key(key));
+                response::Status::SwitchingProtocols
+            }
+            _ => response::Status::BadRequest,
+        }
+    };
+    Response::builder()
+        .status(status)
+        .headers(response_headers)
+        .empty()
+}
+
+/// A complete data message
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// Any stream a WebSocket can run over
+pub trait Duplex: Read + Write {}
+impl<T: Read + Write> Duplex for T {}
+
+/// Server side of an upgraded WebSocket connection
+///
+/// Pings are answered and fragmented messages reassembled while receiving.
+/// Clients silent for the read timeout of the stream are pinged, and closed
+/// on with 1001 if they stay silent for another one. A normal closure is
+/// sent when the socket is dropped without being closed.
+pub struct WebSocket<'a> {
+    stream: Box<dyn Duplex + 'a>,
+    /// Bytes read but not yet parsed into frames
+    buffer: Vec<u8>,
+    /// Opcode and payload of a message whose final fragment has not arrived
+    fragments: Option<(Opcode, Vec<u8>)>,
+    close_sent: bool,
+    close_received: bool,
+    /// Ping sent after a silent read timeout, any bytes from the client answer it
+    ping_sent: bool,
+}
+impl<'a> WebSocket<'a> {
+    /// Takes over `stream`, with `buffer` holding any bytes already read off it
+    pub fn new(stream: Box<dyn Duplex + 'a>, buffer: Vec<u8>) -> Self {
+        Self {
+            stream,
+            buffer,
+            fragments: None,
+            close_sent: false,
+            close_received: false,
+            ping_sent: false,
+        }
+    }
+    /// Receives the next message, returning `Ok(None)` once the connection is closed
+    ///
+    /// Protocol violations close the connection with the matching close code
+    /// and are returned as `InvalidData` errors.
+    pub fn recv(&mut self) -> io::Result<Option<Message>> {
+        while !self.close_received {
+            let frame = match self.read_frame() {
+                Ok(Some(Ok(frame))) => frame,
+                Ok(Some(Err(e))) => return self.fail(e),
+                Ok(None) => {
+                    log_from_mod!("websocket closed without a close frame");
+                    return Ok(None);
+                }
+                Err(e) if is_timeout(&e) && !self.ping_sent && !self.close_sent => {
+                    self.ping_sent = true;
+                    self.write_frame(&Frame::new(Opcode::Ping, vec![]))?;
+                    continue;
+                }
+                Err(e) if is_timeout(&e) => {
+                    log_from_mod!("closing silent websocket");
+                    self.close_received = true;
+                    self.close(1001, "idle")?;
+                    return Ok(None);
+                }
+                Err(e) => return Err(e),
+            };
+            match self.on_frame(frame) {
+                Ok(Some(message)) => return Ok(Some(message)),
+                Ok(None) => (),
+                Err(e) => return self.fail(e),
+            }
+        }
+        Ok(None)
+    }
+    /// Sends a whole message in a single frame
+    pub fn send(&mut self, message: &Message) -> io::Result<()> {
+        if self.close_sent {
+            let message = "websocket already closed";
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, message));
+        }
+        let frame = match message {
+            Message::Text(text) => Frame::new(Opcode::Text, text.as_bytes().to_vec()),
+            Message::Binary(bytes) => Frame::new(Opcode::Binary, bytes.clone()),
+        };
+        self.write_frame(&frame)
+    }
+    /// Starts the closing handshake, messages can still be received until the client answers
+    pub fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        if self.close_sent {
+            return Ok(());
+        }
+        self.close_sent = true;
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend(reason.bytes().take(123));
+        self.write_frame(&Frame::new(Opcode::Close, payload))
+    }
+    fn fail(&mut self, error: Error) -> io::Result<Option<Message>> {
+        elog_from_mod!("closing websocket", error);
+        self.close(error.close_code(), "")?;
+        self.close_received = true;
+        Err(io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+    /// Reads the next frame, returning `Ok(None)` once the client closed the connection
+    fn read_frame(&mut self) -> io::Result<Option<Result<Frame, Error>>> {
+        loop {
+            match Frame::parse(&self.buffer) {
+                Ok(Some((frame, consumed))) => {
+                    self.buffer.drain(..consumed);
+                    return Ok(Some(Ok(frame)));
+                }
+                Ok(None) => (),
+                Err(e) => return Ok(Some(Err(e))),
+            }
+            let mut chunk = [0u8; READ_CHUNK_SIZE];
+            match self.stream.read(&mut chunk)? {
+                0 => return Ok(None),
+                bytes_read => {
+                    self.ping_sent = false;
+                    self.buffer.extend_from_slice(&chunk[..bytes_read]);
+                }
+            }
+        }
+    }
+    fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        self.stream.write_all(&frame.encode())?;
+        self.stream.flush()
+    }
+    fn on_frame(&mut self, frame: Frame) -> Result<Option<Message>, Error> {
+        let io_error = |e: io::Error| Error::Protocol(e.to_string());
+        match frame.opcode {
+            Opcode::Ping => {
+                if !self.close_sent {
+                    self.write_frame(&Frame::new(Opcode::Pong, frame.payload))
+                        .map_err(io_error)?;
+                }
+                Ok(None)
+            }
+            Opcode::Pong => Ok(None),
+            Opcode::Close => {
+                let code = close_code(&frame.payload)?;
+                self.close_received = true;
+                self.close(code.unwrap_or(1000), "").map_err(io_error)?;
+                Ok(None)
+            }
+            Opcode::Text | Opcode::Binary if self.fragments.is_some() => Err(Error::Protocol(
+                String::from("new message before the last one finished"),
+            )),
+            Opcode::Text | Opcode::Binary if frame.fin => {
+                message(frame.opcode, frame.payload).map(Some)
+            }
+            Opcode::Text | Opcode::Binary => {
+                self.fragments = Some((frame.opcode, frame.payload));
+                Ok(None)
+            }
+            Opcode::Continuation => {
+                let (opcode, mut payload) = self.fragments.take().ok_or_else(|| {
+                    Error::Protocol(String::from("continuation without a message"))
+                })?;
+                payload.extend_from_slice(&frame.payload);
+                if payload.len() > MAX_PAYLOAD_SIZE {
+                    return Err(Error::TooBig(payload.len() as u64));
+                }
+                if frame.fin {
+                    message(opcode, payload).map(Some)
+                } else {
+                    self.fragments = Some((opcode, payload));
+                    Ok(None)
+                }
+            }
+        }
+    }
+}
+impl Drop for WebSocket<'_> {
+    fn drop(&mut self) {
+        let _ = self.close(1000, "");
+    }
+}
+
+/// Builds a message out of a reassembled payload, text must be valid UTF-8
+fn message(opcode: Opcode, payload: Vec<u8>) -> Result<Message, Error> {
+    match opcode {
+        Opcode::Text => String::from_utf8(payload)
+            .map(Message::Text)
+            .map_err(|e| Error::InvalidData(e.to_string())),
+        _ => Ok(Message::Binary(payload)),
+    }
+}
+
+/// Validates the body of a close frame, returning its status code if it has one
+fn close_code(payload: &[u8]) -> Result<Option<u16>, Error> {
+    match payload {
+        [] => Ok(None),
+        [_] => Err(Error::Protocol(String::from("truncated close code"))),
+        [high, low, reason @ ..] => {
+            let code = u16::from_be_bytes([*high, *low]);
+            if !matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999) {
+                return Err(Error::Protocol(format!("invalid close code {}", code)));
+            }
+            std::str::from_utf8(reason).map_err(|e| Error::InvalidData(e.to_string()))?;
+            Ok(Some(code))
+        }
+    }
+}
+
+/// Sends every message received back to the client
+pub fn echo(_request: Request, mut socket: WebSocket<'_>) -> io::Result<()> {
+    while let Some(message) = socket.recv()? {
+        socket.send(&message)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{accept_key, close_code, handshake, WebSocket};
+    use crate::http::Request;
+    use std::io::{self, Read, Write};
+
+    /// Stream replaying scripted client bytes and recording what the server writes
+    struct Script {
+        incoming: io::Cursor<Vec<u8>>,
+        outgoing: Vec<u8>,
+    }
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.incoming.read(buf)
+        }
+    }
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.outgoing.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Masks `payload` with an all zero key, which leaves it unchanged
+    fn client_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![first, 0x80 | payload.len() as u8, 0, 0, 0, 0];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn accept_key_matches_rfc_6455_example() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn handshake_status_depends_on_the_request() {
+        let upgrade = "GET /ws/echo HTTP/1.1\r\nHost: a\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+        let status = |raw: &str| {
+            let request = Request::try_construct(raw.as_bytes()).unwrap();
+            handshake(&request).start_line.status as u16
+        };
+        assert_eq!(status(upgrade), 101);
+        assert_eq!(status("GET /ws/echo HTTP/1.1\r\nHost: a\r\n\r\n"), 426);
+        assert_eq!(status(&upgrade.replace("Version: 13", "Version: 8")), 426);
+        assert_eq!(
+            status(&upgrade.replace("dGhlIHNhbXBsZSBub25jZQ==", "abc")),
+            400
+        );
+        assert_eq!(status(&upgrade.replace("GET", "POST")), 400);
+    }
+
+    #[test]
+    fn fragments_are_reassembled_around_pings() {
+        let mut incoming = client_frame(0x01, b"Hel");
+        incoming.extend(client_frame(0x89, b"hi"));
+        incoming.extend(client_frame(0x80, b"lo"));
+        incoming.extend(client_frame(0x88, &1000u16.to_be_bytes()));
+        let mut script = Script {
+            incoming: io::Cursor::new(incoming),
+            outgoing: vec![],
+        };
+        {
+            let mut socket = WebSocket::new(Box::new(&mut script), vec![]);
+            let message = socket.recv().unwrap().unwrap();
+            assert_eq!(message, super::Message::Text(String::from("Hello")));
+            assert!(socket.recv().unwrap().is_none());
+        }
+        // pong, then the close echoed back
+        assert_eq!(
+            script.outgoing,
+            vec![0x8a, 0x02, b'h', b'i', 0x88, 0x02, 0x03, 0xe8]
+        );
+    }
+
+    #[test]
+    fn silent_clients_are_pinged_then_closed() {
+        /// Stream whose reads all time out
+        struct Silent(Vec<u8>);
+        impl Read for Silent {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::from(io::ErrorKind::WouldBlock))
+            }
+        }
+        impl Write for Silent {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut silent = Silent(vec![]);
+        {
+            let mut socket = WebSocket::new(Box::new(&mut silent), vec![]);
+            assert!(socket.recv().unwrap().is_none());
+        }
+        // empty ping, then a going away close with its reason
+        let mut expected = vec![0x89, 0x00, 0x88, 0x06, 0x03, 0xe9];
+        expected.extend_from_slice(b"idle");
+        assert_eq!(silent.0, expected);
+    }
+
+    #[test]
+    fn invalid_utf8_closes_with_1007() {
+        let mut script = Script {
+            incoming: io::Cursor::new(client_frame(0x81, &[0xff])),
+            outgoing: vec![],
+        };
+        {
+            let mut socket = WebSocket::new(Box::new(&mut script), vec![]);
+            assert!(socket.recv().is_err());
+        }
+        assert_eq!(script.outgoing, vec![0x88, 0x02, 0x03, 0xef]);
+    }
+
+    #[test]
+    fn close_codes_are_validated() {
+        assert_eq!(close_code(&[]).unwrap(), None);
+        assert!(close_code(&[0x03]).is_err());
+        assert!(close_code(&1005u16.to_be_bytes()).is_err());
+        assert_eq!(close_code(&4000u16.to_be_bytes()).unwrap(), Some(4000));
+    }
+}
@@ -5,6 +5,8 @@ use std::net;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream, ToSocketAddrs};
 #[allow(unused_imports)]
 use std::sync::Arc;
+
+use http::connection::Timeouts;
 #[macro_export]
 macro_rules! log_from_mod_debug {
     ($msg:literal, $val:expr) => {
@@ -38,7 +40,14 @@ macro_rules! log_from_mod {
         let stdout = std::io::stdout();
         let mut handle = stdout.lock();
 
-        let s = format!("[{} {}] {}: {}\n", line!(), module_path!(), $msg, $val);
+        let s = format!(
+            "[{} {}] {}{}: {}\n",
+            line!(),
+            module_path!(),
+            $crate::http::request::id::tag(),
+            $msg,
+            $val
+        );
         let _ = std::io::Write::write_all(&mut handle, s.as_bytes());
     };
     ( $mgs:literal, $( $val:expr ),* ) => {
@@ -50,22 +59,48 @@ macro_rules! log_from_mod {
         let stdout = std::io::stdout();
         let mut handle = stdout.lock();
 
-        let s = format!("[{} {}] {}: {}\n", line!(), module_path!(), $msg, $val)
+        let s = format!(
+            "[{} {}] {}{}: {}\n",
+            line!(),
+            module_path!(),
+            $crate::http::request::id::tag(),
+            $msg,
+            $val
+        )
         let _ = std::io::Write::write_all(&mut handle, s.as_bytes());
     };
     ($msg:literal) => {
-        println!("[{} {}] {}", line!(), module_path!(), $msg)
+        println!(
+            "[{} {}] {}{}",
+            line!(),
+            module_path!(),
+            $crate::http::request::id::tag(),
+            $msg
+        )
     };
 }
 #[macro_export]
 macro_rules! elog_from_mod {
     ($msg:literal, $err:expr) => {
-        eprintln!("[{}] {}: {}", module_path!(), $msg, $err)
+        eprintln!(
+            "[{}] {}{}: {}",
+            module_path!(),
+            $crate::http::request::id::tag(),
+            $msg,
+            $err
+        )
     };
     ($msg:literal) => {
-        eprintln!("[{}] {}", module_path!(), $msg)
+        eprintln!(
+            "[{}] {}{}",
+            module_path!(),
+            $crate::http::request::id::tag(),
+            $msg
+        )
     };
 }
+/// Module to read command line flags
+mod args;
 mod http;
 /// Module to store information regarding IPs
 #[macro_use]
@@ -186,37 +221,155 @@ mod socket {
     }
 }
 
+/// Module to run connections on a fixed set of workers
+mod pool;
+/// Module to stop the server gracefully
+mod shutdown;
 mod tcp;
+/// Module to terminate TLS
+mod tls;
 
-const GET_MAX_SIZE: usize = 1024;
+/// Seconds a client turned away by a saturated pool is asked to wait
+const RETRY_AFTER: u64 = 1;
 
-#[allow(unused_imports)]
-use std::io::Read;
+/// A connection along with its place in the per address count
+type Admitted = (TcpStream, tcp::Slot);
 
-fn handle_stream(tcp_stream: std::io::Result<TcpStream>) -> std::io::Result<()> {
-    match tcp_stream {
-        Ok(mut stream) => {
-            log_from_mod!("new incoming connection");
-            let mut stream_buffer = [0u8; GET_MAX_SIZE];
+fn handle_stream(
+    stream: TcpStream,
+    router: Arc<http::router::Router>,
+    timeouts: Timeouts,
+) -> std::io::Result<()> {
+    log_from_mod!("new incoming connection");
+    let _guard = shutdown::track(&stream)?;
+    http::connection::serve(stream, router, timeouts)
+}
 
-            let bytes_read = stream.read(&mut stream_buffer)?;
+/// Counts `stream` against its client address, `None` if the address is at its cap
+fn admit(stream: &TcpStream, per_ip: &Arc<tcp::PerIp>) -> Option<tcp::Slot> {
+    let ip = stream.peer_addr().ok()?.ip();
+    let slot = per_ip.acquire(ip);
+    if slot.is_none() {
+        elog_from_mod!("too many connections from", ip);
+    }
+    slot
+}
 
-            let req = http::Request::try_construct(&stream_buffer, bytes_read).unwrap();
+/// Listening socket called `name`, inherited from the process that started
+/// this one or freshly bound to `address`
+fn listen(name: &str, address: SocketAddr) -> std::io::Result<TcpListener> {
+    match shutdown::restart::inherited(name) {
+        Some(listener) => Ok(listener),
+        None => tcp::listener(address),
+    }
+}
 
-            let res = http::Response::try_from(req).unwrap();
+/// Waits for the connections of `pool` to finish, closing them all at the deadline
+fn drain<T: Send + 'static>(name: &str, pool: pool::Pool<T>) {
+    log_from_mod!("draining connections", name);
+    if !pool.join(shutdown::deadline()) {
+        shutdown::close_all();
+    }
+}
 
-            let _n_written = stream.write(res.to_string().as_bytes())?;
-            Ok(())
+/// Accepts HTTPS connections on a thread of its own, served by a pool of their own
+///
+/// Connections arriving while the pool is saturated are closed, answering
+/// them would take a handshake on the accepting thread.
+fn spawn_tls_listener(
+    config: &tls::Config,
+    tls_listener: TcpListener,
+    pool_config: pool::Config,
+    router: Arc<http::router::Router>,
+    timeouts: Timeouts,
+    per_ip: Arc<tcp::PerIp>,
+) -> std::io::Result<std::thread::JoinHandle<()>> {
+    let (server_config, resolver) = tls::server_config(config)?;
+    tls::reload_on_hangup(resolver)?;
+    let pool = pool::Pool::new("tls", pool_config, move |(stream, _slot): Admitted| {
+        let _guard = shutdown::track(&stream)?;
+        tls::serve(stream, server_config.clone(), router.clone(), timeouts)
+    })?;
+    let incoming = shutdown::incoming(tls_listener)?;
+    Ok(std::thread::spawn(move || {
+        for tcp_stream in incoming {
+            match tcp_stream {
+                Ok(stream) => {
+                    let Some(slot) = admit(&stream, &per_ip) else {
+                        continue;
+                    };
+                    if pool.execute((stream, slot)).is_err() {
+                        elog_from_mod!("workers saturated, closing tls connection");
+                    }
+                }
+                Err(e) => elog_from_mod!("failed to accept connection", e),
+            }
         }
-        Err(e) => Err(e),
+        drain("tls", pool);
+    }))
+}
+
+/// Answers a connection that will not be served with 503
+fn refuse(mut stream: TcpStream, reason: &str) {
+    elog_from_mod!("refusing connection", reason);
+    let refused = stream
+        .set_write_timeout(Some(std::time::Duration::from_secs(1)))
+        .and_then(|()| http::connection::refuse(&mut stream, RETRY_AFTER));
+    if let Err(e) = refused {
+        elog_from_mod!("failed to refuse connection", e);
     }
 }
 
+/// Serves until SIGINT or SIGTERM, or until SIGUSR2 handed the listeners to a new process
 fn main() -> std::io::Result<()> {
-    let tcp_listener = tcp::listener(socket::v4::addr::DEFAULT_GENERIC)?;
-    for tcp_stream in tcp_listener.incoming() {
-        std::thread::spawn(|| handle_stream(tcp_stream));
+    use std::os::fd::AsRawFd;
+    let router = Arc::new(http::router::routes()?);
+    let pool_config = pool::Config::from_args();
+    let timeouts = Timeouts::from_args();
+    let per_ip = tcp::PerIp::from_args();
+    let tcp_listener = listen("http", socket::v4::addr::DEFAULT_GENERIC)?;
+    let mut listeners = vec![("http", tcp_listener.as_raw_fd())];
+    let mut plain_router = router.clone();
+    let mut tls_thread = None;
+    if let Some(config) = tls::Config::from_args()? {
+        let address = SocketAddr::new(socket::v4::addr::DEFAULT_GENERIC.ip(), config.port);
+        let tls_listener = listen("tls", address)?;
+        log_from_mod!("listening for tls on", address);
+        listeners.push(("tls", tls_listener.as_raw_fd()));
+        tls_thread = Some(spawn_tls_listener(
+            &config,
+            tls_listener,
+            pool_config,
+            router,
+            timeouts,
+            per_ip.clone(),
+        )?);
+        if args::flag("--redirect-https") {
+            plain_router = Arc::new(http::router::Router::new(tls::redirect(config.port)));
+        }
+    }
+    shutdown::on_signals(listeners)?;
+    let pool = pool::Pool::new("http", pool_config, move |(stream, _slot): Admitted| {
+        handle_stream(stream, plain_router.clone(), timeouts)
+    })?;
+    for tcp_stream in shutdown::incoming(tcp_listener)? {
+        match tcp_stream {
+            Ok(stream) => match admit(&stream, &per_ip) {
+                Some(slot) => {
+                    if let Err((stream, _slot)) = pool.execute((stream, slot)) {
+                        refuse(stream, "workers saturated");
+                    }
+                }
+                None => refuse(stream, "too many connections from its address"),
+            },
+            Err(e) => elog_from_mod!("failed to accept connection", e),
+        }
+    }
+    drain("http", pool);
+    if let Some(tls_thread) = tls_thread {
+        let _ = tls_thread.join();
     }
+    log_from_mod!("stopped");
     Ok(())
 }
 // #[cfg(test)]
@@ -0,0 +1,176 @@
+//! Fixed set of worker threads fed through a bounded queue
+use std::{
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Workers started when `--workers` is not given
+pub const DEFAULT_WORKERS: usize = 64;
+/// Items waiting for a worker when `--queue` is not given
+pub const DEFAULT_QUEUE: usize = 128;
+/// How often [`Pool::join`] checks on the workers
+const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Pool size, read from the `--workers` and `--queue` flags
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Config {
+    pub workers: usize,
+    pub queue: usize,
+}
+impl Config {
+    pub fn from_args() -> Self {
+        let parse = |flag: &str, default: usize| {
+            crate::args::value(flag)
+                .and_then(|value| value.parse::<usize>().ok())
+                .filter(|value| *value > 0)
+                .unwrap_or(default)
+        };
+        Self {
+            workers: parse("--workers", DEFAULT_WORKERS),
+            queue: parse("--queue", DEFAULT_QUEUE),
+        }
+    }
+}
+
+/// Handles each item on whichever worker takes it off the queue
+///
+/// Errors and panics of the handler are logged, and the worker moves on to
+/// the next item.
+pub struct Pool<T> {
+    sender: Option<SyncSender<T>>,
+    workers: Vec<JoinHandle<()>>,
+}
+impl<T: Send + 'static> Pool<T> {
+    pub fn new<F>(name: &str, config: Config, handler: F) -> io::Result<Self>
+    where
+        F: Fn(T) -> io::Result<()> + Send + Sync + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel::<T>(config.queue);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handler = Arc::new(handler);
+        let workers = (0..config.workers)
+            .map(|index| {
+                let (receiver, handler) = (receiver.clone(), handler.clone());
+                thread::Builder::new()
+                    .name(format!("{}-{}", name, index))
+                    .spawn(move || work(&receiver, handler.as_ref()))
+            })
+            .collect::<io::Result<Vec<JoinHandle<()>>>>()?;
+        log_from_mod!("started workers", format!("{} x{}", name, config.workers));
+        Ok(Self {
+            sender: Some(sender),
+            workers,
+        })
+    }
+    /// Stops taking items and waits for the workers to finish those queued until `deadline`
+    ///
+    /// Returns false if some worker was still busy at the deadline, it is
+    /// then left running.
+    pub fn join(mut self, deadline: Instant) -> bool {
+        drop(self.sender.take());
+        while Instant::now() < deadline {
+            if self.workers.iter().all(JoinHandle::is_finished) {
+                return true;
+            }
+            thread::sleep(JOIN_POLL_INTERVAL);
+        }
+        self.workers.retain(|worker| !worker.is_finished());
+        let finished = self.workers.is_empty();
+        self.workers.clear();
+        finished
+    }
+    /// Queues `item` for the next free worker, giving it back if the queue is full
+    pub fn execute(&self, item: T) -> Result<(), T> {
+        match self.sender.as_ref().map(|sender| sender.try_send(item)) {
+            Some(Ok(())) => Ok(()),
+            Some(Err(TrySendError::Full(item) | TrySendError::Disconnected(item))) => Err(item),
+            None => unreachable!("sender is only taken on drop"),
+        }
+    }
+}
+impl<T> Drop for Pool<T> {
+    /// Lets the workers finish what is queued, then waits for them
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        self.workers.drain(..).for_each(|worker| {
+            let _ = worker.join();
+        });
+    }
+}
+
+/// Runs `handler` on items off the queue until every sender is gone
+fn work<T, F>(receiver: &Mutex<Receiver<T>>, handler: &F)
+where
+    F: Fn(T) -> io::Result<()>,
+{
+    loop {
+        // the lock is held only while waiting, never while handling
+        let item = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        let item = match item {
+            Ok(item) => item,
+            Err(_) => return,
+        };
+        match panic::catch_unwind(AssertUnwindSafe(|| handler(item))) {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => elog_from_mod!("connection failed", e),
+            Err(_) => elog_from_mod!("connection handler panicked"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Pool};
+    use std::sync::{mpsc, Mutex};
+
+    #[test]
+    fn items_are_handled_by_workers() {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let config = Config {
+            workers: 2,
+            queue: 4,
+        };
+        let pool = Pool::new("test", config, move |item: u32| {
+            sender.lock().unwrap().send(item * 2).unwrap();
+            Ok(())
+        })
+        .unwrap();
+        (1..=3).for_each(|item| assert!(pool.execute(item).is_ok()));
+        drop(pool);
+        let mut handled = receiver.iter().collect::<Vec<u32>>();
+        handled.sort();
+        assert_eq!(handled, vec![2, 4, 6]);
+    }
+
+    #[test]
+    fn full_queues_give_items_back() {
+        let (started, wait_started) = mpsc::channel();
+        let (release, wait_release) = mpsc::channel::<()>();
+        let (started, wait_release) = (Mutex::new(started), Mutex::new(wait_release));
+        let config = Config {
+            workers: 1,
+            queue: 1,
+        };
+        let pool = Pool::new("test", config, move |_: u32| {
+            started.lock().unwrap().send(()).unwrap();
+            let _ = wait_release.lock().unwrap().recv();
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(pool.execute(1), Ok(()));
+        wait_started.recv().unwrap();
+        assert_eq!(pool.execute(2), Ok(()));
+        assert_eq!(pool.execute(3), Err(3));
+        drop(release);
+    }
+}
@@ -0,0 +1,232 @@
+//! Graceful shutdown: stop accepting, let requests in flight finish, close idle connections
+//!
+//! Connections register themselves while they are served and tell apart the
+//! time spent waiting for a request from the time spent handling one. Once a
+//! stop is requested idle connections are closed at once, busy ones after
+//! their current response.
+
+/// Module to hand listening sockets over to a new process
+pub(crate) mod restart;
+
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    io,
+    net::{Shutdown, TcpListener, TcpStream},
+    os::fd::{AsRawFd, RawFd},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+/// Time connections get to finish once a stop is requested, unless `--shutdown-timeout` is given
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often accept loops look for a stop request while no connection arrives
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+static STOPPING: AtomicBool = AtomicBool::new(false);
+static DEADLINE: OnceLock<Instant> = OnceLock::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+/// Connections being served, and whether they are waiting for a request
+static CONNECTIONS: Mutex<BTreeMap<u64, Tracked>> = Mutex::new(BTreeMap::new());
+
+thread_local! {
+    /// Connection served by the current thread
+    static CURRENT: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+struct Tracked {
+    stream: TcpStream,
+    idle: bool,
+}
+
+/// Returns true once a stop has been requested
+pub fn requested() -> bool {
+    STOPPING.load(Ordering::SeqCst)
+}
+
+/// Instant by which connections still open get closed, set when a stop is requested
+pub fn deadline() -> Instant {
+    *DEADLINE.get_or_init(|| {
+        let timeout = crate::args::value("--shutdown-timeout")
+            .and_then(|secs| secs.parse().ok())
+            .map_or(DEFAULT_TIMEOUT, Duration::from_secs);
+        Instant::now() + timeout
+    })
+}
+
+/// Stops accepting connections and closes those waiting for a request
+pub fn request() {
+    let connections = lock();
+    if STOPPING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    log_from_mod!("stopping, open connections", connections.len());
+    deadline();
+    connections
+        .values()
+        .filter(|tracked| tracked.idle)
+        .for_each(|tracked| {
+            let _ = tracked.stream.shutdown(Shutdown::Both);
+        });
+}
+
+/// Closes every connection still open, for when the deadline passed
+pub fn close_all() {
+    let connections = lock();
+    elog_from_mod!("deadline passed, closing connections", connections.len());
+    connections.values().for_each(|tracked| {
+        let _ = tracked.stream.shutdown(Shutdown::Both);
+    });
+}
+
+fn lock() -> std::sync::MutexGuard<'static, BTreeMap<u64, Tracked>> {
+    CONNECTIONS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Keeps a connection registered until dropped
+pub struct Guard(u64);
+impl Drop for Guard {
+    fn drop(&mut self) {
+        lock().remove(&self.0);
+        CURRENT.with(|current| current.set(None));
+    }
+}
+
+/// Registers the connection served by the current thread, busy until it waits for a request
+pub fn track(stream: &TcpStream) -> io::Result<Guard> {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let tracked = Tracked {
+        stream: stream.try_clone()?,
+        idle: false,
+    };
+    lock().insert(id, tracked);
+    CURRENT.with(|current| current.set(Some(id)));
+    Ok(Guard(id))
+}
+
+/// Marks the current connection as waiting for a request
+///
+/// Returns true if the server is stopping, in which case the connection
+/// should be closed instead of waiting.
+pub fn idle() -> bool {
+    set_idle(true);
+    requested()
+}
+
+/// Marks the current connection as handling a request
+pub fn busy() {
+    set_idle(false);
+}
+
+fn set_idle(idle: bool) {
+    if let Some(id) = CURRENT.with(Cell::get) {
+        // a stop requested after this knows the connection is idle, one
+        // requested before is seen by the caller
+        if let Some(tracked) = lock().get_mut(&id) {
+            tracked.idle = idle;
+        }
+    }
+}
+
+/// Waits until `fd` has a connection to accept or the poll interval passed
+fn readable(fd: RawFd) -> io::Result<bool> {
+    let mut poll_fd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: poll_fd is a single valid pollfd for the duration of the call
+    let ready = unsafe { libc::poll(&mut poll_fd, 1, POLL_INTERVAL.as_millis() as libc::c_int) };
+    match ready {
+        -1 => match io::Error::last_os_error() {
+            e if e.kind().eq(&io::ErrorKind::Interrupted) => Ok(false),
+            e => Err(e),
+        },
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+/// Accepts connections on `listener` until a stop is requested
+///
+/// The listener is made non blocking, as another process may share it and
+/// take the connection first.
+pub fn incoming(listener: TcpListener) -> io::Result<Incoming> {
+    listener.set_nonblocking(true)?;
+    Ok(Incoming { listener })
+}
+
+pub struct Incoming {
+    listener: TcpListener,
+}
+impl Iterator for Incoming {
+    type Item = io::Result<TcpStream>;
+    fn next(&mut self) -> Option<Self::Item> {
+        while !requested() {
+            match readable(self.listener.as_raw_fd()) {
+                Ok(true) => (),
+                Ok(false) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+            match self.listener.accept() {
+                Ok((stream, _address)) => {
+                    return Some(stream.set_nonblocking(false).map(|()| stream));
+                }
+                Err(e) if e.kind().eq(&io::ErrorKind::WouldBlock) => (),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        None
+    }
+}
+
+/// Requests a stop on SIGINT and SIGTERM, and a hot restart on SIGUSR2
+///
+/// A hot restart starts a new process inheriting `listeners`, then stops this
+/// one as SIGTERM would.
+pub fn on_signals(listeners: Vec<(&'static str, RawFd)>) -> io::Result<()> {
+    use signal_hook::consts::{SIGINT, SIGTERM, SIGUSR2};
+    let mut signals = signal_hook::iterator::Signals::new([SIGINT, SIGTERM, SIGUSR2])?;
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGUSR2 {
+                match restart::spawn(&listeners) {
+                    Ok(pid) => {
+                        log_from_mod!("started new process", pid);
+                    }
+                    Err(e) => {
+                        elog_from_mod!("hot restart failed, still serving", e);
+                        continue;
+                    }
+                }
+            }
+            request();
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{busy, idle, requested, track};
+    use std::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn connections_switch_between_idle_and_busy() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let guard = track(&stream).unwrap();
+        assert_eq!(idle(), requested());
+        assert!(super::lock().get(&guard.0).unwrap().idle);
+        busy();
+        assert!(!super::lock().get(&guard.0).unwrap().idle);
+        let id = guard.0;
+        drop(guard);
+        assert!(super::lock().get(&id).is_none());
+    }
+}
@@ -0,0 +1,85 @@
+//! Zero downtime upgrades: a new process inherits the listening sockets and
+//! accepts alongside this one until it stops
+use std::{
+    io,
+    net::TcpListener,
+    os::fd::{FromRawFd, RawFd},
+    process::Command,
+};
+
+/// Environment variable listing the inherited sockets, as `name=fd` pairs separated by commas
+pub const ENV: &str = "HTTP_SERVER_LISTEN_FDS";
+
+/// Parses the value of [`ENV`] into names and descriptors
+fn parse(value: &str) -> Vec<(String, RawFd)> {
+    value
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .filter_map(|(name, fd)| Some((name.to_string(), fd.parse::<RawFd>().ok()?)))
+        .collect()
+}
+
+/// Listening socket called `name` handed over by the process that started this one
+pub fn inherited(name: &str) -> Option<TcpListener> {
+    let value = std::env::var(ENV).ok()?;
+    let (_, fd) = parse(&value)
+        .into_iter()
+        .find(|(inherited, _)| inherited.eq(name))?;
+    log_from_mod!("inherited listener", format!("{}={}", name, fd));
+    // SAFETY: the parent process left `fd` open for us, and nothing else in
+    // this process owns it
+    Some(unsafe { TcpListener::from_raw_fd(fd) })
+}
+
+/// Sets or clears close-on-exec on `fd`
+fn set_cloexec(fd: RawFd, cloexec: bool) -> io::Result<()> {
+    // SAFETY: fcntl on a descriptor we own, with integer arguments only
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let flags = match cloexec {
+        true => flags | libc::FD_CLOEXEC,
+        false => flags & !libc::FD_CLOEXEC,
+    };
+    // SAFETY: as above
+    match unsafe { libc::fcntl(fd, libc::F_SETFD, flags) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Starts this executable again with the same arguments, inheriting `listeners`
+///
+/// Returns the id of the new process.
+pub fn spawn(listeners: &[(&str, RawFd)]) -> io::Result<u32> {
+    let value = listeners
+        .iter()
+        .map(|(name, fd)| format!("{}={}", name, fd))
+        .collect::<Vec<String>>()
+        .join(",");
+    for (_, fd) in listeners {
+        set_cloexec(*fd, false)?;
+    }
+    let child = Command::new(std::env::current_exe()?)
+        .args(std::env::args_os().skip(1))
+        .env(ENV, value)
+        .spawn();
+    for (_, fd) in listeners {
+        set_cloexec(*fd, true)?;
+    }
+    child.map(|child| child.id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn inherited_sockets_are_parsed() {
+        assert_eq!(
+            parse("http=3,tls=4,bad,x=y"),
+            vec![(String::from("http"), 3), (String::from("tls"), 4)]
+        );
+    }
+}
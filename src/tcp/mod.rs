@@ -1,4 +1,11 @@
-use std::net::{SocketAddr, TcpListener};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr, TcpListener},
+    sync::{Arc, Mutex},
+};
+
+/// Connections a single address may hold open when `--max-connections-per-ip` is not given
+pub const DEFAULT_MAX_PER_IP: usize = 32;
 /// Creates a `TcpListener` by attempting to bind to the
 /// passed `SocketAddr`.
 ///
@@ -11,3 +18,76 @@ pub fn listener(socketaddr: SocketAddr) -> std::io::Result<TcpListener> {
         SocketAddr::V6(_socket_address) => unimplemented!(),
     }
 }
+
+/// Counts the connections open from each client address, up to a cap
+pub struct PerIp {
+    max: usize,
+    open: Mutex<HashMap<IpAddr, usize>>,
+}
+impl PerIp {
+    pub fn new(max: usize) -> Arc<Self> {
+        Arc::new(Self {
+            max,
+            open: Mutex::new(HashMap::new()),
+        })
+    }
+    /// Reads the cap from `--max-connections-per-ip`
+    pub fn from_args() -> Arc<Self> {
+        let max = crate::args::value("--max-connections-per-ip")
+            .and_then(|max| max.parse().ok())
+            .filter(|max| *max > 0)
+            .unwrap_or(DEFAULT_MAX_PER_IP);
+        Self::new(max)
+    }
+    /// Counts a new connection from `ip`, or returns `None` if it already holds the most allowed
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr) -> Option<Slot> {
+        let mut open = self.open.lock().unwrap_or_else(|e| e.into_inner());
+        let count = open.entry(ip).or_default();
+        if *count >= self.max {
+            return None;
+        }
+        *count += 1;
+        Some(Slot {
+            per_ip: self.clone(),
+            ip,
+        })
+    }
+}
+
+/// A connection counted against its address until dropped
+pub struct Slot {
+    per_ip: Arc<PerIp>,
+    ip: IpAddr,
+}
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut open = self.per_ip.open.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = open.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PerIp;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn connections_are_capped_per_address() {
+        let per_ip = PerIp::new(2);
+        let (a, b) = (
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+        );
+        let first = per_ip.acquire(a).unwrap();
+        let _second = per_ip.acquire(a).unwrap();
+        assert!(per_ip.acquire(a).is_none());
+        assert!(per_ip.acquire(b).is_some());
+        drop(first);
+        assert!(per_ip.acquire(a).is_some());
+    }
+}
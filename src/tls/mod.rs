@@ -0,0 +1,288 @@
+/// Module to pick and reload certificates
+pub(crate) mod resolver;
+
+use {
+    crate::{
+        args,
+        http::{
+            connection::{self, Handler, Socket, Timeouts},
+            header::typed::Host,
+            response::Status,
+            router::Router,
+            Error, Request, Response,
+        },
+    },
+    resolver::{Resolver, Source},
+    rustls::{ServerConfig, ServerConnection, StreamOwned},
+    std::{
+        io,
+        net::{SocketAddr, TcpStream},
+        path::PathBuf,
+        sync::Arc,
+        time::Duration,
+    },
+};
+
+/// Port the HTTPS listener binds to when `--tls-port` is not given
+pub const DEFAULT_PORT: u16 = 4443;
+
+/// Protocols advertised over ALPN, most preferred first
+const ALPN_PROTOCOLS: [&[u8]; 2] = [b"h2", b"http/1.1"];
+
+/// Settings of the HTTPS listener
+///
+/// Read from `--tls-cert` and `--tls-key` for the default certificate,
+/// `--tls-sni name=cert,key` for each name served with its own certificate,
+/// and `--tls-port` for the port.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Config {
+    pub port: u16,
+    pub default: Option<Source>,
+    pub named: Vec<(String, Source)>,
+}
+impl Config {
+    /// Returns `Ok(None)` unless at least one certificate was given
+    pub fn from_args() -> io::Result<Option<Self>> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        let default = match (args::value("--tls-cert"), args::value("--tls-key")) {
+            (Some(cert), Some(key)) => Some(Source {
+                cert: PathBuf::from(cert),
+                key: PathBuf::from(key),
+            }),
+            (None, None) => None,
+            _ => {
+                let message = String::from("--tls-cert and --tls-key go together");
+                return Err(invalid(message));
+            }
+        };
+        let named = args::values("--tls-sni")
+            .into_iter()
+            .map(|value| {
+                let (name, (cert, key)) = value
+                    .split_once('=')
+                    .and_then(|(name, files)| Some((name, files.split_once(',')?)))
+                    .ok_or_else(|| invalid(format!("expected name=cert,key, got {}", value)))?;
+                let source = Source {
+                    cert: PathBuf::from(cert),
+                    key: PathBuf::from(key),
+                };
+                Ok((name.to_string(), source))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let port = match args::value("--tls-port") {
+            Some(port) => port
+                .parse()
+                .map_err(|_| invalid(format!("invalid --tls-port {}", port)))?,
+            None => DEFAULT_PORT,
+        };
+        if default.is_none() && named.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            port,
+            default,
+            named,
+        }))
+    }
+}
+
+/// Builds the rustls configuration, loading every certificate
+///
+/// The resolver is returned alongside so certificates can be reloaded later.
+pub fn server_config(config: &Config) -> io::Result<(Arc<ServerConfig>, Arc<Resolver>)> {
+    let resolver = Arc::new(Resolver::new(config.default.clone(), config.named.clone())?);
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut server_config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| io::Error::other(e.to_string()))?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver.clone());
+    server_config.alpn_protocols = ALPN_PROTOCOLS.iter().map(|p| p.to_vec()).collect();
+    Ok((Arc::new(server_config), resolver))
+}
+
+/// Reloads the certificates every time the process receives SIGHUP
+///
+/// Certificates that fail to load are reported and the previous ones kept.
+pub fn reload_on_hangup(resolver: Arc<Resolver>) -> io::Result<()> {
+    let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGHUP])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            match resolver.reload() {
+                Ok(()) => log_from_mod!("reloaded certificates"),
+                Err(e) => elog_from_mod!("keeping previous certificates", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Completes the TLS handshake on `tcp_stream`, then serves HTTP over it
+///
+/// The handshake has to complete within the header timeout.
+pub fn serve(
+    tcp_stream: TcpStream,
+    config: Arc<ServerConfig>,
+    router: Arc<Router>,
+    timeouts: Timeouts,
+) -> io::Result<()> {
+    tcp_stream.set_read_timeout(Some(timeouts.header))?;
+    tcp_stream.set_write_timeout(Some(timeouts.write))?;
+    let connection = ServerConnection::new(config).map_err(|e| io::Error::other(e.to_string()))?;
+    let mut stream = StreamOwned::new(connection, tcp_stream);
+    while stream.conn.is_handshaking() {
+        stream.conn.complete_io(&mut stream.sock)?;
+    }
+    let alpn = stream
+        .conn
+        .alpn_protocol()
+        .map(|protocol| String::from_utf8_lossy(protocol).into_owned());
+    log_from_mod!(
+        "tls handshake complete",
+        alpn.as_deref().unwrap_or("no alpn")
+    );
+    connection::serve(stream, router, timeouts)
+}
+
+impl Socket for StreamOwned<ServerConnection, TcpStream> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_write_timeout(timeout)
+    }
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.sock.peer_addr()
+    }
+    fn is_secure(&self) -> bool {
+        true
+    }
+}
+
+/// Handler for the plain listener that sends every request to the HTTPS listener
+pub fn redirect(port: u16) -> Handler {
+    Arc::new(
+        move |request: Request| match request.headers().typed::<Host>() {
+            Some(Host { host, .. }) => {
+                let authority = match port {
+                    443 => host,
+                    port => format!("{}:{}", host, port),
+                };
+                let location = format!("https://{}{}", authority, request.start_line().target.path);
+                Ok(Response::redirect(Status::PermanentRedirect, &location))
+            }
+            None => Ok(Response::from_error(&Error::Host(String::from(
+                "missing Host header to redirect with",
+            )))),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolver::Source, serve, server_config, Config};
+    use crate::http::{connection::Timeouts, response::Status, router::Router, Response};
+    use rustls::{
+        pki_types::{CertificateDer, ServerName},
+        ClientConfig, ClientConnection, RootCertStore, StreamOwned,
+    };
+    use std::{
+        fs::File,
+        io::{BufReader, Read, Write},
+        net::{TcpListener, TcpStream},
+        path::PathBuf,
+        sync::Arc,
+    };
+
+    /// Self-signed certificate and key checked in for `name.test`, made with
+    /// `openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:prime256v1`
+    fn source(name: &str) -> Source {
+        let file = |suffix: &str| {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("src/tls/testdata")
+                .join(format!("{}.{}.pem", name, suffix))
+        };
+        Source {
+            cert: file("cert"),
+            key: file("key"),
+        }
+    }
+
+    fn certificate(name: &str) -> CertificateDer<'static> {
+        let mut reader = BufReader::new(File::open(source(name).cert).unwrap());
+        let certificate = rustls_pemfile::certs(&mut reader).next().unwrap().unwrap();
+        certificate
+    }
+
+    /// Client trusting both test certificates, offering `alpn`
+    fn client(alpn: &[&[u8]]) -> Arc<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        for name in ["default", "a"] {
+            roots.add(certificate(name)).unwrap();
+        }
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+        Arc::new(config)
+    }
+
+    /// Server name and protocols offered, certificate and protocol expected
+    type Case<'a> = (&'a str, &'a [&'a [u8]], &'a str, Option<&'a [u8]>);
+
+    #[test]
+    fn handshakes_select_the_certificate_by_sni_and_negotiate_alpn() {
+        let config = Config {
+            port: 0,
+            default: Some(source("default")),
+            named: vec![(String::from("A.test"), source("a"))],
+        };
+        let (server, _resolver) = server_config(&config).unwrap();
+        let router = Arc::new(Router::new(Arc::new(|_| Ok(Response::empty(Status::Ok)))));
+        let cases: [Case; 4] = [
+            ("a.test", &[b"h2", b"http/1.1"], "a", Some(b"h2")),
+            ("a.test", &[b"http/1.1"], "a", Some(b"http/1.1")),
+            (
+                "default.test",
+                &[b"h2", b"http/1.1"],
+                "default",
+                Some(b"h2"),
+            ),
+            ("default.test", &[], "default", None),
+        ];
+        for (name, alpn, expected_certificate, expected_alpn) in cases {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            let (server, router) = (server.clone(), router.clone());
+            let serving = std::thread::spawn(move || {
+                let (stream, _) = listener.accept().unwrap();
+                serve(stream, server, router, Timeouts::default())
+            });
+            let server_name = ServerName::try_from(name.to_string()).unwrap();
+            let connection = ClientConnection::new(client(alpn), server_name).unwrap();
+            let mut stream = StreamOwned::new(connection, TcpStream::connect(address).unwrap());
+            while stream.conn.is_handshaking() {
+                stream.conn.complete_io(&mut stream.sock).unwrap();
+            }
+            let presented = stream.conn.peer_certificates().unwrap()[0].clone();
+            assert_eq!(presented, certificate(expected_certificate), "{}", name);
+            assert_eq!(stream.conn.alpn_protocol(), expected_alpn, "{}", name);
+            if expected_alpn != Some(b"h2") {
+                let request = format!(
+                    "GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                    name
+                );
+                stream.write_all(request.as_bytes()).unwrap();
+                let mut response = String::new();
+                let _ = stream.read_to_string(&mut response);
+                assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+            }
+            // h2 clients leave without their preface, failing the connection
+            drop(stream);
+            let _ = serving.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use {
+    rustls::{
+        crypto::ring::sign::any_supported_type,
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+    },
+    std::{
+        collections::HashMap,
+        fmt,
+        fs::File,
+        io::{self, BufReader},
+        path::PathBuf,
+        sync::{Arc, RwLock},
+    },
+};
+
+/// PEM files holding a certificate chain and its private key
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Source {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+impl Source {
+    /// Loads the chain and key, checking that the key belongs to the leaf certificate
+    pub fn load(&self) -> io::Result<CertifiedKey> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut reader = BufReader::new(File::open(&self.cert)?);
+        let chain = rustls_pemfile::certs(&mut reader).collect::<io::Result<Vec<_>>>()?;
+        if chain.is_empty() {
+            return Err(invalid(format!(
+                "no certificate in {}",
+                self.cert.display()
+            )));
+        }
+        let mut reader = BufReader::new(File::open(&self.key)?);
+        let key = rustls_pemfile::private_key(&mut reader)?
+            .ok_or_else(|| invalid(format!("no private key in {}", self.key.display())))?;
+        let key = any_supported_type(&key).map_err(|e| invalid(e.to_string()))?;
+        let certified = CertifiedKey::new(chain, key);
+        certified
+            .keys_match()
+            .map_err(|e| invalid(format!("{}: {}", self.key.display(), e)))?;
+        Ok(certified)
+    }
+}
+
+/// Certificates currently served, keyed by lowercase server name
+#[derive(Default)]
+struct Certificates {
+    default: Option<Arc<CertifiedKey>>,
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+impl Certificates {
+    /// Picks the certificate for `server_name`, falling back to the default
+    fn select(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        server_name
+            .map(candidates)
+            .unwrap_or_default()
+            .iter()
+            .find_map(|name| self.by_name.get(name))
+            .or(self.default.as_ref())
+            .cloned()
+    }
+}
+
+/// Names a certificate may be registered under for `server_name`, most specific first
+///
+/// A wildcard only covers a single label, so `*.example.com` matches
+/// `www.example.com` but neither `example.com` nor `a.www.example.com`.
+fn candidates(server_name: &str) -> Vec<String> {
+    let name = server_name.trim_end_matches('.').to_ascii_lowercase();
+    let wildcard = name
+        .split_once('.')
+        .filter(|(_, parent)| parent.contains('.'))
+        .map(|(_, parent)| format!("*.{}", parent));
+    std::iter::once(name).chain(wildcard).collect()
+}
+
+/// Picks certificates by SNI, and reloads them from disk on request
+pub struct Resolver {
+    default: Option<Source>,
+    named: Vec<(String, Source)>,
+    certificates: RwLock<Certificates>,
+}
+impl fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Resolver")
+            .field("default", &self.default)
+            .field("named", &self.named)
+            .finish()
+    }
+}
+impl Resolver {
+    /// Loads every source, failing if any of them can not be loaded
+    pub fn new(default: Option<Source>, named: Vec<(String, Source)>) -> io::Result<Self> {
+        let resolver = Self {
+            default,
+            named,
+            certificates: RwLock::new(Certificates::default()),
+        };
+        resolver.reload()?;
+        Ok(resolver)
+    }
+    /// Reloads every certificate from disk
+    ///
+    /// The certificates are only swapped once all of them loaded, so a bad
+    /// file leaves the previous certificates in place.
+    pub fn reload(&self) -> io::Result<()> {
+        let default = match &self.default {
+            Some(source) => Some(Arc::new(source.load()?)),
+            None => None,
+        };
+        let mut by_name = HashMap::new();
+        for (name, source) in &self.named {
+            by_name.insert(name.to_ascii_lowercase(), Arc::new(source.load()?));
+        }
+        let mut certificates = self
+            .certificates
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *certificates = Certificates { default, by_name };
+        Ok(())
+    }
+}
+impl ResolvesServerCert for Resolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let certificates = self
+            .certificates
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let selected = certificates.select(client_hello.server_name());
+        if selected.is_none() {
+            elog_from_mod!(
+                "no certificate for server name",
+                client_hello.server_name().unwrap_or("<none>")
+            );
+        }
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{candidates, Certificates, Source};
+    use std::path::PathBuf;
+
+    #[test]
+    fn missing_files_fail_to_load() {
+        let source = Source {
+            cert: PathBuf::from("/nonexistent/cert.pem"),
+            key: PathBuf::from("/nonexistent/key.pem"),
+        };
+        assert!(source.load().is_err());
+    }
+
+    #[test]
+    fn server_names_fall_back_to_one_wildcard_label() {
+        assert_eq!(
+            candidates("WWW.Example.com."),
+            vec!["www.example.com", "*.example.com"]
+        );
+        assert_eq!(candidates("example.com"), vec!["example.com"]);
+        assert_eq!(candidates("localhost"), vec!["localhost"]);
+    }
+
+    #[test]
+    fn nothing_is_selected_without_certificates() {
+        let certificates = Certificates::default();
+        assert!(certificates.select(Some("example.com")).is_none());
+        assert!(certificates.select(None).is_none());
+    }
+}